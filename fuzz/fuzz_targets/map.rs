@@ -0,0 +1,16 @@
+#![no_main]
+
+use btree::{map::BTreeMap, testing};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the degree and the rest is the script.
+    let Some((&degree, script)) = data.split_first() else {
+        return;
+    };
+
+    testing::run(
+        &mut BTreeMap::with_degree(2 + degree as usize % 4),
+        &testing::ops_from_bytes(script),
+    );
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use btree::{testing, tree::BTree};
+use libfuzzer_sys::fuzz_target;
+use storage::mem::MemStorage;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the degree and the rest is the script.
+    let Some((&degree, script)) = data.split_first() else {
+        return;
+    };
+
+    let mut tree =
+        BTree::with_storage_and_degree(MemStorage::new(), 2 + degree as usize % 4).unwrap();
+    testing::run(&mut tree, &testing::ops_from_bytes(script));
+
+    // Everything should survive a round trip through storage.
+    tree.persist().unwrap();
+    tree.trim_cache().unwrap();
+    assert!(tree.verify().unwrap().is_ok());
+});
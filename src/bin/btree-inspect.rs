@@ -0,0 +1,160 @@
+use btree::tree::BTree;
+use serde::{Deserialize, Serialize};
+use std::{env, fmt::Debug, fs, process::ExitCode, str::FromStr};
+
+const USAGE: &str = "\
+usage: btree-inspect <dir> <root-id> <command> [--key <type>] [--value <type>]
+
+commands:
+    dump         print every entry in key order
+    get <key>    print the value stored under <key>
+    stats        print the length, degree, height, and node count
+    verify       check the tree's invariants and look for leaked objects
+    occupancy    print how full the nodes at each level are
+    dot          print the tree in Graphviz DOT format, including orphaned objects
+    render       print the tree's structure
+
+types: u64, i64, string (default: u64)";
+
+struct Args {
+    dir: String,
+    root_id: u64,
+    command: Vec<String>,
+    key: String,
+    value: String,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut positional = vec![];
+    let mut key = "u64".to_string();
+    let mut value = "u64".to_string();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => key = args.next().ok_or("missing key type")?,
+            "--value" => value = args.next().ok_or("missing value type")?,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() < 3 {
+        return Err(USAGE.to_string());
+    }
+
+    let command = positional.split_off(2);
+    let root_id = positional[1]
+        .parse()
+        .map_err(|_| format!("invalid root ID: {}", positional[1]))?;
+
+    Ok(Args {
+        dir: positional.swap_remove(0),
+        root_id,
+        command,
+        key,
+        value,
+    })
+}
+
+/// Returns the IDs of the objects in `dir`, i.e. every file named by an ID.
+fn objects(dir: &str) -> Result<Vec<u64>, String> {
+    Ok(fs::read_dir(dir)
+        .map_err(|err| format!("{err}"))?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+fn run<K, V>(args: &Args) -> Result<bool, String>
+where
+    for<'de> K: Ord + Debug + FromStr + Serialize + Deserialize<'de>,
+    for<'de> V: Debug + Serialize + Deserialize<'de>,
+{
+    let tree = BTree::<K, V>::load(args.root_id, &args.dir).map_err(|err| format!("{err}"))?;
+
+    match args.command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["dump"] => {
+            for entry in tree.iter().map_err(|err| format!("{err}"))? {
+                let (k, v) = entry.map_err(|err| format!("{err}"))?;
+                println!("{k:?} => {v:?}");
+            }
+        }
+        ["get", key] => {
+            let key = key
+                .parse::<K>()
+                .map_err(|_| format!("invalid key: {key}"))?;
+            match tree.get(&key).map_err(|err| format!("{err}"))? {
+                Some(v) => println!("{v:?}"),
+                None => return Ok(false),
+            }
+        }
+        ["stats"] => {
+            let report = tree.verify().map_err(|err| format!("{err}"))?;
+            println!("len:    {}", tree.len());
+            println!("degree: {}", tree.degree());
+            println!("height: {}", report.height);
+            println!("nodes:  {}", report.nodes);
+        }
+        ["verify"] => {
+            let report = tree
+                .verify_with_objects(objects(&args.dir)?)
+                .map_err(|err| format!("{err}"))?;
+            for problem in &report.problems {
+                println!("{problem:?}");
+            }
+            println!(
+                "checked {} nodes and {} entries: {} problems",
+                report.nodes,
+                report.entries,
+                report.problems.len()
+            );
+            return Ok(report.is_ok());
+        }
+        ["occupancy"] => {
+            print!("{}", tree.occupancy().map_err(|err| format!("{err}"))?);
+        }
+        ["dot"] => {
+            print!(
+                "{}",
+                tree.to_dot_with_objects(objects(&args.dir)?)
+                    .map_err(|err| format!("{err}"))?
+            );
+        }
+        ["render"] => {
+            print!("{}", tree.render().map_err(|err| format!("{err}"))?);
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+
+    Ok(true)
+}
+
+fn dispatch<K>(args: &Args) -> Result<bool, String>
+where
+    for<'de> K: Ord + Debug + FromStr + Serialize + Deserialize<'de>,
+{
+    match args.value.as_str() {
+        "u64" => run::<K, u64>(args),
+        "i64" => run::<K, i64>(args),
+        "string" => run::<K, String>(args),
+        ty => Err(format!("unknown value type: {ty}")),
+    }
+}
+
+fn main() -> ExitCode {
+    let res = parse_args().and_then(|args| match args.key.as_str() {
+        "u64" => dispatch::<u64>(&args),
+        "i64" => dispatch::<i64>(&args),
+        "string" => dispatch::<String>(&args),
+        ty => Err(format!("unknown key type: {ty}")),
+    });
+
+    match res {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use btree::map::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+    new [degree]          start over with an empty tree (default degree: 2)
+    insert <key> [value]  insert an entry, the value defaults to the key
+    remove <key>          remove an entry
+    get <key>             print the value stored under <key>
+    fill <from> <to>      insert every key in [from, to)
+    clear                 remove every entry
+    show                  print the tree
+    help                  print this message
+    quit                  exit
+
+keys are integers and values are strings, the tree is printed after every change";
+
+fn parse_key(arg: Option<&str>) -> Result<i64, String> {
+    let arg = arg.ok_or("missing key")?;
+    arg.parse().map_err(|_| format!("invalid key: {arg}"))
+}
+
+fn show(map: &BTreeMap<i64, String>) {
+    print!("{map:?}");
+    println!("({} entries)", map.len());
+}
+
+fn main() -> io::Result<()> {
+    let mut map = BTreeMap::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    println!("type `help` for a list of commands");
+
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+
+        let mut args = line.split_whitespace();
+        let res = match args.next() {
+            None => continue,
+            Some("new") => match args.next().map(str::parse::<usize>) {
+                None => {
+                    map = BTreeMap::new();
+                    Ok(true)
+                }
+                Some(Ok(degree)) if degree >= 2 => {
+                    map = BTreeMap::with_degree(degree);
+                    Ok(true)
+                }
+                Some(_) => Err("degree must be an integer of at least 2".to_string()),
+            },
+            Some("insert") => parse_key(args.next()).map(|k| {
+                let v = args.next().map_or_else(|| k.to_string(), str::to_string);
+                if let Some(old) = map.insert(k, v) {
+                    println!("replaced {old:?}");
+                }
+                true
+            }),
+            Some("remove") => parse_key(args.next()).map(|k| match map.remove(&k) {
+                Some(v) => {
+                    println!("removed {v:?}");
+                    true
+                }
+                None => {
+                    println!("not found");
+                    false
+                }
+            }),
+            Some("get") => parse_key(args.next()).map(|k| {
+                match map.get(&k) {
+                    Some(v) => println!("{v:?}"),
+                    None => println!("not found"),
+                }
+                false
+            }),
+            Some("fill") => parse_key(args.next()).and_then(|from| {
+                let to = parse_key(args.next())?;
+                for k in from..to {
+                    map.insert(k, k.to_string());
+                }
+                Ok(true)
+            }),
+            Some("clear") => {
+                map.clear();
+                Ok(true)
+            }
+            Some("show") => {
+                show(&map);
+                Ok(false)
+            }
+            Some("help") => {
+                println!("{HELP}");
+                Ok(false)
+            }
+            Some("quit" | "exit") => break,
+            Some(cmd) => Err(format!("unknown command: {cmd}")),
+        };
+
+        match res {
+            Ok(true) => show(&map),
+            Ok(false) => {}
+            Err(err) => println!("error: {err}"),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::cmp::Ordering;
+
+/// The order a tree keeps its keys in.
+///
+/// A tree's nodes are only valid under the order they were built with, so `ID` is persisted
+/// with the tree and checked when it's loaded. `ID`s must be unique across comparators, and 0
+/// through 15 are reserved for the comparators and collations defined here.
+pub trait Comparator<K: ?Sized> {
+    const ID: u32;
+
+    fn cmp(a: &K, b: &K) -> Ordering;
+}
+
+/// Orders keys by their `Ord` implementation. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Natural;
+
+impl<K> Comparator<K> for Natural
+where
+    K: Ord + ?Sized,
+{
+    const ID: u32 = 0;
+
+    fn cmp(a: &K, b: &K) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys by the reverse of their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Descending;
+
+impl<K> Comparator<K> for Descending
+where
+    K: Ord + ?Sized,
+{
+    const ID: u32 = 1;
+
+    fn cmp(a: &K, b: &K) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Orders strings by their lowercase characters, so keys that only differ in case are the same
+/// key.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CaseInsensitive;
+
+impl<K> Comparator<K> for CaseInsensitive
+where
+    K: AsRef<str> + ?Sized,
+{
+    const ID: u32 = 2;
+
+    fn cmp(a: &K, b: &K) -> Ordering {
+        let a = a.as_ref().chars().flat_map(char::to_lowercase);
+        let b = b.as_ref().chars().flat_map(char::to_lowercase);
+        a.cmp(b)
+    }
+}
+
+/// Orders strings by the Unicode Collation Algorithm, using ICU's root collation.
+///
+/// Strings that the collation considers equal, e.g. ones that only differ in ignorable
+/// characters, are the same key.
+#[cfg(feature = "icu")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnicodeCollation;
+
+#[cfg(feature = "icu")]
+impl<K> Comparator<K> for UnicodeCollation
+where
+    K: AsRef<str> + ?Sized,
+{
+    const ID: u32 = 3;
+
+    fn cmp(a: &K, b: &K) -> Ordering {
+        thread_local! {
+            static COLLATOR: icu_collator::Collator =
+                icu_collator::Collator::try_new(&Default::default(), Default::default())
+                    .expect("the root collation is built in");
+        }
+
+        COLLATOR.with(|collator| collator.compare(a.as_ref(), b.as_ref()))
+    }
+}
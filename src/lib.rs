@@ -1,2 +1,9 @@
+pub mod comparator;
 pub mod map;
+pub mod occupancy;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "persistent")]
 pub mod tree;
+#[cfg(feature = "workload")]
+pub mod workload;
@@ -0,0 +1,151 @@
+use super::BTreeMap;
+use crate::comparator::Comparator;
+use std::ops::Bound;
+use thiserror::Error;
+
+/// Returned by `CursorMut::insert_after` and `CursorMut::insert_before` when the key wouldn't
+/// be between the entries it's inserted between, handing the entry back.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("key isn't ordered between the cursor's neighbours")]
+pub struct UnorderedKeyError<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+/// A cursor over a `BTreeMap` that can move between entries and edit the map around it, from
+/// `BTreeMap::cursor_mut`.
+///
+/// The cursor is either at an entry or at the ghost position past the last entry, which moving
+/// forward from wraps around to the first entry. It tracks its place by the number of entries
+/// before it, so each move is a descent from the root using the sizes of the subtrees.
+pub struct CursorMut<'a, K, V, C> {
+    map: &'a mut BTreeMap<K, V, C>,
+    idx: usize,
+}
+
+impl<K, V, C> BTreeMap<K, V, C>
+where
+    C: Comparator<K>,
+{
+    /// Returns a cursor at the first entry with a key at least `k`, or at the ghost position if
+    /// there isn't one.
+    pub fn cursor_mut(&mut self, k: &K) -> CursorMut<'_, K, V, C> {
+        self.lower_bound_mut(Bound::Included(k))
+    }
+
+    /// Returns a cursor at the entry `lower_bound` would return, or at the ghost position if
+    /// there isn't one.
+    pub fn lower_bound_mut(&mut self, bound: Bound<&K>) -> CursorMut<'_, K, V, C> {
+        let idx = self.lower_rank(bound);
+        CursorMut { map: self, idx }
+    }
+
+    /// Returns a cursor at the entry `upper_bound` would return, or at the ghost position if
+    /// there isn't one.
+    pub fn upper_bound_mut(&mut self, bound: Bound<&K>) -> CursorMut<'_, K, V, C> {
+        let idx = self.upper_rank(bound).checked_sub(1).unwrap_or(self.len);
+        CursorMut { map: self, idx }
+    }
+}
+
+impl<K, V, C> CursorMut<'_, K, V, C>
+where
+    C: Comparator<K>,
+{
+    fn is_ghost(&self) -> bool {
+        self.idx == self.map.len
+    }
+
+    /// Returns the place of the entry after the cursor, if there is one.
+    fn next_idx(&self) -> Option<usize> {
+        let idx = if self.is_ghost() { 0 } else { self.idx + 1 };
+        (idx < self.map.len).then_some(idx)
+    }
+
+    /// Returns the place of the entry before the cursor, if there is one.
+    fn prev_idx(&self) -> Option<usize> {
+        self.idx.checked_sub(1)
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.key_value().map(|(k, _)| k)
+    }
+
+    pub fn key_value(&self) -> Option<(&K, &V)> {
+        (!self.is_ghost()).then(|| self.map.root.nth(self.idx))
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        if self.is_ghost() {
+            return None;
+        }
+        Some(self.map.root.nth_mut(self.idx).1)
+    }
+
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        self.next_idx().map(|idx| self.map.root.nth(idx))
+    }
+
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        self.prev_idx().map(|idx| self.map.root.nth(idx))
+    }
+
+    /// Moves to the next entry, from the last entry to the ghost position, and from the ghost
+    /// position to the first entry.
+    pub fn move_next(&mut self) {
+        self.idx = if self.is_ghost() { 0 } else { self.idx + 1 };
+    }
+
+    /// Moves to the previous entry, from the first entry to the ghost position, and from the
+    /// ghost position to the last entry.
+    pub fn move_prev(&mut self) {
+        self.idx = self.prev_idx().unwrap_or(self.map.len);
+    }
+
+    /// Removes the entry at the cursor, moving the cursor to the entry after it.
+    ///
+    /// Returns `None` at the ghost position.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        self.map.remove_index(self.idx)
+    }
+
+    /// Inserts an entry right after the cursor, or at the front at the ghost position, without
+    /// moving the cursor.
+    ///
+    /// Fails if `k` isn't strictly between the keys of the entries it's inserted between.
+    pub fn insert_after(&mut self, k: K, v: V) -> Result<(), UnorderedKeyError<K, V>> {
+        let prev = self.key();
+        let next = self.peek_next().map(|(k, _)| k);
+        if !Self::is_between(&k, prev, next) {
+            return Err(UnorderedKeyError { key: k, value: v });
+        }
+
+        // The ghost position stays past the last entry.
+        if self.is_ghost() {
+            self.idx += 1;
+        }
+        self.map.insert(k, v);
+        Ok(())
+    }
+
+    /// Inserts an entry right before the cursor, or at the back at the ghost position, without
+    /// moving the cursor.
+    ///
+    /// Fails if `k` isn't strictly between the keys of the entries it's inserted between.
+    pub fn insert_before(&mut self, k: K, v: V) -> Result<(), UnorderedKeyError<K, V>> {
+        let prev = self.peek_prev().map(|(k, _)| k);
+        let next = self.key();
+        if !Self::is_between(&k, prev, next) {
+            return Err(UnorderedKeyError { key: k, value: v });
+        }
+
+        self.idx += 1;
+        self.map.insert(k, v);
+        Ok(())
+    }
+
+    fn is_between(k: &K, prev: Option<&K>, next: Option<&K>) -> bool {
+        prev.is_none_or(|prev| C::cmp(prev, k).is_lt())
+            && next.is_none_or(|next| C::cmp(k, next).is_lt())
+    }
+}
@@ -0,0 +1,187 @@
+use super::BTreeMap;
+use crate::comparator::Comparator;
+use std::borrow::Borrow;
+
+/// An entry of a `BTreeMap` looked up by a borrowed key, from `BTreeMap::entry_ref`.
+pub enum EntryRef<'a, 'q, K, Q: ?Sized, V, C> {
+    Occupied(OccupiedEntryRef<'a, V>),
+    Vacant(VacantEntryRef<'a, 'q, K, Q, V, C>),
+}
+
+/// An entry whose key is in the map.
+pub struct OccupiedEntryRef<'a, V> {
+    v: &'a mut V,
+}
+
+/// The entry with the smallest or largest key in a `BTreeMap`, from `BTreeMap::first_entry` or
+/// `BTreeMap::last_entry`.
+///
+/// The entry is found again by walking down the edge of the tree, which takes no key
+/// comparisons.
+pub struct OccupiedEntry<'a, K, V, C> {
+    map: &'a mut BTreeMap<K, V, C>,
+    last: bool,
+}
+
+/// An entry whose key isn't in the map, which only becomes an owned key when it's inserted.
+pub struct VacantEntryRef<'a, 'q, K, Q: ?Sized, V, C> {
+    map: &'a mut BTreeMap<K, V, C>,
+    q: &'q Q,
+}
+
+impl<K, V, C> BTreeMap<K, V, C> {
+    /// Looks up the entry for `q`, a borrowed form of the key.
+    ///
+    /// Unlike `get_mut` followed by `insert`, the key is only converted into a `K` if the entry
+    /// is vacant and a value is inserted, so hits don't allocate for keys like `String`.
+    pub fn entry_ref<'a, 'q, Q>(&'a mut self, q: &'q Q) -> EntryRef<'a, 'q, K, Q, V, C>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        // Checking first keeps the borrow of the map from outliving a miss.
+        if self.root.get::<C, Q>(q).is_none() {
+            return EntryRef::Vacant(VacantEntryRef { map: self, q });
+        }
+
+        let (idx, node) = self.root.get_mut::<C, Q>(q).unwrap();
+        EntryRef::Occupied(OccupiedEntryRef {
+            v: &mut node.vals[idx],
+        })
+    }
+}
+
+impl<K, V, C> BTreeMap<K, V, C> {
+    pub fn first_entry(&mut self) -> Option<OccupiedEntry<'_, K, V, C>> {
+        (!self.is_empty()).then_some(OccupiedEntry {
+            map: self,
+            last: false,
+        })
+    }
+
+    pub fn last_entry(&mut self) -> Option<OccupiedEntry<'_, K, V, C>> {
+        (!self.is_empty()).then_some(OccupiedEntry {
+            map: self,
+            last: true,
+        })
+    }
+
+    /// Returns the entry with the smallest key, or the largest if `last`.
+    fn edge_mut(&mut self, last: bool) -> Option<(&K, &mut V)> {
+        let mut node = &mut self.root;
+        while !node.is_leaf() {
+            let idx = if last { node.children.len() - 1 } else { 0 };
+            node = &mut node.children[idx];
+        }
+
+        let idx = if last { node.len().checked_sub(1)? } else { 0 };
+        node.keys.get(idx).zip(node.vals.get_mut(idx))
+    }
+}
+
+impl<'a, 'q, K, Q, V, C> EntryRef<'a, 'q, K, Q, V, C>
+where
+    K: Borrow<Q> + From<&'q Q>,
+    Q: ?Sized,
+    C: Comparator<K> + Comparator<Q>,
+{
+    pub fn or_insert(self, v: V) -> &'a mut V {
+        self.or_insert_with(|| v)
+    }
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Self::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, V> OccupiedEntryRef<'a, V> {
+    pub fn get(&self) -> &V {
+        self.v
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.v
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.v
+    }
+
+    pub fn insert(&mut self, v: V) -> V {
+        std::mem::replace(self.v, v)
+    }
+}
+
+impl<'a, K, V, C> OccupiedEntry<'a, K, V, C> {
+    pub fn key(&self) -> &K {
+        self.get_key_value().0
+    }
+
+    pub fn get(&self) -> &V {
+        self.get_key_value().1
+    }
+
+    fn get_key_value(&self) -> (&K, &V) {
+        let entry = if self.last {
+            self.map.last_key_value()
+        } else {
+            self.map.first_key_value()
+        };
+        entry.unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map.edge_mut(self.last).unwrap().1
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.map.edge_mut(self.last).unwrap().1
+    }
+
+    pub fn insert(&mut self, v: V) -> V {
+        std::mem::replace(self.get_mut(), v)
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let entry = if self.last {
+            self.map.pop_last()
+        } else {
+            self.map.pop_first()
+        };
+        entry.unwrap()
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+impl<'a, 'q, K, Q, V, C> VacantEntryRef<'a, 'q, K, Q, V, C>
+where
+    K: Borrow<Q> + From<&'q Q>,
+    Q: ?Sized,
+    C: Comparator<K> + Comparator<Q>,
+{
+    pub fn key(&self) -> &'q Q {
+        self.q
+    }
+
+    /// Converts the key into a `K` and inserts it with `v`.
+    pub fn insert(self, v: V) -> &'a mut V {
+        self.map.insert(K::from(self.q), v);
+
+        let (idx, node) = self.map.root.get_mut::<C, Q>(self.q).unwrap();
+        &mut node.vals[idx]
+    }
+}
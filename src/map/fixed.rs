@@ -0,0 +1,634 @@
+use heapless::Vec;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Debug, Formatter},
+    mem,
+};
+use thiserror::Error;
+
+/// Returned by `FixedMap::insert` when the node pool can't fit the entry, handing it back.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("no free nodes left to insert into")]
+pub struct CapacityError<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+struct Node<K, V, const C: usize> {
+    keys: Vec<K, C>,
+    vals: Vec<V, C>,
+    children: Vec<usize, C>,
+}
+
+impl<K, V, const C: usize> Node<K, V, C> {
+    const fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            vals: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.keys.len() == C - 1
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+/// A map that never allocates, for targets without a heap.
+///
+/// Nodes hold at most `C - 1` keys and `C` children, i.e. the degree is `C / 2`, and live in
+/// a pool of `N` nodes that's part of the map itself. Insertions that would need more nodes
+/// than are left fail with a `CapacityError` instead, leaving the map unchanged. The map can
+/// be large, so it's usually placed in a `static`.
+pub struct FixedMap<K, V, const C: usize, const N: usize> {
+    len: usize,
+    root: Option<usize>,
+    nodes: Vec<Node<K, V, C>, N>,
+    free: Vec<usize, N>,
+}
+
+impl<K, V, const C: usize, const N: usize> FixedMap<K, V, C, N> {
+    const DEGREE: usize = {
+        assert!(
+            C >= 4 && C.is_multiple_of(2),
+            "node capacity must be even and at least 4"
+        );
+        C / 2
+    };
+
+    pub const fn new() -> Self {
+        // Rejects invalid node capacities at compile time.
+        let _ = Self::DEGREE;
+
+        Self {
+            len: 0,
+            root: None,
+            nodes: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many more nodes can be allocated from the pool.
+    pub fn free_nodes(&self) -> usize {
+        N - self.nodes.len() + self.free.len()
+    }
+
+    pub fn contains(&self, k: &K) -> bool
+    where
+        K: Ord,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn get(&self, k: &K) -> Option<&V>
+    where
+        K: Ord,
+    {
+        self.find(k).map(|(id, idx)| &self.nodes[id].vals[idx])
+    }
+
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V>
+    where
+        K: Ord,
+    {
+        self.find(k).map(|(id, idx)| &mut self.nodes[id].vals[idx])
+    }
+
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)>
+    where
+        K: Ord,
+    {
+        self.find(k)
+            .map(|(id, idx)| (&self.nodes[id].keys[idx], &self.nodes[id].vals[idx]))
+    }
+
+    pub fn insert(&mut self, k: K, mut v: V) -> Result<Option<V>, CapacityError<K, V>>
+    where
+        K: Ord,
+    {
+        if self.needed(&k) > self.free_nodes() {
+            return Err(CapacityError { key: k, value: v });
+        }
+
+        let root = match self.root {
+            Some(root) => root,
+            None => self.alloc(),
+        };
+
+        let mut id = root;
+        if self.nodes[root].is_full() {
+            id = self.alloc();
+            push(&mut self.nodes[id].children, root);
+            self.split_child(id, 0);
+        }
+        self.root = Some(id);
+
+        loop {
+            // Find index to insert key into or of the child to recurse down.
+            let mut idx = match self.nodes[id].keys.binary_search(&k) {
+                Ok(idx) => {
+                    // The key already exists, so swap in the value.
+                    mem::swap(&mut self.nodes[id].vals[idx], &mut v);
+                    return Ok(Some(v));
+                }
+                Err(idx) => idx,
+            };
+
+            let node = &mut self.nodes[id];
+            if node.is_leaf() {
+                insert(&mut node.keys, idx, k);
+                insert(&mut node.vals, idx, v);
+                self.len += 1;
+                return Ok(None);
+            }
+
+            let child = node.children[idx];
+            if self.nodes[child].is_full() {
+                // The split may have moved the key up into this node.
+                self.split_child(id, idx);
+                match self.nodes[id].keys[idx].cmp(&k) {
+                    Ordering::Less => idx += 1,
+                    Ordering::Equal => {
+                        mem::swap(&mut self.nodes[id].vals[idx], &mut v);
+                        return Ok(Some(v));
+                    }
+                    Ordering::Greater => {}
+                }
+            }
+            id = self.nodes[id].children[idx];
+        }
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V>
+    where
+        K: Ord,
+    {
+        self.remove_entry(k).map(|(_, val)| val)
+    }
+
+    pub fn remove_entry(&mut self, k: &K) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        let root = self.root?;
+        let entry = self.remove_from(root, k);
+
+        // Rebalancing on the way down can empty the root even if the key isn't found.
+        let node = &mut self.nodes[root];
+        if !node.is_leaf() && node.is_empty() {
+            self.root = node.children.pop();
+            self.dealloc(root);
+        }
+
+        if entry.is_some() {
+            self.len -= 1;
+        }
+
+        entry
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.root = None;
+        self.nodes.clear();
+        self.free.clear();
+    }
+
+    /// Iterates over the entries in key order.
+    ///
+    /// The iterator keeps no stack, so each step searches down from the root.
+    pub fn iter(&self) -> Iter<'_, K, V, C, N>
+    where
+        K: Ord,
+    {
+        Iter {
+            map: self,
+            last: None,
+        }
+    }
+
+    /// Returns the number of nodes an insertion of `k` would allocate, by following the path
+    /// it takes and counting the full nodes it would split.
+    fn needed(&self, k: &K) -> usize
+    where
+        K: Ord,
+    {
+        let Some(mut id) = self.root else {
+            return 1;
+        };
+
+        let mut needed = if self.nodes[id].is_full() { 2 } else { 0 };
+        loop {
+            let node = &self.nodes[id];
+            match node.keys.binary_search(k) {
+                Err(idx) if !node.is_leaf() => {
+                    id = node.children[idx];
+                    if self.nodes[id].is_full() {
+                        needed += 1;
+                    }
+                }
+                _ => return needed,
+            }
+        }
+    }
+
+    fn find(&self, k: &K) -> Option<(usize, usize)>
+    where
+        K: Ord,
+    {
+        let mut id = self.root?;
+        loop {
+            let node = &self.nodes[id];
+            match node.keys.binary_search(k) {
+                Ok(idx) => return Some((id, idx)),
+                Err(_) if node.is_leaf() => return None,
+                Err(idx) => id = node.children[idx],
+            }
+        }
+    }
+
+    /// Finds the smallest entry with a key greater than `k`, or the smallest entry overall.
+    fn successor(&self, k: Option<&K>) -> Option<(usize, usize)>
+    where
+        K: Ord,
+    {
+        let mut id = self.root?;
+        let mut found = None;
+        loop {
+            let node = &self.nodes[id];
+            let idx = match k.map(|k| node.keys.binary_search(k)) {
+                None => 0,
+                Some(Ok(idx)) => idx + 1,
+                Some(Err(idx)) => idx,
+            };
+
+            // Anything in the child to the left of this key is smaller, so a better match.
+            if idx < node.len() {
+                found = Some((id, idx));
+            }
+            if node.is_leaf() {
+                return found;
+            }
+            id = node.children[idx];
+        }
+    }
+
+    fn alloc(&mut self) -> usize {
+        if let Some(id) = self.free.pop() {
+            return id;
+        }
+        if self.nodes.push(Node::new()).is_err() {
+            panic!("node pool is full");
+        }
+        self.nodes.len() - 1
+    }
+
+    fn dealloc(&mut self, id: usize) {
+        let node = &mut self.nodes[id];
+        node.keys.clear();
+        node.vals.clear();
+        node.children.clear();
+        push(&mut self.free, id);
+    }
+
+    /// Borrows two distinct nodes at once.
+    fn pair(&mut self, a: usize, b: usize) -> (&mut Node<K, V, C>, &mut Node<K, V, C>) {
+        assert_ne!(a, b);
+        if a < b {
+            let (left, right) = self.nodes.split_at_mut(b);
+            (&mut left[a], &mut right[0])
+        } else {
+            let (left, right) = self.nodes.split_at_mut(a);
+            (&mut right[0], &mut left[b])
+        }
+    }
+
+    fn split_child(&mut self, id: usize, idx: usize) {
+        let degree = Self::DEGREE;
+        let left = self.nodes[id].children[idx];
+        let right = self.alloc();
+        let (l, r) = self.pair(left, right);
+
+        // Move the largest keys, values, and children from the left to the right.
+        move_tail(&mut l.keys, &mut r.keys, degree);
+        move_tail(&mut l.vals, &mut r.vals, degree);
+        move_tail(&mut l.children, &mut r.children, degree);
+
+        // Take the median (separator) key and value from the left.
+        let key = l.keys.pop().expect("couldn't pop median key");
+        let val = l.vals.pop().expect("couldn't pop median value");
+
+        let node = &mut self.nodes[id];
+        insert(&mut node.keys, idx, key);
+        insert(&mut node.vals, idx, val);
+        insert(&mut node.children, idx + 1, right);
+    }
+
+    fn remove_from(&mut self, id: usize, k: &K) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        let degree = Self::DEGREE;
+        let node = &mut self.nodes[id];
+
+        match node.keys.binary_search(k) {
+            // Case 1: Key found in node and node is a leaf.
+            Ok(idx) if node.is_leaf() => Some((node.keys.remove(idx), node.vals.remove(idx))),
+
+            // Case 2: Key found in node and node is an internal node.
+            Ok(idx) => {
+                let pred = node.children[idx];
+                let succ = node.children[idx + 1];
+
+                if self.nodes[pred].len() >= degree {
+                    // Case 2a: Replace the key with its predecessor.
+                    let (key, val) = self.remove_max(pred);
+                    Some(self.replace(id, idx, key, val))
+                } else if self.nodes[succ].len() >= degree {
+                    // Case 2b: Replace the key with its successor.
+                    let (key, val) = self.remove_min(succ);
+                    Some(self.replace(id, idx, key, val))
+                } else {
+                    // Case 2c: Merge the key and successor into the predecessor.
+                    self.merge(id, idx);
+                    self.remove_from(pred, k)
+                }
+            }
+
+            // If on a leaf, then no appropriate subtree contains the key.
+            Err(_) if node.is_leaf() => None,
+
+            // Case 3: Key not found in internal node.
+            Err(idx) => {
+                let idx = self.fill_child(id, idx);
+                self.remove_from(self.nodes[id].children[idx], k)
+            }
+        }
+    }
+
+    fn replace(&mut self, id: usize, idx: usize, mut key: K, mut val: V) -> (K, V) {
+        let node = &mut self.nodes[id];
+        mem::swap(&mut node.keys[idx], &mut key);
+        mem::swap(&mut node.vals[idx], &mut val);
+        (key, val)
+    }
+
+    /// Removes the largest entry in the subtree rooted at `id`, which must have at least t keys.
+    fn remove_max(&mut self, mut id: usize) -> (K, V) {
+        while !self.nodes[id].is_leaf() {
+            let idx = self.fill_child(id, self.nodes[id].children.len() - 1);
+            id = self.nodes[id].children[idx];
+        }
+
+        let node = &mut self.nodes[id];
+        let key = node.keys.pop().expect("couldn't pop largest key");
+        let val = node.vals.pop().expect("couldn't pop largest value");
+        (key, val)
+    }
+
+    /// Removes the smallest entry in the subtree rooted at `id`, which must have at least t keys.
+    fn remove_min(&mut self, mut id: usize) -> (K, V) {
+        while !self.nodes[id].is_leaf() {
+            let idx = self.fill_child(id, 0);
+            id = self.nodes[id].children[idx];
+        }
+
+        let node = &mut self.nodes[id];
+        (node.keys.remove(0), node.vals.remove(0))
+    }
+
+    /// Ensures the child at `idx` has at least t keys before descending into it, by borrowing
+    /// from or merging with a sibling. Returns the index of the child to descend into.
+    fn fill_child(&mut self, id: usize, idx: usize) -> usize {
+        let degree = Self::DEGREE;
+        let node = &self.nodes[id];
+        let child = node.children[idx];
+
+        if self.nodes[child].len() + 1 != degree {
+            return idx;
+        }
+
+        let left = idx.checked_sub(1).map(|idx| node.children[idx]);
+        let right = node.children.get(idx + 1).copied();
+
+        if let Some(left) = left.filter(|&left| self.nodes[left].len() >= degree) {
+            // Case 3a: Rotate the left sibling's largest key through the parent.
+            let (l, c) = self.pair(left, child);
+            let key = l.keys.pop().unwrap();
+            let val = l.vals.pop().unwrap();
+            if let Some(grandchild) = l.children.pop() {
+                insert(&mut c.children, 0, grandchild);
+            }
+
+            let (key, val) = self.replace(id, idx - 1, key, val);
+            let c = &mut self.nodes[child];
+            insert(&mut c.keys, 0, key);
+            insert(&mut c.vals, 0, val);
+            idx
+        } else if let Some(right) = right.filter(|&right| self.nodes[right].len() >= degree) {
+            // Case 3a: Rotate the right sibling's smallest key through the parent.
+            let (c, r) = self.pair(child, right);
+            let key = r.keys.remove(0);
+            let val = r.vals.remove(0);
+            if !r.is_leaf() {
+                push(&mut c.children, r.children.remove(0));
+            }
+
+            let (key, val) = self.replace(id, idx, key, val);
+            let c = &mut self.nodes[child];
+            push(&mut c.keys, key);
+            push(&mut c.vals, val);
+            idx
+        } else if left.is_some() {
+            // Case 3b: Merge into left sibling.
+            self.merge(id, idx - 1);
+            idx - 1
+        } else {
+            // Case 3b: Merge right sibling into child.
+            self.merge(id, idx);
+            idx
+        }
+    }
+
+    /// Merges the separator at `idx` and the child after it into the child before it.
+    fn merge(&mut self, id: usize, idx: usize) {
+        let node = &mut self.nodes[id];
+        let key = node.keys.remove(idx);
+        let val = node.vals.remove(idx);
+        let left = node.children[idx];
+        let right = node.children.remove(idx + 1);
+
+        let (l, r) = self.pair(left, right);
+        push(&mut l.keys, key);
+        push(&mut l.vals, val);
+        move_tail(&mut r.keys, &mut l.keys, 0);
+        move_tail(&mut r.vals, &mut l.vals, 0);
+        move_tail(&mut r.children, &mut l.children, 0);
+
+        self.dealloc(right);
+    }
+
+    /// Checks the tree's structural invariants, describing the first violation found.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn check(&self) -> Result<(), String>
+    where
+        K: Ord + Debug,
+    {
+        let mut nodes = 0;
+        let mut count = 0;
+        let mut leaf_depth = None;
+        let mut stack = std::vec::Vec::from_iter(self.root.map(|root| (root, 0, None, None)));
+
+        while let Some((id, depth, lower, upper)) = stack.pop() {
+            let node = &self.nodes[id];
+            nodes += 1;
+            count += node.len();
+
+            if node.keys.len() != node.vals.len() {
+                return Err(format!("node {:?} has mismatched values", node.keys));
+            }
+
+            if node.keys.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("node {:?} isn't sorted", node.keys));
+            }
+
+            if lower.is_some_and(|lower| node.keys.first().is_some_and(|k| k <= lower))
+                || upper.is_some_and(|upper| node.keys.last().is_some_and(|k| k >= upper))
+            {
+                return Err(format!(
+                    "node {:?} is outside its separators {lower:?} and {upper:?}",
+                    node.keys
+                ));
+            }
+
+            if (depth > 0 && node.len() + 1 < Self::DEGREE) || (!node.is_leaf() && node.is_empty())
+            {
+                return Err(format!("node {:?} is underfull", node.keys));
+            }
+
+            if node.is_leaf() {
+                if *leaf_depth.get_or_insert(depth) != depth {
+                    return Err(format!("leaf {:?} is at the wrong depth", node.keys));
+                }
+                continue;
+            }
+
+            if node.children.len() != node.len() + 1 {
+                return Err(format!(
+                    "node {:?} has {} children",
+                    node.keys,
+                    node.children.len()
+                ));
+            }
+
+            for (idx, &child) in node.children.iter().enumerate() {
+                let lower = if idx == 0 {
+                    lower
+                } else {
+                    node.keys.get(idx - 1)
+                };
+                let upper = if idx < node.len() {
+                    node.keys.get(idx)
+                } else {
+                    upper
+                };
+                stack.push((child, depth + 1, lower, upper));
+            }
+        }
+
+        if count != self.len {
+            return Err(format!(
+                "found {count} entries but the length is {}",
+                self.len
+            ));
+        }
+
+        if nodes + self.free.len() != self.nodes.len() {
+            return Err(format!(
+                "{nodes} reachable and {} free nodes, but {} allocated",
+                self.free.len(),
+                self.nodes.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, const C: usize, const N: usize> Default for FixedMap<K, V, C, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, const C: usize, const N: usize> Debug for FixedMap<K, V, C, N>
+where
+    K: Ord + Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, K, V, const C: usize, const N: usize> {
+    map: &'a FixedMap<K, V, C, N>,
+    last: Option<&'a K>,
+}
+
+impl<'a, K, V, const C: usize, const N: usize> Iterator for Iter<'a, K, V, C, N>
+where
+    K: Ord,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, idx) = self.map.successor(self.last)?;
+        let node = &self.map.nodes[id];
+        self.last = Some(&node.keys[idx]);
+        Some((&node.keys[idx], &node.vals[idx]))
+    }
+}
+
+/// Pushes onto a vector that's known to have room.
+fn push<T, const M: usize>(vec: &mut Vec<T, M>, item: T) {
+    if vec.push(item).is_err() {
+        panic!("node is overfull");
+    }
+}
+
+/// Inserts into a vector that's known to have room.
+fn insert<T, const M: usize>(vec: &mut Vec<T, M>, idx: usize, item: T) {
+    if vec.insert(idx, item).is_err() {
+        panic!("node is overfull");
+    }
+}
+
+/// Appends the elements of `from` past `at` to `to`, in order.
+fn move_tail<T, const M: usize>(from: &mut Vec<T, M>, to: &mut Vec<T, M>, at: usize) {
+    let start = to.len();
+    while from.len() > at {
+        push(to, from.pop().unwrap());
+    }
+    to[start..].reverse();
+}
@@ -0,0 +1,108 @@
+use super::{node::Node, BTreeMap};
+use crate::comparator::{Comparator, Natural};
+use std::{borrow::Borrow, iter::Zip, marker::PhantomData, slice};
+
+/// An immutable map made by `BTreeMap::freeze`, for when a map is built once and then only
+/// read.
+///
+/// The keys and values are kept in two flat, sorted arrays, so lookups are a binary search
+/// over contiguous keys and iteration is a walk over contiguous memory.
+pub struct FrozenBTreeMap<K, V, C = Natural> {
+    keys: Vec<K>,
+    vals: Vec<V>,
+    degree: usize,
+    order: PhantomData<fn() -> C>,
+}
+
+impl<K, V, C> BTreeMap<K, V, C> {
+    pub fn freeze(self) -> FrozenBTreeMap<K, V, C> {
+        let mut keys = Vec::with_capacity(self.len);
+        let mut vals = Vec::with_capacity(self.len);
+        self.root.into_sorted(&mut keys, &mut vals);
+
+        FrozenBTreeMap {
+            keys,
+            vals,
+            degree: self.degree,
+            order: PhantomData,
+        }
+    }
+}
+
+impl<K, V, C> FrozenBTreeMap<K, V, C>
+where
+    C: Comparator<K>,
+{
+    /// Turns the map back into a `BTreeMap` with the degree it was frozen with.
+    ///
+    /// The arrays are already sorted, so the tree is built from them in one pass, with full
+    /// nodes.
+    pub fn thaw(self) -> BTreeMap<K, V, C> {
+        BTreeMap {
+            len: self.keys.len(),
+            degree: self.degree,
+            root: Node::build(self.keys, self.vals, self.degree),
+            order: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn find<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.keys
+            .binary_search_by(|key| C::cmp(key.borrow(), k))
+            .ok()
+    }
+
+    pub fn contains<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find(k).is_some()
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find(k).map(|idx| &self.vals[idx])
+    }
+
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.find(k).map(|idx| (&self.keys[idx], &self.vals[idx]))
+    }
+
+    /// Returns the keys, in order.
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// Returns the values, in the order of their keys.
+    pub fn values(&self) -> &[V] {
+        &self.vals
+    }
+
+    pub fn iter(&self) -> Zip<slice::Iter<'_, K>, slice::Iter<'_, V>> {
+        self.keys.iter().zip(&self.vals)
+    }
+}
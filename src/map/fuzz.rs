@@ -0,0 +1,31 @@
+use super::BTreeMap;
+use crate::comparator::Comparator;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// Picks a degree, and then inserts pairs one at a time, so the tree's shape depends on the
+/// order of the pairs as well as on the keys, and later pairs replace earlier ones with the same
+/// key.
+impl<'a, K, V, C> Arbitrary<'a> for BTreeMap<K, V, C>
+where
+    K: Arbitrary<'a>,
+    V: Arbitrary<'a>,
+    C: Comparator<K>,
+{
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut map = Self::with_comparator(u.int_in_range(2..=8)?);
+        for entry in u.arbitrary_iter::<(K, V)>()? {
+            let (k, v) = entry?;
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+
+    fn arbitrary_take_rest(mut u: Unstructured<'a>) -> Result<Self> {
+        let mut map = Self::with_comparator(u.int_in_range(2..=8)?);
+        for entry in u.arbitrary_take_rest_iter::<(K, V)>()? {
+            let (k, v) = entry?;
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+}
@@ -0,0 +1,115 @@
+use super::BTreeMap;
+use crate::comparator::Comparator;
+use std::mem;
+
+/// Remembers the leaf the last `BTreeMap::insert_hint` went into, so inserts of nearby keys can
+/// go straight back to it.
+///
+/// A hint is only a guess, and is checked before it's used: it can be kept across other changes
+/// to the map, or even used with a different map, at worst costing a full descent.
+#[derive(Clone, Debug, Default)]
+pub struct InsertHint {
+    /// The index of the child taken at each level from the root down to the leaf.
+    path: Vec<usize>,
+}
+
+impl InsertHint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K, V, C> BTreeMap<K, V, C>
+where
+    C: Comparator<K>,
+{
+    /// Like `insert`, but starts from the leaf in `hint` if `k` belongs there, and updates
+    /// `hint` to the leaf `k` went into.
+    ///
+    /// Following the hint takes no key comparisons on the way down, only checks against the
+    /// separators on either side of the leaf and a search of the leaf itself. This pays off for
+    /// runs of nearly sorted keys, which land in the same leaf until it fills up and splits.
+    pub fn insert_hint(&mut self, hint: &mut InsertHint, k: K, v: V) -> Option<V> {
+        let Some(found) = self.check_hint(&hint.path, &k) else {
+            // The entries before `k` stay before it through any splits, so its place finds the
+            // leaf it ends up in without comparing keys again.
+            let before = self.root.count_before(|key| C::cmp(key, &k).is_lt());
+            let res = self.insert(k, v);
+            self.hint_at(&mut hint.path, before);
+            return res;
+        };
+
+        let mut node = &mut self.root;
+        for &idx in &hint.path {
+            node.size += usize::from(!found);
+            node = &mut node.children[idx];
+        }
+
+        let idx = node.keys.partition_point(|key| C::cmp(key, &k).is_lt());
+        if found {
+            return Some(mem::replace(&mut node.vals[idx], v));
+        }
+
+        node.keys.insert(idx, k);
+        node.vals.insert(idx, v);
+        node.size += 1;
+        self.len += 1;
+        None
+    }
+
+    /// Returns whether `k` is already in the leaf at the end of `path`, or `None` if `path`
+    /// doesn't lead to a leaf that `k` belongs in with room for it.
+    fn check_hint(&self, path: &[usize], k: &K) -> Option<bool> {
+        let mut lower = None;
+        let mut upper = None;
+
+        let mut node = &self.root;
+        for &idx in path {
+            if idx >= node.children.len() {
+                return None;
+            }
+
+            // The separators around the child are tighter than any further up.
+            if idx > 0 {
+                lower = Some(&node.keys[idx - 1]);
+            }
+            if idx < node.len() {
+                upper = Some(&node.keys[idx]);
+            }
+            node = &node.children[idx];
+        }
+
+        if !node.is_leaf()
+            || lower.is_some_and(|lower| C::cmp(lower, k).is_ge())
+            || upper.is_some_and(|upper| C::cmp(k, upper).is_ge())
+        {
+            return None;
+        }
+
+        let idx = node.keys.partition_point(|key| C::cmp(key, k).is_lt());
+        let found = node.keys.get(idx).is_some_and(|key| C::cmp(key, k).is_eq());
+        (found || !node.is_full(self.degree)).then_some(found)
+    }
+
+    /// Sets `path` to the leaf that the entry `n` places into the map is in, or for an entry in
+    /// an internal node, to the leaf right after it, where the keys after it go.
+    fn hint_at(&self, path: &mut Vec<usize>, mut n: usize) {
+        path.clear();
+
+        let mut node = &self.root;
+        while !node.is_leaf() {
+            let idx = match node.locate(n) {
+                (idx, Some(rest)) => {
+                    n = rest;
+                    idx
+                }
+                (idx, None) => {
+                    n = 0;
+                    idx + 1
+                }
+            };
+            path.push(idx);
+            node = &node.children[idx];
+        }
+    }
+}
@@ -1,26 +1,312 @@
-use super::node::Node;
+use super::{node::Node, BTreeMap};
+use crate::comparator::Comparator;
+use std::{
+    array,
+    marker::PhantomData,
+    mem,
+    ops::{Bound, RangeBounds},
+    slice, vec,
+};
 
+/// The most levels a tree can have in practice. Every internal node but the root has at least
+/// two children, so a tree with more levels would need over 2^47 nodes of more than 64 bytes
+/// each, several petabytes of memory.
+const MAX_HEIGHT: usize = 48;
+
+/// A way of holding a node that the iterators walk through: shared, mutable or owned.
+trait Walk: Sized {
+    type Key;
+    type Val;
+    type Keys: DoubleEndedIterator<Item = Self::Key>;
+    type Vals: DoubleEndedIterator<Item = Self::Val>;
+    type Children: DoubleEndedIterator<Item = Self> + ExactSizeIterator;
+
+    /// Splits the node into its keys and values from `idx` on, and its children from the one
+    /// before the key at `idx` on.
+    fn open(self, idx: usize) -> (Self::Keys, Self::Vals, Self::Children);
+}
+
+impl<'a, K, V> Walk for &'a Node<K, V> {
+    type Key = &'a K;
+    type Val = &'a V;
+    type Keys = slice::Iter<'a, K>;
+    type Vals = slice::Iter<'a, V>;
+    type Children = slice::Iter<'a, Node<K, V>>;
+
+    fn open(self, idx: usize) -> (Self::Keys, Self::Vals, Self::Children) {
+        let first = idx.min(self.children.len());
+        (
+            self.keys[idx..].iter(),
+            self.vals[idx..].iter(),
+            self.children[first..].iter(),
+        )
+    }
+}
+
+impl<'a, K, V> Walk for &'a mut Node<K, V> {
+    type Key = &'a K;
+    type Val = &'a mut V;
+    type Keys = slice::Iter<'a, K>;
+    type Vals = slice::IterMut<'a, V>;
+    type Children = slice::IterMut<'a, Node<K, V>>;
+
+    fn open(self, idx: usize) -> (Self::Keys, Self::Vals, Self::Children) {
+        let first = idx.min(self.children.len());
+        let Node {
+            keys,
+            vals,
+            children,
+            ..
+        } = self;
+        (
+            keys[idx..].iter(),
+            vals[idx..].iter_mut(),
+            children[first..].iter_mut(),
+        )
+    }
+}
+
+impl<K, V> Walk for Node<K, V> {
+    type Key = K;
+    type Val = V;
+    type Keys = vec::IntoIter<K>;
+    type Vals = vec::IntoIter<V>;
+    type Children = vec::IntoIter<Node<K, V>>;
+
+    fn open(mut self, idx: usize) -> (Self::Keys, Self::Vals, Self::Children) {
+        let first = idx.min(self.children.len());
+        self.keys.drain(..idx);
+        self.vals.drain(..idx);
+        self.children.drain(..first);
+        (
+            self.keys.into_iter(),
+            self.vals.into_iter(),
+            self.children.into_iter(),
+        )
+    }
+}
+
+/// What's at either end of a frame: a child to walk into, or an entry.
+enum Step<N: Walk> {
+    Child(N),
+    Entry(N::Key, N::Val),
+}
+
+/// The part of a node that's left to visit, which runs from a child or a key at the front to a
+/// child or a key at the back.
+struct Frame<N: Walk> {
+    keys: N::Keys,
+    vals: N::Vals,
+    children: N::Children,
+    child_first: bool,
+    child_last: bool,
+}
+
+impl<N: Walk> Frame<N> {
+    fn new(node: N, idx: usize) -> Self {
+        let (keys, vals, children) = node.open(idx);
+        let internal = children.len() > 0;
+        Self {
+            keys,
+            vals,
+            children,
+            child_first: internal,
+            child_last: internal,
+        }
+    }
+
+    /// Takes what's at the front, or returns `None` if the frame is used up.
+    fn front(&mut self) -> Option<Step<N>> {
+        // The child may already have been taken from the back, along with everything after it.
+        if mem::take(&mut self.child_first) {
+            if let Some(child) = self.children.next() {
+                return Some(Step::Child(child));
+            }
+        }
+
+        let key = self.keys.next()?;
+        let val = self.vals.next().unwrap();
+        self.child_first = self.children.len() > 0;
+        Some(Step::Entry(key, val))
+    }
+
+    /// Takes what's at the back, or returns `None` if the frame is used up.
+    fn back(&mut self) -> Option<Step<N>> {
+        if mem::take(&mut self.child_last) {
+            if let Some(child) = self.children.next_back() {
+                return Some(Step::Child(child));
+            }
+        }
+
+        let key = self.keys.next_back()?;
+        let val = self.vals.next_back().unwrap();
+        self.child_last = self.children.len() > 0;
+        Some(Step::Entry(key, val))
+    }
+}
+
+/// Frames along a path down the tree, indexed by depth, from `lo` down to just above `hi`.
+struct Stack<N: Walk> {
+    frames: [Option<Frame<N>>; MAX_HEIGHT],
+    lo: usize,
+    hi: usize,
+}
+
+impl<N: Walk> Stack<N> {
+    fn new() -> Self {
+        Self {
+            frames: array::from_fn(|_| None),
+            lo: 0,
+            hi: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lo == self.hi
+    }
+
+    /// Pushes a frame at `depth`, which is right below the deepest frame if there is one.
+    fn push(&mut self, depth: usize, frame: Frame<N>) {
+        if self.is_empty() {
+            self.lo = depth;
+            self.hi = depth;
+        }
+        self.frames[self.hi] = Some(frame);
+        self.hi += 1;
+    }
+
+    fn pop_deepest(&mut self) {
+        self.hi -= 1;
+        self.frames[self.hi] = None;
+    }
+
+    fn pop_shallowest(&mut self) {
+        self.frames[self.lo] = None;
+        self.lo += 1;
+    }
+}
+
+/// Walks a tree from both ends without allocating.
+///
+/// Each end keeps the frames of the nodes it's walking through, and every frame belongs to one
+/// end only: what's left to visit is the front frames from the deepest up, then the back frames
+/// from the shallowest down. The parts of a node handed out are disjoint from the parts still in
+/// its frame, so no `unsafe` is needed to hand out `&mut V`s, and once one end has used up its
+/// frames it carries on from the other end's shallowest one.
+struct Walker<N: Walk> {
+    front: Stack<N>,
+    back: Stack<N>,
+}
+
+impl<N: Walk> Walker<N> {
+    fn new(root: N) -> Self {
+        Self::seek(root, |_| 0)
+    }
+
+    /// Starts at the entry `find` returns the index of in each node on the way down, or at the
+    /// child before it.
+    fn seek(root: N, find: impl Fn(&N) -> usize) -> Self {
+        let mut walker = Self::new_empty();
+
+        let mut node = root;
+        let mut depth = 0;
+        loop {
+            let idx = find(&node);
+            let mut frame = Frame::new(node, idx);
+            let child = if mem::take(&mut frame.child_first) {
+                frame.children.next()
+            } else {
+                None
+            };
+            walker.front.push(depth, frame);
+            depth += 1;
+
+            match child {
+                Some(child) => node = child,
+                None => return walker,
+            }
+        }
+    }
+
+    fn new_empty() -> Self {
+        Self {
+            front: Stack::new(),
+            back: Stack::new(),
+        }
+    }
+
+    fn next(&mut self) -> Option<(N::Key, N::Val)> {
+        loop {
+            let from_back = self.front.is_empty();
+            let (stack, depth) = if !from_back {
+                let depth = self.front.hi - 1;
+                (&mut self.front, depth)
+            } else if !self.back.is_empty() {
+                let depth = self.back.lo;
+                (&mut self.back, depth)
+            } else {
+                return None;
+            };
+
+            match stack.frames[depth].as_mut().unwrap().front() {
+                Some(Step::Entry(key, val)) => return Some((key, val)),
+                Some(Step::Child(child)) => self.front.push(depth + 1, Frame::new(child, 0)),
+                None if from_back => stack.pop_shallowest(),
+                None => stack.pop_deepest(),
+            }
+        }
+    }
+
+    fn next_back(&mut self) -> Option<(N::Key, N::Val)> {
+        loop {
+            let from_front = self.back.is_empty();
+            let (stack, depth) = if !from_front {
+                let depth = self.back.hi - 1;
+                (&mut self.back, depth)
+            } else if !self.front.is_empty() {
+                let depth = self.front.lo;
+                (&mut self.front, depth)
+            } else {
+                return None;
+            };
+
+            match stack.frames[depth].as_mut().unwrap().back() {
+                Some(Step::Entry(key, val)) => return Some((key, val)),
+                Some(Step::Child(child)) => self.back.push(depth + 1, Frame::new(child, 0)),
+                None if from_front => stack.pop_shallowest(),
+                None => stack.pop_deepest(),
+            }
+        }
+    }
+}
+
+/// Iterates over a map's entries in key order, from either end.
+///
+/// The paths to the entries at either end are kept in fixed-size arrays rather than `Vec`s, so
+/// iteration doesn't allocate.
 pub struct Iter<'a, K, V> {
-    nodes: Vec<&'a Node<K, V>>,
-    indices: Vec<usize>,
+    inner: Walker<&'a Node<K, V>>,
 }
 
 impl<'a, K, V> Iter<'a, K, V> {
-    pub(crate) fn new(mut root: &'a Node<K, V>) -> Self {
-        let mut nodes = vec![];
-        let mut indices = vec![];
-
-        if !root.is_empty() {
-            while !root.is_leaf() {
-                nodes.push(root);
-                indices.push(0);
-                root = root.children.first().unwrap();
-            }
-            nodes.push(root);
-            indices.push(0);
+    pub(crate) fn new(root: &'a Node<K, V>) -> Self {
+        Self {
+            inner: Walker::new(root),
+        }
+    }
+
+    /// Starts at the first entry whose key isn't `before` the one sought, where `before` must
+    /// hold for a prefix of the keys in order.
+    pub(crate) fn seek(root: &'a Node<K, V>, before: impl Fn(&K) -> bool) -> Self {
+        Self {
+            inner: Walker::seek(root, |node| node.keys.partition_point(&before)),
         }
+    }
 
-        Self { nodes, indices }
+    pub(crate) fn new_empty() -> Self {
+        Self {
+            inner: Walker::new_empty(),
+        }
     }
 }
 
@@ -28,38 +314,190 @@ impl<'a, K, V> Iterator for Iter<'a, K, V> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.nodes.is_empty() {
-            return None;
+        self.inner.next()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// Iterates over a map's entries in key order, from either end, with mutable references to the
+/// values.
+pub struct IterMut<'a, K, V> {
+    inner: Walker<&'a mut Node<K, V>>,
+}
+
+impl<'a, K, V> IterMut<'a, K, V> {
+    pub(crate) fn new(root: &'a mut Node<K, V>) -> Self {
+        Self {
+            inner: Walker::new(root),
         }
+    }
 
-        let node = *self.nodes.last().unwrap();
-        let mut idx = *self.indices.last().unwrap();
+    /// Starts at the first entry whose key isn't `before` the one sought, where `before` must
+    /// hold for a prefix of the keys in order.
+    pub(crate) fn seek(root: &'a mut Node<K, V>, before: impl Fn(&K) -> bool) -> Self {
+        Self {
+            inner: Walker::seek(root, |node| node.keys.partition_point(&before)),
+        }
+    }
 
-        let key = node.keys.get(idx).unwrap();
-        let val = node.vals.get(idx).unwrap();
+    pub(crate) fn new_empty() -> Self {
+        Self {
+            inner: Walker::new_empty(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
 
-        idx += 1;
-        *self.indices.last_mut().unwrap() = idx;
+impl<K, V> DoubleEndedIterator for IterMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// Removes and yields every entry of a map in key order, from `BTreeMap::drain`.
+///
+/// The map's nodes are taken out of it and walked like `IterMut` walks them, moving each
+/// entry out as it's reached. The map is left empty even if the iterator isn't used up.
+pub struct Drain<K, V> {
+    inner: Walker<Node<K, V>>,
+}
 
-        if idx == node.len() {
-            self.nodes.truncate(self.nodes.len() - 1);
-            self.indices.truncate(self.indices.len() - 1);
+impl<K, V> Drain<K, V> {
+    pub(crate) fn new(root: Node<K, V>) -> Self {
+        Self {
+            inner: Walker::new(root),
         }
+    }
+}
 
-        if idx < node.children.len() {
-            let mut n = &node.children[idx];
+impl<K, V> Iterator for Drain<K, V> {
+    type Item = (K, V);
 
-            while !n.is_leaf() {
-                self.nodes.push(n);
-                self.indices.push(0);
-                n = n.children.first().unwrap();
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Drain<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// Returns whether `k` comes before the start of `range`.
+fn before_start<K, C, R>(range: &R, k: &K) -> bool
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    match range.start_bound() {
+        Bound::Included(start) => C::cmp(k, start).is_lt(),
+        Bound::Excluded(start) => C::cmp(k, start).is_le(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Returns whether `k` comes after the end of `range`.
+fn past_end<K, C, R>(range: &R, k: &K) -> bool
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    match range.end_bound() {
+        Bound::Included(end) => C::cmp(k, end).is_gt(),
+        Bound::Excluded(end) => C::cmp(k, end).is_ge(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Iterates over the entries of a map in a range of keys, in key order.
+pub struct Range<'a, K, V, C, R> {
+    inner: Iter<'a, K, V>,
+    range: R,
+    order: PhantomData<fn() -> C>,
+}
 
-            self.nodes.push(n);
-            self.indices.push(0);
+impl<'a, K, V, C, R> Range<'a, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    pub(crate) fn new(root: &'a Node<K, V>, range: R) -> Self {
+        Self {
+            inner: Iter::seek(root, |k| before_start::<K, C, R>(&range, k)),
+            range,
+            order: PhantomData,
         }
+    }
+}
+
+impl<'a, K, V, C, R> Iterator for Range<'a, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    type Item = (&'a K, &'a V);
 
-        Some((key, val))
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        if past_end::<K, C, R>(&self.range, k) {
+            // Skip the rest, which are all past the end too.
+            self.inner = Iter::new_empty();
+            return None;
+        }
+        Some((k, v))
+    }
+}
+
+/// Iterates over the entries of a map in a range of keys, in key order, with mutable
+/// references to the values.
+pub struct RangeMut<'a, K, V, C, R> {
+    inner: IterMut<'a, K, V>,
+    range: R,
+    order: PhantomData<fn() -> C>,
+}
+
+impl<'a, K, V, C, R> RangeMut<'a, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    pub(crate) fn new(root: &'a mut Node<K, V>, range: R) -> Self {
+        Self {
+            inner: IterMut::seek(root, |k| before_start::<K, C, R>(&range, k)),
+            range,
+            order: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V, C, R> Iterator for RangeMut<'a, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        if past_end::<K, C, R>(&self.range, k) {
+            // Skip the rest, which are all past the end too.
+            self.inner = IterMut::new_empty();
+            return None;
+        }
+        Some((k, v))
     }
 }
 
@@ -81,6 +519,12 @@ impl<'a, K, V> Iterator for Keys<'a, K, V> {
     }
 }
 
+impl<K, V> DoubleEndedIterator for Keys<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
 pub struct Values<'a, K, V> {
     inner: Iter<'a, K, V>,
 }
@@ -98,3 +542,91 @@ impl<'a, K, V> Iterator for Values<'a, K, V> {
         self.inner.next().map(|(_, v)| v)
     }
 }
+
+impl<K, V> DoubleEndedIterator for Values<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>,
+}
+
+impl<'a, K, V> ValuesMut<'a, K, V> {
+    pub(crate) fn new(inner: IterMut<'a, K, V>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> DoubleEndedIterator for ValuesMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+pub struct Chunks<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    size: usize,
+}
+
+impl<'a, K, V> Chunks<'a, K, V> {
+    pub(crate) fn new(inner: Iter<'a, K, V>, size: usize) -> Self {
+        Self { inner, size }
+    }
+}
+
+impl<'a, K, V> Iterator for Chunks<'a, K, V> {
+    type Item = Vec<(&'a K, &'a V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.inner.by_ref().take(self.size).collect::<Vec<_>>();
+        (!chunk.is_empty()).then_some(chunk)
+    }
+}
+
+/// Removes and yields the entries of a map that satisfy a predicate, in key order, from
+/// `BTreeMap::extract_if`.
+///
+/// The iterator keeps the number of entries before the next one to check, and each extracted
+/// entry is removed from the tree as it's reached, the way `BTreeMap::remove_index` removes it.
+/// The map stays valid throughout, so entries that aren't visited are kept even if the iterator
+/// is leaked.
+pub struct ExtractIf<'a, K, V, C, F> {
+    map: &'a mut BTreeMap<K, V, C>,
+    idx: usize,
+    pred: F,
+}
+
+impl<'a, K, V, C, F> ExtractIf<'a, K, V, C, F> {
+    pub(crate) fn new(map: &'a mut BTreeMap<K, V, C>, pred: F) -> Self {
+        Self { map, idx: 0, pred }
+    }
+}
+
+impl<K, V, C, F> Iterator for ExtractIf<'_, K, V, C, F>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx < self.map.len {
+            let (k, v) = self.map.root.nth_mut(self.idx);
+            if (self.pred)(k, v) {
+                // The entries after it move down a place, so `idx` is already the next one.
+                return self.map.remove_index(self.idx);
+            }
+            self.idx += 1;
+        }
+        None
+    }
+}
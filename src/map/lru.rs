@@ -0,0 +1,120 @@
+use super::{iter::Iter, BTreeMap};
+
+/// A `BTreeMap` that holds at most `capacity` entries, evicting the least recently used entry
+/// to make room for a new one.
+///
+/// Entries are used when they're inserted or read through `get` or `get_mut`, but not through
+/// `peek` or iteration. Iteration is still in key order.
+pub struct LruMap<K, V> {
+    map: BTreeMap<K, V>,
+    capacity: usize,
+    tick: u64,
+    ticks: BTreeMap<K, u64>,
+    recency: BTreeMap<u64, K>,
+}
+
+impl<K, V> LruMap<K, V>
+where
+    K: Ord + Clone,
+{
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "the capacity must be at least 1");
+
+        Self {
+            map: BTreeMap::new(),
+            capacity,
+            tick: 0,
+            ticks: BTreeMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.map
+    }
+
+    pub fn as_map(&self) -> &BTreeMap<K, V> {
+        &self.map
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> bool {
+        self.map.contains(k)
+    }
+
+    /// Returns the value of `k` without marking it as used.
+    pub fn peek(&self, k: &K) -> Option<&V> {
+        self.map.get(k)
+    }
+
+    pub fn get(&mut self, k: &K) -> Option<&V> {
+        self.get_mut(k).map(|v| &*v)
+    }
+
+    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+        if self.map.contains(k) {
+            self.touch(k.clone());
+        }
+        self.map.get_mut(k)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    /// Inserts an entry, evicting the least recently used one if the map is full and `k` is
+    /// new.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if !self.map.contains(&k) && self.len() == self.capacity {
+            self.pop_lru();
+        }
+
+        self.touch(k.clone());
+        self.map.insert(k, v)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry(&mut self, k: &K) -> Option<(K, V)> {
+        let tick = self.ticks.remove(k)?;
+        self.recency.remove(&tick);
+        self.map.remove_entry(k)
+    }
+
+    /// Removes and returns the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let k = self.recency.values().next()?.clone();
+        self.remove_entry(&k)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.ticks.clear();
+        self.recency.clear();
+    }
+
+    /// Marks `k` as the most recently used key.
+    fn touch(&mut self, k: K) {
+        if let Some(old) = self.ticks.insert(k.clone(), self.tick) {
+            self.recency.remove(&old);
+        }
+        self.recency.insert(self.tick, k);
+        self.tick += 1;
+    }
+}
@@ -1,21 +1,62 @@
+mod cursor;
+mod entry;
+#[cfg(feature = "heapless")]
+mod fixed;
+mod frozen;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+mod hint;
 mod iter;
+mod lru;
 mod node;
+#[cfg(feature = "rayon")]
+mod par;
+mod prefix;
+#[cfg(feature = "serde")]
+mod serialize;
+mod stats;
 #[cfg(test)]
 mod tests;
+mod undo;
+mod view;
 
-use iter::{Iter, Keys, Values};
+pub use cursor::{CursorMut, UnorderedKeyError};
+pub use entry::{EntryRef, OccupiedEntry, OccupiedEntryRef, VacantEntryRef};
+#[cfg(feature = "heapless")]
+pub use fixed::{CapacityError, FixedMap};
+pub use frozen::FrozenBTreeMap;
+pub use hint::InsertHint;
+pub use iter::{Chunks, Drain, ExtractIf, Iter, IterMut, Keys, Range, RangeMut, Values, ValuesMut};
+pub use lru::LruMap;
+#[cfg(feature = "rayon")]
+pub use par::ParIter;
+pub use prefix::{Prefix, StartsWith};
+pub use stats::MapStats;
+pub use undo::UndoMap;
+pub use view::{View, ViewIter};
+
+use crate::{
+    comparator::{Comparator, Natural},
+    occupancy::Occupancy,
+};
 use node::Node;
 use std::{
+    borrow::Borrow,
+    collections::TryReserveError,
     fmt::{self, Debug, Formatter},
+    marker::PhantomData,
     mem,
+    ops::{Bound, RangeBounds},
 };
 
 const DEFAULT_DEGREE: usize = 2;
 
-pub struct BTreeMap<K, V> {
+/// An in-memory B-tree map, ordered by the comparator `C`.
+pub struct BTreeMap<K, V, C = Natural> {
     len: usize,
     degree: usize,
     root: Node<K, V>,
+    order: PhantomData<fn() -> C>,
 }
 
 impl<K, V> BTreeMap<K, V> {
@@ -24,10 +65,18 @@ impl<K, V> BTreeMap<K, V> {
     }
 
     pub fn with_degree(degree: usize) -> Self {
+        Self::with_comparator(degree)
+    }
+}
+
+impl<K, V, C> BTreeMap<K, V, C> {
+    /// Creates a map that orders its keys by `C` rather than by `Ord`.
+    pub fn with_comparator(degree: usize) -> Self {
         Self {
             len: 0,
             degree,
             root: Node::new(),
+            order: PhantomData,
         }
     }
 
@@ -39,39 +88,165 @@ impl<K, V> BTreeMap<K, V> {
         self.len() == 0
     }
 
-    pub fn contains(&self, k: &K) -> bool
+    pub fn contains<Q>(&self, k: &Q) -> bool
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
         self.get(k).is_some()
     }
 
-    pub fn get(&self, k: &K) -> Option<&V>
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
-        self.root.get(k).map(|(idx, node)| &node.vals[idx])
+        self.root.get::<C, Q>(k).map(|(idx, node)| &node.vals[idx])
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V>
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
-        self.root.get_mut(k).map(|(idx, node)| &mut node.vals[idx])
+        self.root
+            .get_mut::<C, Q>(k)
+            .map(|(idx, node)| &mut node.vals[idx])
     }
 
-    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)>
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
         self.root
-            .get(k)
+            .get::<C, Q>(k)
             .map(|(idx, node)| (&node.keys[idx], &node.vals[idx]))
     }
 
+    /// Returns the entry with the greatest key at most `k`, in a single descent.
+    pub fn floor<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.root.floor::<C, Q>(k)
+    }
+
+    /// Returns the entry with the least key at least `k`, in a single descent.
+    pub fn ceiling<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.root.ceiling::<C, Q>(k)
+    }
+
+    /// Returns the number of keys less than `k`, whether or not `k` is in the map.
+    ///
+    /// Nodes keep the sizes of their subtrees, so this is a single descent.
+    pub fn rank<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.root
+            .count_before(|key| C::cmp(key.borrow(), k).is_lt())
+    }
+
+    /// Returns the entry with `n` keys less than it, i.e. the `n`th smallest counting from 0.
+    pub fn select(&self, n: usize) -> Option<(&K, &V)> {
+        (n < self.len).then(|| self.root.nth(n))
+    }
+
+    /// Returns the number of entries with keys in `range`, from the ranks of its ends rather than
+    /// by visiting the entries.
+    pub fn range_count<R>(&self, range: R) -> usize
+    where
+        R: RangeBounds<K>,
+        C: Comparator<K>,
+    {
+        let end = self.upper_rank(range.end_bound());
+        let start = self.lower_rank(range.start_bound());
+        end.saturating_sub(start)
+    }
+
+    /// Returns the entry at `idx` in key order, the same as `select`.
+    pub fn get_index(&self, idx: usize) -> Option<(&K, &V)> {
+        self.select(idx)
+    }
+
+    pub fn get_index_mut(&mut self, idx: usize) -> Option<(&K, &mut V)> {
+        (idx < self.len).then(|| self.root.nth_mut(idx))
+    }
+
+    /// Removes and returns the entry at `idx` in key order, descending by the sizes of the
+    /// subtrees rather than by comparing keys.
+    pub fn remove_index(&mut self, idx: usize) -> Option<(K, V)> {
+        if idx >= self.len {
+            return None;
+        }
+
+        let entry = self.root.remove_nth(idx, self.degree);
+        self.shrink_root();
+        self.len -= 1;
+        Some(entry)
+    }
+
+    /// Returns the first entry whose key is above `bound`, e.g. the first key at least `k` for
+    /// `Bound::Included(k)`, for keys that may not be in the map.
+    pub fn lower_bound(&self, bound: Bound<&K>) -> Option<(&K, &V)>
+    where
+        C: Comparator<K>,
+    {
+        let idx = self.lower_rank(bound);
+        (idx < self.len).then(|| self.root.nth(idx))
+    }
+
+    /// Returns the last entry whose key is below `bound`, e.g. the last key at most `k` for
+    /// `Bound::Included(k)`, for keys that may not be in the map.
+    pub fn upper_bound(&self, bound: Bound<&K>) -> Option<(&K, &V)>
+    where
+        C: Comparator<K>,
+    {
+        let idx = self.upper_rank(bound).checked_sub(1)?;
+        Some(self.root.nth(idx))
+    }
+
+    /// Returns the number of entries whose keys are below the lower bound `bound`.
+    fn lower_rank(&self, bound: Bound<&K>) -> usize
+    where
+        C: Comparator<K>,
+    {
+        match bound {
+            Bound::Included(k) => self.root.count_before(|key| C::cmp(key, k).is_lt()),
+            Bound::Excluded(k) => self.root.count_before(|key| C::cmp(key, k).is_le()),
+            Bound::Unbounded => 0,
+        }
+    }
+
+    /// Returns the number of entries whose keys are within the upper bound `bound`.
+    fn upper_rank(&self, bound: Bound<&K>) -> usize
+    where
+        C: Comparator<K>,
+    {
+        match bound {
+            Bound::Included(k) => self.root.count_before(|key| C::cmp(key, k).is_le()),
+            Bound::Excluded(k) => self.root.count_before(|key| C::cmp(key, k).is_lt()),
+            Bound::Unbounded => self.len,
+        }
+    }
+
     pub fn insert(&mut self, k: K, v: V) -> Option<V>
     where
-        K: Ord,
+        C: Comparator<K>,
     {
         if self.root.is_full(self.degree) {
             let mut new_root = Node::new();
@@ -80,7 +255,7 @@ impl<K, V> BTreeMap<K, V> {
             self.root.split_child(0, self.degree);
         }
 
-        let res = self.root.insert_nonfull(k, v, self.degree);
+        let res = self.root.insert_nonfull::<C>(k, v, self.degree);
 
         if res.is_none() {
             self.len += 1;
@@ -89,28 +264,328 @@ impl<K, V> BTreeMap<K, V> {
         res
     }
 
-    pub fn remove(&mut self, k: &K) -> Option<V>
+    /// Like `insert`, but returns an error instead of aborting if memory for the entry can't be
+    /// allocated, in which case the map is left unchanged.
+    ///
+    /// All the memory the insertion may need is reserved before the map is modified.
+    ///
+    /// Only plain inserts have a fallible variant. The other operations that allocate, such as
+    /// `insert_hint`, inserting through entries and cursors, `split_off`, `remove_range`,
+    /// `retain` and `rebuild`, still abort if memory runs out.
+    pub fn try_insert_alloc(&mut self, k: K, v: V) -> Result<Option<V>, TryReserveError>
     where
-        K: Ord,
+        C: Comparator<K>,
+    {
+        let root_full = self.root.is_full(self.degree);
+        let mut new_root = None;
+        if root_full {
+            new_root = Some(Node::try_with_capacity(self.degree, false)?);
+        }
+
+        let (mut internal, mut leaf) = self.root.try_reserve_insert::<C>(&k, self.degree)?;
+        if root_full {
+            if self.root.is_leaf() {
+                leaf += 1;
+            } else {
+                internal += 1;
+            }
+        }
+
+        let mut internals = Vec::new();
+        internals.try_reserve_exact(internal)?;
+        for _ in 0..internal {
+            internals.push(Node::try_with_capacity(self.degree, false)?);
+        }
+        let mut leaves = Vec::new();
+        leaves.try_reserve_exact(leaf)?;
+        for _ in 0..leaf {
+            leaves.push(Node::try_with_capacity(self.degree, true)?);
+        }
+        let mut spare = |is_leaf: bool| {
+            let spares = if is_leaf { &mut leaves } else { &mut internals };
+            spares.pop().expect("not enough nodes were reserved")
+        };
+
+        if let Some(mut new_root) = new_root {
+            mem::swap(&mut self.root, &mut new_root);
+            self.root.children.push(new_root);
+            let right = spare(self.root.children[0].is_leaf());
+            self.root.split_child_into(0, self.degree, right);
+        }
+
+        let res = self
+            .root
+            .insert_nonfull_with::<C>(k, v, self.degree, &mut spare);
+
+        if res.is_none() {
+            self.len += 1;
+        }
+
+        Ok(res)
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
         self.remove_entry(k).map(|(_, val)| val)
     }
 
-    pub fn remove_entry(&mut self, k: &K) -> Option<(K, V)>
+    pub fn remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
-        if let Some(entry) = self.root.remove(k, self.degree) {
-            if !self.root.is_leaf() && self.root.is_empty() {
-                self.root = self.root.children.pop().unwrap();
-            }
+        let entry = self.root.remove::<C, Q>(k, self.degree);
+
+        // Rebalancing on the way down can empty the root even if the key isn't found.
+        self.shrink_root();
+
+        if entry.is_some() {
             self.len -= 1;
-            Some(entry)
+        }
+
+        entry
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = &self.root;
+        while !node.is_leaf() {
+            node = &node.children[0];
+        }
+        node.keys.first().zip(node.vals.first())
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = &self.root;
+        while !node.is_leaf() {
+            node = node.children.last().unwrap();
+        }
+        node.keys.last().zip(node.vals.last())
+    }
+
+    /// Removes and returns the entry with the smallest key, in one pass down the left edge.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let entry = self.root.remove_min(self.degree);
+        self.shrink_root();
+        self.len -= 1;
+        Some(entry)
+    }
+
+    /// Removes and returns the entry with the largest key, in one pass down the right edge.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let entry = self.root.remove_max(self.degree);
+        self.shrink_root();
+        self.len -= 1;
+        Some(entry)
+    }
+
+    /// Replaces an internal root that a removal left without keys with its only child.
+    fn shrink_root(&mut self) {
+        if !self.root.is_leaf() && self.root.is_empty() {
+            self.root = self.root.children.pop().unwrap();
+        }
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, visiting them in key order.
+    ///
+    /// Rather than removing entries one at a time, the entries are taken out of the tree in a
+    /// single pass, and the ones that are kept are built back into a tree bottom-up.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        let mut keys = Vec::with_capacity(self.len);
+        let mut vals = Vec::with_capacity(self.len);
+        mem::replace(&mut self.root, Node::new()).into_sorted(&mut keys, &mut vals);
+
+        let mut kept = 0;
+        for idx in 0..keys.len() {
+            if f(&keys[idx], &mut vals[idx]) {
+                keys.swap(kept, idx);
+                vals.swap(kept, idx);
+                kept += 1;
+            }
+        }
+        keys.truncate(kept);
+        vals.truncate(kept);
+
+        self.len = kept;
+        self.root = Node::build(keys, vals, self.degree);
+    }
+
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
+    /// Rebuilds the tree with a new degree, with its nodes as full as possible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2.
+    pub fn set_degree(&mut self, degree: usize) {
+        assert!(degree >= 2, "the degree must be at least 2");
+
+        self.degree = degree;
+        self.optimize();
+    }
+
+    /// Rebuilds the tree bottom-up with its nodes as full as possible, which shrinks its height
+    /// and memory after many removals have left nodes near the minimum of t - 1 keys.
+    pub fn optimize(&mut self) {
+        self.rebuild(1.0);
+    }
+
+    /// Rebuilds the tree bottom-up with `fill` of each node's key slots in use, from 0 to 1.
+    ///
+    /// Nodes still hold at least t - 1 keys, however low `fill` is. Leaving room in each node
+    /// means inserts that follow split fewer nodes.
+    pub fn rebuild(&mut self, fill: f64) {
+        let max = 2 * self.degree - 1;
+        let target = (fill * max as f64).round() as usize;
+
+        let mut keys = Vec::with_capacity(self.len);
+        let mut vals = Vec::with_capacity(self.len);
+        mem::replace(&mut self.root, Node::new()).into_sorted(&mut keys, &mut vals);
+        self.root =
+            Node::build_with_fill(keys, vals, self.degree, target.clamp(self.degree - 1, max));
+    }
+
+    /// Moves the entries with keys from `k` on into a new map, which is returned.
+    ///
+    /// The tree is cut along the path to `k`, so only the nodes along the cut are touched.
+    pub fn split_off(&mut self, k: &K) -> Self
+    where
+        C: Comparator<K>,
+    {
+        let mut right = Self::with_comparator(self.degree);
+        right.root = self.root.split_off::<C>(k);
+
+        self.root.fix_border(self.degree, true);
+        right.root.fix_border(self.degree, false);
+
+        self.len = self.root.size;
+        right.len = right.root.size;
+        right
+    }
+
+    /// Removes the entries with keys in `range`, returning them as a map.
+    ///
+    /// Rather than removing the entries one at a time, the tree is split at both ends of the
+    /// range and the pieces on either side are joined back together, so only the nodes along
+    /// the cuts are touched.
+    pub fn remove_range<R>(&mut self, range: R) -> Self
+    where
+        R: RangeBounds<K>,
+        C: Comparator<K>,
+    {
+        let mut removed = match range.start_bound() {
+            Bound::Included(start) => self.split_off(start),
+            Bound::Excluded(start) => {
+                let mut removed = self.split_off(start);
+                if let Some(entry) = removed
+                    .first_entry()
+                    .filter(|e| C::cmp(e.key(), start).is_eq())
+                {
+                    let (k, v) = entry.remove_entry();
+                    self.insert(k, v);
+                }
+                removed
+            }
+            Bound::Unbounded => mem::replace(self, Self::with_comparator(self.degree)),
+        };
+
+        let rest = match range.end_bound() {
+            Bound::Included(end) => {
+                let mut rest = removed.split_off(end);
+                if let Some(entry) = rest.first_entry().filter(|e| C::cmp(e.key(), end).is_eq()) {
+                    let (k, v) = entry.remove_entry();
+                    removed.insert(k, v);
+                }
+                rest
+            }
+            Bound::Excluded(end) => removed.split_off(end),
+            Bound::Unbounded => Self::with_comparator(self.degree),
+        };
+
+        self.concat(rest);
+        removed
+    }
+
+    /// Appends the entries of `other`, whose keys must all come after this map's, by joining
+    /// the two trees along their facing edges.
+    fn concat(&mut self, mut other: Self)
+    where
+        C: Comparator<K>,
+    {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            self.len = other.len;
+            self.root = other.root;
+            return;
+        }
+
+        // The first entry of `other` goes between the two trees.
+        let sep = other.pop_first().unwrap();
+        self.len += other.len + 1;
+
+        let left = self.root.height();
+        let right = other.root.height();
+        if left == right {
+            let lower = mem::replace(&mut self.root, Node::new());
+            let mut root = Node::join(lower, sep, other.root);
+
+            // Either root may have had fewer than t - 1 keys, unless they've been merged.
+            for idx in [1, 0] {
+                if root.children.len() == 2 && root.children[idx].len() + 1 < self.degree {
+                    root.stock_child(idx, self.degree);
+                }
+            }
+            self.root = root;
+            self.shrink_root();
+        } else if left > right {
+            let depth = left - right - 1;
+            if let Some((sep, upper)) = self.root.push_back(depth, sep, other.root, self.degree) {
+                let lower = mem::replace(&mut self.root, Node::new());
+                self.root = Node::join(lower, sep, upper);
+            }
         } else {
-            None
+            let depth = right - left - 1;
+            let left = mem::replace(&mut self.root, other.root);
+            if let Some((lower, sep)) = self.root.push_front(depth, sep, left, self.degree) {
+                let upper = mem::replace(&mut self.root, Node::new());
+                self.root = Node::join(lower, sep, upper);
+            }
         }
     }
 
+    /// Returns an iterator that removes and yields the entries for which `pred` returns `true`,
+    /// in key order.
+    ///
+    /// Entries the iterator doesn't get to before it's dropped are kept.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, C, F>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Removes every entry, returning an iterator over them in key order.
+    pub fn drain(&mut self) -> Drain<K, V> {
+        self.len = 0;
+        Drain::new(mem::replace(&mut self.root, Node::new()))
+    }
+
     pub fn clear(&mut self) {
         self.len = 0;
         self.root = Node::new();
@@ -120,6 +595,41 @@ impl<K, V> BTreeMap<K, V> {
         Iter::new(&self.root)
     }
 
+    /// Iterates over the entries in key order, with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut::new(&mut self.root)
+    }
+
+    /// Iterates over the entries in key order, `size` at a time, for consumers that work in
+    /// batches. Every chunk but the last has exactly `size` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn iter_chunks(&self, size: usize) -> Chunks<'_, K, V> {
+        assert!(size > 0, "the chunk size must be at least 1");
+        Chunks::new(self.iter(), size)
+    }
+
+    /// Iterates over the entries with keys in `range`, in key order.
+    pub fn range<R>(&self, range: R) -> Range<'_, K, V, C, R>
+    where
+        C: Comparator<K>,
+        R: RangeBounds<K>,
+    {
+        Range::new(&self.root, range)
+    }
+
+    /// Iterates over the entries with keys in `range`, in key order, with mutable references to
+    /// the values.
+    pub fn range_mut<R>(&mut self, range: R) -> RangeMut<'_, K, V, C, R>
+    where
+        C: Comparator<K>,
+        R: RangeBounds<K>,
+    {
+        RangeMut::new(&mut self.root, range)
+    }
+
     pub fn keys(&self) -> Keys<'_, K, V> {
         Keys::new(self.iter())
     }
@@ -127,14 +637,58 @@ impl<K, V> BTreeMap<K, V> {
     pub fn values(&self) -> Values<'_, K, V> {
         Values::new(self.iter())
     }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut::new(self.iter_mut())
+    }
+
+    /// Reports how full the nodes at each level are, and how many children they have.
+    pub fn occupancy(&self) -> Occupancy {
+        let mut occupancy = Occupancy::new(self.degree);
+        let mut stack = vec![(&self.root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            occupancy.record(depth, node.len(), node.children.len());
+            stack.extend(node.children.iter().map(|child| (child, depth + 1)));
+        }
+
+        occupancy
+    }
+
+    /// Checks the tree's structural invariants, describing the first violation found.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn check(&self) -> Result<(), String>
+    where
+        C: Comparator<K>,
+        K: Debug,
+    {
+        let count = self
+            .root
+            .check::<C>(self.degree, 0, None, None, &mut None)?;
+
+        if count != self.len {
+            return Err(format!(
+                "found {count} entries but the length is {}",
+                self.len
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<K, V, C> Default for BTreeMap<K, V, C> {
+    fn default() -> Self {
+        Self::with_comparator(DEFAULT_DEGREE)
+    }
 }
 
-impl<K, V> Debug for BTreeMap<K, V>
+impl<K, V, C> Debug for BTreeMap<K, V, C>
 where
     K: Debug,
     V: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self.root)
+        self.root.fmt_tree(f, Some(2 * self.degree - 1))
     }
 }
@@ -1,5 +1,8 @@
+use crate::comparator::Comparator;
 use std::{
+    borrow::Borrow,
     cmp::Ordering,
+    collections::TryReserveError,
     fmt::{self, Debug, Formatter},
     mem,
 };
@@ -8,6 +11,8 @@ pub(crate) struct Node<K, V> {
     pub(crate) keys: Vec<K>,
     pub(crate) vals: Vec<V>,
     pub(crate) children: Vec<Node<K, V>>,
+    /// The number of entries in the subtree rooted at this node.
+    pub(crate) size: usize,
 }
 
 impl<K, V> Node<K, V> {
@@ -16,9 +21,22 @@ impl<K, V> Node<K, V> {
             keys: Vec::new(),
             vals: Vec::new(),
             children: Vec::new(),
+            size: 0,
         }
     }
 
+    /// Creates a node with room for as many entries, and children if it's internal, as it can
+    /// hold.
+    pub fn try_with_capacity(degree: usize, leaf: bool) -> Result<Self, TryReserveError> {
+        let mut node = Self::new();
+        node.keys.try_reserve_exact(2 * degree - 1)?;
+        node.vals.try_reserve_exact(2 * degree - 1)?;
+        if !leaf {
+            node.children.try_reserve_exact(2 * degree)?;
+        }
+        Ok(node)
+    }
+
     pub fn len(&self) -> usize {
         self.keys.len()
     }
@@ -35,9 +53,168 @@ impl<K, V> Node<K, V> {
         self.children.is_empty()
     }
 
-    fn find_index(&self, k: &K) -> usize
+    /// Moves the entries of this node's subtree onto the ends of `keys` and `vals`, in order.
+    pub fn into_sorted(self, keys: &mut Vec<K>, vals: &mut Vec<V>) {
+        let mut children = self.children.into_iter();
+        for (k, v) in self.keys.into_iter().zip(self.vals) {
+            if let Some(child) = children.next() {
+                child.into_sorted(keys, vals);
+            }
+            keys.push(k);
+            vals.push(v);
+        }
+        if let Some(child) = children.next() {
+            child.into_sorted(keys, vals);
+        }
+    }
+
+    /// Builds a tree bottom-up from entries sorted by strictly increasing key, returning its
+    /// root.
+    pub fn build(keys: Vec<K>, vals: Vec<V>, degree: usize) -> Self {
+        Self::build_with_fill(keys, vals, degree, 2 * degree - 1)
+    }
+
+    /// Like `build`, but aims for `target` keys per node, which must be from t - 1 to 2t - 1.
+    pub fn build_with_fill(
+        mut keys: Vec<K>,
+        mut vals: Vec<V>,
+        degree: usize,
+        target: usize,
+    ) -> Self {
+        let mut children: Vec<Self> = Vec::new();
+
+        loop {
+            // Use as few nodes as fit the keys at the target, but never so few that spreading
+            // the keys evenly over them leaves any with fewer than `degree - 1` keys.
+            let count = (keys.len() + 1)
+                .div_ceil(target + 1)
+                .min((keys.len() + 1) / degree);
+
+            if count <= 1 {
+                let mut root = Self::new();
+                root.keys = keys;
+                root.vals = vals;
+                root.children = children;
+                root.recount();
+                return root;
+            }
+
+            // One key between each pair of nodes is pulled up as a separator.
+            let per_node = (keys.len() - (count - 1)) / count;
+            let extra = (keys.len() - (count - 1)) % count;
+
+            let mut level = Vec::with_capacity(count);
+            let mut parent_keys = Vec::with_capacity(count - 1);
+            let mut parent_vals = Vec::with_capacity(count - 1);
+
+            let mut keys_iter = keys.into_iter();
+            let mut vals_iter = vals.into_iter();
+            let mut children_iter = children.into_iter();
+
+            for i in 0..count {
+                let n = per_node + usize::from(i < extra);
+
+                // Leaves have no children to hand out, so `take` is a no-op for them.
+                let mut node = Self::new();
+                node.keys.extend(keys_iter.by_ref().take(n));
+                node.vals.extend(vals_iter.by_ref().take(n));
+                node.children.extend(children_iter.by_ref().take(n + 1));
+                node.recount();
+
+                if i + 1 < count {
+                    parent_keys.push(keys_iter.next().unwrap());
+                    parent_vals.push(vals_iter.next().unwrap());
+                }
+
+                level.push(node);
+            }
+
+            children = level;
+            keys = parent_keys;
+            vals = parent_vals;
+        }
+    }
+
+    /// Recomputes the size of this node's subtree from its children's sizes.
+    fn recount(&mut self) {
+        self.size = self.len() + self.children.iter().map(|child| child.size).sum::<usize>();
+    }
+
+    /// Counts the entries in this node's subtree whose keys satisfy `before`, which must hold
+    /// for a prefix of the keys in order.
+    pub fn count_before(&self, before: impl Fn(&K) -> bool) -> usize {
+        let mut count = 0;
+
+        let mut node = self;
+        loop {
+            let idx = node.keys.partition_point(&before);
+            count += idx;
+            if node.is_leaf() {
+                return count;
+            }
+
+            count += node.children[..idx]
+                .iter()
+                .map(|child| child.size)
+                .sum::<usize>();
+            node = &node.children[idx];
+        }
+    }
+
+    /// Finds where the entry `n` places into this node's subtree is, returning the index of the
+    /// key if it's in this node, and otherwise the index of the child it's under and its place
+    /// in that child's subtree.
+    pub fn locate(&self, mut n: usize) -> (usize, Option<usize>) {
+        if self.is_leaf() {
+            return (n, None);
+        }
+
+        for (idx, child) in self.children.iter().enumerate() {
+            if n < child.size {
+                return (idx, Some(n));
+            }
+            n -= child.size;
+            if n == 0 {
+                return (idx, None);
+            }
+            n -= 1;
+        }
+        unreachable!("place is past the end of the subtree")
+    }
+
+    /// Returns the entry `n` places into this node's subtree, which must have more than `n`
+    /// entries.
+    pub fn nth(&self, mut n: usize) -> (&K, &V) {
+        let mut node = self;
+        loop {
+            match node.locate(n) {
+                (idx, None) => return (&node.keys[idx], &node.vals[idx]),
+                (idx, Some(rest)) => {
+                    node = &node.children[idx];
+                    n = rest;
+                }
+            }
+        }
+    }
+
+    pub fn nth_mut(&mut self, mut n: usize) -> (&K, &mut V) {
+        let mut node = self;
+        loop {
+            match node.locate(n) {
+                (idx, None) => return (&node.keys[idx], &mut node.vals[idx]),
+                (idx, Some(rest)) => {
+                    node = &mut node.children[idx];
+                    n = rest;
+                }
+            }
+        }
+    }
+
+    fn find_index<C, Q>(&self, k: &Q) -> usize
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
         let mut size = self.len();
         let mut left = 0;
@@ -46,7 +223,7 @@ impl<K, V> Node<K, V> {
         while left < right {
             let mid = left + size / 2;
 
-            match self.keys[mid].cmp(k) {
+            match C::cmp(self.keys[mid].borrow(), k) {
                 Ordering::Equal => return mid,
                 Ordering::Less => left = mid + 1,
                 Ordering::Greater => right = mid,
@@ -58,14 +235,16 @@ impl<K, V> Node<K, V> {
         left
     }
 
-    pub fn get(&self, k: &K) -> Option<(usize, &Node<K, V>)>
+    pub fn get<C, Q>(&self, k: &Q) -> Option<(usize, &Node<K, V>)>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
         let mut node = self;
         loop {
-            let idx = node.find_index(k);
-            if idx < node.len() && node.keys[idx] == *k {
+            let idx = node.find_index::<C, Q>(k);
+            if idx < node.len() && C::cmp(node.keys[idx].borrow(), k).is_eq() {
                 return Some((idx, node));
             } else if node.is_leaf() {
                 return None;
@@ -75,14 +254,66 @@ impl<K, V> Node<K, V> {
         }
     }
 
-    pub fn get_mut(&mut self, k: &K) -> Option<(usize, &mut Node<K, V>)>
+    /// Returns the entry with the greatest key at most `k`.
+    pub fn floor<C, Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut best = None;
+        let mut node = self;
+        loop {
+            let idx = node.find_index::<C, Q>(k);
+            if idx < node.len() && C::cmp(node.keys[idx].borrow(), k).is_eq() {
+                return Some((&node.keys[idx], &node.vals[idx]));
+            }
+
+            // Keys further down are between the separators around the child, so closer to `k`.
+            if idx > 0 {
+                best = Some((&node.keys[idx - 1], &node.vals[idx - 1]));
+            }
+            match node.children.get(idx) {
+                Some(child) => node = child,
+                None => return best,
+            }
+        }
+    }
+
+    /// Returns the entry with the least key at least `k`.
+    pub fn ceiling<C, Q>(&self, k: &Q) -> Option<(&K, &V)>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
+        let mut best = None;
         let mut node = self;
         loop {
-            let idx = node.find_index(k);
-            if idx < node.len() && node.keys[idx] == *k {
+            let idx = node.find_index::<C, Q>(k);
+            if idx < node.len() {
+                if C::cmp(node.keys[idx].borrow(), k).is_eq() {
+                    return Some((&node.keys[idx], &node.vals[idx]));
+                }
+                best = Some((&node.keys[idx], &node.vals[idx]));
+            }
+            match node.children.get(idx) {
+                Some(child) => node = child,
+                None => return best,
+            }
+        }
+    }
+
+    pub fn get_mut<C, Q>(&mut self, k: &Q) -> Option<(usize, &mut Node<K, V>)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let mut node = self;
+        loop {
+            let idx = node.find_index::<C, Q>(k);
+            if idx < node.len() && C::cmp(node.keys[idx].borrow(), k).is_eq() {
                 return Some((idx, node));
             } else if node.is_leaf() {
                 return None;
@@ -93,11 +324,16 @@ impl<K, V> Node<K, V> {
     }
 
     pub fn split_child(&mut self, idx: usize, degree: usize) {
+        self.split_child_into(idx, degree, Self::new());
+    }
+
+    /// Splits the child at `idx`, moving its larger half into `right`, which must be empty.
+    pub fn split_child_into(&mut self, idx: usize, degree: usize, mut right: Self) {
         assert!(!self.is_full(degree));
         assert!(self.children[idx].is_full(degree));
+        assert!(right.is_empty() && right.is_leaf());
 
         let left = &mut self.children[idx];
-        let mut right = Self::new();
 
         // Move the largest keys and values from the left to the right.
         right.vals.extend(left.vals.drain(degree..));
@@ -112,88 +348,150 @@ impl<K, V> Node<K, V> {
             right.children.extend(left.children.drain(degree..));
         }
 
+        left.recount();
+        right.recount();
+
         // Insert new key, value, and right child into the root.
         self.keys.insert(idx, key);
         self.vals.insert(idx, val);
         self.children.insert(idx + 1, right);
+        self.recount();
     }
 
-    pub fn insert_nonfull(&mut self, k: K, mut v: V, degree: usize) -> Option<V>
+    pub fn insert_nonfull<C>(&mut self, k: K, v: V, degree: usize) -> Option<V>
     where
-        K: Ord,
+        C: Comparator<K>,
     {
-        assert!(!self.is_full(degree));
+        self.insert_nonfull_with::<C>(k, v, degree, &mut |_| Self::new())
+    }
+
+    /// Reserves room for `insert_nonfull_with` to insert `k` below this node without growing
+    /// any of the nodes it passes through.
+    ///
+    /// Returns how many internal and leaf nodes it will split, each of which needs an empty
+    /// node from `Node::try_with_capacity` to move half of its entries into.
+    pub fn try_reserve_insert<C>(
+        &mut self,
+        k: &K,
+        degree: usize,
+    ) -> Result<(usize, usize), TryReserveError>
+    where
+        C: Comparator<K>,
+    {
+        let (mut internal, mut leaf) = (0, 0);
 
         let mut node = self;
         loop {
-            // Find index to insert key into or of the child to recurse down.
-            let mut idx = node.find_index(&k);
+            let idx = node.find_index::<C, K>(k);
+            if idx < node.len() && C::cmp(k, &node.keys[idx]).is_eq() {
+                return Ok((internal, leaf));
+            }
 
+            // Either `k` or the median of a split child ends up in this node.
+            node.keys.try_reserve(1)?;
+            node.vals.try_reserve(1)?;
             if node.is_leaf() {
-                // Insert key and value into non-full node.
-                if idx < node.len() && k == node.keys[idx] {
-                    // The key already exists, so swap in the value.
-                    std::mem::swap(&mut node.vals[idx], &mut v);
-                    return Some(v);
+                return Ok((internal, leaf));
+            }
+            node.children.try_reserve(1)?;
+
+            let child = &mut node.children[idx];
+            if child.is_full(degree) {
+                if child.is_leaf() {
+                    leaf += 1;
                 } else {
-                    // The key doesn't exist yet.
-                    node.keys.insert(idx, k);
-                    node.vals.insert(idx, v);
-                    return None;
+                    internal += 1;
                 }
-            } else {
-                if node.children[idx].is_full(degree) {
-                    // Split the child and determine which child to recurse down.
-                    node.split_child(idx, degree);
-                    if node.keys[idx] < k {
-                        idx += 1;
-                    }
-                }
-                node = &mut node.children[idx];
             }
+            node = child;
         }
     }
 
-    fn min_key(&self) -> &K {
-        let mut node = self;
-        while !node.is_leaf() && !node.children.first().unwrap().is_empty() {
-            node = node.children.first().unwrap();
+    /// Like `insert_nonfull`, with the larger half of each child that's split moved into a node
+    /// from `spare`, which is passed whether the child is a leaf.
+    pub fn insert_nonfull_with<C>(
+        &mut self,
+        k: K,
+        mut v: V,
+        degree: usize,
+        spare: &mut impl FnMut(bool) -> Self,
+    ) -> Option<V>
+    where
+        C: Comparator<K>,
+    {
+        assert!(!self.is_full(degree));
+
+        // Find index to insert key into or of the child to recurse down.
+        let mut idx = self.find_index::<C, K>(&k);
+
+        if idx < self.len() && C::cmp(&k, &self.keys[idx]).is_eq() {
+            // The key already exists, so swap in the value.
+            std::mem::swap(&mut self.vals[idx], &mut v);
+            return Some(v);
         }
-        node.keys.first().unwrap()
-    }
 
-    fn max_key(&self) -> &K {
-        let mut node = self;
-        while !node.is_leaf() && !node.children.last().unwrap().is_empty() {
-            node = node.children.last().unwrap()
+        if self.is_leaf() {
+            // Insert key and value into non-full node.
+            self.keys.insert(idx, k);
+            self.vals.insert(idx, v);
+            self.size += 1;
+            return None;
+        }
+
+        if self.children[idx].is_full(degree) {
+            // Split the child and determine which child to recurse down. The split may have
+            // moved the key up into this node.
+            let right = spare(self.children[idx].is_leaf());
+            self.split_child_into(idx, degree, right);
+            match C::cmp(&self.keys[idx], &k) {
+                Ordering::Less => idx += 1,
+                Ordering::Equal => {
+                    std::mem::swap(&mut self.vals[idx], &mut v);
+                    return Some(v);
+                }
+                Ordering::Greater => {}
+            }
+        }
+
+        let res = self.children[idx].insert_nonfull_with::<C>(k, v, degree, spare);
+        if res.is_none() {
+            self.size += 1;
         }
-        node.keys.last().unwrap()
+        res
     }
 
-    pub fn remove(&mut self, k: &K, degree: usize) -> Option<(K, V)>
+    pub fn remove<C, Q>(&mut self, k: &Q, degree: usize) -> Option<(K, V)>
     where
-        K: Ord,
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
     {
-        let mut idx = self.find_index(k);
+        let entry = self.remove_uncounted::<C, Q>(k, degree);
+        self.recount();
+        entry
+    }
+
+    fn remove_uncounted<C, Q>(&mut self, k: &Q, degree: usize) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        let idx = self.find_index::<C, Q>(k);
 
         // Case 1: Key found in node and node is a leaf.
-        if idx < self.len() && self.keys[idx] == *k && self.is_leaf() {
+        if idx < self.len() && C::cmp(self.keys[idx].borrow(), k).is_eq() && self.is_leaf() {
             let key = self.keys.remove(idx);
             let val = self.vals.remove(idx);
             return Some((key, val));
         }
 
         // Case 2: Key found in node and node is an internal node.
-        if idx < self.len() && self.keys[idx] == *k && !self.is_leaf() {
+        if idx < self.len() && C::cmp(self.keys[idx].borrow(), k).is_eq() && !self.is_leaf() {
             if self.children[idx].len() >= degree {
                 // Case 2a: Child node that precedes k has at least t keys.
-                let pred = &mut self.children[idx];
-
-                // Replace key with the predecessor key and recursively delete it.
-                // Safety: we won't ever use the reference past this point.
-                let pred_key = pred.max_key() as *const _;
-                let (mut pred_key, mut pred_val) =
-                    pred.remove(unsafe { &*pred_key }, degree).unwrap();
+                // Replace key with the predecessor key, deleting it from the child.
+                let (mut pred_key, mut pred_val) = self.children[idx].remove_max(degree);
 
                 // The actual replacement.
                 mem::swap(&mut self.keys[idx], &mut pred_key);
@@ -202,13 +500,8 @@ impl<K, V> Node<K, V> {
                 return Some((pred_key, pred_val));
             } else if self.children[idx + 1].len() >= degree {
                 // Case 2b: Child node that succeeds k has at least t keys.
-                let succ = &mut self.children[idx + 1];
-
-                // Replace key with the successor key and recursively delete it.
-                // Safety: we don't ever use the reference past this point.
-                let succ_key = succ.min_key() as *const _;
-                let (mut succ_key, mut succ_val) =
-                    succ.remove(unsafe { &*succ_key }, degree).unwrap();
+                // Replace key with the successor key, deleting it from the child.
+                let (mut succ_key, mut succ_val) = self.children[idx + 1].remove_min(degree);
 
                 // The actual replacement.
                 mem::swap(&mut self.keys[idx], &mut succ_key);
@@ -231,7 +524,7 @@ impl<K, V> Node<K, V> {
                 pred.children.append(&mut succ.children);
                 assert!(pred.is_full(degree));
 
-                return pred.remove(k, degree);
+                return pred.remove::<C, Q>(k, degree);
             }
         }
 
@@ -241,132 +534,492 @@ impl<K, V> Node<K, V> {
         }
 
         // Case 3: Key not found in internal node.
-        if self.children[idx].len() + 1 == degree {
-            if idx > 0 && self.children[idx - 1].len() >= degree {
-                // Case 3a: Immediate left sibling has at least t keys.
+        let idx = self.fill_child(idx, degree);
+        self.children[idx].remove::<C, Q>(k, degree)
+    }
 
-                // Move key and value from parent down to child.
-                {
-                    let parent_key = self.keys.remove(idx - 1);
-                    let parent_val = self.vals.remove(idx - 1);
+    /// Removes the entry `n` places into this node's subtree, which must have more than `n`
+    /// entries, rebalancing on the way down like `remove`.
+    pub fn remove_nth(&mut self, n: usize, degree: usize) -> (K, V) {
+        let entry = self.remove_nth_uncounted(n, degree);
+        self.recount();
+        entry
+    }
 
-                    let mid = &mut self.children[idx];
-                    mid.keys.insert(0, parent_key);
-                    mid.vals.insert(0, parent_val);
+    fn remove_nth_uncounted(&mut self, n: usize, degree: usize) -> (K, V) {
+        match self.locate(n) {
+            (idx, None) if self.is_leaf() => (self.keys.remove(idx), self.vals.remove(idx)),
+            (idx, None) => {
+                if self.children[idx].len() >= degree {
+                    let (mut pred_key, mut pred_val) = self.children[idx].remove_max(degree);
+                    mem::swap(&mut self.keys[idx], &mut pred_key);
+                    mem::swap(&mut self.vals[idx], &mut pred_val);
+                    (pred_key, pred_val)
+                } else if self.children[idx + 1].len() >= degree {
+                    let (mut succ_key, mut succ_val) = self.children[idx + 1].remove_min(degree);
+                    mem::swap(&mut self.keys[idx], &mut succ_key);
+                    mem::swap(&mut self.vals[idx], &mut succ_val);
+                    (succ_key, succ_val)
+                } else {
+                    // The entry lands right after the left child's entries in the merged child.
+                    let rest = self.children[idx].size;
+                    self.merge_children(idx);
+                    self.children[idx].remove_nth(rest, degree)
                 }
+            }
+            (idx, Some(rest)) => {
+                if self.children[idx].len() + 1 == degree {
+                    // Filling the child moves entries between children, so look again.
+                    self.fill_child(idx, degree);
+                    return self.remove_nth_uncounted(n, degree);
+                }
+                self.children[idx].remove_nth(rest, degree)
+            }
+        }
+    }
 
-                // Move rightmost key and value in left sibling to parent.
-                {
-                    let left = &mut self.children[idx - 1];
-                    let left_key = left.keys.pop().unwrap();
-                    let left_val = left.vals.pop().unwrap();
+    /// Removes the largest entry in the subtree rooted at this node.
+    ///
+    /// The node must have at least t keys unless it's the root, and mustn't be empty.
+    pub fn remove_max(&mut self, degree: usize) -> (K, V) {
+        let entry = self.remove_max_uncounted(degree);
+        self.recount();
+        entry
+    }
 
-                    self.keys.insert(idx - 1, left_key);
-                    self.vals.insert(idx - 1, left_val);
-                }
+    fn remove_max_uncounted(&mut self, degree: usize) -> (K, V) {
+        if self.is_leaf() {
+            let key = self.keys.pop().expect("couldn't pop largest key");
+            let val = self.vals.pop().expect("couldn't pop largest value");
+            return (key, val);
+        }
 
-                // Move rightmost child in left sibling to child.
-                let left = &mut self.children[idx - 1];
-                if !left.is_leaf() {
-                    let child = left.children.pop().unwrap();
-                    self.children[idx].children.insert(0, child);
-                }
+        let idx = self.fill_child(self.children.len() - 1, degree);
+        self.children[idx].remove_max(degree)
+    }
+
+    /// Removes the smallest entry in the subtree rooted at this node.
+    ///
+    /// The node must have at least t keys unless it's the root, and mustn't be empty.
+    pub fn remove_min(&mut self, degree: usize) -> (K, V) {
+        let entry = self.remove_min_uncounted(degree);
+        self.recount();
+        entry
+    }
+
+    fn remove_min_uncounted(&mut self, degree: usize) -> (K, V) {
+        if self.is_leaf() {
+            return (self.keys.remove(0), self.vals.remove(0));
+        }
+
+        let idx = self.fill_child(0, degree);
+        self.children[idx].remove_min(degree)
+    }
+
+    /// Ensures the child at `idx` has at least t keys before descending into it, by borrowing
+    /// from or merging with a sibling. Returns the index of the child to descend into, which
+    /// changes if the child was merged into its left sibling.
+    fn fill_child(&mut self, mut idx: usize, degree: usize) -> usize {
+        if self.children[idx].len() + 1 == degree {
+            if idx > 0 && self.children[idx - 1].len() >= degree {
+                // Case 3a: Immediate left sibling has at least t keys.
+                self.rotate_right(idx);
             } else if idx + 1 < self.children.len() && self.children[idx + 1].len() >= degree {
                 // Case 3a: Immediate right sibling has at least t keys.
+                self.rotate_left(idx);
+            } else if idx > 0 {
+                // Case 3b: Merge into left sibling.
+                // The only case where you fix the child to recurse down.
+                idx -= 1;
+                self.merge_children(idx);
+            } else if idx + 1 < self.children.len() {
+                // Case 3b: Merge into right sibling.
+                self.merge_children(idx);
+            }
+        }
 
-                // Move key and value from parent down to child.
-                {
-                    let parent_key = self.keys.remove(idx);
-                    let parent_val = self.vals.remove(idx);
+        idx
+    }
 
-                    let mid = &mut self.children[idx];
-                    mid.keys.push(parent_key);
-                    mid.vals.push(parent_val);
-                }
+    /// Moves the last entry of the child before `idx` up into this node, and the separator it
+    /// replaces down to the front of the child at `idx`, along with the left sibling's last
+    /// child.
+    fn rotate_right(&mut self, idx: usize) {
+        // Move key and value from parent down to child.
+        {
+            let parent_key = self.keys.remove(idx - 1);
+            let parent_val = self.vals.remove(idx - 1);
 
-                // Move leftmost key and value in right sibling to parent.
-                {
-                    let right = &mut self.children[idx + 1];
-                    let right_key = right.keys.remove(0);
-                    let right_val = right.vals.remove(0);
+            let mid = &mut self.children[idx];
+            mid.keys.insert(0, parent_key);
+            mid.vals.insert(0, parent_val);
+        }
 
-                    self.keys.insert(idx, right_key);
-                    self.vals.insert(idx, right_val);
-                }
+        // Move rightmost key and value in left sibling to parent.
+        {
+            let left = &mut self.children[idx - 1];
+            let left_key = left.keys.pop().unwrap();
+            let left_val = left.vals.pop().unwrap();
 
-                // Move leftmost child in right sibling to child.
-                let right = &mut self.children[idx + 1];
-                if !right.is_leaf() {
-                    let child = right.children.remove(0);
-                    self.children[idx].children.push(child);
-                }
-            } else if idx > 0 {
-                // Case 3b: Merge into left sibling.
+            self.keys.insert(idx - 1, left_key);
+            self.vals.insert(idx - 1, left_val);
+        }
 
-                // Move key and value from parent down to left sibling (merged node).
-                {
-                    let parent_key = self.keys.remove(idx - 1);
-                    let parent_val = self.vals.remove(idx - 1);
+        // Move rightmost child in left sibling to child.
+        let left = &mut self.children[idx - 1];
+        if !left.is_leaf() {
+            let child = left.children.pop().unwrap();
+            self.children[idx].children.insert(0, child);
+        }
+        self.children[idx - 1].recount();
+        self.children[idx].recount();
+    }
 
-                    let mid = &mut self.children[idx];
-                    let mut mid_keys = mid.keys.drain(..).collect();
-                    let mut mid_vals = mid.vals.drain(..).collect();
-                    let mut mid_children = mid.children.drain(..).collect();
+    /// Moves the first entry of the child after `idx` up into this node, and the separator it
+    /// replaces down to the end of the child at `idx`, along with the right sibling's first
+    /// child.
+    fn rotate_left(&mut self, idx: usize) {
+        // Move key and value from parent down to child.
+        {
+            let parent_key = self.keys.remove(idx);
+            let parent_val = self.vals.remove(idx);
 
-                    let left = &mut self.children[idx - 1];
-                    left.keys.push(parent_key);
-                    left.vals.push(parent_val);
+            let mid = &mut self.children[idx];
+            mid.keys.push(parent_key);
+            mid.vals.push(parent_val);
+        }
 
-                    // Merge all keys, values, and children from child into left sibling.
-                    left.keys.append(&mut mid_keys);
-                    left.vals.append(&mut mid_vals);
-                    left.children.append(&mut mid_children);
-                }
+        // Move leftmost key and value in right sibling to parent.
+        {
+            let right = &mut self.children[idx + 1];
+            let right_key = right.keys.remove(0);
+            let right_val = right.vals.remove(0);
 
-                // Remove the merged child.
-                self.children.remove(idx);
+            self.keys.insert(idx, right_key);
+            self.vals.insert(idx, right_val);
+        }
 
-                // The only case where you fix the child to recurse down.
-                idx -= 1;
-            } else if idx + 1 < self.children.len() {
-                // Case 3b: Merge into right sibling.
+        // Move leftmost child in right sibling to child.
+        let right = &mut self.children[idx + 1];
+        if !right.is_leaf() {
+            let child = right.children.remove(0);
+            self.children[idx].children.push(child);
+        }
+        self.children[idx].recount();
+        self.children[idx + 1].recount();
+    }
 
-                // Move key and value from parent down to right sibling (merged node).
-                {
-                    let parent_key = self.keys.remove(idx);
-                    let parent_val = self.vals.remove(idx);
-
-                    let right = &mut self.children[idx + 1];
-                    let mut right_keys = right.keys.drain(..).collect();
-                    let mut right_vals = right.vals.drain(..).collect();
-                    let mut right_children = right.children.drain(..).collect();
-
-                    let mid = &mut self.children[idx];
-                    mid.keys.push(parent_key);
-                    mid.vals.push(parent_val);
-                    mid.keys.append(&mut right_keys);
-                    mid.vals.append(&mut right_vals);
-                    mid.children.append(&mut right_children);
-                }
+    /// Merges the child after `idx` and the separator between them into the child at `idx`.
+    fn merge_children(&mut self, idx: usize) {
+        let parent_key = self.keys.remove(idx);
+        let parent_val = self.vals.remove(idx);
+        let mut right = self.children.remove(idx + 1);
+
+        let left = &mut self.children[idx];
+        left.keys.push(parent_key);
+        left.vals.push(parent_val);
+        left.keys.append(&mut right.keys);
+        left.vals.append(&mut right.vals);
+        left.children.append(&mut right.children);
+        left.recount();
+    }
+
+    /// Moves the entries with keys from `k` on into a new tree of the same height, which is
+    /// returned. Both trees may be left with underfull or empty nodes along the split, which
+    /// `fix_border` then fixes.
+    pub fn split_off<C>(&mut self, k: &K) -> Self
+    where
+        C: Comparator<K>,
+    {
+        let idx = self.keys.partition_point(|key| C::cmp(key, k).is_lt());
 
-                // Remove the right sibling.
-                self.children.remove(idx + 1);
+        let mut right = Self::new();
+        right.keys = self.keys.split_off(idx);
+        right.vals = self.vals.split_off(idx);
+        if !self.is_leaf() {
+            right.children = self.children.split_off(idx + 1);
+            right
+                .children
+                .insert(0, self.children[idx].split_off::<C>(k));
+        }
+
+        self.recount();
+        right.recount();
+        right
+    }
+
+    /// Restores the invariants along the right border of a tree left by `split_off` if `last`,
+    /// and along its left border otherwise.
+    ///
+    /// Going down from the root, each node on the border is topped up to at least t keys from
+    /// its sibling, or merged with it if they fit in one node, so it can spare a key if its own
+    /// child on the border has to be merged. Empty roots are trimmed along the way.
+    pub fn fix_border(&mut self, degree: usize, last: bool) {
+        let mut node = self;
+        loop {
+            // Only the root can be empty, since every other node on the border is topped up.
+            while node.is_empty() && !node.is_leaf() {
+                *node = node.children.pop().unwrap();
+            }
+            if node.is_leaf() {
+                return;
+            }
+
+            let idx = if last { node.children.len() - 1 } else { 0 };
+            let idx = node.stock_child(idx, degree);
+
+            // Merging the root's only two children empties it, so it's trimmed and the merged
+            // child is topped up as the new root's.
+            if !node.is_empty() {
+                node = &mut node.children[idx];
             }
         }
+    }
+
+    /// Tops up the child at `idx`, which is the first or last child, to at least t keys from
+    /// its sibling, or merges them if they fit in one node. Returns the index of the child.
+    pub fn stock_child(&mut self, idx: usize, degree: usize) -> usize {
+        if self.children[idx].len() >= degree {
+            return idx;
+        }
+
+        let sibling = if idx > 0 { idx - 1 } else { idx + 1 };
+        if self.children[idx].len() + self.children[sibling].len() < 2 * degree - 1 {
+            let idx = idx.min(sibling);
+            self.merge_children(idx);
+            return idx;
+        }
 
-        self.children[idx].remove(k, degree)
+        while self.children[idx].len() < degree {
+            if sibling < idx {
+                self.rotate_right(idx);
+            } else {
+                self.rotate_left(idx);
+            }
+        }
+        idx
+    }
+
+    /// Makes a node with a single entry between two subtrees of the same height.
+    pub fn join(lower: Self, sep: (K, V), upper: Self) -> Self {
+        let mut node = Self::new();
+        node.keys.push(sep.0);
+        node.vals.push(sep.1);
+        node.children.extend([lower, upper]);
+        node.recount();
+        node
+    }
+
+    /// Returns the number of levels in this node's subtree, counting the leaves.
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = self;
+        while !node.is_leaf() {
+            height += 1;
+            node = &node.children[0];
+        }
+        height
+    }
+
+    /// Attaches `right` as the last child of the node `depth` levels down the right edge of
+    /// this subtree, with `sep` between it and the child before it. `right` must be as tall as
+    /// the children it joins, and its keys must all come after `sep`, whose key must come after
+    /// every key in this subtree.
+    ///
+    /// Returns the upper half of this node and the entry separating it from the lower half if
+    /// it overflowed, for the caller to attach in turn.
+    pub fn push_back(
+        &mut self,
+        depth: usize,
+        sep: (K, V),
+        right: Self,
+        degree: usize,
+    ) -> Option<((K, V), Self)> {
+        let (sep, right) = if depth == 0 {
+            (sep, right)
+        } else {
+            let last = self.children.last_mut().unwrap();
+            let Some(split) = last.push_back(depth - 1, sep, right, degree) else {
+                self.recount();
+                return None;
+            };
+            split
+        };
+
+        self.keys.push(sep.0);
+        self.vals.push(sep.1);
+        self.children.push(right);
+
+        // The attached tree may have been a root with fewer than t - 1 keys.
+        let idx = self.children.len() - 1;
+        if self.children[idx].len() + 1 < degree {
+            self.stock_child(idx, degree);
+        }
+
+        self.split_overflow(degree)
+    }
+
+    /// Like `push_back`, but attaches `left` as the first child down the left edge, returning
+    /// the lower half of this node if it overflowed.
+    pub fn push_front(
+        &mut self,
+        depth: usize,
+        sep: (K, V),
+        left: Self,
+        degree: usize,
+    ) -> Option<(Self, (K, V))> {
+        let (left, sep) = if depth == 0 {
+            (left, sep)
+        } else {
+            let Some(split) = self.children[0].push_front(depth - 1, sep, left, degree) else {
+                self.recount();
+                return None;
+            };
+            split
+        };
+
+        self.keys.insert(0, sep.0);
+        self.vals.insert(0, sep.1);
+        self.children.insert(0, left);
+
+        if self.children[0].len() + 1 < degree {
+            self.stock_child(0, degree);
+        }
+
+        let (sep, mut upper) = self.split_overflow(degree)?;
+        mem::swap(self, &mut upper);
+        Some((upper, sep))
+    }
+
+    /// Splits this node in two if an attached child left it with more than 2t - 1 keys,
+    /// returning the upper half and the entry between the halves, and recounts it otherwise.
+    fn split_overflow(&mut self, degree: usize) -> Option<((K, V), Self)> {
+        if self.len() < 2 * degree {
+            self.recount();
+            return None;
+        }
+
+        let mut upper = Self::new();
+        upper.keys = self.keys.split_off(degree + 1);
+        upper.vals = self.vals.split_off(degree + 1);
+        if !self.is_leaf() {
+            upper.children = self.children.split_off(degree + 1);
+        }
+        let sep = (self.keys.pop().unwrap(), self.vals.pop().unwrap());
+
+        self.recount();
+        upper.recount();
+        Some((sep, upper))
+    }
+
+    /// Checks the invariants of the subtree rooted at this node, returning its entry count.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn check<C>(
+        &self,
+        degree: usize,
+        depth: usize,
+        lower: Option<&K>,
+        upper: Option<&K>,
+        leaf_depth: &mut Option<usize>,
+    ) -> Result<usize, String>
+    where
+        C: Comparator<K>,
+        K: Debug,
+    {
+        if self.keys.len() != self.vals.len() {
+            return Err(format!(
+                "node {:?} has {} keys but {} values",
+                self.keys,
+                self.keys.len(),
+                self.vals.len()
+            ));
+        }
+
+        if self.keys.windows(2).any(|w| C::cmp(&w[0], &w[1]).is_ge()) {
+            return Err(format!("node {:?} isn't sorted", self.keys));
+        }
+
+        if lower.is_some_and(|lower| self.keys.first().is_some_and(|k| C::cmp(k, lower).is_le()))
+            || upper.is_some_and(|upper| self.keys.last().is_some_and(|k| C::cmp(k, upper).is_ge()))
+        {
+            return Err(format!(
+                "node {:?} is outside its separators {lower:?} and {upper:?}",
+                self.keys
+            ));
+        }
+
+        if (depth > 0 && self.len() + 1 < degree) || (!self.is_leaf() && self.is_empty()) {
+            return Err(format!("node {:?} is underfull", self.keys));
+        }
+
+        if self.len() > 2 * degree - 1 {
+            return Err(format!("node {:?} is overfull", self.keys));
+        }
+
+        if self.is_leaf() {
+            return match *leaf_depth.get_or_insert(depth) {
+                expected if expected != depth => Err(format!(
+                    "leaf {:?} is at depth {depth} instead of {expected}",
+                    self.keys
+                )),
+                _ if self.size != self.len() => Err(format!(
+                    "leaf {:?} has size {} instead of {}",
+                    self.keys,
+                    self.size,
+                    self.len()
+                )),
+                _ => Ok(self.len()),
+            };
+        }
+
+        if self.children.len() != self.len() + 1 {
+            return Err(format!(
+                "node {:?} has {} children",
+                self.keys,
+                self.children.len()
+            ));
+        }
+
+        let mut count = self.len();
+        for (idx, child) in self.children.iter().enumerate() {
+            let lower = if idx == 0 {
+                lower
+            } else {
+                self.keys.get(idx - 1)
+            };
+            let upper = if idx < self.len() {
+                self.keys.get(idx)
+            } else {
+                upper
+            };
+
+            count += child.check::<C>(degree, depth + 1, lower, upper, leaf_depth)?;
+        }
+
+        if self.size != count {
+            return Err(format!(
+                "node {:?} has size {} instead of {count}",
+                self.keys, self.size
+            ));
+        }
+
+        Ok(count)
     }
 }
 
-impl<K, V> Debug for Node<K, V>
+impl<K, V> Node<K, V>
 where
     K: Debug,
     V: Debug,
 {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    /// Draws the subtree rooted at this node, one node per line. The alternate form, `{:#?}`,
+    /// shows the entries rather than just the keys, and how many of its `capacity` key slots
+    /// each node is using if it's given.
+    pub fn fmt_tree(&self, f: &mut Formatter<'_>, capacity: Option<usize>) -> fmt::Result {
         fn fmt_tree<K, V>(
             f: &mut Formatter,
             node: &Node<K, V>,
+            capacity: Option<usize>,
             prefix: String,
             last: bool,
             root: bool,
@@ -388,12 +1041,19 @@ where
                 )?;
             }
 
-            writeln!(f, "{:?}", node.keys)?;
-            // writeln!(
-            //     f,
-            //     "{:?}",
-            //     node.keys.iter().zip(node.vals.iter()).collect::<Vec<_>>()
-            // )?;
+            if f.alternate() {
+                write!(
+                    f,
+                    "{:?}",
+                    node.keys.iter().zip(node.vals.iter()).collect::<Vec<_>>()
+                )?;
+                if let Some(capacity) = capacity {
+                    write!(f, " ({}/{})", node.len(), capacity)?;
+                }
+                writeln!(f)?;
+            } else {
+                writeln!(f, "{:?}", node.keys)?;
+            }
 
             if !node.is_leaf() {
                 for (i, c) in node.children.iter().enumerate() {
@@ -405,13 +1065,24 @@ where
                         format!("{prefix}│    ")
                     };
 
-                    fmt_tree(f, c, next_prefix, i + 1 == node.children.len(), false)?;
+                    let last = i + 1 == node.children.len();
+                    fmt_tree(f, c, capacity, next_prefix, last, false)?;
                 }
             }
 
             Ok(())
         }
 
-        fmt_tree(f, self, String::new(), true, true)
+        fmt_tree(f, self, capacity, String::new(), true, true)
+    }
+}
+
+impl<K, V> Debug for Node<K, V>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_tree(f, None)
     }
 }
@@ -0,0 +1,112 @@
+use super::{iter::Iter, node::Node, BTreeMap};
+use rayon::iter::{
+    plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer},
+    IntoParallelIterator, ParallelIterator,
+};
+
+/// A parallel iterator over the entries of a `BTreeMap`, from `BTreeMap::par_iter`.
+///
+/// Work is split by subtrees: a subtree that's handed to a thread on its own is split into its
+/// children and the entries between them, so each thread walks whole subtrees in key order.
+pub struct ParIter<'a, K, V> {
+    root: &'a Node<K, V>,
+}
+
+impl<K, V, C> BTreeMap<K, V, C>
+where
+    K: Sync,
+    V: Sync,
+{
+    pub fn par_iter(&self) -> ParIter<'_, K, V> {
+        ParIter { root: &self.root }
+    }
+}
+
+impl<'a, K, V, C> IntoParallelIterator for &'a BTreeMap<K, V, C>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Iter = ParIter<'a, K, V>;
+    type Item = (&'a K, &'a V);
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+impl<'a, K, V> ParallelIterator for ParIter<'a, K, V>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<R>(self, consumer: R) -> R::Result
+    where
+        R: UnindexedConsumer<Self::Item>,
+    {
+        let producer = Producer {
+            pieces: vec![Piece::Subtree(self.root)],
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+/// Part of the work left to a producer, in key order.
+enum Piece<'a, K, V> {
+    Subtree(&'a Node<K, V>),
+    Entry(&'a K, &'a V),
+}
+
+struct Producer<'a, K, V> {
+    pieces: Vec<Piece<'a, K, V>>,
+}
+
+impl<'a, K, V> UnindexedProducer for Producer<'a, K, V>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Item = (&'a K, &'a V);
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        // A lone internal node is opened up into its children and the entries between them.
+        if let [Piece::Subtree(node)] = self.pieces[..] {
+            if node.is_leaf() {
+                return (self, None);
+            }
+
+            self.pieces.clear();
+            for (idx, child) in node.children.iter().enumerate() {
+                self.pieces.push(Piece::Subtree(child));
+                if let Some((k, v)) = node.keys.get(idx).zip(node.vals.get(idx)) {
+                    self.pieces.push(Piece::Entry(k, v));
+                }
+            }
+        }
+
+        if self.pieces.len() < 2 {
+            return (self, None);
+        }
+
+        let right = self.pieces.split_off(self.pieces.len() / 2);
+        (self, Some(Self { pieces: right }))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        for piece in self.pieces {
+            folder = match piece {
+                Piece::Subtree(node) => folder.consume_iter(Iter::new(node)),
+                Piece::Entry(k, v) => folder.consume((k, v)),
+            };
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
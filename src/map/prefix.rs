@@ -0,0 +1,78 @@
+use super::{iter::Iter, BTreeMap};
+use crate::comparator::Natural;
+use std::borrow::Borrow;
+
+/// Keys that can be scanned by prefix with `BTreeMap::prefix_range`.
+///
+/// Under their `Ord` implementation, every key starting with a prefix must sort at or after it
+/// and right next to each other, as strings and byte strings do.
+pub trait StartsWith {
+    fn starts_with(&self, prefix: &Self) -> bool;
+}
+
+impl StartsWith for str {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        str::starts_with(self, prefix)
+    }
+}
+
+impl StartsWith for String {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        str::starts_with(self, prefix)
+    }
+}
+
+impl StartsWith for [u8] {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        <[u8]>::starts_with(self, prefix)
+    }
+}
+
+impl StartsWith for Vec<u8> {
+    fn starts_with(&self, prefix: &Self) -> bool {
+        <[u8]>::starts_with(self, prefix)
+    }
+}
+
+/// Iterates over the entries of a map whose keys start with a prefix, in key order, from
+/// `BTreeMap::prefix_range`.
+pub struct Prefix<'a, 'p, K, V, Q: ?Sized> {
+    inner: Iter<'a, K, V>,
+    prefix: &'p Q,
+}
+
+impl<K, V> BTreeMap<K, V, Natural> {
+    /// Iterates over the entries whose keys start with `prefix`, in key order.
+    ///
+    /// The scan starts at `prefix` itself and stops at the first key that doesn't start with
+    /// it, so there's no upper bound to work out, which would need care for prefixes ending in
+    /// `char::MAX` or `0xff`.
+    pub fn prefix_range<'p, Q>(&self, prefix: &'p Q) -> Prefix<'_, 'p, K, V, Q>
+    where
+        K: Borrow<Q>,
+        Q: StartsWith + Ord + ?Sized,
+    {
+        Prefix {
+            inner: Iter::seek(&self.root, |k| k.borrow() < prefix),
+            prefix,
+        }
+    }
+}
+
+impl<'a, K, V, Q> Iterator for Prefix<'a, '_, K, V, Q>
+where
+    K: Borrow<Q>,
+    Q: StartsWith + ?Sized,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        if !k.borrow().starts_with(self.prefix) {
+            // Skip the rest, which all come after the keys with the prefix.
+            self.inner = Iter::new_empty();
+            return None;
+        }
+        Some((k, v))
+    }
+}
@@ -0,0 +1,104 @@
+use super::{node::Node, BTreeMap, DEFAULT_DEGREE};
+use crate::comparator::Comparator;
+use serde::{
+    de::{SeqAccess, Visitor},
+    ser::SerializeSeq,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    fmt::{self, Formatter},
+    marker::PhantomData,
+};
+
+/// Serializes the map as a sequence of key-value pairs, in key order.
+impl<K, V, C> Serialize for BTreeMap<K, V, C>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for entry in self.iter() {
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+/// Deserializes a sequence of key-value pairs into a map with the default degree.
+///
+/// Pairs in strictly increasing key order, as `Serialize` writes them, are built into a tree
+/// bottom-up. Otherwise they're inserted one at a time, with later pairs replacing earlier ones
+/// with the same key.
+impl<'de, K, V, C> Deserialize<'de> for BTreeMap<K, V, C>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    C: Comparator<K>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(PairsVisitor(PhantomData))
+    }
+}
+
+/// The most entries to reserve room for up front, whatever length the input claims.
+const MAX_PREALLOC: usize = 4096;
+
+/// Marks the type of map a `PairsVisitor` builds, without owning one.
+type Builds<K, V, C> = PhantomData<fn() -> BTreeMap<K, V, C>>;
+
+struct PairsVisitor<K, V, C>(Builds<K, V, C>);
+
+impl<'de, K, V, C> Visitor<'de> for PairsVisitor<K, V, C>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    C: Comparator<K>,
+{
+    type Value = BTreeMap<K, V, C>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "a sequence of key-value pairs")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // The length comes from the input, so it's only trusted up to a point.
+        let hint = seq.size_hint().unwrap_or(0).min(MAX_PREALLOC);
+        let mut keys: Vec<K> = Vec::with_capacity(hint);
+        let mut vals = Vec::with_capacity(hint);
+
+        while let Some((k, v)) = seq.next_element()? {
+            if keys.last().is_some_and(|last| C::cmp(last, &k).is_ge()) {
+                // Out of order, so give up on building and insert the rest.
+                let mut map = Self::Value::default();
+                for (k, v) in keys.into_iter().zip(vals) {
+                    map.insert(k, v);
+                }
+                map.insert(k, v);
+                while let Some((k, v)) = seq.next_element()? {
+                    map.insert(k, v);
+                }
+                return Ok(map);
+            }
+
+            keys.push(k);
+            vals.push(v);
+        }
+
+        Ok(BTreeMap {
+            len: keys.len(),
+            degree: DEFAULT_DEGREE,
+            root: Node::build(keys, vals, DEFAULT_DEGREE),
+            order: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use super::{node::Node, BTreeMap};
+use std::mem;
+
+/// A summary of the shape of a `BTreeMap`, from `BTreeMap::stats`, for tuning its degree.
+///
+/// `Occupancy` breaks the fill down further, by level and by number of keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapStats {
+    pub degree: usize,
+    pub len: usize,
+    pub height: usize,
+    pub nodes: usize,
+
+    /// The mean fraction of key slots in use across every node, from 0 to 1.
+    pub fill_factor: f64,
+
+    /// The number of keys at each level, from the root down to the leaves.
+    pub keys_per_level: Vec<usize>,
+
+    /// The estimate of the bytes allocated for the nodes from `BTreeMap::memory_usage`.
+    pub bytes: usize,
+}
+
+impl<K, V, C> BTreeMap<K, V, C> {
+    pub fn stats(&self) -> MapStats {
+        let occupancy = self.occupancy();
+
+        MapStats {
+            degree: self.degree,
+            len: self.len,
+            height: occupancy.levels.len(),
+            nodes: occupancy.nodes(),
+            fill_factor: occupancy.fill_factor(),
+            keys_per_level: occupancy.levels.iter().map(|level| level.keys).collect(),
+            bytes: self.memory_usage(),
+        }
+    }
+
+    /// Estimates the bytes the map has allocated on the heap for its nodes.
+    ///
+    /// Each node's keys, values, and children are counted by the capacities of their vectors,
+    /// so space reserved for entries yet to come is included, and a node's own header is counted
+    /// in its parent's children. Memory the keys and values own themselves, like a `String`'s
+    /// buffer, isn't counted.
+    pub fn memory_usage(&self) -> usize {
+        heap_bytes(&self.root)
+    }
+}
+
+fn heap_bytes<K, V>(node: &Node<K, V>) -> usize {
+    node.keys.capacity() * mem::size_of::<K>()
+        + node.vals.capacity() * mem::size_of::<V>()
+        + node.children.capacity() * mem::size_of::<Node<K, V>>()
+        + node.children.iter().map(heap_bytes).sum::<usize>()
+}
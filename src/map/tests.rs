@@ -1,4 +1,8 @@
-use super::{BTreeMap, Node};
+use super::{BTreeMap, EntryRef, InsertHint, LruMap, Node, UndoMap, UnorderedKeyError};
+#[cfg(feature = "heapless")]
+use super::{CapacityError, FixedMap};
+use crate::comparator::CaseInsensitive;
+use std::ops::{Bound, RangeBounds};
 
 #[test]
 fn iter() {
@@ -427,3 +431,1237 @@ fn clrs_example_18_8_extended() {
 "#
     );
 }
+
+#[test]
+fn overwrite() {
+    let mut m = BTreeMap::new();
+
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+
+    // Every key, including those stored in internal nodes, should be overwritten in place.
+    for i in 0..100 {
+        assert_eq!(m.insert(i, i + 1), Some(i));
+        assert_eq!(m.check(), Ok(()));
+    }
+
+    assert_eq!(m.len(), 100);
+
+    for i in 0..100 {
+        assert_eq!(m.get(&i), Some(&(i + 1)));
+    }
+}
+
+#[test]
+fn undo_redo() {
+    let mut m = UndoMap::new(10);
+
+    m.insert(1, 'a');
+    m.insert(2, 'b');
+    m.insert(1, 'c');
+    m.remove(&2);
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&1, &'c')]);
+
+    assert!(m.undo());
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&1, &'c'), (&2, &'b')]);
+
+    assert!(m.undo());
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&1, &'a'), (&2, &'b')]);
+
+    assert!(m.redo());
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&1, &'c'), (&2, &'b')]);
+
+    assert!(m.undo());
+    assert!(m.undo());
+    assert!(m.undo());
+    assert!(!m.undo());
+    assert!(m.is_empty());
+
+    // A new change discards anything that could be redone.
+    m.insert(3, 'd');
+    assert!(!m.can_redo());
+    assert!(!m.redo());
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&3, &'d')]);
+}
+
+#[test]
+fn undo_bounded() {
+    let mut m = UndoMap::new(3);
+
+    for i in 0..5 {
+        m.insert(i, i);
+    }
+
+    // Only the last three inserts can be undone.
+    while m.undo() {}
+    assert_eq!(m.iter().collect::<Vec<_>>(), [(&0, &0), (&1, &1)]);
+
+    while m.redo() {}
+    assert_eq!(m.len(), 5);
+}
+
+#[test]
+fn remove_internal() {
+    let mut m = BTreeMap::new();
+
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+
+    // Removing every third key takes keys out of internal nodes while their neighbors are
+    // being rebalanced.
+    for i in (0..100).step_by(3) {
+        assert_eq!(m.remove(&i), Some(i));
+        assert_eq!(m.check(), Ok(()));
+    }
+
+    assert!(m.keys().copied().eq((0..100).filter(|i| i % 3 != 0)));
+}
+
+#[cfg(feature = "heapless")]
+#[test]
+fn fixed_capacity() {
+    let mut m = FixedMap::<u32, u32, 4, 8>::new();
+    let mut full = None;
+
+    for i in 0..100 {
+        match m.insert(i, i) {
+            Ok(res) => assert_eq!(res, None),
+            Err(err) => {
+                full = Some(i);
+                assert_eq!(err, CapacityError { key: i, value: i });
+                break;
+            }
+        }
+        assert_eq!(m.check(), Ok(()));
+    }
+
+    // A failed insert hands the entry back and leaves the map as it was.
+    let full = full.expect("pool never filled up");
+    assert_eq!(m.len(), full as usize);
+    assert_eq!(m.check(), Ok(()));
+    assert!(m.iter().map(|(k, _)| *k).eq(0..full));
+
+    // Merges return nodes to the pool for later inserts.
+    for i in 0..full {
+        assert!(m.remove(&i).is_some());
+        assert_eq!(m.check(), Ok(()));
+    }
+    assert!(m.is_empty());
+    assert_eq!(m.free_nodes(), 7);
+
+    for i in 0..full {
+        assert_eq!(m.insert(i, i), Ok(None));
+    }
+}
+
+#[test]
+fn lru() {
+    let mut m = LruMap::new(3);
+
+    m.insert(3, 'c');
+    m.insert(1, 'a');
+    m.insert(2, 'b');
+
+    // Reading 3 makes 1 the least recently used, but peeking doesn't count.
+    assert_eq!(m.get(&3), Some(&'c'));
+    assert_eq!(m.peek(&1), Some(&'a'));
+    m.insert(4, 'd');
+    assert_eq!(
+        m.iter().collect::<Vec<_>>(),
+        [(&2, &'b'), (&3, &'c'), (&4, &'d')]
+    );
+
+    // Replacing a value doesn't evict anything.
+    assert_eq!(m.insert(2, 'e'), Some('b'));
+    assert_eq!(m.len(), 3);
+
+    assert_eq!(m.pop_lru(), Some((3, 'c')));
+    assert_eq!(m.remove(&4), Some('d'));
+    assert_eq!(m.pop_lru(), Some((2, 'e')));
+    assert_eq!(m.pop_lru(), None);
+    assert!(m.is_empty());
+}
+
+#[test]
+fn case_insensitive() {
+    let mut m = BTreeMap::<_, _, CaseInsensitive>::with_comparator(2);
+
+    for (i, k) in ["b", "A", "c", "D", "e", "F"].into_iter().enumerate() {
+        m.insert(k, i);
+    }
+    assert_eq!(m.insert("B", 6), Some(0));
+    assert_eq!(m.get(&"d"), Some(&3));
+    assert!(m.check().is_ok());
+
+    assert_eq!(
+        m.keys().copied().collect::<Vec<_>>(),
+        ["A", "b", "c", "D", "e", "F"]
+    );
+    assert_eq!(m.remove(&"a"), Some(1));
+}
+
+#[cfg(feature = "icu")]
+#[test]
+fn unicode_collation() {
+    use crate::comparator::UnicodeCollation;
+
+    let mut m = BTreeMap::<_, _, UnicodeCollation>::with_comparator(2);
+    for k in ["zebra", "Zebra", "éclair", "eclair", "apple"] {
+        m.insert(k, ());
+    }
+
+    // Accents and case only break ties between otherwise equal strings.
+    assert_eq!(
+        m.keys().copied().collect::<Vec<_>>(),
+        ["apple", "eclair", "éclair", "zebra", "Zebra"]
+    );
+}
+
+#[test]
+fn entry_ref() {
+    let mut m = BTreeMap::<String, usize>::new();
+
+    for word in "the cat and the hat and the bat".split(' ') {
+        *m.entry_ref(word).or_insert(0) += 1;
+    }
+    assert_eq!(m.len(), 5);
+    assert_eq!(m.get(&"the".to_string()), Some(&3));
+    assert_eq!(m.get(&"and".to_string()), Some(&2));
+    assert!(m.check().is_ok());
+
+    match m.entry_ref("cat") {
+        EntryRef::Occupied(mut entry) => assert_eq!(entry.insert(10), 1),
+        EntryRef::Vacant(_) => panic!("cat should be present"),
+    }
+    match m.entry_ref("dog") {
+        EntryRef::Occupied(_) => panic!("dog shouldn't be present"),
+        EntryRef::Vacant(entry) => assert_eq!(entry.key(), "dog"),
+    }
+
+    m.entry_ref("hat").and_modify(|v| *v += 1).or_insert(0);
+    assert_eq!(m.get(&"hat".to_string()), Some(&2));
+    assert_eq!(m.get(&"cat".to_string()), Some(&10));
+}
+
+#[test]
+fn iter_chunks() {
+    let mut m = BTreeMap::new();
+
+    for i in 0..103 {
+        m.insert(i, i * 2);
+    }
+
+    let chunks = m.iter_chunks(10).collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 11);
+    assert!(chunks[..10].iter().all(|chunk| chunk.len() == 10));
+    assert_eq!(chunks[10].len(), 3);
+    assert!(chunks.into_iter().flatten().eq(m.iter()));
+
+    assert_eq!(BTreeMap::<u32, u32>::new().iter_chunks(4).count(), 0);
+}
+
+#[test]
+fn iter_deep() {
+    let mut m = BTreeMap::with_degree(2);
+
+    for i in (0..10_000).rev() {
+        m.insert(i, ());
+    }
+
+    assert!(m.keys().copied().eq(0..10_000));
+    assert!(m.check().is_ok());
+
+    // The stacks are sized for realistic heights, not one frame per bit of `usize`.
+    assert!(std::mem::size_of::<super::Iter<u64, u64>>() < 6 * 1024);
+}
+
+#[test]
+fn try_insert_alloc() {
+    let mut m = BTreeMap::with_degree(3);
+    let mut n = BTreeMap::with_degree(3);
+    let shuffled = (0..1000u32).map(|i| i * 7919 % 1000);
+
+    for i in shuffled.clone() {
+        assert_eq!(m.try_insert_alloc(i, i), Ok(None));
+        n.insert(i, i);
+    }
+    for i in shuffled {
+        assert_eq!(m.try_insert_alloc(i, i + 1), Ok(Some(i)));
+    }
+
+    assert_eq!(m.len(), 1000);
+    assert!(m.check().is_ok());
+    assert!(m.keys().eq(n.keys()));
+    assert!(m.values().copied().eq(1..1001));
+}
+
+#[test]
+fn view() {
+    let mut m = BTreeMap::with_degree(3);
+
+    for i in 0..1000 {
+        m.insert(i, i * 2);
+    }
+    for i in (0..1000).step_by(3) {
+        m.remove(&i);
+    }
+    assert!(m.check().is_ok());
+
+    let view = m.view(100..200);
+    assert_eq!(view.len(), view.iter().count());
+    assert_eq!(view.len(), (100..200).filter(|i| i % 3 != 0).count());
+    assert!(view
+        .iter()
+        .map(|(k, _)| *k)
+        .eq((100..200).filter(|i| i % 3 != 0)));
+    assert_eq!(view.get(&101), Some(&202));
+    assert_eq!(view.get(&102), None);
+    assert_eq!(view.get(&200), None);
+    assert!(view.contains(&199));
+    assert!(!view.contains(&50));
+
+    assert_eq!(m.view(..).len(), m.len());
+    assert_eq!(m.view(..=1).len(), 1);
+    assert_eq!(m.view(998..).iter().count(), 1);
+    assert!(m.view(1000..).is_empty());
+    assert_eq!(
+        m.view((Bound::Excluded(100), Bound::Included(103))).len(),
+        2
+    );
+}
+
+#[test]
+fn freeze() {
+    let mut m = BTreeMap::with_degree(3);
+
+    for i in (0..1000).rev() {
+        m.insert(i, i * 2);
+    }
+
+    let frozen = m.freeze();
+    assert_eq!(frozen.len(), 1000);
+    assert!(frozen.keys().iter().copied().eq(0..1000));
+    assert!(frozen.iter().all(|(k, v)| *v == k * 2));
+    assert_eq!(frozen.get(&421), Some(&842));
+    assert_eq!(frozen.get_key_value(&0), Some((&0, &0)));
+    assert_eq!(frozen.get(&1000), None);
+    assert!(frozen.contains(&999));
+
+    let mut m = frozen.thaw();
+    assert!(m.check().is_ok());
+    assert_eq!(m.len(), 1000);
+    assert!(m.stats().fill_factor > 0.9);
+    assert_eq!(m.insert(1000, 2000), None);
+    assert_eq!(m.remove(&0), Some(0));
+    assert!(m.keys().copied().eq(1..1001));
+
+    let frozen = BTreeMap::<u32, u32>::new().freeze();
+    assert!(frozen.is_empty());
+    assert!(frozen.thaw().is_empty());
+
+    let mut m = BTreeMap::<_, _, CaseInsensitive>::with_comparator(4);
+    m.insert("B".to_string(), 2);
+    m.insert("a".to_string(), 1);
+    let frozen = m.freeze();
+    assert_eq!(frozen.get(&"A".to_string()), Some(&1));
+    assert_eq!(frozen.values(), [1, 2]);
+}
+
+#[test]
+fn range() {
+    let mut m = BTreeMap::with_degree(3);
+
+    for i in (0..1000).step_by(2) {
+        m.insert(i, i);
+    }
+
+    assert!(m.range(3..=10).map(|(k, _)| *k).eq([4, 6, 8, 10]));
+    assert!(m.range(3..10).map(|(k, _)| *k).eq([4, 6, 8]));
+    assert!(m
+        .range((Bound::Excluded(4), Bound::Excluded(10)))
+        .map(|(k, _)| *k)
+        .eq([6, 8]));
+    assert!(m
+        .range(990..)
+        .map(|(k, _)| *k)
+        .eq([990, 992, 994, 996, 998]));
+    assert_eq!(m.range(..).count(), 500);
+    assert_eq!(m.range(..0).count(), 0);
+    assert_eq!(m.range(999..).count(), 0);
+
+    for (k, v) in m.range_mut(100..200) {
+        *v = k + 1;
+    }
+    assert_eq!(m.range_mut(..).count(), 500);
+    assert!(m.range_mut(200..=200).map(|(k, _)| *k).eq([200]));
+    assert!(m
+        .iter()
+        .all(|(k, v)| *v == if (100..200).contains(k) { k + 1 } else { *k }));
+
+    let mut m = BTreeMap::<_, _, CaseInsensitive>::with_comparator(2);
+    for k in ["a", "B", "c", "D"] {
+        m.insert(k.to_string(), ());
+    }
+    assert!(m
+        .range("b".to_string()..="C".to_string())
+        .map(|(k, _)| k.as_str())
+        .eq(["B", "c"]));
+}
+
+#[test]
+fn iter_mut() {
+    let mut m = BTreeMap::with_degree(3);
+
+    for i in (0..1000).rev() {
+        m.insert(i, i);
+    }
+
+    let mut expected = 0;
+    for (k, v) in m.iter_mut() {
+        assert_eq!(*k, expected);
+        *v *= 10;
+        expected += 1;
+    }
+    assert_eq!(expected, 1000);
+    assert!(m.iter().all(|(k, v)| *v == k * 10));
+    assert!(m.check().is_ok());
+
+    for v in m.values_mut() {
+        *v += 1;
+    }
+    assert!(m.values().copied().eq((0..1000).map(|i| i * 10 + 1)));
+
+    assert_eq!(BTreeMap::<u32, u32>::new().iter_mut().next(), None);
+}
+
+#[test]
+fn retain() {
+    for degree in [2, 3, 5] {
+        for n in [0, 1, 10, 100, 1000] {
+            let mut m = BTreeMap::with_degree(degree);
+            for i in 0..n {
+                m.insert(i, i);
+            }
+
+            let mut visited = vec![];
+            m.retain(|k, v| {
+                visited.push(*k);
+                *v += 1;
+                k % 3 == 0
+            });
+            assert!(visited.into_iter().eq(0..n));
+            assert!(m.check().is_ok());
+            assert_eq!(m.len(), (0..n).step_by(3).count());
+            assert!(m
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .eq((0..n).step_by(3).map(|i| (i, i + 1))));
+            assert_eq!(m.view(..n / 2).len(), (0..n / 2).step_by(3).count());
+
+            // The rebuilt tree still takes inserts and removals.
+            m.insert(n, n);
+            assert_eq!(m.remove(&0), Some(if n > 0 { 1 } else { 0 }));
+            assert!(m.check().is_ok());
+        }
+    }
+
+    let mut m = BTreeMap::new();
+    m.insert(1, 1);
+    m.retain(|_, _| false);
+    assert!(m.is_empty());
+    assert_eq!(m.iter().next(), None);
+}
+
+#[test]
+fn extract_if() {
+    let mut m = BTreeMap::with_degree(3);
+    for i in 0..1000 {
+        m.insert(i, i);
+    }
+
+    let mut evens = BTreeMap::new();
+    for (k, v) in m.extract_if(|k, v| {
+        *v += 1;
+        k % 2 == 0
+    }) {
+        evens.insert(k, v);
+    }
+    assert_eq!(m.len(), 500);
+    assert_eq!(evens.len(), 500);
+    assert!(m.check().is_ok());
+    assert!(m.keys().copied().eq((1..1000).step_by(2)));
+    assert!(evens.iter().all(|(k, v)| *v == k + 1));
+
+    // Entries that weren't visited are kept.
+    let first = m.extract_if(|k, _| k % 3 == 0).take(2).collect::<Vec<_>>();
+    assert_eq!(first, [(3, 4), (9, 10)]);
+    assert_eq!(m.len(), 498);
+    assert!(m.check().is_ok());
+    assert!(m.contains(&15));
+    assert!(!m.contains(&9));
+    assert_eq!(m.extract_if(|_, _| false).next(), None);
+    assert_eq!(m.len(), 498);
+
+    // A leaked iterator leaves the map valid, with only the entries it yielded removed. The
+    // iterator has no `Drop` to skip, which is what makes this hold.
+    let mut iter = m.extract_if(|k, _| *k < 100);
+    assert_eq!(iter.next(), Some((1, 2)));
+    #[allow(clippy::forget_non_drop)]
+    std::mem::forget(iter);
+    assert_eq!(m.len(), 497);
+    assert_eq!(m.iter().count(), 497);
+    assert!(m.check().is_ok());
+}
+
+#[test]
+fn drain() {
+    let mut m = BTreeMap::with_degree(3);
+    for i in (0..1000).rev() {
+        m.insert(i, i.to_string());
+    }
+
+    assert!(m.drain().eq((0..1000).map(|i| (i, i.to_string()))));
+    assert!(m.is_empty());
+    assert_eq!(m.iter().next(), None);
+
+    // The map is emptied even if the iterator isn't used up.
+    m.insert(1, "1".to_string());
+    m.insert(2, "2".to_string());
+    assert_eq!(m.drain().next(), Some((1, "1".to_string())));
+    assert!(m.is_empty());
+    assert!(m.check().is_ok());
+    assert_eq!(m.drain().next(), None);
+
+    m.insert(3, "3".to_string());
+    assert_eq!(m.get(&3).map(String::as_str), Some("3"));
+}
+
+#[test]
+fn split_off() {
+    for degree in [2, 3, 6] {
+        for n in [0usize, 1, 2, 10, 100, 1000] {
+            for at in [0, 1, n / 3, n / 2, n.saturating_sub(1), n, n + 1] {
+                let mut m = BTreeMap::with_degree(degree);
+                for i in 0..n {
+                    m.insert(i * 2, i);
+                }
+
+                let right = m.split_off(&(at * 2));
+                assert!(m.check().is_ok(), "{:?}", m.check());
+                assert!(right.check().is_ok(), "{:?}", right.check());
+                assert!(m.keys().copied().eq((0..at.min(n)).map(|i| i * 2)));
+                assert!(right.keys().copied().eq((at.min(n)..n).map(|i| i * 2)));
+                assert_eq!(m.len() + right.len(), n);
+
+                // Keys between entries split at the next entry.
+                let mut m = right;
+                let rest = m.split_off(&(at * 2 + 1));
+                assert!(m.check().is_ok() && rest.check().is_ok());
+                assert_eq!(m.len(), usize::from(at < n));
+            }
+        }
+    }
+}
+
+#[test]
+fn pop_first_last() {
+    let mut m = BTreeMap::with_degree(2);
+    assert_eq!(m.first_key_value(), None);
+    assert_eq!(m.last_key_value(), None);
+    assert_eq!(m.pop_first(), None);
+    assert_eq!(m.pop_last(), None);
+
+    for i in 0..200 {
+        m.insert(i, i * 10);
+    }
+    assert_eq!(m.first_key_value(), Some((&0, &0)));
+    assert_eq!(m.last_key_value(), Some((&199, &1990)));
+
+    for i in 0..100 {
+        assert_eq!(m.pop_first(), Some((i, i * 10)));
+        assert_eq!(m.pop_last(), Some((199 - i, (199 - i) * 10)));
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert_eq!(m.len(), 198 - 2 * i);
+    }
+    assert!(m.is_empty());
+    assert_eq!(m.pop_first(), None);
+}
+
+#[test]
+fn first_last_entry() {
+    let mut m = BTreeMap::<i32, i32>::with_degree(2);
+    assert!(m.first_entry().is_none());
+    assert!(m.last_entry().is_none());
+
+    for i in 0..50 {
+        m.insert(i, i);
+    }
+
+    let mut entry = m.first_entry().unwrap();
+    assert_eq!((entry.key(), entry.get()), (&0, &0));
+    *entry.get_mut() += 100;
+    assert_eq!(entry.insert(7), 100);
+    assert_eq!(m.get(&0), Some(&7));
+
+    let entry = m.last_entry().unwrap();
+    assert_eq!(entry.key(), &49);
+    *entry.into_mut() = -1;
+    assert_eq!(m.get(&49), Some(&-1));
+
+    // Drain from both ends, deciding by the values.
+    while let Some(entry) = m.last_entry() {
+        if *entry.get() < 0 {
+            assert_eq!(entry.remove_entry(), (49, -1));
+        } else if *entry.key() > 25 {
+            entry.remove();
+        } else {
+            break;
+        }
+    }
+    assert_eq!(m.first_entry().unwrap().remove(), 7);
+    assert!(m.check().is_ok());
+    assert!(m.keys().copied().eq(1..=25));
+}
+
+#[test]
+fn cursor_mut() {
+    let mut m = BTreeMap::with_degree(2);
+    for i in 0..50 {
+        m.insert(i * 2, i);
+    }
+
+    // Positioned at the first key at least the one asked for.
+    let mut cursor = m.cursor_mut(&21);
+    assert_eq!(cursor.key_value(), Some((&22, &11)));
+    assert_eq!(cursor.peek_prev(), Some((&20, &10)));
+    assert_eq!(cursor.peek_next(), Some((&24, &12)));
+    cursor.move_prev();
+    *cursor.value_mut().unwrap() = 100;
+    cursor.move_next();
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&24));
+
+    assert_eq!(cursor.insert_after(25, 0), Ok(()));
+    assert_eq!(cursor.insert_before(23, 0), Ok(()));
+    assert_eq!(cursor.key(), Some(&24));
+    assert_eq!(
+        cursor.insert_after(27, 1),
+        Err(UnorderedKeyError { key: 27, value: 1 })
+    );
+    assert!(cursor.insert_before(24, 1).is_err());
+
+    // Removing moves to the next entry.
+    assert_eq!(cursor.remove_current(), Some((24, 12)));
+    assert_eq!(cursor.key(), Some(&25));
+    for _ in 0..10 {
+        cursor.remove_current();
+    }
+    assert_eq!(cursor.key(), Some(&44));
+    assert!(m.check().is_ok(), "{:?}", m.check());
+    assert_eq!(m.get(&20), Some(&100));
+    assert_eq!(m.len(), 41);
+
+    // The ghost position wraps around both ends.
+    let mut cursor = m.cursor_mut(&1000);
+    assert_eq!(cursor.key(), None);
+    assert_eq!(cursor.remove_current(), None);
+    assert_eq!(cursor.peek_next(), Some((&0, &0)));
+    assert_eq!(cursor.peek_prev(), Some((&98, &49)));
+    assert!(cursor.insert_before(99, 0).is_ok());
+    assert!(cursor.insert_after(-1, 0).is_ok());
+    assert_eq!(cursor.key(), None);
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&-1));
+    cursor.move_prev();
+    cursor.move_prev();
+    assert_eq!(cursor.key(), Some(&99));
+
+    // Removing everything through a cursor keeps the tree valid.
+    let mut cursor = m.cursor_mut(&0);
+    cursor.move_prev();
+    while cursor.remove_current().is_some() {}
+    assert_eq!(m.len(), 0);
+    assert!(m.check().is_ok());
+}
+
+#[test]
+fn bounds() {
+    let mut m = BTreeMap::with_degree(2);
+    assert_eq!(m.lower_bound(Bound::Unbounded), None);
+    assert_eq!(m.upper_bound(Bound::Unbounded), None);
+
+    for i in 0..100 {
+        m.insert(i * 10, i);
+    }
+
+    assert_eq!(m.lower_bound(Bound::Included(&250)), Some((&250, &25)));
+    assert_eq!(m.lower_bound(Bound::Excluded(&250)), Some((&260, &26)));
+    assert_eq!(m.lower_bound(Bound::Included(&251)), Some((&260, &26)));
+    assert_eq!(m.lower_bound(Bound::Included(&991)), None);
+    assert_eq!(m.lower_bound(Bound::Unbounded), Some((&0, &0)));
+
+    assert_eq!(m.upper_bound(Bound::Included(&250)), Some((&250, &25)));
+    assert_eq!(m.upper_bound(Bound::Excluded(&250)), Some((&240, &24)));
+    assert_eq!(m.upper_bound(Bound::Included(&259)), Some((&250, &25)));
+    assert_eq!(m.upper_bound(Bound::Excluded(&0)), None);
+    assert_eq!(m.upper_bound(Bound::Unbounded), Some((&990, &99)));
+
+    let mut cursor = m.upper_bound_mut(Bound::Included(&259));
+    assert_eq!(cursor.key(), Some(&250));
+    cursor.move_next();
+    assert_eq!(cursor.key(), Some(&260));
+    assert_eq!(m.upper_bound_mut(Bound::Excluded(&0)).key(), None);
+    assert_eq!(m.lower_bound_mut(Bound::Excluded(&980)).key(), Some(&990));
+}
+
+#[test]
+fn default() {
+    #[derive(Default)]
+    struct Index {
+        words: BTreeMap<String, usize>,
+        names: BTreeMap<String, usize, CaseInsensitive>,
+    }
+
+    let mut index = Index::default();
+    assert!(index.words.is_empty());
+    index.names.insert("Ada".to_string(), 1);
+    assert_eq!(index.names.get(&"ADA".to_string()), Some(&1));
+
+    let mut slot: Option<BTreeMap<i32, i32>> = None;
+    slot.get_or_insert_with(Default::default).insert(1, 2);
+    assert_eq!(slot.unwrap().len(), 1);
+}
+
+#[cfg(all(feature = "serde", feature = "persistent"))]
+#[test]
+fn serde() {
+    let mut m = BTreeMap::new();
+    for i in 0..500 {
+        m.insert(i, i.to_string());
+    }
+
+    let bytes = bincode::serialize(&m).unwrap();
+    let pairs: Vec<(i32, String)> = bincode::deserialize(&bytes).unwrap();
+    assert!(pairs.iter().map(|(k, v)| (k, v)).eq(m.iter()));
+
+    let de: BTreeMap<i32, String> = bincode::deserialize(&bytes).unwrap();
+    assert!(de.check().is_ok(), "{:?}", de.check());
+    assert!(de.iter().eq(m.iter()));
+
+    // Unsorted pairs with duplicates are inserted, with later ones winning.
+    let bytes = bincode::serialize(&vec![(3, 'c'), (1, 'a'), (3, 'd'), (2, 'b')]).unwrap();
+    let de: BTreeMap<i32, char> = bincode::deserialize(&bytes).unwrap();
+    assert!(de.check().is_ok());
+    assert!(de.iter().eq([(&1, &'a'), (&2, &'b'), (&3, &'d')]));
+
+    // A length prefix claiming far more pairs than there are fails on the missing pairs rather
+    // than on reserving room for them.
+    let mut bytes = (1u64 << 40).to_le_bytes().to_vec();
+    bytes.extend(bincode::serialize(&(1, 'a')).unwrap());
+    assert!(bincode::deserialize::<BTreeMap<i32, char>>(&bytes).is_err());
+}
+
+#[test]
+fn borrowed_lookups() {
+    let mut m = BTreeMap::<String, usize>::new();
+    for (i, word) in ["apple", "banana", "cherry"].into_iter().enumerate() {
+        m.insert(word.to_string(), i);
+    }
+
+    assert!(m.contains("banana"));
+    assert_eq!(m.get("cherry"), Some(&2));
+    assert_eq!(m.get_key_value("apple"), Some((&"apple".to_string(), &0)));
+    *m.get_mut("apple").unwrap() += 10;
+    assert_eq!(m.remove("apple"), Some(10));
+    assert_eq!(m.remove_entry("banana"), Some(("banana".to_string(), 1)));
+    assert_eq!(m.remove("durian"), None);
+    assert_eq!(m.len(), 1);
+
+    let mut m = BTreeMap::<String, usize, CaseInsensitive>::with_comparator(2);
+    m.insert("Apple".to_string(), 1);
+    assert_eq!(m.get("APPLE"), Some(&1));
+
+    let frozen = m.freeze();
+    assert!(frozen.contains("apple"));
+    assert_eq!(frozen.get("aPPle"), Some(&1));
+}
+
+#[test]
+fn insert_hint() {
+    for degree in [2, 3, 8] {
+        let mut m = BTreeMap::with_degree(degree);
+        let mut hint = InsertHint::new();
+
+        // Nearly sorted keys, with each pair swapped.
+        for i in 0..2000 {
+            assert_eq!(m.insert_hint(&mut hint, i ^ 1, i), None);
+        }
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert!(m
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .eq((0..2000).map(|k| (k, k ^ 1))));
+
+        assert_eq!(m.insert_hint(&mut hint, 1000, 1000), Some(1001));
+        assert_eq!(m.insert_hint(&mut hint, 1001, 1001), Some(1000));
+        assert_eq!(m.len(), 2000);
+
+        // A stale hint is checked rather than trusted.
+        for i in (0..2000).step_by(2) {
+            m.remove(&i);
+        }
+        for i in (0..2000).step_by(2).rev() {
+            m.insert_hint(&mut hint, i, i);
+        }
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert_eq!(m.len(), 2000);
+        assert!(m.iter().all(|(k, v)| k % 2 != 0 || k == v));
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    // Odd bytes keep `arbitrary_iter` going until the input runs out.
+    let bytes = (0..4096u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8 | 1)
+        .collect::<Vec<_>>();
+    for start in (0..bytes.len()).step_by(512) {
+        let mut u = Unstructured::new(&bytes[start..]);
+        let m = BTreeMap::<u8, u16>::arbitrary(&mut u).unwrap();
+        assert!(m.check().is_ok(), "{:?}", m.check());
+
+        let m =
+            BTreeMap::<u16, u8>::arbitrary_take_rest(Unstructured::new(&bytes[start..])).unwrap();
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert!(m.len() > 50);
+    }
+}
+
+#[test]
+fn rank_select() {
+    let mut m = BTreeMap::with_degree(3);
+    assert_eq!(m.rank(&5), 0);
+    assert_eq!(m.select(0), None);
+
+    for i in 0..300 {
+        m.insert(i * 3, i);
+    }
+    for i in (0..300).step_by(7) {
+        m.remove(&(i * 3));
+    }
+
+    let keys = m.keys().copied().collect::<Vec<_>>();
+    for (n, k) in keys.iter().enumerate() {
+        assert_eq!(m.rank(k), n);
+        assert_eq!(m.rank(&(k + 1)), n + 1);
+        assert_eq!(m.select(n), Some((k, &(k / 3))));
+    }
+    assert_eq!(m.rank(&-1), 0);
+    assert_eq!(m.rank(&10_000), m.len());
+    assert_eq!(m.select(m.len()), None);
+
+    // The median, without walking the entries.
+    assert_eq!(
+        m.select(m.len() / 2).map(|(k, _)| *k),
+        Some(keys[keys.len() / 2])
+    );
+}
+
+#[test]
+fn index() {
+    let mut m = BTreeMap::with_degree(2);
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+
+    // Pages of 10, addressed by position.
+    let page = (30..40)
+        .map(|idx| m.get_index(idx).map(|(k, _)| *k))
+        .collect::<Option<Vec<_>>>();
+    assert_eq!(page, Some((30..40).collect()));
+    assert_eq!(m.get_index(100), None);
+
+    *m.get_index_mut(5).unwrap().1 = 500;
+    assert_eq!(m.get(&5), Some(&500));
+    assert!(m.get_index_mut(100).is_none());
+
+    assert_eq!(m.remove_index(5), Some((5, 500)));
+    assert_eq!(m.get_index(5), Some((&6, &6)));
+    while m.len() > 1 {
+        let idx = m.len() / 2;
+        let (k, _) = m.remove_index(idx).unwrap();
+        assert_eq!(m.rank(&k), idx);
+        assert!(m.check().is_ok(), "{:?}", m.check());
+    }
+    assert_eq!(m.remove_index(1), None);
+    assert_eq!(m.remove_index(0), Some((0, 0)));
+    assert!(m.is_empty());
+}
+
+#[test]
+fn range_count() {
+    let mut m = BTreeMap::with_degree(2);
+    assert_eq!(m.range_count(..), 0);
+
+    for i in 0..500 {
+        m.insert(i * 2, ());
+    }
+
+    assert_eq!(m.range_count(..), 500);
+    assert_eq!(m.range_count(100..200), 50);
+    assert_eq!(m.range_count(100..=200), 51);
+    assert_eq!(m.range_count(101..200), 49);
+    assert_eq!(m.range_count(..10), 5);
+    assert_eq!(m.range_count(990..), 5);
+    assert_eq!(m.range_count(5000..), 0);
+    assert_eq!(
+        m.range_count((Bound::Excluded(100), Bound::Excluded(102))),
+        0
+    );
+    for (start, end) in [(0, 999), (37, 411), (411, 37), (3, 3)] {
+        assert_eq!(m.range_count(start..end), m.range(start..end).count());
+    }
+}
+
+#[test]
+fn remove_range() {
+    for degree in [2, 3, 5] {
+        for n in [0i32, 1, 10, 100, 1000] {
+            let bounds = [0, 1, n / 4, n / 2, n - 1, n, n + 5];
+            for start in bounds {
+                for end in bounds {
+                    let ranges: [(Bound<i32>, Bound<i32>); 4] = [
+                        (Bound::Included(start), Bound::Excluded(end)),
+                        (Bound::Excluded(start), Bound::Included(end)),
+                        (Bound::Unbounded, Bound::Included(end)),
+                        (Bound::Included(start), Bound::Unbounded),
+                    ];
+                    for range in ranges {
+                        if start > end && range.0 != Bound::Unbounded && range.1 != Bound::Unbounded
+                        {
+                            continue;
+                        }
+
+                        let mut m = BTreeMap::with_degree(degree);
+                        for i in 0..n {
+                            m.insert(i, i);
+                        }
+
+                        let removed = m.remove_range(range);
+                        assert!(m.check().is_ok(), "{:?}", m.check());
+                        assert!(removed.check().is_ok(), "{:?}", removed.check());
+                        assert!(removed.keys().all(|k| range.contains(k)));
+                        assert!(m.keys().all(|k| !range.contains(k)));
+                        assert_eq!(m.len() + removed.len(), n as usize);
+                        assert!(m.keys().zip(m.keys().skip(1)).all(|(a, b)| a < b));
+                    }
+                }
+            }
+        }
+    }
+
+    // Trees of very different heights are joined back together.
+    let mut m = BTreeMap::with_degree(2);
+    for i in 0..1000 {
+        m.insert(i, ());
+    }
+    assert_eq!(m.remove_range(3..990).len(), 987);
+    assert!(m.check().is_ok());
+    assert!(m.keys().copied().eq((0..3).chain(990..1000)));
+    assert_eq!(m.remove_range(995..).len(), 5);
+    assert_eq!(m.remove_range(..1).len(), 1);
+    assert!(m.check().is_ok());
+    assert!(m.keys().copied().eq((1..3).chain(990..995)));
+}
+
+#[test]
+fn stats() {
+    let m = BTreeMap::<u64, u64>::new();
+    let stats = m.stats();
+    assert_eq!((stats.len, stats.height, stats.nodes), (0, 1, 1));
+    assert_eq!(stats.keys_per_level, [0]);
+
+    let mut m = BTreeMap::with_degree(4);
+    for i in 0..1000u64 {
+        m.insert(i, i);
+    }
+    let stats = m.stats();
+    assert_eq!(stats.degree, 4);
+    assert_eq!(stats.len, 1000);
+    assert_eq!(stats.height, stats.keys_per_level.len());
+    assert_eq!(stats.keys_per_level.iter().sum::<usize>(), 1000);
+    assert_eq!(stats.nodes, m.occupancy().nodes());
+    assert!(stats.fill_factor > 0.4 && stats.fill_factor <= 1.0);
+    assert!(stats.bytes >= 1000 * 16);
+}
+
+#[test]
+fn debug() {
+    let mut m = BTreeMap::new();
+    for i in 0..4 {
+        m.insert(i, i * 10);
+    }
+
+    assert_eq!(format!("{m:?}"), "[1]\n├─── [0]\n└─── [2, 3]\n");
+    assert_eq!(
+        format!("{m:#?}"),
+        "[(1, 10)] (1/3)\n├─── [(0, 0)] (1/3)\n└─── [(2, 20), (3, 30)] (2/3)\n"
+    );
+}
+
+#[test]
+fn memory_usage() {
+    let mut m = BTreeMap::<u64, [u8; 32]>::with_degree(8);
+    assert_eq!(m.memory_usage(), 0);
+
+    for i in 0..1000 {
+        m.insert(i, [0; 32]);
+    }
+    let used = m.memory_usage();
+    assert!(used >= 1000 * 40, "{used}");
+    assert_eq!(m.stats().bytes, used);
+
+    // Fewer, fuller nodes after a rebuild take less.
+    m.retain(|k, _| k % 2 == 0);
+    assert!(m.memory_usage() < used);
+
+    m.clear();
+    assert_eq!(m.memory_usage(), 0);
+}
+
+#[test]
+fn optimize() {
+    for degree in [2, 3, 8] {
+        let mut m = BTreeMap::with_degree(degree);
+        for i in 0..5000 {
+            m.insert(i, i);
+        }
+        for i in 0..5000 {
+            if i % 4 != 0 {
+                m.remove(&i);
+            }
+        }
+
+        let before = m.stats();
+        m.optimize();
+        let after = m.stats();
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert!(m
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..5000).step_by(4).map(|i| (i, i))));
+        assert!(after.height <= before.height);
+        assert!(after.nodes < before.nodes);
+        assert!(after.fill_factor > 0.9, "{}", after.fill_factor);
+
+        for fill in [0.0, 0.5, 0.75] {
+            m.rebuild(fill);
+            assert!(m.check().is_ok(), "{:?}", m.check());
+            assert_eq!(m.len(), 1250);
+            let max = 2 * degree - 1;
+            let target = ((fill * max as f64).round() as usize).max(degree - 1);
+            let expected = target as f64 / max as f64;
+            let actual = m.stats().fill_factor;
+            assert!((actual - expected).abs() < 0.1, "{actual} {expected}");
+        }
+    }
+
+    // Small maps fit in the root.
+    let mut m = BTreeMap::with_degree(3);
+    m.insert(1, ());
+    m.insert(2, ());
+    m.rebuild(0.0);
+    assert!(m.check().is_ok());
+    assert_eq!(m.stats().height, 1);
+}
+
+#[test]
+fn set_degree() {
+    let mut m = BTreeMap::new();
+    for i in 0..1000 {
+        m.insert(i, i);
+    }
+    assert_eq!(m.degree(), 2);
+
+    for degree in [16, 3, 2, 64] {
+        m.set_degree(degree);
+        assert_eq!(m.degree(), degree);
+        assert!(m.check().is_ok(), "{:?}", m.check());
+        assert!(m.keys().copied().eq(0..1000));
+        assert!(m.root.keys.len() < 2 * degree);
+
+        // The map keeps working at the new degree.
+        m.insert(1000, 0);
+        m.remove(&1000);
+        assert!(m.check().is_ok());
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter() {
+    use rayon::prelude::*;
+
+    for degree in [2, 5] {
+        let mut m = BTreeMap::with_degree(degree);
+        assert_eq!(m.par_iter().count(), 0);
+
+        for i in 0..10_000u64 {
+            m.insert(i, i * 2);
+        }
+
+        assert_eq!(
+            m.par_iter().map(|(_, v)| v).sum::<u64>(),
+            (0..10_000).sum::<u64>() * 2
+        );
+        assert_eq!(
+            (&m).into_par_iter().filter(|(k, _)| *k % 3 == 0).count(),
+            3334
+        );
+
+        // Splitting by subtrees keeps the entries in order.
+        let collected = m.par_iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert!(collected.into_iter().eq(0..10_000));
+    }
+}
+
+#[test]
+fn double_ended() {
+    for degree in [2, 3, 6] {
+        let mut m = BTreeMap::with_degree(degree);
+        assert_eq!(m.iter().next_back(), None);
+
+        for i in (0..500).rev() {
+            m.insert(i, i * 2);
+        }
+
+        assert!(m
+            .iter()
+            .rev()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..500).rev().map(|i| (i, i * 2))));
+        assert!(m.keys().rev().copied().eq((0..500).rev()));
+        assert!(m.values().rev().copied().eq((0..500).rev().map(|i| i * 2)));
+        assert_eq!(
+            m.keys().rev().take(3).copied().collect::<Vec<_>>(),
+            [499, 498, 497]
+        );
+
+        // Both ends meet in the middle without yielding an entry twice, wherever they meet.
+        for split in [0, 1, 137, 250, 499, 500] {
+            let mut iter = m.keys();
+            let front = iter.by_ref().take(split).copied().collect::<Vec<_>>();
+            let mut back = iter.by_ref().rev().copied().collect::<Vec<_>>();
+            assert_eq!(iter.next(), None);
+            assert_eq!(iter.next_back(), None);
+            back.reverse();
+            assert!(front.into_iter().chain(back).eq(0..500));
+        }
+
+        let mut iter = m.iter();
+        let mut seen = Vec::new();
+        while let Some((k, _)) = iter.next() {
+            seen.push(*k);
+            seen.extend(iter.next_back().map(|(k, _)| *k));
+        }
+        seen.sort_unstable();
+        assert!(seen.into_iter().eq(0..500));
+
+        for (_, v) in m.iter_mut().rev().take(10) {
+            *v = 0;
+        }
+        assert_eq!(m.values().filter(|v| **v == 0).count(), 11);
+        for v in m.values_mut().rev() {
+            *v += 1;
+        }
+        assert_eq!(m.get(&499), Some(&1));
+        assert_eq!(m.get(&1), Some(&3));
+
+        assert_eq!(m.drain().next_back(), Some((499, 1)));
+        assert!(m.is_empty());
+    }
+}
+
+#[test]
+fn prefix_range() {
+    let mut m = BTreeMap::with_degree(2);
+    for k in [
+        "a",
+        "ab",
+        "abc",
+        "abd",
+        "ac",
+        "b",
+        "ab\u{10ffff}",
+        "ab\u{10ffff}x",
+        "aa",
+    ] {
+        m.insert(k.to_string(), k.len());
+    }
+
+    let keys = |prefix: &str| {
+        m.prefix_range(prefix)
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        keys("ab"),
+        ["ab", "abc", "abd", "ab\u{10ffff}", "ab\u{10ffff}x"]
+    );
+    assert_eq!(keys("ab\u{10ffff}"), ["ab\u{10ffff}", "ab\u{10ffff}x"]);
+    assert_eq!(keys("a").len(), 8);
+    assert_eq!(keys(""), m.keys().map(String::as_str).collect::<Vec<_>>());
+    assert!(keys("abe").is_empty());
+    assert!(keys("c").is_empty());
+
+    let mut m = BTreeMap::with_degree(3);
+    for i in 0..=u8::MAX {
+        m.insert(vec![1, i], ());
+        m.insert(vec![1, 0xff, i], ());
+        m.insert(vec![2, i], ());
+    }
+    assert_eq!(m.prefix_range(&[1u8][..]).count(), 512);
+    assert_eq!(m.prefix_range(&[1u8, 0xff][..]).count(), 257);
+    assert_eq!(
+        m.prefix_range(&vec![2u8, 7]).next(),
+        Some((&vec![2, 7], &()))
+    );
+}
+
+#[test]
+fn floor_ceiling() {
+    let mut m = BTreeMap::with_degree(2);
+    assert_eq!(m.floor(&0), None);
+    assert_eq!(m.ceiling(&0), None);
+
+    for i in 0..300 {
+        m.insert(i * 3, i);
+    }
+
+    for k in -2..=900 {
+        let floor = m.range(..=k).last();
+        let ceiling = m.range(k..).next();
+        assert_eq!(m.floor(&k), floor, "floor of {k}");
+        assert_eq!(m.ceiling(&k), ceiling, "ceiling of {k}");
+    }
+
+    let mut m = BTreeMap::<String, (), CaseInsensitive>::with_comparator(3);
+    for k in ["apple", "Banana", "cherry"] {
+        m.insert(k.to_string(), ());
+    }
+    assert_eq!(m.floor("BANANA").map(|(k, _)| k.as_str()), Some("Banana"));
+    assert_eq!(
+        m.floor("Blueberry").map(|(k, _)| k.as_str()),
+        Some("Banana")
+    );
+    assert_eq!(
+        m.ceiling("Blueberry").map(|(k, _)| k.as_str()),
+        Some("cherry")
+    );
+    assert_eq!(m.ceiling("date"), None);
+}
@@ -0,0 +1,162 @@
+use super::{iter::Iter, BTreeMap};
+use std::collections::VecDeque;
+
+/// Restores `k` to `v`, or removes it if `v` is `None`.
+struct Restore<K, V> {
+    k: K,
+    v: Option<V>,
+}
+
+impl<K, V> Restore<K, V>
+where
+    K: Ord + Clone,
+{
+    /// Applies the change, returning the change that reverses it.
+    fn apply(self, map: &mut BTreeMap<K, V>) -> Self {
+        let old = match self.v {
+            Some(v) => map.insert(self.k.clone(), v),
+            None => map.remove(&self.k),
+        };
+        Self { k: self.k, v: old }
+    }
+}
+
+/// A `BTreeMap` that remembers its most recent changes so that they can be undone and redone.
+///
+/// Only the last `capacity` changes made through `insert`, `remove`, and `remove_entry` are
+/// kept, along with the values they displaced. Making a new change discards everything that
+/// could be redone.
+pub struct UndoMap<K, V> {
+    map: BTreeMap<K, V>,
+    capacity: usize,
+    undo: VecDeque<Restore<K, V>>,
+    redo: Vec<Restore<K, V>>,
+}
+
+impl<K, V> UndoMap<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self::with_map(BTreeMap::new(), capacity)
+    }
+
+    /// Wraps an existing map, whose current contents can't be undone.
+    pub fn with_map(map: BTreeMap<K, V>, capacity: usize) -> Self {
+        Self {
+            map,
+            capacity,
+            undo: VecDeque::with_capacity(capacity),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.map
+    }
+
+    pub fn as_map(&self) -> &BTreeMap<K, V> {
+        &self.map
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> bool {
+        self.map.contains(k)
+    }
+
+    pub fn get(&self, k: &K) -> Option<&V> {
+        self.map.get(k)
+    }
+
+    pub fn get_key_value(&self, k: &K) -> Option<(&K, &V)> {
+        self.map.get_key_value(k)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let old = self.map.insert(k.clone(), v);
+        self.record(Restore { k, v: old.clone() });
+        old
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry(&mut self, k: &K) -> Option<(K, V)> {
+        let (k, v) = self.map.remove_entry(k)?;
+        self.record(Restore {
+            k: k.clone(),
+            v: Some(v.clone()),
+        });
+        Some((k, v))
+    }
+
+    /// Removes every entry and forgets the history, since clearing can't be undone.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Reverses the most recent change, returning whether there was one to reverse.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop_back() {
+            Some(change) => {
+                self.redo.push(change.apply(&mut self.map));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the most recently undone change, returning whether there was one to reapply.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(change) => {
+                let inverse = change.apply(&mut self.map);
+                self.push_undo(inverse);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn record(&mut self, change: Restore<K, V>) {
+        self.redo.clear();
+        self.push_undo(change);
+    }
+
+    fn push_undo(&mut self, change: Restore<K, V>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(change);
+    }
+}
@@ -0,0 +1,104 @@
+use super::{iter::Iter, BTreeMap};
+use crate::comparator::Comparator;
+use std::ops::{Bound, RangeBounds};
+
+/// The entries of a `BTreeMap` in a range of keys, from `BTreeMap::view`.
+///
+/// Lookups outside the range miss, and `len` counts the entries in the range using the sizes
+/// of the subtrees on the way to its ends, without visiting the entries in between.
+pub struct View<'a, K, V, C, R> {
+    map: &'a BTreeMap<K, V, C>,
+    range: R,
+}
+
+impl<K, V, C> BTreeMap<K, V, C>
+where
+    C: Comparator<K>,
+{
+    pub fn view<R>(&self, range: R) -> View<'_, K, V, C, R>
+    where
+        R: RangeBounds<K>,
+    {
+        View { map: self, range }
+    }
+}
+
+impl<'a, K, V, C, R> View<'a, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    fn before_start(&self, k: &K) -> bool {
+        match self.range.start_bound() {
+            Bound::Included(start) => C::cmp(k, start).is_lt(),
+            Bound::Excluded(start) => C::cmp(k, start).is_le(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn before_end(&self, k: &K) -> bool {
+        match self.range.end_bound() {
+            Bound::Included(end) => C::cmp(k, end).is_le(),
+            Bound::Excluded(end) => C::cmp(k, end).is_lt(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    pub fn range(&self) -> &R {
+        &self.range
+    }
+
+    pub fn len(&self) -> usize {
+        let root = &self.map.root;
+        let end = root.count_before(|k| self.before_end(k));
+        let start = root.count_before(|k| self.before_start(k));
+        end.saturating_sub(start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn contains(&self, k: &K) -> bool {
+        self.get(k).is_some()
+    }
+
+    pub fn get(&self, k: &K) -> Option<&'a V> {
+        if self.before_start(k) || !self.before_end(k) {
+            return None;
+        }
+        self.map.get(k)
+    }
+
+    /// Iterates over the entries in the range, in key order.
+    pub fn iter(&self) -> ViewIter<'a, '_, K, V, C, R> {
+        ViewIter {
+            inner: Iter::seek(&self.map.root, |k| self.before_start(k)),
+            view: self,
+        }
+    }
+}
+
+/// Iterates over the entries of a `View`, in key order.
+pub struct ViewIter<'a, 'v, K, V, C, R> {
+    inner: Iter<'a, K, V>,
+    view: &'v View<'a, K, V, C, R>,
+}
+
+impl<'a, K, V, C, R> Iterator for ViewIter<'a, '_, K, V, C, R>
+where
+    C: Comparator<K>,
+    R: RangeBounds<K>,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        if !self.view.before_end(k) {
+            // Skip the rest, which are all past the end too.
+            self.inner = Iter::new_empty();
+            return None;
+        }
+        Some((k, v))
+    }
+}
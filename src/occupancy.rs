@@ -0,0 +1,114 @@
+//! Node fill and fanout statistics, per level of a tree.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Statistics for the nodes at one depth of a tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Level {
+    pub nodes: usize,
+    pub keys: usize,
+
+    /// Number of nodes holding each possible number of keys, indexed by key count.
+    pub fill: Vec<usize>,
+
+    /// Number of nodes with each possible number of children, indexed by child count.
+    pub fanout: Vec<usize>,
+}
+
+impl Level {
+    fn new(degree: usize) -> Self {
+        Self {
+            nodes: 0,
+            keys: 0,
+            fill: vec![0; 2 * degree],
+            fanout: vec![0; 2 * degree + 1],
+        }
+    }
+
+    /// Returns the mean fraction of key slots in use, from 0 to 1.
+    pub fn fill_factor(&self) -> f64 {
+        if self.nodes == 0 {
+            return 0.0;
+        }
+        self.keys as f64 / (self.nodes * (self.fill.len() - 1)) as f64
+    }
+}
+
+/// Node occupancy of a whole tree, from the root level down to the leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Occupancy {
+    pub degree: usize,
+    pub levels: Vec<Level>,
+}
+
+impl Occupancy {
+    pub(crate) fn new(degree: usize) -> Self {
+        Self {
+            degree,
+            levels: vec![],
+        }
+    }
+
+    pub(crate) fn record(&mut self, depth: usize, keys: usize, children: usize) {
+        if self.levels.len() <= depth {
+            self.levels.resize(depth + 1, Level::new(self.degree));
+        }
+
+        let level = &mut self.levels[depth];
+        level.nodes += 1;
+        level.keys += keys;
+
+        // Malformed nodes are counted in the last bucket rather than dropped.
+        let last = level.fill.len() - 1;
+        level.fill[keys.min(last)] += 1;
+        let last = level.fanout.len() - 1;
+        level.fanout[children.min(last)] += 1;
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.levels.iter().map(|level| level.nodes).sum()
+    }
+
+    /// Returns the mean fraction of key slots in use across every node, from 0 to 1.
+    pub fn fill_factor(&self) -> f64 {
+        let nodes = self.nodes();
+        if nodes == 0 {
+            return 0.0;
+        }
+        let keys: usize = self.levels.iter().map(|level| level.keys).sum();
+        keys as f64 / (nodes * (2 * self.degree - 1)) as f64
+    }
+
+    /// Returns the leaf level, if the tree has any nodes.
+    pub fn leaves(&self) -> Option<&Level> {
+        self.levels.last()
+    }
+}
+
+impl Display for Occupancy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "degree {}, {} nodes, {:.1}% full",
+            self.degree,
+            self.nodes(),
+            100.0 * self.fill_factor()
+        )?;
+
+        for (depth, level) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "level {depth}: {} nodes, {} keys, {:.1}% full",
+                level.nodes,
+                level.keys,
+                100.0 * level.fill_factor()
+            )?;
+            writeln!(f, "  keys:     {:?}", level.fill)?;
+            if depth + 1 < self.levels.len() {
+                writeln!(f, "  children: {:?}", level.fanout)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Differential testing against `std::collections::BTreeMap`.
+//!
+//! `run` applies a sequence of operations to both a `Subject` and a std map, panicking as soon
+//! as their observable behavior differs or the subject's invariants are broken.
+
+#[cfg(feature = "heapless")]
+use crate::map::FixedMap;
+use crate::{
+    map::BTreeMap,
+    tree::{BTree, KeyCodec, ValueCodec},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::BTreeMap as StdBTreeMap, fmt::Debug};
+use storage::Storage;
+
+/// A single step of a test sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Get(K),
+    Contains(K),
+}
+
+/// A map implementation that can be checked against the std map.
+pub trait Subject<K, V> {
+    fn insert(&mut self, k: K, v: V) -> Option<V>;
+
+    fn remove(&mut self, k: &K) -> Option<V>;
+
+    fn get(&mut self, k: &K) -> Option<V>;
+
+    fn contains(&mut self, k: &K) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every entry in key order.
+    fn entries(&mut self) -> Vec<(K, V)>;
+
+    /// Checks the structural invariants, describing the first violation found.
+    fn check(&mut self) -> Result<(), String>;
+}
+
+impl<K, V> Subject<K, V> for BTreeMap<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.insert(k, v)
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove(k)
+    }
+
+    fn get(&mut self, k: &K) -> Option<V> {
+        BTreeMap::get(self, k).cloned()
+    }
+
+    fn contains(&mut self, k: &K) -> bool {
+        BTreeMap::contains(self, k)
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn entries(&mut self) -> Vec<(K, V)> {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        BTreeMap::check(self)
+    }
+}
+
+/// Panics if the node pool runs out.
+#[cfg(feature = "heapless")]
+impl<K, V, const C: usize, const N: usize> Subject<K, V> for FixedMap<K, V, C, N>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        FixedMap::insert(self, k, v)
+            .map_err(|err| err.to_string())
+            .expect("couldn't insert")
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        FixedMap::remove(self, k)
+    }
+
+    fn get(&mut self, k: &K) -> Option<V> {
+        FixedMap::get(self, k).cloned()
+    }
+
+    fn contains(&mut self, k: &K) -> bool {
+        FixedMap::contains(self, k)
+    }
+
+    fn len(&self) -> usize {
+        FixedMap::len(self)
+    }
+
+    fn entries(&mut self) -> Vec<(K, V)> {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        FixedMap::check(self)
+    }
+}
+
+impl<K, V, S> Subject<K, V> for BTree<K, V, S>
+where
+    K: Ord + Clone + Debug + KeyCodec,
+    V: Clone + ValueCodec,
+    S: Storage<Id = u64>,
+    S::Error: Debug,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.insert(k, v).expect("insert failed")
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        self.remove(k).expect("remove failed")
+    }
+
+    fn get(&mut self, k: &K) -> Option<V> {
+        BTree::get(self, k).expect("get failed").cloned()
+    }
+
+    fn contains(&mut self, k: &K) -> bool {
+        BTree::contains(self, k).expect("contains failed")
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn entries(&mut self) -> Vec<(K, V)> {
+        self.iter()
+            .expect("iter failed")
+            .map(|entry| {
+                let (k, v) = entry.expect("iter failed");
+                (k.clone(), v.clone())
+            })
+            .collect()
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        let report = self.verify().map_err(|err| format!("{err:?}"))?;
+        match report.problems.first() {
+            Some(problem) => Err(format!("{problem:?}")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Generates `count` random operations on keys in `0..keys`, reproducibly from `seed`.
+///
+/// A small key space makes overwrites and removals of present keys common.
+pub fn random_ops(seed: u64, count: usize, keys: u64) -> Vec<Op<u64, u64>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    (0..count)
+        .map(|_| {
+            let k = rng.gen_range(0..keys);
+            match rng.gen_range(0..8) {
+                0..=3 => Op::Insert(k, rng.gen()),
+                4..=5 => Op::Remove(k),
+                6 => Op::Get(k),
+                _ => Op::Contains(k),
+            }
+        })
+        .collect()
+}
+
+/// Decodes an operation script from raw bytes, such as fuzzer input.
+///
+/// Each operation is a tag byte followed by a key byte, and insertions take one more byte for
+/// the value. Keys fit in a byte so that scripts revisit the same keys often. Trailing bytes
+/// that don't make up a whole operation are ignored.
+pub fn ops_from_bytes(bytes: &[u8]) -> Vec<Op<u64, u64>> {
+    let mut bytes = bytes.iter().map(|&b| b as u64);
+    let mut ops = vec![];
+
+    while let (Some(tag), Some(k)) = (bytes.next(), bytes.next()) {
+        ops.push(match tag % 4 {
+            0 => match bytes.next() {
+                Some(v) => Op::Insert(k, v),
+                None => break,
+            },
+            1 => Op::Remove(k),
+            2 => Op::Get(k),
+            _ => Op::Contains(k),
+        });
+    }
+
+    ops
+}
+
+/// Applies `ops` to both `subject` and a std map, checking after every step that they agree
+/// and that the subject's invariants hold.
+///
+/// # Panics
+///
+/// Panics on the first divergence, naming the step and operation that caused it.
+pub fn run<K, V, T>(subject: &mut T, ops: &[Op<K, V>])
+where
+    K: Ord + Clone + Debug,
+    V: Clone + PartialEq + Debug,
+    T: Subject<K, V>,
+{
+    let mut model = StdBTreeMap::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        match op {
+            Op::Insert(k, v) => assert_eq!(
+                subject.insert(k.clone(), v.clone()),
+                model.insert(k.clone(), v.clone()),
+                "step {step}: {op:?}"
+            ),
+            Op::Remove(k) => assert_eq!(subject.remove(k), model.remove(k), "step {step}: {op:?}"),
+            Op::Get(k) => assert_eq!(subject.get(k), model.get(k).cloned(), "step {step}: {op:?}"),
+            Op::Contains(k) => assert_eq!(
+                subject.contains(k),
+                model.contains_key(k),
+                "step {step}: {op:?}"
+            ),
+        }
+
+        assert_eq!(subject.len(), model.len(), "step {step}: {op:?}");
+
+        if let Err(err) = subject.check() {
+            panic!("step {step}: {op:?}: {err}");
+        }
+
+        assert!(
+            subject.entries().into_iter().eq(model.clone()),
+            "step {step}: {op:?}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn map() {
+        for seed in 0..20 {
+            for degree in 2..5 {
+                run(
+                    &mut BTreeMap::with_degree(degree),
+                    &random_ops(seed, 500, 64),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn script() {
+        assert_eq!(
+            ops_from_bytes(&[0, 1, 2, 1, 3, 2, 3, 7, 5, 0, 6]),
+            vec![Op::Insert(1, 2), Op::Remove(3), Op::Get(3), Op::Contains(5),]
+        );
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn fixed() {
+        for seed in 0..20 {
+            let ops = random_ops(seed, 500, 64);
+            run(&mut FixedMap::<u64, u64, 4, 64>::new(), &ops);
+            run(&mut FixedMap::<u64, u64, 6, 32>::new(), &ops);
+        }
+    }
+
+    #[test]
+    fn tree() -> Result<()> {
+        for seed in 0..5 {
+            run(
+                &mut BTree::with_storage(MemStorage::new())?,
+                &random_ops(seed, 300, 64),
+            );
+        }
+
+        Ok(())
+    }
+}
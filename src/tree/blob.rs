@@ -0,0 +1,342 @@
+use super::{codec::KeyCodec, error::Error, BTree};
+use embedded_io::blocking::{Read as _, Write as _};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    mem,
+};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// The most bytes of a blob that are stored in, and buffered for, each of its chunks.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the bytes of a blob are stored: a chain of storage objects of `CHUNK_SIZE` bytes
+/// each, except for the last, which holds the rest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blob {
+    len: u64,
+    chunks: Vec<u64>,
+}
+
+impl Blob {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A map from keys to blobs too large to hold in memory, which are streamed to and from
+/// storage with `put_stream` and `get_stream`.
+///
+/// Only the `Blob`s that locate each value are kept in the tree's nodes. The chunks they point
+/// to aren't nodes, so they'd be freed by `BTree::gc` on the underlying tree.
+pub struct BlobBTree<K, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, Blob, S>,
+}
+
+impl<K, S> BlobBTree<K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    pub fn new(tree: BTree<K, Blob, S>) -> Self {
+        Self { tree }
+    }
+
+    pub fn into_inner(self) -> BTree<K, Blob, S> {
+        self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.tree.contains(k)
+    }
+
+    /// Returns the length of the blob stored under `k`.
+    pub fn blob_len(&self, k: &K) -> Result<Option<u64>, Error<S::Error>> {
+        Ok(self.tree.get(k)?.map(Blob::len))
+    }
+
+    /// Returns a writer whose bytes are stored under `k` once it's finished, replacing any
+    /// blob already stored there.
+    ///
+    /// Bytes are written to storage a chunk at a time as they come in. If the writer is dropped
+    /// without calling `BlobWriter::finish`, the chunks written so far are freed and `k` is left
+    /// as it was.
+    pub fn put_stream(&mut self, k: K) -> BlobWriter<'_, K, S> {
+        BlobWriter {
+            map: self,
+            k: Some(k),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            blob: Blob::default(),
+        }
+    }
+
+    /// Returns a reader over the blob stored under `k`, which reads it a chunk at a time.
+    pub fn get_stream(&self, k: &K) -> Result<Option<BlobReader<'_, K, S>>, Error<S::Error>> {
+        Ok(self.tree.get(k)?.map(|blob| BlobReader {
+            map: self,
+            blob: blob.clone(),
+            next: 0,
+            buf: Vec::new(),
+            pos: 0,
+        }))
+    }
+
+    /// Removes the blob stored under `k` and frees its chunks, returning whether there was one.
+    pub fn remove(&mut self, k: &K) -> Result<bool, Error<S::Error>> {
+        match self.tree.remove(k)? {
+            Some(blob) => {
+                self.free(blob.chunks)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    fn free(&mut self, chunks: Vec<u64>) -> Result<(), Error<S::Error>> {
+        let storage = self.tree.storage.get_mut().map_err(|_| Error::Poisoned)?;
+        for id in chunks {
+            storage.dealloc_id(id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Streams a blob into a `BlobBTree`, from `BlobBTree::put_stream`.
+pub struct BlobWriter<'a, K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    map: &'a mut BlobBTree<K, S>,
+    k: Option<K>,
+    buf: Vec<u8>,
+    blob: Blob,
+}
+
+impl<K, S> BlobWriter<'_, K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    /// Writes out the last chunk and stores the blob under its key, returning its length.
+    pub fn finish(mut self) -> Result<u64, Error<S::Error>> {
+        if !self.buf.is_empty() {
+            self.write_chunk()?;
+        }
+
+        let blob = mem::take(&mut self.blob);
+        let len = blob.len;
+        let k = self.k.take().unwrap();
+        if let Some(old) = self.map.tree.insert(k, blob)? {
+            self.map.free(old.chunks)?;
+        }
+
+        Ok(len)
+    }
+
+    fn write_chunk(&mut self) -> Result<(), Error<S::Error>> {
+        let storage = self
+            .map
+            .tree
+            .storage
+            .get_mut()
+            .map_err(|_| Error::Poisoned)?;
+        let id = storage.alloc_id()?;
+        self.blob.chunks.push(id);
+
+        let mut writer = storage.write_handle(&id)?;
+        writer.write_all(&self.buf).map_err(|_| Error::Write)?;
+        writer.flush().map_err(|_| Error::Write)?;
+
+        self.blob.len += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<K, S> Write for BlobWriter<'_, K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let n = bytes.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&bytes[..n]);
+
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk().map_err(to_io)?;
+        }
+
+        Ok(n)
+    }
+
+    /// Does nothing, since only whole chunks are written out until the writer is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<K, S> Drop for BlobWriter<'_, K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    fn drop(&mut self) {
+        if self.k.is_some() {
+            let chunks = mem::take(&mut self.blob.chunks);
+            let _ = self.map.free(chunks);
+        }
+    }
+}
+
+/// Streams a blob out of a `BlobBTree`, from `BlobBTree::get_stream`.
+pub struct BlobReader<'a, K, S>
+where
+    S: Storage,
+{
+    map: &'a BlobBTree<K, S>,
+    blob: Blob,
+    next: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<K, S> BlobReader<'_, K, S>
+where
+    S: Storage<Id = u64>,
+{
+    pub fn len(&self) -> u64 {
+        self.blob.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blob.is_empty()
+    }
+
+    fn read_chunk(&mut self) -> Result<(), Error<S::Error>> {
+        let id = self.blob.chunks[self.next];
+        let len = if self.next + 1 < self.blob.chunks.len() {
+            CHUNK_SIZE
+        } else {
+            (self.blob.len - (self.next * CHUNK_SIZE) as u64) as usize
+        };
+
+        let mut storage = self.map.tree.storage.lock().map_err(|_| Error::Poisoned)?;
+        let mut reader = storage.read_handle(&id)?;
+        self.buf.resize(len, 0);
+        reader.read_exact(&mut self.buf).map_err(|_| Error::Read)?;
+
+        self.next += 1;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<K, S> Read for BlobReader<'_, K, S>
+where
+    S: Storage<Id = u64>,
+{
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            if self.next == self.blob.chunks.len() {
+                return Ok(0);
+            }
+            self.read_chunk().map_err(to_io)?;
+        }
+
+        let n = bytes.len().min(self.buf.len() - self.pos);
+        bytes[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn to_io(err: impl Display) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn streams() -> Result<()> {
+        let mut blobs = BlobBTree::new(BTree::with_storage(MemStorage::new())?);
+        let bytes = (0..3 * CHUNK_SIZE + 123)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let mut writer = blobs.put_stream("large".to_string());
+        for piece in bytes.chunks(1000) {
+            writer.write_all(piece)?;
+        }
+        assert_eq!(writer.finish()?, bytes.len() as u64);
+
+        let mut writer = blobs.put_stream("empty".to_string());
+        writer.write_all(&[])?;
+        writer.finish()?;
+
+        // Dropping a writer stores nothing.
+        blobs.put_stream("dropped".to_string()).write_all(&bytes)?;
+
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(
+            blobs.blob_len(&"large".to_string())?,
+            Some(bytes.len() as u64)
+        );
+        assert!(!blobs.contains(&"dropped".to_string())?);
+
+        let mut read = vec![];
+        blobs
+            .get_stream(&"large".to_string())?
+            .unwrap()
+            .read_to_end(&mut read)?;
+        assert_eq!(read, bytes);
+
+        let mut read = vec![];
+        let mut reader = blobs.get_stream(&"empty".to_string())?.unwrap();
+        assert!(reader.is_empty());
+        reader.read_to_end(&mut read)?;
+        assert!(read.is_empty());
+        assert!(blobs.get_stream(&"missing".to_string())?.is_none());
+
+        // Replacing a blob frees the old one's chunks.
+        let mut writer = blobs.put_stream("large".to_string());
+        writer.write_all(b"small now")?;
+        writer.finish()?;
+        let mut read = String::new();
+        blobs
+            .get_stream(&"large".to_string())?
+            .unwrap()
+            .read_to_string(&mut read)?;
+        assert_eq!(read, "small now");
+
+        assert!(blobs.remove(&"large".to_string())?);
+        assert!(!blobs.remove(&"large".to_string())?);
+        assert_eq!(blobs.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    BTree,
+};
+use std::mem;
+use storage::Storage;
+
+const U64: usize = mem::size_of::<u64>();
+const CHECKSUM: usize = mem::size_of::<u32>();
+
+/// Bounds on the serialized sizes of keys and values, and on the size of a node.
+///
+/// `bytes` is usually the page or block size of the target storage, e.g.
+/// `FlashStorage::capacity`, or how much RAM a node may take up when read in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub max_key: usize,
+    pub max_value: usize,
+    pub bytes: usize,
+}
+
+impl Budget {
+    /// Returns the worst-case size of a persisted node of a tree with `degree`, including the
+    /// metadata stored with the root.
+    pub const fn node_size(&self, degree: usize) -> usize {
+        let keys = 2 * degree - 1;
+
+        // Keys, values, and child IDs are each an encoded vector, i.e. a length followed
+        // by the elements, behind another length prefix, and then there's the checksum. The
+        // root also stores the tree's length and degree.
+        3 * 2 * U64 + keys * (self.max_key + self.max_value) + (keys + 1) * U64 + CHECKSUM + 2 * U64
+    }
+
+    pub const fn fits(&self, degree: usize) -> bool {
+        self.node_size(degree) <= self.bytes
+    }
+
+    /// Returns the largest degree whose nodes fit, if any.
+    pub const fn max_degree(&self) -> Option<usize> {
+        // Solves `node_size(degree) <= bytes`, as the size is linear in the degree.
+        let entry = self.max_key + self.max_value;
+        let degree = match self
+            .bytes
+            .saturating_add(entry)
+            .checked_sub(8 * U64 + CHECKSUM)
+        {
+            Some(room) => room / (2 * entry + 2 * U64),
+            None => 0,
+        };
+
+        if degree >= 2 {
+            Some(degree)
+        } else {
+            None
+        }
+    }
+}
+
+/// A degree that's checked against a node size budget at compile time.
+///
+/// Using `NodeBudget::<DEGREE, MAX_KEY, MAX_VALUE, BYTES>::DEGREE` fails to compile if nodes of
+/// a tree with that degree could exceed `BYTES`.
+pub struct NodeBudget<
+    const DEGREE: usize,
+    const MAX_KEY: usize,
+    const MAX_VALUE: usize,
+    const BYTES: usize,
+>;
+
+impl<const DEGREE: usize, const MAX_KEY: usize, const MAX_VALUE: usize, const BYTES: usize>
+    NodeBudget<DEGREE, MAX_KEY, MAX_VALUE, BYTES>
+{
+    pub const BUDGET: Budget = Budget {
+        max_key: MAX_KEY,
+        max_value: MAX_VALUE,
+        bytes: BYTES,
+    };
+
+    pub const DEGREE: usize = {
+        assert!(DEGREE >= 2, "the degree must be at least 2");
+        assert!(
+            Self::BUDGET.fits(DEGREE),
+            "nodes could exceed the byte budget"
+        );
+        DEGREE
+    };
+}
+
+impl<K, V, S> BTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Creates a tree, failing if its nodes could exceed `budget`.
+    ///
+    /// Keys and values aren't checked against the budget's bounds as they're inserted.
+    pub fn with_budget(storage: S, degree: usize, budget: Budget) -> Result<Self, Error<S::Error>> {
+        if !budget.fits(degree) {
+            return Err(Error::OverBudget {
+                size: budget.node_size(degree),
+                budget: budget.bytes,
+            });
+        }
+
+        Self::with_storage_and_degree(storage, degree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::node::{Child, Node};
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn budget() -> Result<()> {
+        let budget = Budget {
+            max_key: 8,
+            max_value: 8,
+            bytes: 512,
+        };
+
+        // A full internal node, plus the root's metadata, is the worst case.
+        let degree = 4;
+        let node = Node::<u64, u64> {
+            id: 0,
+            keys: vec![0; 2 * degree - 1],
+            vals: vec![0; 2 * degree - 1],
+            children: (0..2 * degree as u64).map(Child::unloaded).collect(),
+            schema: Default::default(),
+            order: Default::default(),
+        };
+        assert_eq!(
+            node.encode::<storage::mem::Error>()?.len() + 2 * U64,
+            budget.node_size(degree)
+        );
+
+        let max = budget.max_degree().unwrap();
+        assert!(budget.fits(max));
+        assert!(!budget.fits(max + 1));
+        assert_eq!(NodeBudget::<4, 8, 8, 512>::DEGREE, 4);
+
+        assert!(BTree::<u64, u64, _>::with_budget(MemStorage::new(), max, budget).is_ok());
+        assert!(matches!(
+            BTree::<u64, u64, _>::with_budget(MemStorage::new(), max + 1, budget),
+            Err(Error::OverBudget { budget: 512, .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    hooks::Hooks,
+    node::{Child, Node},
+    schema::SharedSchema,
+    BTree, DEFAULT_DEGREE,
+};
+use crate::comparator::Comparator;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::{iter, sync::Mutex};
+use storage::{
+    dir::{self, DirectoryStorage},
+    Storage,
+};
+
+impl<K, V> BTree<K, V, DirectoryStorage>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+{
+    pub fn bulk_load<I>(path: impl AsRef<str>, entries: I) -> Result<Self, Error<dir::Error>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Self::bulk_load_with_storage_and_degree(
+            DirectoryStorage::new(path.as_ref())?,
+            DEFAULT_DEGREE,
+            entries,
+        )
+    }
+}
+
+impl<K, V, S> BTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Builds a tree bottom-up from entries sorted by strictly increasing key.
+    ///
+    /// The entries are collected up front to lay the tree out evenly. Apart from them, only the
+    /// nodes on the path being built are in memory, since every node below the root is written
+    /// to storage as soon as it's finished.
+    pub fn bulk_load_with_storage_and_degree<I>(
+        storage: S,
+        degree: usize,
+        entries: I,
+    ) -> Result<Self, Error<S::Error>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Self::build(storage, degree, entries, |builder, entries| {
+            builder.root(entries)
+        })
+    }
+
+    /// Like `bulk_load_with_storage_and_degree`, but builds distinct subtrees on separate
+    /// threads.
+    ///
+    /// The sorted entries are split into a run for each node of the highest level with enough
+    /// nodes to go around, and each run's subtree is built, encoded, and written by one worker,
+    /// with its own batch of IDs. Only the writes themselves take turns on the storage. The few
+    /// levels above are then built on top of the subtrees.
+    #[cfg(feature = "rayon")]
+    pub fn par_bulk_load_with_storage_and_degree<I>(
+        storage: S,
+        degree: usize,
+        entries: I,
+    ) -> Result<Self, Error<S::Error>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Send,
+        S::Error: Send,
+    {
+        Self::build(storage, degree, entries, |builder, entries| {
+            builder.par_root(entries)
+        })
+    }
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    fn build<I, F>(storage: S, degree: usize, entries: I, root: F) -> Result<Self, Error<S::Error>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnOnce(&Builder<'_, V, S>, Vec<(K, V)>) -> Result<Node<K, V, C>, Error<S::Error>>,
+    {
+        let mut sorted: Vec<(K, V)> = Vec::new();
+        for (k, v) in entries {
+            if sorted
+                .last()
+                .is_some_and(|(last, _)| C::cmp(last, &k).is_ge())
+            {
+                return Err(Error::Unsorted);
+            }
+            sorted.push((k, v));
+        }
+
+        let len = sorted.len();
+        let levels = levels(len, degree);
+        let storage = Mutex::new(storage);
+        let schema = SharedSchema::default();
+
+        let root = root(
+            &Builder {
+                levels: &levels,
+                storage: &storage,
+                schema: &schema,
+            },
+            sorted,
+        )?;
+
+        Ok(Self {
+            len,
+            degree,
+            root,
+            storage,
+            hooks: Hooks::default(),
+        })
+    }
+}
+
+/// How the keys of one level of a bulk loaded tree are spread over its nodes.
+///
+/// Each level packs as many keys into each node as possible, and then spreads the keys evenly
+/// over the nodes, which leaves every node with at least `degree - 1` keys. One key between
+/// each pair of nodes is pulled up into the level above as a separator.
+#[derive(Clone, Copy)]
+struct Level {
+    count: usize,
+    per_node: usize,
+    extra: usize,
+}
+
+impl Level {
+    fn new(keys: usize, degree: usize) -> Self {
+        let count = (keys + 1).div_ceil(2 * degree);
+        Self {
+            count,
+            per_node: (keys - (count - 1)) / count,
+            extra: (keys - (count - 1)) % count,
+        }
+    }
+
+    fn keys(&self, idx: usize) -> usize {
+        self.per_node + usize::from(idx < self.extra)
+    }
+
+    /// Returns where node `idx` starts among the nodes of the level below, or among the
+    /// entries if this is the leaf level, counting the separator after each node.
+    fn start(&self, idx: usize) -> usize {
+        idx * (self.per_node + 1) + idx.min(self.extra)
+    }
+}
+
+/// Returns the levels of a bulk loaded tree of `len` entries, from the leaves up to the root.
+fn levels(len: usize, degree: usize) -> Vec<Level> {
+    let mut levels = vec![Level::new(len, degree)];
+    while let Some(&Level { count: 2.., .. }) = levels.last() {
+        levels.push(Level::new(levels.last().unwrap().count - 1, degree));
+    }
+    levels
+}
+
+/// Builds the nodes of a bulk loaded tree, writing out each one below the root once it's
+/// finished.
+struct Builder<'a, V, S> {
+    levels: &'a [Level],
+    storage: &'a Mutex<S>,
+    schema: &'a SharedSchema<V>,
+}
+
+impl<V, S> Builder<'_, V, S>
+where
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    fn root<K, C>(&self, entries: Vec<(K, V)>) -> Result<Node<K, V, C>, Error<S::Error>>
+    where
+        K: KeyCodec,
+    {
+        self.node(
+            self.levels.len() - 1,
+            0,
+            &mut entries.into_iter(),
+            &mut iter::empty(),
+            None,
+            &mut || self.alloc(),
+        )
+    }
+
+    #[cfg(feature = "rayon")]
+    fn par_root<K, C>(&self, entries: Vec<(K, V)>) -> Result<Node<K, V, C>, Error<S::Error>>
+    where
+        K: KeyCodec + Send + Sync,
+        V: Send + Sync,
+        S: Send,
+        S::Error: Send,
+    {
+        // Levels with fewer nodes than this aren't worth splitting up.
+        let workers = 4 * rayon::current_num_threads();
+        let top = self.levels.len() - 1;
+        let Some(ready) = (0..top)
+            .rev()
+            .find(|&level| self.levels[level].count >= workers)
+        else {
+            return self.root(entries);
+        };
+
+        // The entries of each subtree are contiguous, and the separators between them go in the
+        // levels above.
+        let count = self.levels[ready].count;
+        let mut entries = entries.into_iter();
+        let mut runs = Vec::with_capacity(count);
+        let mut separators = Vec::with_capacity(count - 1);
+        for idx in 0..count {
+            let (nodes, len) = self.subtree(ready, idx);
+            let ids = {
+                let mut storage = self.storage.lock().map_err(|_| Error::Poisoned)?;
+                (0..nodes)
+                    .map(|_| storage.alloc_id())
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            runs.push((idx, entries.by_ref().take(len).collect::<Vec<_>>(), ids));
+            separators.extend(entries.next());
+        }
+
+        let subtrees = runs
+            .into_par_iter()
+            .map(|(idx, run, ids)| {
+                let mut ids = ids.into_iter();
+                let node = self.node::<K, C>(
+                    ready,
+                    idx,
+                    &mut run.into_iter(),
+                    &mut iter::empty(),
+                    None,
+                    &mut || Ok(ids.next().unwrap()),
+                )?;
+                self.write(&node)?;
+                Ok(Child::unloaded(node.id))
+            })
+            .collect::<Result<Vec<_>, Error<S::Error>>>()?;
+
+        self.node(
+            top,
+            0,
+            &mut separators.into_iter(),
+            &mut subtrees.into_iter(),
+            Some(ready),
+            &mut || self.alloc(),
+        )
+    }
+
+    /// Builds node `idx` of `level` from the entries under it, taken in order.
+    ///
+    /// The nodes of the `ready` level, if any, have already been built and written, and are
+    /// taken in order from `subtrees` instead.
+    fn node<K, C>(
+        &self,
+        level: usize,
+        idx: usize,
+        entries: &mut impl Iterator<Item = (K, V)>,
+        subtrees: &mut impl Iterator<Item = Child<K, V, C>>,
+        ready: Option<usize>,
+        alloc: &mut impl FnMut() -> Result<u64, Error<S::Error>>,
+    ) -> Result<Node<K, V, C>, Error<S::Error>>
+    where
+        K: KeyCodec,
+    {
+        let n = self.levels[level].keys(idx);
+        let mut keys = Vec::with_capacity(n);
+        let mut vals = Vec::with_capacity(n);
+        let mut children = Vec::new();
+
+        if level == 0 {
+            (keys, vals) = entries.by_ref().take(n).unzip();
+        } else {
+            let first = self.levels[level].start(idx);
+            for child in first..=first + n {
+                if ready == Some(level - 1) {
+                    children.push(subtrees.next().unwrap());
+                } else {
+                    let node = self.node(level - 1, child, entries, subtrees, ready, alloc)?;
+                    self.write(&node)?;
+                    children.push(Child::unloaded(node.id));
+                }
+
+                // Every child but the last is followed by a separator.
+                if child < first + n {
+                    let (k, v) = entries.next().unwrap();
+                    keys.push(k);
+                    vals.push(v);
+                }
+            }
+        }
+
+        let mut node = Node::new(alloc()?, SharedSchema::clone(self.schema));
+        node.keys = keys;
+        node.vals = vals;
+        node.children = children;
+        Ok(node)
+    }
+
+    /// Returns how many nodes and how many entries there are in the subtree under node `idx`
+    /// of `level`.
+    #[cfg(feature = "rayon")]
+    fn subtree(&self, level: usize, idx: usize) -> (usize, usize) {
+        // The subtree's nodes on each level are contiguous.
+        let (mut lo, mut hi) = (idx, idx + 1);
+        let mut nodes = 1;
+        for level in (1..=level).rev() {
+            (lo, hi) = (self.levels[level].start(lo), self.levels[level].start(hi));
+            nodes += hi - lo;
+        }
+
+        let entries = self.levels[0].start(hi) - self.levels[0].start(lo) - 1;
+        (nodes, entries)
+    }
+
+    fn alloc(&self) -> Result<u64, Error<S::Error>> {
+        let mut storage = self.storage.lock().map_err(|_| Error::Poisoned)?;
+        Ok(storage.alloc_id()?)
+    }
+
+    fn write<K, C>(&self, node: &Node<K, V, C>) -> Result<(), Error<S::Error>>
+    where
+        K: KeyCodec,
+    {
+        // Encode before taking the lock, so that only the writes take turns.
+        let bytes = node.encode::<S::Error>()?;
+        let mut storage = self.storage.lock().map_err(|_| Error::Poisoned)?;
+        Node::<K, V, C>::write(node.id, &bytes, &mut *storage)
+    }
+}
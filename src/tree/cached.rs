@@ -0,0 +1,211 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    BTree,
+};
+use std::{collections::HashMap, hash::Hash};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A `BTree` with a small cache of recently read entries in front of it, for workloads where a
+/// few keys get most of the reads.
+///
+/// Reads of cached keys are a hash lookup, without descending the tree. The cache holds copies
+/// of the values, and an entry is dropped from it whenever its key is written. When the cache is
+/// full, an entry that hasn't been read since the last time the cache was swept is evicted.
+pub struct CachedBTree<K, V, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, V, S>,
+    capacity: usize,
+    index: HashMap<K, usize>,
+    slots: Vec<Slot<K, V>>,
+    hand: usize,
+}
+
+struct Slot<K, V> {
+    k: K,
+    v: V,
+    used: bool,
+}
+
+impl<K, V, S> CachedBTree<K, V, S>
+where
+    K: Ord + Hash + Clone + KeyCodec,
+    V: Clone + ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(tree: BTree<K, V, S>, capacity: usize) -> Self {
+        assert!(capacity > 0, "the capacity must be at least 1");
+
+        Self {
+            tree,
+            capacity,
+            index: HashMap::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            hand: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> BTree<K, V, S> {
+        self.tree
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of entries in the cache.
+    pub fn cached(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        if self.index.contains_key(k) {
+            return Ok(true);
+        }
+        self.tree.contains(k)
+    }
+
+    /// Looks up `k`, in the cache if it's there and otherwise in the tree, caching what's found.
+    pub fn get(&mut self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        if let Some(&idx) = self.index.get(k) {
+            let slot = &mut self.slots[idx];
+            slot.used = true;
+            return Ok(Some(&slot.v));
+        }
+
+        let Some(v) = self.tree.get(k)? else {
+            return Ok(None);
+        };
+        let idx = self.admit(k.clone(), v.clone());
+        Ok(Some(&self.slots[idx].v))
+    }
+
+    /// Looks up `k` in the tree for writing, dropping it from the cache.
+    pub fn get_mut(&mut self, k: &K) -> Result<Option<&mut V>, Error<S::Error>> {
+        self.invalidate(k);
+        self.tree.get_mut(k)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        self.invalidate(&k);
+        self.tree.insert(k, v)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>> {
+        self.invalidate(k);
+        self.tree.remove(k)
+    }
+
+    pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        self.index.clear();
+        self.slots.clear();
+        self.hand = 0;
+        self.tree.clear()
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    /// Caches an entry, evicting one if the cache is full, and returns its slot.
+    fn admit(&mut self, k: K, v: V) -> usize {
+        if self.slots.len() < self.capacity {
+            self.index.insert(k.clone(), self.slots.len());
+            self.slots.push(Slot { k, v, used: false });
+            return self.slots.len() - 1;
+        }
+
+        // Sweep past the entries that have been read since the last sweep, giving each of them
+        // another chance, and evict the first one that hasn't.
+        while self.slots[self.hand].used {
+            self.slots[self.hand].used = false;
+            self.hand = (self.hand + 1) % self.slots.len();
+        }
+
+        let idx = self.hand;
+        self.hand = (self.hand + 1) % self.slots.len();
+        self.index.remove(&self.slots[idx].k);
+        self.index.insert(k.clone(), idx);
+        self.slots[idx] = Slot { k, v, used: false };
+        idx
+    }
+
+    fn invalidate(&mut self, k: &K) {
+        let Some(idx) = self.index.remove(k) else {
+            return;
+        };
+
+        self.slots.swap_remove(idx);
+        if let Some(moved) = self.slots.get(idx) {
+            self.index.insert(moved.k.clone(), idx);
+        }
+        if self.hand >= self.slots.len() {
+            self.hand = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn cached() -> Result<()> {
+        let mut tree = CachedBTree::new(BTree::with_storage(MemStorage::new())?, 4);
+        for i in 0..100 {
+            tree.insert(i, i.to_string())?;
+        }
+        assert_eq!(tree.cached(), 0);
+
+        for _ in 0..10 {
+            for i in [1, 2, 3] {
+                assert_eq!(tree.get(&i)?, Some(&i.to_string()));
+            }
+        }
+        assert_eq!(tree.cached(), 3);
+
+        // Cold keys cycle through the one free slot without evicting the hot ones.
+        for i in 10..20 {
+            assert_eq!(tree.get(&i)?, Some(&i.to_string()));
+            for i in [1, 2, 3] {
+                tree.get(&i)?;
+            }
+        }
+        assert_eq!(tree.cached(), 4);
+        for i in [1, 2, 3] {
+            assert!(tree.index.contains_key(&i));
+        }
+        assert_eq!(tree.get(&100)?, None);
+
+        // Writes aren't hidden by the cache.
+        tree.insert(2, "two".to_string())?;
+        assert_eq!(tree.get(&2)?.map(String::as_str), Some("two"));
+        *tree.get_mut(&2)?.unwrap() = "deux".to_string();
+        assert_eq!(tree.get(&2)?.map(String::as_str), Some("deux"));
+        assert_eq!(tree.remove(&1)?, Some("1".to_string()));
+        assert_eq!(tree.get(&1)?, None);
+        assert!(!tree.contains(&1)?);
+        assert_eq!(tree.len(), 99);
+
+        tree.clear()?;
+        assert_eq!(tree.cached(), 0);
+        assert_eq!(tree.get(&3)?, None);
+
+        Ok(())
+    }
+}
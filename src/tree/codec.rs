@@ -0,0 +1,246 @@
+//! The encoding of the keys, values, and child IDs stored in nodes.
+//!
+//! This is pinned down here rather than left to bincode's defaults, so that trees written on
+//! one platform or version stay readable on another. Integers are fixed-width and little-endian,
+//! lengths of sequences and strings are `u64`s, enum variants are `u32` indices, and struct
+//! fields are in declaration order.
+//!
+//! Keys and values are encoded through `KeyCodec` and `ValueCodec`, which are implemented with
+//! the above encoding for every type that implements serde's traits, and can be implemented
+//! directly for types that don't. A sequence of keys or values is encoded as its `u64` length
+//! followed by each element's encoding, the same as bincode encodes a `Vec` of serde types.
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+/// A key or value that couldn't be encoded or decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Error)]
+#[error("couldn't encode or decode a key or value")]
+pub struct CodecError;
+
+/// How keys are encoded in nodes.
+///
+/// Nodes compare keys after decoding them, with their `Ord` or `Comparator`, so an encoding
+/// doesn't have to preserve their order.
+pub trait KeyCodec: Sized {
+    /// Appends the encoding of the key to `buf`.
+    fn encode_key(&self, buf: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    /// Decodes a key from the front of `bytes`, advancing it past the key's encoding.
+    fn decode_key(bytes: &mut &[u8]) -> Result<Self, CodecError>;
+}
+
+/// How values are encoded in nodes.
+pub trait ValueCodec: Sized {
+    /// Appends the encoding of the value to `buf`.
+    fn encode_value(&self, buf: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    /// Decodes a value from the front of `bytes`, advancing it past the value's encoding.
+    fn decode_value(bytes: &mut &[u8]) -> Result<Self, CodecError>;
+}
+
+impl<T> KeyCodec for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn encode_key(&self, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+        options().serialize_into(buf, self).map_err(|_| CodecError)
+    }
+
+    fn decode_key(bytes: &mut &[u8]) -> Result<Self, CodecError> {
+        options()
+            .allow_trailing_bytes()
+            .deserialize_from(bytes)
+            .map_err(|_| CodecError)
+    }
+}
+
+impl<T> ValueCodec for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn encode_value(&self, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+        options().serialize_into(buf, self).map_err(|_| CodecError)
+    }
+
+    fn decode_value(bytes: &mut &[u8]) -> Result<Self, CodecError> {
+        options()
+            .allow_trailing_bytes()
+            .deserialize_from(bytes)
+            .map_err(|_| CodecError)
+    }
+}
+
+/// Encodes `items` as a `u64` count followed by each item, encoded by `encode`.
+pub(crate) fn encode_seq<T>(
+    items: &[T],
+    encode: impl Fn(&T, &mut Vec<u8>) -> Result<(), CodecError>,
+) -> Result<Vec<u8>, CodecError> {
+    let mut buf = (items.len() as u64).to_le_bytes().to_vec();
+    for item in items {
+        encode(item, &mut buf)?;
+    }
+    Ok(buf)
+}
+
+/// Decodes a sequence encoded by `encode_seq`, rejecting any bytes left over.
+pub(crate) fn decode_seq<T>(
+    mut bytes: &[u8],
+    decode: impl Fn(&mut &[u8]) -> Result<T, CodecError>,
+) -> Result<Vec<T>, CodecError> {
+    let len = u64::decode_key(&mut bytes)?;
+
+    // Don't trust the count to size the allocation, in case the bytes are corrupt.
+    let mut items = Vec::with_capacity((len as usize).min(bytes.len()));
+    for _ in 0..len {
+        items.push(decode(&mut bytes)?);
+    }
+
+    if !bytes.is_empty() {
+        return Err(CodecError);
+    }
+    Ok(items)
+}
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_no_limit()
+        .with_little_endian()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+pub(crate) fn serialize<T>(value: &T) -> bincode::Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    options().serialize(value)
+}
+
+pub(crate) fn deserialize<T>(bytes: &[u8]) -> bincode::Result<T>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    options().deserialize(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CodecError, KeyCodec, ValueCodec};
+    use crate::tree::{
+        node::{Child, Node},
+        schema::{Schema, SharedSchema},
+        BTree,
+    };
+    use anyhow::Result;
+    use storage::{
+        mem::{self, MemStorage},
+        Storage,
+    };
+
+    #[rustfmt::skip]
+    const GOLDEN: [u8; 103] = [
+        // Keys, `[1u32, 2]`.
+        0x10, 0, 0, 0, 0, 0, 0, 0,
+        2, 0, 0, 0, 0, 0, 0, 0,
+        1, 0, 0, 0,
+        2, 0, 0, 0,
+
+        // Values, `["a", "bc"]`, tagged with version 3.
+        0x1b, 0, 0, 0, 0, 0, 0, 3,
+        2, 0, 0, 0, 0, 0, 0, 0,
+        1, 0, 0, 0, 0, 0, 0, 0, b'a',
+        2, 0, 0, 0, 0, 0, 0, 0, b'b', b'c',
+
+        // Child IDs, `[5, 6, 7]`.
+        0x20, 0, 0, 0, 0, 0, 0, 0,
+        3, 0, 0, 0, 0, 0, 0, 0,
+        5, 0, 0, 0, 0, 0, 0, 0,
+        6, 0, 0, 0, 0, 0, 0, 0,
+        7, 0, 0, 0, 0, 0, 0, 0,
+
+        // CRC-32 of everything before it.
+        0x77, 0x8f, 0x3c, 0xba,
+    ];
+
+    #[test]
+    fn golden() -> Result<()> {
+        let schema = SharedSchema::new(Schema::new(3));
+        let mut storage = MemStorage::new();
+        let id = storage.alloc_id()?;
+
+        let mut node = Node::<u32, String>::new(id, SharedSchema::clone(&schema));
+        node.keys = vec![1, 2];
+        node.vals = vec!["a".to_string(), "bc".to_string()];
+        node.children = (5..8).map(Child::unloaded).collect();
+        assert_eq!(node.encode::<mem::Error>()?, GOLDEN);
+
+        Node::<u32, String>::write(id, &GOLDEN, &mut storage)?;
+        let node = Node::<u32, String>::load(id, &mut storage, &schema)?;
+        assert_eq!(node.keys, [1, 2]);
+        assert_eq!(node.vals, ["a", "bc"]);
+        assert_eq!(
+            node.children.iter().map(Child::id).collect::<Vec<_>>(),
+            [5, 6, 7]
+        );
+
+        Ok(())
+    }
+
+    /// A key without serde impls, encoded as its four bytes.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Tag([u8; 4]);
+
+    impl KeyCodec for Tag {
+        fn encode_key(&self, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+            buf.extend(self.0);
+            Ok(())
+        }
+
+        fn decode_key(bytes: &mut &[u8]) -> Result<Self, CodecError> {
+            let (tag, rest) = bytes.split_first_chunk().ok_or(CodecError)?;
+            *bytes = rest;
+            Ok(Self(*tag))
+        }
+    }
+
+    /// A value without serde impls, encoded as a byte of length and then its bytes.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct Short(Vec<u8>);
+
+    impl ValueCodec for Short {
+        fn encode_value(&self, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+            buf.push(u8::try_from(self.0.len()).map_err(|_| CodecError)?);
+            buf.extend(&self.0);
+            Ok(())
+        }
+
+        fn decode_value(bytes: &mut &[u8]) -> Result<Self, CodecError> {
+            let (&len, rest) = bytes.split_first().ok_or(CodecError)?;
+            if rest.len() < len as usize {
+                return Err(CodecError);
+            }
+            let (value, rest) = rest.split_at(len as usize);
+            *bytes = rest;
+            Ok(Self(value.to_vec()))
+        }
+    }
+
+    #[test]
+    fn custom_codecs() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for i in 0..200u8 {
+            tree.insert(Tag([i, 0, 0, i]), Short(vec![i; i as usize % 7]))?;
+        }
+
+        let root_id = tree.persist()?;
+        let storage = tree.into_storage()?;
+        let tree = BTree::<Tag, Short, _>::load_with_storage(root_id, storage)?;
+        assert_eq!(tree.len(), 200);
+        assert_eq!(tree.get(&Tag([9, 0, 0, 9]))?, Some(&Short(vec![9, 9])));
+        assert!(tree.iter()?.map(|entry| entry.unwrap().0 .0[0]).eq(0..200));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use super::{
+    codec::{self, KeyCodec},
+    error::Error,
+    BTree,
+};
+use serde::{Deserialize, Serialize};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A value made up of columns that are each encoded on their own.
+///
+/// Loading a node only splits rows into their columns' bytes, so a column is only decoded when
+/// it's read, and reading a small column doesn't decode a large one next to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Row(Vec<Vec<u8>>);
+
+impl Row {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the next column.
+    pub fn push<T, E>(mut self, value: &T) -> Result<Self, Error<E>>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.0
+            .push(codec::serialize(value).map_err(|_| Error::Serialization)?);
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Decodes the column at `idx`, if the row has that many columns.
+    pub fn get<T, E>(&self, idx: usize) -> Result<Option<T>, Error<E>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.0
+            .get(idx)
+            .map(|raw| codec::deserialize(raw).map_err(|_| Error::Deserialization))
+            .transpose()
+    }
+}
+
+/// The columns of a row picked out by `ColumnarBTree::get_columns`, in the order asked for.
+pub struct Projection<'a> {
+    columns: Vec<Option<&'a [u8]>>,
+}
+
+impl Projection<'_> {
+    /// Decodes the `idx`th projected column, or returns `None` if the row doesn't have it.
+    pub fn get<T, E>(&self, idx: usize) -> Result<Option<T>, Error<E>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.columns[idx]
+            .map(|raw| codec::deserialize(raw).map_err(|_| Error::Deserialization))
+            .transpose()
+    }
+}
+
+/// A map whose values are `Row`s with named columns, which can be read individually.
+///
+/// The names aren't persisted, just the position of each column in its row, so columns can
+/// only be added at the end.
+pub struct ColumnarBTree<K, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, Row, S>,
+    columns: Vec<String>,
+}
+
+impl<K, S> ColumnarBTree<K, S>
+where
+    K: Ord + KeyCodec,
+    S: Storage<Id = u64>,
+{
+    pub fn new<I>(tree: BTree<K, Row, S>, columns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            tree,
+            columns: columns.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn into_inner(self) -> BTree<K, Row, S> {
+        self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    fn column(&self, name: &str) -> Result<usize, Error<S::Error>> {
+        self.columns
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| Error::UnknownColumn(name.to_string()))
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<&Row>, Error<S::Error>> {
+        self.tree.get(k)
+    }
+
+    /// Decodes just the column `name` of the row for `k`.
+    ///
+    /// Returns `None` if there's no row or the row was written before the column was added.
+    pub fn get_column<T>(&self, k: &K, name: &str) -> Result<Option<T>, Error<S::Error>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let idx = self.column(name)?;
+        match self.tree.get(k)? {
+            Some(row) => row.get(idx),
+            None => Ok(None),
+        }
+    }
+
+    /// Picks out the columns `names` of the row for `k`, to be decoded with `Projection::get`.
+    pub fn get_columns(
+        &self,
+        k: &K,
+        names: &[&str],
+    ) -> Result<Option<Projection<'_>>, Error<S::Error>> {
+        let idxs = names
+            .iter()
+            .map(|name| self.column(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.tree.get(k)?.map(|row| Projection {
+            columns: idxs
+                .into_iter()
+                .map(|idx| row.0.get(idx).map(Vec::as_slice))
+                .collect(),
+        }))
+    }
+
+    pub fn insert(&mut self, k: K, row: Row) -> Result<Option<Row>, Error<S::Error>> {
+        self.tree.insert(k, row)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<Row>, Error<S::Error>> {
+        self.tree.remove(k)
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::{self, MemStorage};
+
+    #[test]
+    fn projection() -> Result<()> {
+        let tree = BTree::with_storage(MemStorage::new())?;
+        let mut users = ColumnarBTree::new(tree, ["name", "age", "avatar"]);
+
+        for id in 0..100u32 {
+            let row = Row::new()
+                .push::<_, mem::Error>(&format!("user {id}"))?
+                .push::<_, mem::Error>(&(20 + id % 50))?
+                .push::<_, mem::Error>(&vec![id as u8; 4096])?;
+            users.insert(id, row)?;
+        }
+
+        assert_eq!(users.get_column::<u32>(&7, "age")?, Some(27));
+        let projection = users.get_columns(&42, &["name", "age"])?.unwrap();
+        assert_eq!(
+            projection.get::<String, mem::Error>(0)?.as_deref(),
+            Some("user 42")
+        );
+        assert_eq!(projection.get::<u32, mem::Error>(1)?, Some(62));
+
+        assert!(users.get_columns(&100, &["name"])?.is_none());
+        assert!(matches!(
+            users.get_column::<u32>(&0, "email"),
+            Err(Error::UnknownColumn(column)) if column == "email"
+        ));
+
+        // Rows written before a column was added don't have it.
+        let root_id = users.persist()?;
+        let storage = users.into_inner().into_storage()?;
+        let users = ColumnarBTree::new(
+            BTree::load_with_storage(root_id, storage)?,
+            ["name", "age", "avatar", "email"],
+        );
+        assert_eq!(users.get_column::<String>(&0, "email")?, None);
+        assert_eq!(
+            users.get_column::<Vec<u8>>(&1, "avatar")?,
+            Some(vec![1; 4096])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use crate::comparator::Comparator;
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Write},
+    sync::Mutex,
+};
+use storage::Storage;
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Renders the tree in Graphviz DOT format, loading nodes as needed.
+    ///
+    /// Each node is labeled with its ID, keys, the key range its parent allows it, and how
+    /// many of its key slots are in use. Underfull nodes are outlined in orange, and nodes
+    /// that can't be read are drawn in red instead of failing the export.
+    pub fn to_dot(&self) -> Result<String, Error<S::Error>>
+    where
+        K: Debug,
+    {
+        self.to_dot_with_objects([])
+    }
+
+    /// Like `to_dot`, but also draws any of `objects` that aren't reachable from the root as
+    /// orphans.
+    ///
+    /// Storage can't list the objects it holds, so they have to be supplied, e.g. from the
+    /// file names of a `DirectoryStorage`.
+    pub fn to_dot_with_objects(
+        &self,
+        objects: impl IntoIterator<Item = u64>,
+    ) -> Result<String, Error<S::Error>>
+    where
+        K: Debug,
+    {
+        let mut dot = Dot {
+            out: String::new(),
+            degree: self.degree,
+            reachable: HashSet::new(),
+        };
+
+        dot.out.push_str("digraph btree {\n");
+        dot.out
+            .push_str("    node [shape=box, fontname=monospace];\n");
+        dot.node(&self.root, &self.storage, true, None, None);
+
+        for id in objects {
+            if !dot.reachable.contains(&id) {
+                let _ = writeln!(
+                    dot.out,
+                    "    n{id} [label=\"#{id}\\norphan\", style=dashed, color=gray];"
+                );
+            }
+        }
+
+        dot.out.push_str("}\n");
+
+        Ok(dot.out)
+    }
+}
+
+struct Dot {
+    out: String,
+    degree: usize,
+    reachable: HashSet<u64>,
+}
+
+impl Dot {
+    fn node<K, V, S, C>(
+        &mut self,
+        node: &Node<K, V, C>,
+        storage: &Mutex<S>,
+        root: bool,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) where
+        K: Debug + KeyCodec,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        self.reachable.insert(node.id);
+
+        let bound = |k: Option<&K>| k.map_or("..".to_string(), |k| format!("{k:?}"));
+        let label = format!(
+            "#{}\n{:?}\nrange ({}, {})\n{}/{} keys",
+            node.id,
+            node.keys,
+            bound(lower),
+            bound(upper),
+            node.len(),
+            2 * self.degree - 1
+        );
+
+        // Writing to a `String` can't fail.
+        let _ = write!(self.out, "    n{} [label=\"{}\"", node.id, escape(&label));
+        if !root && node.len() + 1 < self.degree {
+            self.out.push_str(", color=orange");
+        }
+        self.out.push_str("];\n");
+
+        for idx in 0..node.children.len() {
+            let lower = if idx == 0 {
+                lower
+            } else {
+                node.keys.get(idx - 1)
+            };
+            let upper = if idx < node.len() {
+                node.keys.get(idx)
+            } else {
+                upper
+            };
+
+            let id = node.children[idx].id();
+            let _ = writeln!(self.out, "    n{} -> n{id};", node.id);
+
+            match node.load_child(idx, storage) {
+                Ok(child) => self.node(child, storage, false, lower, upper),
+                Err(err) => {
+                    self.reachable.insert(id);
+                    let label = format!("#{id}\nunreadable: {err}");
+                    let _ = writeln!(
+                        self.out,
+                        "    n{id} [label=\"{}\", color=red, fontcolor=red];",
+                        escape(&label)
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Escapes a label for use inside a quoted DOT string.
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn dot() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-dot")?;
+
+        for i in 0..4 {
+            tree.insert(format!("\"{i}\""), i)?;
+        }
+
+        let root_id = tree.persist()?;
+        let tree = BTree::<String, i32>::load(root_id, "/tmp/btreedir-dot")?;
+        let dot = tree.to_dot_with_objects([root_id, 1000])?;
+
+        assert!(dot.starts_with("digraph btree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches(" -> ").count(), 2);
+        assert!(dot.contains(&format!(
+            "n{root_id} [label=\"#{root_id}\\n[\\\"\\\\\\\"1\\\\\\\"\\\"]"
+        )));
+        assert!(dot.contains("n1000 [label=\"#1000\\norphan\""));
+        assert!(!dot.contains("unreadable"));
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-dot");
+
+        Ok(())
+    }
+}
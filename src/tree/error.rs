@@ -1,3 +1,5 @@
+#[cfg(feature = "retry")]
+use storage::retry::Transient;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,6 +10,9 @@ pub enum Error<E> {
     #[error("deserialization error")]
     Deserialization,
 
+    #[error("checksum mismatch in node {0}")]
+    Checksum(u64),
+
     #[error("read error")]
     Read,
 
@@ -17,6 +22,27 @@ pub enum Error<E> {
     #[error("seek error")]
     Seek,
 
+    #[error("entries aren't sorted")]
+    Unsorted,
+
+    #[error("key already exists")]
+    AlreadyExists,
+
+    #[error("lock poisoned")]
+    Poisoned,
+
+    #[error("no upgrade from value version {0}")]
+    UnknownVersion(u8),
+
+    #[error("tree is ordered by comparator {found}, not {expected}")]
+    Comparator { expected: u32, found: u32 },
+
+    #[error("nodes of up to {size} bytes exceed the budget of {budget}")]
+    OverBudget { size: usize, budget: usize },
+
+    #[error("no column named {0}")]
+    UnknownColumn(String),
+
     #[error(transparent)]
     Storage(#[from] E),
 
@@ -25,3 +51,21 @@ pub enum Error<E> {
 }
 
 pub type Result<T, E> = std::result::Result<T, Error<E>>;
+
+#[cfg(feature = "retry")]
+impl<E> Error<E>
+where
+    E: Transient,
+{
+    /// Returns whether the operation failed on a storage error that may go away if it's tried
+    /// again, e.g. with a `RetryStorage`.
+    ///
+    /// Failed reads and writes through handles don't keep their cause, so they're treated as
+    /// fatal, as are all the other errors.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Storage(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+}
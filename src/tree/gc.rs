@@ -0,0 +1,149 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use crate::comparator::Comparator;
+use std::collections::{BTreeSet, HashSet};
+use storage::Storage;
+
+/// The outcome of `BTree::gc` or `BTree::gc_dry_run`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Number of nodes reachable from the root.
+    pub reachable: usize,
+
+    /// The supplied objects that aren't reachable from the root, in increasing order.
+    pub orphans: Vec<u64>,
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Reports which of `objects` aren't reachable from the root, without freeing them.
+    ///
+    /// Storage can't list the objects it holds, so they have to be supplied, e.g. from the
+    /// file names of a `DirectoryStorage`. Nodes that aren't already loaded are read from
+    /// storage just to find their children and aren't kept in memory.
+    pub fn gc_dry_run(
+        &mut self,
+        objects: impl IntoIterator<Item = u64>,
+    ) -> Result<GcReport, Error<S::Error>> {
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
+        let mut reachable = HashSet::new();
+        mark(&self.root, storage, &mut reachable)?;
+
+        let orphans = objects
+            .into_iter()
+            .filter(|id| !reachable.contains(id))
+            .collect::<BTreeSet<_>>();
+
+        Ok(GcReport {
+            reachable: reachable.len(),
+            orphans: orphans.into_iter().collect(),
+        })
+    }
+
+    /// Persists the tree, then deallocates every one of `objects` that isn't reachable from
+    /// the root.
+    ///
+    /// The tree is persisted first so that the freed nodes aren't referenced by its persisted
+    /// copy either, as long as it's reloaded from the current `root_id`. Nothing is freed if
+    /// any reachable node can't be read.
+    pub fn gc(
+        &mut self,
+        objects: impl IntoIterator<Item = u64>,
+    ) -> Result<GcReport, Error<S::Error>> {
+        self.persist()?;
+
+        let report = self.gc_dry_run(objects)?;
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+        for id in &report.orphans {
+            storage.dealloc_id(*id)?;
+        }
+
+        trace!(orphans = report.orphans.len(), "collect garbage");
+
+        Ok(report)
+    }
+}
+
+fn mark<K, V, C, S>(
+    node: &Node<K, V, C>,
+    storage: &mut S,
+    reachable: &mut HashSet<u64>,
+) -> Result<(), Error<S::Error>>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    // Guard against cycles in a damaged tree.
+    if !reachable.insert(node.id) {
+        return Ok(());
+    }
+
+    for child in &node.children {
+        // Prefer the loaded copy since its children may have changed.
+        match child.as_option() {
+            Some(child) => mark(child, storage, reachable)?,
+            None => {
+                let child = Node::<K, V, C>::load(child.id(), storage, &node.schema)?;
+                mark(&child, storage, reachable)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn gc() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+
+        // Nodes that were persisted and then merged away are left behind in storage.
+        for i in 0..500 {
+            tree.insert(i, i)?;
+        }
+        tree.persist()?;
+        for i in 0..450 {
+            tree.remove(&i)?;
+        }
+        let root_id = tree.persist()?;
+
+        let storage = tree.into_storage()?;
+        let objects = (0..10_000)
+            .filter(|id| storage.get(*id).is_some())
+            .collect::<Vec<_>>();
+
+        let mut tree = BTree::<i32, i32, _>::load_with_storage(root_id, storage)?;
+        let report = tree.gc_dry_run(objects.iter().copied())?;
+        assert!(!report.orphans.is_empty());
+        assert_eq!(report.reachable + report.orphans.len(), objects.len());
+        assert_eq!(tree.verify()?.nodes, report.reachable);
+
+        assert_eq!(tree.gc(objects)?, report);
+
+        let root_id = tree.root_id();
+        let storage = tree.into_storage()?;
+        assert_eq!(storage.len(), report.reachable);
+
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, storage)?;
+        let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq(450..500));
+
+        Ok(())
+    }
+}
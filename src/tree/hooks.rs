@@ -0,0 +1,59 @@
+/// A structural change to the tree.
+///
+/// `level` is the depth below the root of the nodes that changed, at the time of the change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The full node `left` was split, moving its upper half into the new node `right` and its
+    /// median key up into `parent`.
+    Split {
+        parent: u64,
+        left: u64,
+        right: u64,
+        level: usize,
+    },
+
+    /// The node `from` was merged into its sibling `into`, along with their separator key from
+    /// `parent`.
+    Merge {
+        parent: u64,
+        into: u64,
+        from: u64,
+        level: usize,
+    },
+
+    /// The node `into` took a key from its sibling `from` by rotating it through `parent`.
+    Borrow {
+        parent: u64,
+        into: u64,
+        from: u64,
+        level: usize,
+    },
+
+    /// The full root `old` was given the new root `new` as a parent.
+    RootGrown { old: u64, new: u64 },
+
+    /// The empty root `old` was replaced by its only child `new`.
+    RootShrunk { old: u64, new: u64 },
+}
+
+type Hook = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// Callbacks registered with `BTree::on_event`.
+#[derive(Default)]
+pub(crate) struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn push(&mut self, hook: Hook) {
+        self.0.push(hook);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn emit(&self, event: Event) {
+        for hook in &self.0 {
+            hook(&event);
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use super::{error::Error, iter::Iter, BTree};
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::{Bound, RangeBounds},
+    slice,
+};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// The most values an array container holds before it becomes a bitmap.
+const ARRAY_MAX: usize = 4096;
+
+/// The most runs a run container holds before it becomes a bitmap, at which point it'd take
+/// as many bytes.
+const RUNS_MAX: usize = 2048;
+
+/// The number of words in a bitmap container, one bit for each of the 2^16 values.
+const WORDS: usize = 1024;
+
+/// The values of an `IntSet` that share their upper 48 bits, stored by their lower 16 bits in
+/// whichever of three forms is smallest for them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Container {
+    /// Sorted values, for sparse containers.
+    Array(Vec<u16>),
+
+    /// One bit for each possible value, for dense containers.
+    Bitmap { len: u32, words: Vec<u64> },
+
+    /// Sorted, disjoint runs of consecutive values, as their first and last values.
+    Runs(Vec<(u16, u16)>),
+}
+
+impl Container {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Array(values) => values.len(),
+            Self::Bitmap { len, .. } => *len as usize,
+            Self::Runs(runs) => runs
+                .iter()
+                .map(|&(first, last)| (last - first) as usize + 1)
+                .sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, v: u16) -> bool {
+        match self {
+            Self::Array(values) => values.binary_search(&v).is_ok(),
+            Self::Bitmap { words, .. } => words[v as usize / 64] & (1 << (v % 64)) != 0,
+            Self::Runs(runs) => {
+                let idx = runs.partition_point(|&(first, _)| first <= v);
+                idx > 0 && runs[idx - 1].1 >= v
+            }
+        }
+    }
+
+    /// Adds `v`, returning whether it wasn't already there.
+    fn insert(&mut self, v: u16) -> bool {
+        let inserted = match self {
+            Self::Array(values) => match values.binary_search(&v) {
+                Ok(_) => false,
+                Err(idx) => {
+                    values.insert(idx, v);
+                    true
+                }
+            },
+            Self::Bitmap { len, words } => {
+                let word = &mut words[v as usize / 64];
+                let inserted = *word & (1 << (v % 64)) == 0;
+                *word |= 1 << (v % 64);
+                *len += inserted as u32;
+                inserted
+            }
+            Self::Runs(runs) => {
+                let idx = runs.partition_point(|&(first, _)| first <= v);
+                if idx > 0 && runs[idx - 1].1 >= v {
+                    return false;
+                }
+
+                let joins_prev = idx > 0 && runs[idx - 1].1 as u32 + 1 == v as u32;
+                let joins_next = idx < runs.len() && runs[idx].0 as u32 == v as u32 + 1;
+                match (joins_prev, joins_next) {
+                    (true, true) => {
+                        runs[idx - 1].1 = runs[idx].1;
+                        runs.remove(idx);
+                    }
+                    (true, false) => runs[idx - 1].1 = v,
+                    (false, true) => runs[idx].0 = v,
+                    (false, false) => runs.insert(idx, (v, v)),
+                }
+                true
+            }
+        };
+
+        self.rebalance();
+        inserted
+    }
+
+    /// Removes `v`, returning whether it was there.
+    fn remove(&mut self, v: u16) -> bool {
+        let removed = match self {
+            Self::Array(values) => match values.binary_search(&v) {
+                Ok(idx) => {
+                    values.remove(idx);
+                    true
+                }
+                Err(_) => false,
+            },
+            Self::Bitmap { len, words } => {
+                let word = &mut words[v as usize / 64];
+                let removed = *word & (1 << (v % 64)) != 0;
+                *word &= !(1 << (v % 64));
+                *len -= removed as u32;
+                removed
+            }
+            Self::Runs(runs) => {
+                let idx = runs.partition_point(|&(first, _)| first <= v);
+                if idx == 0 || runs[idx - 1].1 < v {
+                    return false;
+                }
+
+                let (first, last) = runs[idx - 1];
+                if first == last {
+                    runs.remove(idx - 1);
+                } else if v == first {
+                    runs[idx - 1].0 = v + 1;
+                } else if v == last {
+                    runs[idx - 1].1 = v - 1;
+                } else {
+                    runs[idx - 1].1 = v - 1;
+                    runs.insert(idx, (v + 1, last));
+                }
+                true
+            }
+        };
+
+        self.rebalance();
+        removed
+    }
+
+    /// Switches to a bitmap once an array or runs would take more space than one, and back to
+    /// an array once a bitmap would take more space than that.
+    fn rebalance(&mut self) {
+        let too_big = match self {
+            Self::Array(values) => values.len() > ARRAY_MAX,
+            Self::Bitmap { len, .. } => (*len as usize) < ARRAY_MAX,
+            Self::Runs(runs) => runs.len() > RUNS_MAX,
+        };
+
+        if too_big {
+            *self = match self {
+                Self::Bitmap { .. } => Self::Array(self.iter_from(0).collect()),
+                _ => Self::bitmap(self.iter_from(0)),
+            };
+        }
+    }
+
+    /// Switches to whichever form takes the least space, which is the only way to get runs.
+    fn optimize(&mut self) {
+        let len = self.len();
+        let runs = count_runs(self.iter_from(0));
+
+        // The sizes of an array, a bitmap, and runs, in bytes.
+        let sizes = [2 * len, 8 * WORDS, 4 * runs];
+        let smallest = (0..3).min_by_key(|&idx| sizes[idx]).unwrap();
+
+        *self = match smallest {
+            0 => Self::Array(self.iter_from(0).collect()),
+            1 => Self::bitmap(self.iter_from(0)),
+            _ => Self::Runs(to_runs(self.iter_from(0))),
+        };
+    }
+
+    fn bitmap(values: impl Iterator<Item = u16>) -> Self {
+        let mut len = 0;
+        let mut words = vec![0; WORDS];
+        for v in values {
+            words[v as usize / 64] |= 1 << (v % 64);
+            len += 1;
+        }
+        Self::Bitmap { len, words }
+    }
+
+    /// Iterates over the values from `from` on, in order.
+    fn iter_from(&self, from: u16) -> ContainerIter<'_> {
+        match self {
+            Self::Array(values) => {
+                let idx = values.partition_point(|&v| v < from);
+                ContainerIter::Array(values[idx..].iter())
+            }
+            Self::Bitmap { words, .. } => ContainerIter::Bitmap {
+                words,
+                next: from as u32,
+            },
+            Self::Runs(runs) => {
+                let idx = runs.partition_point(|&(_, last)| last < from);
+                let mut runs = runs[idx..].iter();
+                let (next, last) = match runs.next() {
+                    Some(&(first, last)) => (first.max(from) as u32, last as u32),
+                    None => (1, 0),
+                };
+                ContainerIter::Runs { runs, next, last }
+            }
+        }
+    }
+}
+
+fn count_runs(values: impl Iterator<Item = u16>) -> usize {
+    let mut runs = 0;
+    let mut prev = None;
+    for v in values {
+        if prev.is_none_or(|prev: u16| prev + 1 != v) {
+            runs += 1;
+        }
+        prev = Some(v);
+    }
+    runs
+}
+
+fn to_runs(values: impl Iterator<Item = u16>) -> Vec<(u16, u16)> {
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for v in values {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == v => *last = v,
+            _ => runs.push((v, v)),
+        }
+    }
+    runs
+}
+
+/// Iterates over the values in a `Container`, in order.
+enum ContainerIter<'a> {
+    Array(slice::Iter<'a, u16>),
+    Bitmap {
+        words: &'a [u64],
+        next: u32,
+    },
+    Runs {
+        runs: slice::Iter<'a, (u16, u16)>,
+        next: u32,
+        last: u32,
+    },
+}
+
+impl Iterator for ContainerIter<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        match self {
+            Self::Array(values) => values.next().copied(),
+            Self::Bitmap { words, next } => {
+                while (*next as usize) < WORDS * 64 {
+                    let word = words[*next as usize / 64] >> (*next % 64);
+                    if word == 0 {
+                        // Skip the rest of the word.
+                        *next = (*next | 63) + 1;
+                        continue;
+                    }
+                    *next += word.trailing_zeros();
+                    *next += 1;
+                    return Some((*next - 1) as u16);
+                }
+                None
+            }
+            Self::Runs { runs, next, last } => {
+                if *next > *last {
+                    let &(first, end) = runs.next()?;
+                    (*next, *last) = (first as u32, end as u32);
+                }
+                *next += 1;
+                Some((*next - 1) as u16)
+            }
+        }
+    }
+}
+
+/// A set of integers, for dense ones like IDs, stored as compressed containers of up to 2^16
+/// values each, in the manner of roaring bitmaps.
+///
+/// Each container is an entry in the underlying tree, keyed by the upper 48 bits of its values,
+/// so a run of a million consecutive values takes 16 entries rather than a million. Containers
+/// switch between arrays and bitmaps as they fill up, and `optimize` run-length encodes the
+/// ones that would be smaller for it.
+pub struct IntSet<S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<u64, Container, S>,
+}
+
+impl<S> IntSet<S>
+where
+    S: Storage<Id = u64>,
+{
+    pub fn new(tree: BTree<u64, Container, S>) -> Self {
+        Self { tree }
+    }
+
+    pub fn into_inner(self) -> BTree<u64, Container, S> {
+        self.tree
+    }
+
+    /// Counts the values, which means visiting every container.
+    pub fn len(&self) -> Result<u64, Error<S::Error>> {
+        let mut len = 0;
+        for entry in self.tree.iter()? {
+            len += entry?.1.len() as u64;
+        }
+        Ok(len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains(&self, k: u64) -> Result<bool, Error<S::Error>> {
+        Ok(self
+            .tree
+            .get(&(k >> 16))?
+            .is_some_and(|container| container.contains(k as u16)))
+    }
+
+    /// Adds `k`, returning whether it wasn't already in the set.
+    pub fn insert(&mut self, k: u64) -> Result<bool, Error<S::Error>> {
+        if let Some(container) = self.tree.get_mut(&(k >> 16))? {
+            return Ok(container.insert(k as u16));
+        }
+
+        self.tree
+            .insert(k >> 16, Container::Array(vec![k as u16]))?;
+        Ok(true)
+    }
+
+    /// Removes `k`, returning whether it was in the set.
+    pub fn remove(&mut self, k: u64) -> Result<bool, Error<S::Error>> {
+        let Some(container) = self.tree.get_mut(&(k >> 16))? else {
+            return Ok(false);
+        };
+
+        let removed = container.remove(k as u16);
+        if container.is_empty() {
+            self.tree.remove(&(k >> 16))?;
+        }
+        Ok(removed)
+    }
+
+    /// Stores each container in whichever form takes the least space, run-length encoding
+    /// the ones that are mostly runs of consecutive values.
+    pub fn optimize(&mut self) -> Result<(), Error<S::Error>> {
+        let highs = self
+            .tree
+            .keys()?
+            .map(|high| high.copied())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for high in highs {
+            if let Some(container) = self.tree.get_mut(&high)? {
+                container.optimize();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.clear()
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    /// Iterates over the values in `range`, in order.
+    pub fn range<R>(&self, range: R) -> Result<IntSetIter<'_, S, R>, Error<S::Error>>
+    where
+        R: RangeBounds<u64>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+
+        let inner = Iter::seek(&self.tree.root, &self.tree.storage, |&high| {
+            start.is_some_and(|start| high < start >> 16)
+        })?;
+
+        Ok(IntSetIter {
+            inner,
+            values: None,
+            start,
+            range,
+        })
+    }
+
+    /// Iterates over every value, in order.
+    pub fn iter(&self) -> Result<IntSetIter<'_, S, std::ops::RangeFull>, Error<S::Error>> {
+        self.range(..)
+    }
+}
+
+/// Iterates over the values of an `IntSet` in a range, in order.
+pub struct IntSetIter<'a, S, R>
+where
+    S: Storage,
+{
+    inner: Iter<'a, u64, Container, S>,
+    values: Option<(u64, ContainerIter<'a>)>,
+    start: Option<u64>,
+    range: R,
+}
+
+impl<S, R> Iterator for IntSetIter<'_, S, R>
+where
+    S: Storage<Id = u64>,
+    R: RangeBounds<u64>,
+{
+    type Item = Result<u64, Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A start past `u64::MAX` leaves nothing in the range.
+        let start = self.start?;
+
+        loop {
+            if let Some((high, values)) = &mut self.values {
+                if let Some(low) = values.next() {
+                    let k = (*high << 16) | low as u64;
+                    let past_end = match self.range.end_bound() {
+                        Bound::Included(&end) => k > end,
+                        Bound::Excluded(&end) => k >= end,
+                        Bound::Unbounded => false,
+                    };
+                    if past_end {
+                        self.start = None;
+                        return None;
+                    }
+                    return Some(Ok(k));
+                }
+            }
+
+            let (&high, container) = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            let from = if high == start >> 16 { start as u16 } else { 0 };
+            self.values = Some((high, container.iter_from(from)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn containers() {
+        let mut container = Container::Array(vec![]);
+        for v in (0..10_000).step_by(2) {
+            assert!(container.insert(v));
+        }
+        assert!(matches!(container, Container::Bitmap { len: 5000, .. }));
+        assert!(!container.insert(42));
+        assert!(container.contains(9998) && !container.contains(9999));
+
+        for v in (0..10_000).step_by(4) {
+            assert!(container.remove(v));
+        }
+        assert!(matches!(&container, Container::Array(values) if values.len() == 2500));
+        assert!(container.iter_from(9000).eq((9002..10_000).step_by(4)));
+
+        let mut container = Container::bitmap((100..60_000).chain(u16::MAX - 1..=u16::MAX));
+        container.optimize();
+        assert_eq!(
+            container,
+            Container::Runs(vec![(100, 59_999), (u16::MAX - 1, u16::MAX)])
+        );
+        assert!(container.insert(60_000));
+        assert!(container.insert(99));
+        assert!(container.remove(500));
+        assert!(!container.remove(500));
+        assert!(container.insert(u16::MAX - 2));
+        assert_eq!(
+            container,
+            Container::Runs(vec![(99, 499), (501, 60_000), (u16::MAX - 2, u16::MAX)])
+        );
+        assert_eq!(container.len(), 59_904);
+        assert!(container
+            .iter_from(60_000)
+            .eq([60_000, 65_533, 65_534, 65_535]));
+    }
+
+    #[test]
+    fn int_set() -> Result<()> {
+        let mut set = IntSet::new(BTree::with_storage(MemStorage::new())?);
+        for k in 1_000_000..1_200_000 {
+            assert!(set.insert(k)?);
+        }
+        for k in (0..1_000_000).step_by(1000) {
+            assert!(set.insert(k)?);
+        }
+        assert!(set.insert(u64::MAX)?);
+        assert!(!set.insert(1_000_000)?);
+        assert_eq!(set.len()?, 201_001);
+
+        set.optimize()?;
+        let containers = set.tree.iter()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(containers.len() < 25);
+        assert!(matches!(containers[16].1, Container::Runs(_)));
+
+        assert!(set.contains(1_100_000)?);
+        assert!(set.contains(999_000)?);
+        assert!(!set.contains(999_001)?);
+        assert!(set.remove(1_100_000)?);
+        assert!(!set.remove(1_100_000)?);
+        assert!(!set.contains(1_100_000)?);
+
+        assert!(set
+            .range(1_099_998..=1_100_002)?
+            .map(Result::unwrap)
+            .eq([1_099_998, 1_099_999, 1_100_001, 1_100_002]));
+        assert!(set
+            .range(997_500..1_000_002)?
+            .map(Result::unwrap)
+            .eq([998_000, 999_000, 1_000_000, 1_000_001]));
+        assert!(set
+            .range((Bound::Excluded(1_199_999), Bound::Unbounded))?
+            .map(Result::unwrap)
+            .eq([u64::MAX]));
+        assert_eq!(
+            set.range((Bound::Excluded(u64::MAX), Bound::Unbounded))?
+                .count(),
+            0
+        );
+        assert_eq!(set.iter()?.count(), 201_000);
+
+        // Emptying a container removes it.
+        assert!(set.remove(u64::MAX)?);
+        assert_eq!(set.range(1_200_000..)?.count(), 0);
+
+        let root_id = set.persist()?;
+        let storage = set.into_inner().into_storage()?;
+        let set = IntSet::new(BTree::load_with_storage(root_id, storage)?);
+        assert_eq!(set.len()?, 200_999);
+        assert!(set.contains(1_199_999)?);
+
+        Ok(())
+    }
+}
@@ -1,64 +1,101 @@
-use std::marker::PhantomData;
-
-use crate::{error::Error, node::Node};
-use serde::Deserialize;
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+};
+use crate::comparator::Natural;
+use std::sync::Mutex;
 use storage::Storage;
 
-pub struct Iter<'a, K, V, S> {
-    nodes: Vec<u64>,
+pub struct Iter<'a, K, V, S, C = Natural> {
+    nodes: Vec<&'a Node<K, V, C>>,
     indices: Vec<usize>,
-    storage: &'a mut S,
-    pd: PhantomData<(K, V)>,
+    storage: &'a Mutex<S>,
 }
 
-impl<'a, K, V, S> Iter<'a, K, V, S>
+impl<'a, K, V, S, C> Iter<'a, K, V, S, C>
 where
-    for<'de> K: Deserialize<'de>,
-    for<'de> V: Deserialize<'de>,
+    K: KeyCodec,
+    V: ValueCodec,
     S: Storage<Id = u64>,
 {
     pub(crate) fn new(
-        mut root: &'a mut Node<K, V>,
-        storage: &'a mut S,
+        root: &'a Node<K, V, C>,
+        storage: &'a Mutex<S>,
     ) -> Result<Self, Error<S::Error>> {
-        let mut nodes = vec![];
-        let mut indices = vec![];
+        let mut iter = Self {
+            nodes: vec![],
+            indices: vec![],
+            storage,
+        };
 
         if !root.is_empty() {
-            while !root.is_leaf() {
-                nodes.push(root.id);
-                indices.push(0);
-                root = root.access_child(0, storage)?;
-            }
-            nodes.push(root.id);
-            indices.push(0);
+            iter.descend(root)?;
         }
 
-        Ok(Self {
-            nodes,
-            indices,
+        Ok(iter)
+    }
+
+    /// Starts at the first entry whose key isn't `before` the one sought, where `before` must
+    /// hold for a prefix of the keys in order.
+    pub(crate) fn seek(
+        root: &'a Node<K, V, C>,
+        storage: &'a Mutex<S>,
+        before: impl Fn(&K) -> bool,
+    ) -> Result<Self, Error<S::Error>> {
+        let mut iter = Self {
+            nodes: vec![],
+            indices: vec![],
             storage,
-            pd: PhantomData,
-        })
+        };
+
+        let mut node = root;
+        loop {
+            let idx = node.keys.partition_point(&before);
+
+            // Nodes with nothing left to yield after the child at `idx` are skipped.
+            if idx < node.len() {
+                iter.nodes.push(node);
+                iter.indices.push(idx);
+            }
+            if node.is_leaf() {
+                return Ok(iter);
+            }
+            node = node.load_child(idx, storage)?;
+        }
+    }
+
+    fn descend(&mut self, mut node: &'a Node<K, V, C>) -> Result<(), Error<S::Error>> {
+        while !node.is_leaf() {
+            self.nodes.push(node);
+            self.indices.push(0);
+            node = node.load_child(0, self.storage)?;
+        }
+        self.nodes.push(node);
+        self.indices.push(0);
+        Ok(())
     }
 }
 
-impl<'a, K, V, S> Iterator for Iter<'a, K, V, S>
+impl<'a, K, V, S, C> Iterator for Iter<'a, K, V, S, C>
 where
-    for<'de> K: Deserialize<'de> + 'a,
-    for<'de> V: Deserialize<'de> + 'a,
+    K: KeyCodec,
+    V: ValueCodec,
     S: Storage<Id = u64>,
 {
-    type Item = (K, V);
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.nodes.is_empty() {
             return None;
         }
 
-        let mut node = Node::load(*self.nodes.last().unwrap(), self.storage).ok()?;
+        let node = *self.nodes.last().unwrap();
         let mut idx = *self.indices.last().unwrap();
 
+        let key = node.keys.get(idx).unwrap();
+        let val = node.vals.get(idx).unwrap();
+
         idx += 1;
         *self.indices.last_mut().unwrap() = idx;
 
@@ -68,67 +105,64 @@ where
         }
 
         if idx < node.children.len() {
-            let mut n = node.access_child(idx, self.storage).ok()?;
-
-            while !n.is_leaf() {
-                self.nodes.push(n.id);
-                self.indices.push(0);
-                n = n.access_child(0, self.storage).ok()?;
+            let res = node
+                .load_child(idx, self.storage)
+                .and_then(|child| self.descend(child));
+
+            if let Err(err) = res {
+                // Stop iterating since we can't tell where the next entry is.
+                self.nodes.clear();
+                self.indices.clear();
+                return Some(Err(err));
             }
-
-            self.nodes.push(n.id);
-            self.indices.push(0);
         }
 
-        let key = node.keys.remove(idx - 1);
-        let val = node.vals.remove(idx - 1);
-
-        Some((key, val))
+        Some(Ok((key, val)))
     }
 }
 
-pub struct Keys<'a, K, V, S> {
-    inner: Iter<'a, K, V, S>,
+pub struct Keys<'a, K, V, S, C = Natural> {
+    inner: Iter<'a, K, V, S, C>,
 }
 
-impl<'a, K, V, S> Keys<'a, K, V, S> {
-    pub(crate) fn new(inner: Iter<'a, K, V, S>) -> Self {
+impl<'a, K, V, S, C> Keys<'a, K, V, S, C> {
+    pub(crate) fn new(inner: Iter<'a, K, V, S, C>) -> Self {
         Self { inner }
     }
 }
 
-impl<'a, K, V, S> Iterator for Keys<'a, K, V, S>
+impl<'a, K, V, S, C> Iterator for Keys<'a, K, V, S, C>
 where
-    for<'de> K: Deserialize<'de> + 'a,
-    for<'de> V: Deserialize<'de> + 'a,
+    K: KeyCodec,
+    V: ValueCodec,
     S: Storage<Id = u64>,
 {
-    type Item = K;
+    type Item = Result<&'a K, Error<S::Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|(k, _)| k)
+        self.inner.next().map(|res| res.map(|(k, _)| k))
     }
 }
 
-pub struct Values<'a, K, V, S> {
-    inner: Iter<'a, K, V, S>,
+pub struct Values<'a, K, V, S, C = Natural> {
+    inner: Iter<'a, K, V, S, C>,
 }
 
-impl<'a, K, V, S> Values<'a, K, V, S> {
-    pub(crate) fn new(inner: Iter<'a, K, V, S>) -> Self {
+impl<'a, K, V, S, C> Values<'a, K, V, S, C> {
+    pub(crate) fn new(inner: Iter<'a, K, V, S, C>) -> Self {
         Self { inner }
     }
 }
 
-impl<'a, K, V, S> Iterator for Values<'a, K, V, S>
+impl<'a, K, V, S, C> Iterator for Values<'a, K, V, S, C>
 where
-    for<'de> K: Deserialize<'de> + 'a,
-    for<'de> V: Deserialize<'de> + 'a,
+    K: KeyCodec,
+    V: ValueCodec,
     S: Storage<Id = u64>,
 {
-    type Item = V;
+    type Item = Result<&'a V, Error<S::Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|(_, v)| v)
+        self.inner.next().map(|res| res.map(|(_, v)| v))
     }
 }
@@ -0,0 +1,303 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    SharedBTree,
+};
+use crate::comparator::Comparator;
+use std::{
+    fmt::{self, Debug},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use storage::Storage;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lists the objects in a tree's storage, for `MaintenancePolicy::gc`.
+pub type ListObjects = Arc<dyn Fn() -> Vec<u64> + Send + Sync>;
+
+/// What the background maintenance task does, and how often.
+#[derive(Clone)]
+pub struct MaintenancePolicy {
+    /// Time to wait between runs.
+    pub interval: Duration,
+
+    /// Persist the tree on every run.
+    pub flush: bool,
+
+    /// Unload every node but the root after persisting.
+    pub trim_cache: bool,
+
+    /// Free the orphaned nodes among the objects this lists on every run, like `BTree::gc`.
+    ///
+    /// Storage can't list the objects it holds, so they have to be supplied, e.g. from the
+    /// file names of a `DirectoryStorage`. Collecting persists the tree first.
+    pub gc: Option<ListObjects>,
+}
+
+impl Default for MaintenancePolicy {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            flush: true,
+            trim_cache: false,
+            gc: None,
+        }
+    }
+}
+
+impl Debug for MaintenancePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MaintenancePolicy")
+            .field("interval", &self.interval)
+            .field("flush", &self.flush)
+            .field("trim_cache", &self.trim_cache)
+            .field("gc", &self.gc.is_some())
+            .finish()
+    }
+}
+
+/// Counters describing what the maintenance task has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceStats {
+    pub runs: u64,
+    pub flushes: u64,
+    pub trims: u64,
+    pub collections: u64,
+    pub freed: u64,
+    pub errors: u64,
+    pub last_run_micros: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    runs: AtomicU64,
+    flushes: AtomicU64,
+    trims: AtomicU64,
+    collections: AtomicU64,
+    freed: AtomicU64,
+    errors: AtomicU64,
+    last_run_micros: AtomicU64,
+}
+
+/// Handle to a background thread that periodically maintains a `SharedBTree`.
+///
+/// The thread is stopped when the handle is dropped.
+pub struct Maintenance {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl Maintenance {
+    pub fn start<K, V, S, C>(tree: Arc<SharedBTree<K, V, S, C>>, policy: MaintenancePolicy) -> Self
+    where
+        K: KeyCodec + Send + Sync + 'static,
+        V: ValueCodec + Send + Sync + 'static,
+        S: Storage<Id = u64> + Send + Sync + 'static,
+        C: Comparator<K> + 'static,
+    {
+        let (stop, rx) = mpsc::channel();
+        let counters = Arc::new(Counters::default());
+
+        let thread = {
+            let counters = Arc::clone(&counters);
+            thread::spawn(move || {
+                // Both an explicit stop and a dropped sender end the loop.
+                while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(policy.interval) {
+                    let start = Instant::now();
+
+                    if policy.trim_cache {
+                        // Trimming persists the tree first, so it doubles as a flush.
+                        match tree.trim_cache() {
+                            Ok(_) => {
+                                counters.flushes.fetch_add(1, Ordering::Relaxed);
+                                counters.trims.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(_) => {
+                                counters.errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    } else if policy.flush {
+                        match tree.persist() {
+                            Ok(_) => {
+                                counters.flushes.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(_) => {
+                                counters.errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+
+                    if let Some(list) = &policy.gc {
+                        match tree.gc(list()) {
+                            Ok(report) => {
+                                counters.collections.fetch_add(1, Ordering::Relaxed);
+                                counters
+                                    .freed
+                                    .fetch_add(report.orphans.len() as u64, Ordering::Relaxed);
+                            }
+                            Err(_) => {
+                                counters.errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+
+                    counters.runs.fetch_add(1, Ordering::Relaxed);
+                    counters
+                        .last_run_micros
+                        .store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+                }
+            })
+        };
+
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+            counters,
+        }
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        MaintenanceStats {
+            runs: self.counters.runs.load(Ordering::Relaxed),
+            flushes: self.counters.flushes.load(Ordering::Relaxed),
+            trims: self.counters.trims.load(Ordering::Relaxed),
+            collections: self.counters.collections.load(Ordering::Relaxed),
+            freed: self.counters.freed.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+            last_run_micros: self.counters.last_run_micros.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stops the background thread, waiting for any run in progress to finish.
+    pub fn stop(mut self) -> MaintenanceStats {
+        self.shutdown();
+        self.stats()
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Maintenance {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::BTree;
+    use anyhow::Result;
+    use std::fs;
+
+    /// Waits for the maintenance task to have run at least once.
+    fn wait_for_run(maintenance: &Maintenance) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while maintenance.stats().runs == 0 {
+            assert!(Instant::now() < deadline, "maintenance never ran");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn flush_and_trim() -> Result<()> {
+        let tree = Arc::new(SharedBTree::new(BTree::<u64, u64>::new(
+            "/tmp/btreedir-maintenance",
+        )?));
+
+        let maintenance = Maintenance::start(
+            Arc::clone(&tree),
+            MaintenancePolicy {
+                interval: Duration::from_millis(5),
+                flush: true,
+                trim_cache: true,
+                gc: None,
+            },
+        );
+
+        for i in 0..1000 {
+            tree.insert(i, i + 1)?;
+        }
+
+        wait_for_run(&maintenance);
+        let stats = maintenance.stop();
+
+        assert!(stats.runs > 0);
+        assert_eq!(stats.flushes, stats.runs);
+        assert_eq!(stats.trims, stats.runs);
+        assert_eq!(stats.collections, 0);
+        assert_eq!(stats.errors, 0);
+
+        for i in 0..1000 {
+            assert_eq!(tree.get(&i)?, Some(i + 1));
+        }
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-maintenance");
+
+        Ok(())
+    }
+    #[test]
+    fn gc() -> Result<()> {
+        let path = "/tmp/btreedir-maintenance-gc";
+        let _ = fs::remove_dir_all(path);
+        let mut tree = BTree::<u64, u64>::new(path)?;
+
+        // Nodes that were persisted and then merged away are left behind in storage.
+        for i in 0..500 {
+            tree.insert(i, i)?;
+        }
+        tree.persist()?;
+        for i in 0..450 {
+            tree.remove(&i)?;
+        }
+
+        // Every file in the directory named by an ID is an object.
+        let list = move || {
+            fs::read_dir(path)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect()
+        };
+
+        let tree = Arc::new(SharedBTree::new(tree));
+        let maintenance = Maintenance::start(
+            Arc::clone(&tree),
+            MaintenancePolicy {
+                interval: Duration::from_millis(5),
+                flush: false,
+                trim_cache: false,
+                gc: Some(Arc::new(list)),
+            },
+        );
+
+        wait_for_run(&maintenance);
+        let stats = maintenance.stop();
+
+        assert_eq!(stats.collections, stats.runs);
+        assert!(stats.freed > 0);
+        assert_eq!(stats.errors, 0);
+
+        let tree = Arc::into_inner(tree).unwrap().into_inner()?;
+        let report = tree.verify_with_objects(list())?;
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.entries, 50);
+
+        let _ = fs::remove_dir_all(path);
+
+        Ok(())
+    }
+}
@@ -1,37 +1,94 @@
+// Emits a trace-level event when the `tracing` feature is enabled.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+mod blob;
+mod budget;
+mod bulk;
+mod cached;
+mod codec;
+mod columns;
+mod dot;
 pub mod error;
-// mod iter; // Broken
+mod gc;
+mod hooks;
+mod intset;
+mod iter;
+mod maintenance;
 mod node;
+mod occupancy;
+mod partitioned;
+mod prefix;
+mod render;
+mod salvage;
+mod schema;
+mod scoped;
+mod shared;
+mod soft;
+#[cfg(feature = "sstable")]
+pub mod sstable;
+mod stats;
+mod timestamped;
+mod verify;
+mod versioned;
 
+pub use crate::comparator::{Comparator, Descending, Natural};
+pub use blob::{Blob, BlobBTree, BlobReader, BlobWriter, CHUNK_SIZE};
+pub use budget::{Budget, NodeBudget};
+pub use cached::CachedBTree;
+pub use codec::{CodecError, KeyCodec, ValueCodec};
+pub use columns::{ColumnarBTree, Projection, Row};
 use embedded_io::{
     blocking::{Read, Seek, Write},
     SeekFrom,
 };
 use error::Error;
-// use iter::{Iter, Keys, Values};
+pub use gc::GcReport;
+pub use hooks::Event;
+use hooks::Hooks;
+pub use intset::{Container, IntSet, IntSetIter};
+use iter::{Iter, Keys, Values};
+pub use maintenance::{ListObjects, Maintenance, MaintenancePolicy, MaintenanceStats};
 use node::{Child, Node};
-use serde::{Deserialize, Serialize};
-use std::mem;
+pub use partitioned::PartitionedBTree;
+pub use prefix::{Prefix, PrefixIter};
+pub use salvage::{LostRange, SalvageReport};
+pub use schema::Schema;
+use schema::SharedSchema;
+pub use scoped::{Scoped, ScopedIter};
+pub use shared::{SharedBTree, ValueRef};
+pub use soft::{Record, SoftBTree, SoftIter};
+pub use stats::KeyStats;
+use std::{mem, ops::RangeBounds, sync::Mutex};
 use storage::{
     dir::{self, DirectoryStorage},
     Storage,
 };
+pub use timestamped::{Meta, Stamped, TimestampedBTree};
+pub use verify::{Problem, VerifyReport};
+pub use versioned::{VersionedBTree, VersionedIter};
 
 const DEFAULT_DEGREE: usize = 2;
 
-pub struct BTree<K, V, S = DirectoryStorage>
+pub struct BTree<K, V, S = DirectoryStorage, C = Natural>
 where
     S: Storage,
 {
     len: usize,
     degree: usize,
-    root: Node<K, V>,
-    storage: S,
+    root: Node<K, V, C>,
+    storage: Mutex<S>,
+    hooks: Hooks,
 }
 
 impl<K, V> BTree<K, V, DirectoryStorage>
 where
-    for<'de> K: Ord + Serialize + Deserialize<'de>,
-    for<'de> V: Serialize + Deserialize<'de>,
+    K: Ord + KeyCodec,
+    V: ValueCodec,
 {
     pub fn new(path: impl AsRef<str>) -> Result<Self, Error<dir::Error>> {
         Self::with_degree(path, DEFAULT_DEGREE)
@@ -48,20 +105,66 @@ where
 
 impl<K, V, S> BTree<K, V, S>
 where
-    for<'de> K: Ord + Serialize + Deserialize<'de>,
-    for<'de> V: Serialize + Deserialize<'de>,
+    K: Ord + KeyCodec,
+    V: ValueCodec,
     S: Storage<Id = u64>,
 {
     pub fn with_storage(storage: S) -> Result<Self, Error<S::Error>> {
         Self::with_storage_and_degree(storage, DEFAULT_DEGREE)
     }
 
-    pub fn with_storage_and_degree(mut storage: S, degree: usize) -> Result<Self, Error<S::Error>> {
+    pub fn with_storage_and_degree(storage: S, degree: usize) -> Result<Self, Error<S::Error>> {
+        Self::with_schema(storage, degree, Schema::default())
+    }
+
+    /// Creates a tree whose values are written under `schema`'s version.
+    pub fn with_schema(
+        storage: S,
+        degree: usize,
+        schema: Schema<V>,
+    ) -> Result<Self, Error<S::Error>> {
+        Self::create(storage, degree, schema)
+    }
+
+    pub fn load_with_storage(id: u64, storage: S) -> Result<Self, Error<S::Error>> {
+        Self::load_with_schema(id, storage, Schema::default())
+    }
+
+    /// Loads a tree, upgrading values written under older versions of `schema` as they're read.
+    pub fn load_with_schema(
+        id: u64,
+        storage: S,
+        schema: Schema<V>,
+    ) -> Result<Self, Error<S::Error>> {
+        Self::open(id, storage, schema)
+    }
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Creates a tree that orders its keys by `C` rather than by `Ord`.
+    pub fn with_comparator(storage: S, degree: usize) -> Result<Self, Error<S::Error>> {
+        Self::create(storage, degree, Schema::default())
+    }
+
+    /// Loads a tree that was created with `with_comparator`, failing with
+    /// `Error::Comparator` if it's ordered by a different comparator.
+    pub fn load_with_comparator(id: u64, storage: S) -> Result<Self, Error<S::Error>> {
+        Self::open(id, storage, Schema::default())
+    }
+
+    fn create(mut storage: S, degree: usize, schema: Schema<V>) -> Result<Self, Error<S::Error>> {
         Ok(Self {
             len: 0,
             degree,
-            root: Node::new(storage.alloc_id()?),
-            storage,
+            root: Node::new(storage.alloc_id()?, SharedSchema::new(schema)),
+            storage: Mutex::new(storage),
+            hooks: Hooks::default(),
         })
     }
 
@@ -73,13 +176,28 @@ where
         self.len() == 0
     }
 
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
     pub fn root_id(&self) -> u64 {
         self.root.id
     }
 
-    pub fn load_with_storage(id: u64, mut storage: S) -> Result<Self, Error<S::Error>> {
+    /// Registers a callback that's run on every split, merge, borrow, and root change.
+    pub fn on_event(&mut self, hook: impl Fn(&Event) + Send + Sync + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Unregisters every callback registered with `on_event`.
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", name = "btree.load", skip_all, fields(id = id)))]
+    fn open(id: u64, mut storage: S, schema: Schema<V>) -> Result<Self, Error<S::Error>> {
         // Load the root node.
-        let root = Node::load(id, &mut storage)?;
+        let root = Node::load(id, &mut storage, &SharedSchema::new(schema))?;
 
         // To load with the extra metadata at the end.
         let mut len_raw = [0; mem::size_of::<u64>()];
@@ -96,20 +214,42 @@ where
                 .map_err(|_| Error::Read)?;
         }
 
+        // The comparator's ID shares a word with the degree, in its upper half.
+        let degree = u64::from_le_bytes(degree_raw);
+        let comparator = (degree >> 32) as u32;
+        if comparator != C::ID {
+            return Err(Error::Comparator {
+                expected: C::ID,
+                found: comparator,
+            });
+        }
+
         Ok(Self {
             len: u64::from_le_bytes(len_raw) as usize,
-            degree: u64::from_le_bytes(degree_raw) as usize,
+            degree: degree as u32 as usize,
             root,
-            storage,
+            storage: Mutex::new(storage),
+            hooks: Hooks::default(),
         })
     }
 
+    /// Returns the storage, discarding any changes that haven't been persisted.
+    pub fn into_storage(self) -> Result<S, Error<S::Error>> {
+        self.storage.into_inner().map_err(|_| Error::Poisoned)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", name = "btree.flush", skip_all, fields(root = self.root.id, len = self.len)))]
     pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
         // Persist the root node.
-        self.root.persist(&mut self.storage)?;
+        self.root.persist(storage)?;
 
         // Acquire a write handle.
-        let mut writer = self.storage.write_handle(&self.root.id)?;
+        let mut writer = storage.write_handle(&self.root.id)?;
 
         // Append extra metadata to the end.
         writer.seek(SeekFrom::End(0)).map_err(|_| Error::Seek)?;
@@ -117,51 +257,102 @@ where
             .write_all(&(self.len as u64).to_le_bytes())
             .map_err(|_| Error::Write)?;
         writer
-            .write_all(&(self.degree as u64).to_le_bytes())
+            .write_all(&(self.degree as u64 | (C::ID as u64) << 32).to_le_bytes())
             .map_err(|_| Error::Write)?;
 
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("btree_flush_duration_seconds").record(start.elapsed().as_secs_f64());
+
         Ok(self.root.id)
     }
 
-    pub fn contains(&mut self, k: &K) -> Result<bool, Error<S::Error>> {
+    /// Persists the tree and then unloads every node except the root, returning the root ID.
+    pub fn trim_cache(&mut self) -> Result<u64, Error<S::Error>> {
+        let id = self.persist()?;
+
+        for child in &mut self.root.children {
+            *child = Child::unloaded(child.id());
+        }
+
+        Ok(id)
+    }
+
+    /// Loads every node that may hold keys in `range`, ahead of accessing them.
+    ///
+    /// Returns the number of nodes read from storage.
+    pub fn warm_cache(&self, range: impl RangeBounds<K>) -> Result<usize, Error<S::Error>> {
+        self.root.warm(&range, &self.storage)
+    }
+
+    /// Loads the nodes on the paths to `keys`, ahead of accessing them.
+    ///
+    /// Returns the number of nodes read from storage.
+    pub fn prefetch<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> Result<usize, Error<S::Error>>
+    where
+        K: 'a,
+    {
+        keys.into_iter().try_fold(0, |loaded, k| {
+            Ok(loaded + self.root.prefetch(k, &self.storage)?)
+        })
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
         Ok(self.get(k)?.is_some())
     }
 
-    pub fn get(&mut self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", name = "btree.get", skip_all)
+    )]
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "get").increment(1);
+
         Ok(self
             .root
-            .get(k, &mut self.storage)?
+            .get(k, &self.storage)?
             .map(|(idx, node)| &node.vals[idx]))
     }
 
     pub fn get_mut(&mut self, k: &K) -> Result<Option<&mut V>, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "get").increment(1);
+
         Ok(self
             .root
-            .get_mut(k, &mut self.storage)?
+            .get_mut(k, self.storage.get_mut().map_err(|_| Error::Poisoned)?)?
             .map(|(idx, node)| &mut node.vals[idx]))
     }
 
-    pub fn get_key_value(&mut self, k: &K) -> Result<Option<(&K, &V)>, Error<S::Error>> {
+    pub fn get_key_value(&self, k: &K) -> Result<Option<(&K, &V)>, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "get").increment(1);
+
         Ok(self
             .root
-            .get(k, &mut self.storage)?
+            .get(k, &self.storage)?
             .map(|(idx, node)| (&node.keys[idx], &node.vals[idx])))
     }
 
-    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>>
-    where
-        K: Ord,
-    {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", name = "btree.insert", skip_all)
+    )]
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "insert").increment(1);
+
         if self.root.is_full(self.degree) {
-            let mut new_root = Node::new(self.storage.alloc_id()?);
-            mem::swap(&mut self.root, &mut new_root);
-            self.root.children.push(Child::Loaded(new_root));
-            self.root.split_child(0, self.degree, &mut self.storage)?;
+            self.grow_root()?;
         }
 
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
         let res = self
             .root
-            .insert_nonfull(k, v, self.degree, &mut self.storage)?;
+            .insert_nonfull(k, v, self.degree, storage, &self.hooks)?;
 
         if res.is_none() {
             self.len += 1;
@@ -170,53 +361,183 @@ where
         Ok(res)
     }
 
-    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>>
+    /// Returns the value of `k`, computing it with `f` and persisting the tree if the key
+    /// doesn't exist.
+    ///
+    /// The key is looked up and the computed value inserted in a single descent, and `f` is
+    /// only run if the key is missing. The value is returned by copy, since persisting the tree
+    /// needs it to be borrowed.
+    pub fn get_or_compute(&mut self, k: K, f: impl FnOnce() -> V) -> Result<V, Error<S::Error>>
     where
-        K: Ord,
+        V: Clone,
     {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "get_or_compute").increment(1);
+
+        if self.root.is_full(self.degree) {
+            self.grow_root()?;
+        }
+
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+        let (v, inserted) =
+            self.root
+                .get_or_insert_with(k, f, self.degree, storage, &self.hooks)?;
+        let v = v.clone();
+
+        if inserted {
+            self.len += 1;
+            self.persist()?;
+        }
+
+        Ok(v)
+    }
+
+    /// Inserts an entry, failing with `Error::AlreadyExists` if the key is present.
+    ///
+    /// The tree is only changed if the entry is inserted, which takes a single descent.
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<(), Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "try_insert").increment(1);
+
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
+        if !self
+            .root
+            .insert_new(k, v, self.degree, 0, storage, &self.hooks)?
+        {
+            return Err(Error::AlreadyExists);
+        }
+
+        if self.root.len() == 2 * self.degree {
+            self.grow_root()?;
+        }
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Inserts an entry whose key is larger than every key in the tree, failing with
+    /// `Error::Unsorted` if it isn't.
+    ///
+    /// This skips searching each node on the way down, and only splits nodes that overflow,
+    /// which suits keys that arrive in increasing order like timestamps or sequence numbers.
+    pub fn append_max(&mut self, k: K, v: V) -> Result<(), Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "append_max").increment(1);
+
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
+        if !self
+            .root
+            .append_max(k, v, self.degree, 0, storage, &self.hooks)?
+        {
+            return Err(Error::Unsorted);
+        }
+
+        if self.root.len() == 2 * self.degree {
+            self.grow_root()?;
+        }
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Splits the full, or overfull, root under a new, empty one.
+    fn grow_root(&mut self) -> Result<(), Error<S::Error>> {
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
+        let schema = SharedSchema::clone(&self.root.schema);
+        let mut new_root = Node::new(storage.alloc_id()?, schema);
+        mem::swap(&mut self.root, &mut new_root);
+        trace!(old = new_root.id, new = self.root.id, "grow root");
+        self.hooks.emit(Event::RootGrown {
+            old: new_root.id,
+            new: self.root.id,
+        });
+        self.root.children.push(Child::loaded(new_root));
+        self.root
+            .split_child(0, self.degree, 1, storage, &self.hooks)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>> {
         Ok(self.remove_entry(k)?.map(|(_, val)| val))
     }
 
-    pub fn remove_entry(&mut self, k: &K) -> Result<Option<(K, V)>, Error<S::Error>>
-    where
-        K: Ord,
-    {
-        if let Some(entry) = self.root.remove(k, self.degree, &mut self.storage)? {
-            if !self.root.is_leaf() && self.root.is_empty() {
-                self.root = self.root.children.pop().unwrap().as_option_owned().unwrap();
-            }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", name = "btree.remove", skip_all)
+    )]
+    pub fn remove_entry(&mut self, k: &K) -> Result<Option<(K, V)>, Error<S::Error>> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("btree_ops_total", "op" => "remove").increment(1);
+
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
+        let entry = self.root.remove(k, self.degree, 0, storage, &self.hooks)?;
+
+        // Rebalancing on the way down can empty the root even if the key isn't found.
+        if !self.root.is_leaf() && self.root.is_empty() {
+            let old = self.root.id;
+            self.root = self.root.children.pop().unwrap().as_option_owned().unwrap();
+            trace!(new = self.root.id, "shrink root");
+            self.hooks.emit(Event::RootShrunk {
+                old,
+                new: self.root.id,
+            });
+        }
+
+        if entry.is_some() {
             self.len -= 1;
-            Ok(Some(entry))
-        } else {
-            Ok(None)
         }
+
+        Ok(entry)
     }
 
     pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        let storage = self.storage.get_mut().map_err(|_| Error::Poisoned)?;
+
         self.len = 0;
-        self.root.clear(&mut self.storage)?;
-        self.root = Node::new(self.storage.alloc_id()?);
+        self.root.clear(storage)?;
+        self.root = Node::new(storage.alloc_id()?, SharedSchema::clone(&self.root.schema));
         Ok(self.root.id)
     }
 
-    // pub fn iter(&mut self) -> Result<Iter<'_, K, V, S>, Error<S::Error>> {
-    //     Iter::new(&mut self.root, &mut self.storage)
-    // }
+    pub fn iter(&self) -> Result<Iter<'_, K, V, S, C>, Error<S::Error>> {
+        Iter::new(&self.root, &self.storage)
+    }
 
-    // pub fn keys(&mut self) -> Result<Keys<'_, K, V, S>, Error<S::Error>> {
-    //     self.iter().map(Keys::new)
-    // }
+    pub fn keys(&self) -> Result<Keys<'_, K, V, S, C>, Error<S::Error>> {
+        self.iter().map(Keys::new)
+    }
 
-    // pub fn values(&mut self) -> Result<Values<'_, K, V, S>, Error<S::Error>> {
-    //     self.iter().map(Values::new)
-    // }
+    pub fn values(&self) -> Result<Values<'_, K, V, S, C>, Error<S::Error>> {
+        self.iter().map(Values::new)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::comparator::CaseInsensitive;
     use anyhow::Result;
-    use std::fs;
+    use embedded_storage::{
+        nor_flash::{
+            check_erase, check_read, check_write, ErrorType, MultiwriteNorFlash, NorFlash,
+            NorFlashErrorKind, ReadNorFlash,
+        },
+        ReadStorage,
+    };
+    use std::{fs, sync::Arc, thread, time::Duration};
+    use storage::{
+        embedded::{EepromStorage, NorFlashAdapter},
+        flash::{FlashStorage, RamFlash},
+        kv::{FileKv, KvBackend, KvStorage},
+        mem::MemStorage,
+        retry::{Backoff, RetryStorage, Transient},
+        sim::{Profile, SimStorage},
+    };
 
     #[test]
     fn simple() -> Result<()> {
@@ -246,7 +567,7 @@ mod tests {
         }
         assert_eq!(tree.len(), 1000);
 
-        let mut tree = BTree::load(tree.persist()?, "/tmp/btreedir-reload")?;
+        let tree = BTree::load(tree.persist()?, "/tmp/btreedir-reload")?;
 
         for i in 0..1000 {
             assert_eq!(tree.get_key_value(&i)?, Some((&i, &(i + 1))));
@@ -256,4 +577,808 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn bulk_load() -> Result<()> {
+        for n in [0, 1, 3, 4, 100, 1000] {
+            let tree = BTree::bulk_load("/tmp/btreedir-bulk", (0..n).map(|i| (i, i + 1)))?;
+            assert_eq!(tree.len(), n);
+
+            for i in 0..n {
+                assert_eq!(tree.get(&i)?, Some(&(i + 1)));
+            }
+            assert_eq!(tree.get(&n)?, None);
+
+            let _ = fs::remove_dir_all("/tmp/btreedir-bulk");
+        }
+
+        assert!(matches!(
+            BTree::<i32, i32>::bulk_load("/tmp/btreedir-bulk", [(1, 1), (0, 0)]),
+            Err(Error::Unsorted)
+        ));
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-bulk");
+
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_load_then_modify() -> Result<()> {
+        let mut tree = BTree::bulk_load("/tmp/btreedir-bulk-modify", (0..500).map(|i| (i, i)))?;
+
+        for i in 500..1000 {
+            assert_eq!(tree.insert(i, i)?, None);
+        }
+
+        for i in (0..1000).step_by(2) {
+            assert_eq!(tree.remove(&i)?, Some(i));
+        }
+
+        let tree = BTree::<i32, i32>::load(tree.persist()?, "/tmp/btreedir-bulk-modify")?;
+        assert_eq!(tree.len(), 500);
+
+        for i in 0..1000 {
+            assert_eq!(tree.contains(&i)?, i % 2 == 1);
+        }
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-bulk-modify");
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_bulk_load() -> Result<()> {
+        // Small trees may have no level with enough nodes to split between the workers, and
+        // are built on one thread.
+        for len in [0, 1000, 200_000] {
+            let mut tree = BTree::par_bulk_load_with_storage_and_degree(
+                MemStorage::new(),
+                3,
+                (0..len).map(|i| (i, i + 1)),
+            )?;
+
+            let report = tree.verify()?;
+            assert!(report.is_ok(), "{report:?}");
+            let expected = BTree::bulk_load_with_storage_and_degree(
+                MemStorage::new(),
+                3,
+                (0..len).map(|i| (i, i + 1)),
+            )?
+            .verify()?;
+            assert_eq!(report, expected);
+
+            let root_id = tree.persist()?;
+            let tree = BTree::<i32, i32, _>::load_with_storage(root_id, tree.into_storage()?)?;
+            for i in (0..len).step_by(7) {
+                assert_eq!(tree.get(&i)?, Some(&(i + 1)));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn iter() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-iter")?;
+
+        for i in (0..1000).rev() {
+            tree.insert(i, i + 1)?;
+        }
+
+        let tree = BTree::<i32, i32>::load(tree.persist()?, "/tmp/btreedir-iter")?;
+
+        for (i, kv) in tree.iter()?.enumerate() {
+            assert_eq!((&(i as i32), &(i as i32 + 1)), kv?);
+        }
+
+        for (i, k) in tree.keys()?.enumerate() {
+            assert_eq!(&(i as i32), k?);
+        }
+
+        for (i, v) in tree.values()?.enumerate() {
+            assert_eq!(&(i as i32 + 1), v?);
+        }
+
+        assert_eq!(tree.iter()?.count(), 1000);
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-iter");
+
+        Ok(())
+    }
+
+    #[test]
+    fn send_sync() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<BTree<u64, u64>>();
+
+        let mut tree = BTree::<u64, u64>::new("/tmp/btreedir-send-sync")?;
+
+        for i in 0..1000 {
+            tree.insert(i, i + 1)?;
+        }
+
+        // Readers share the reloaded tree directly and load nodes on demand.
+        let tree = Arc::new(BTree::<u64, u64>::load(
+            tree.persist()?,
+            "/tmp/btreedir-send-sync",
+        )?);
+
+        let readers = (0..4)
+            .map(|_| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        assert_eq!(tree.get(&i).unwrap(), Some(&(i + 1)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-send-sync");
+
+        Ok(())
+    }
+
+    #[test]
+    fn events() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-events")?;
+
+        let events = Arc::new(Mutex::new(vec![]));
+        tree.on_event({
+            let events = Arc::clone(&events);
+            move |event| events.lock().unwrap().push(*event)
+        });
+
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+
+        // Every split and root growth adds exactly one node.
+        let added = events.lock().unwrap().len();
+        assert_eq!(tree.occupancy()?.nodes(), added + 1);
+        assert!(events
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|event| matches!(event, Event::Split { .. } | Event::RootGrown { .. })));
+
+        let grown = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| matches!(event, Event::RootGrown { .. }))
+            .count();
+        events.lock().unwrap().clear();
+
+        for i in 0..100 {
+            tree.remove(&i)?;
+        }
+
+        // Emptying the tree shrinks it all the way back down to a single root.
+        let events = events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Merge { .. })));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Borrow { .. })));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, Event::RootShrunk { .. }))
+                .count(),
+            grown
+        );
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-events");
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulated_storage() -> Result<()> {
+        let storage = SimStorage::new(
+            MemStorage::new(),
+            Profile {
+                read_latency: Duration::from_micros(100),
+                read_bandwidth: Some(1_000_000),
+                ..Default::default()
+            },
+        );
+        let monitor = storage.monitor();
+        let mut tree = BTree::with_storage(storage)?;
+
+        for i in 0..1000 {
+            tree.insert(i, i)?;
+        }
+
+        let height = tree.verify()?.height as u64;
+        tree.trim_cache()?;
+        monitor.reset();
+
+        // A cold lookup reads one node per level below the root.
+        assert_eq!(tree.get(&500)?, Some(&500));
+        let stats = monitor.stats();
+        assert_eq!(stats.reads, height - 1);
+        assert_eq!(stats.writes, 0);
+        assert_eq!(
+            stats.elapsed,
+            Duration::from_micros(100 * stats.reads + stats.bytes_read)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn warm_cache() -> Result<()> {
+        let storage = SimStorage::new(MemStorage::new(), Profile::default());
+        let monitor = storage.monitor();
+        let mut tree = BTree::with_storage(storage)?;
+
+        for i in 0..1000 {
+            tree.insert(i, i)?;
+        }
+
+        let nodes = tree.verify()?.nodes;
+        tree.trim_cache()?;
+        monitor.reset();
+
+        // Once warmed, lookups in the range don't touch storage.
+        let loaded = tree.warm_cache(200..300)?;
+        assert!(loaded > 0);
+        assert_eq!(tree.warm_cache(200..300)?, 0);
+        for i in 200..300 {
+            assert_eq!(tree.get(&i)?, Some(&i));
+        }
+        assert_eq!(monitor.stats().reads, loaded as u64);
+
+        assert_eq!(tree.prefetch(&[250])?, 0);
+        assert!(tree.prefetch(&[0, 999])? > 0);
+        assert_eq!(tree.prefetch(&[0, 999])?, 0);
+
+        // A single key only needs the path to it.
+        tree.trim_cache()?;
+        let path = tree.prefetch(&[500])?;
+        tree.trim_cache()?;
+        assert_eq!(tree.warm_cache(500..=500)?, path);
+
+        tree.trim_cache()?;
+        assert_eq!(tree.warm_cache(..)?, nodes - 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flash_storage() -> Result<()> {
+        let storage = FlashStorage::format(RamFlash::new(512, 128))?.leveling(8);
+        let mut tree = BTree::with_storage(storage)?;
+
+        for round in 0..50 {
+            for i in 0..100 {
+                tree.insert(i, round)?;
+            }
+            tree.persist()?;
+        }
+
+        let root_id = tree.persist()?;
+        let storage = tree.into_storage()?;
+
+        // Rewriting the same keys has to recycle blocks, evenly.
+        let stats = storage.stats();
+        assert!(stats.min_erases > 1);
+        assert!(stats.max_erases - stats.min_erases <= 8 + 1);
+
+        let tree = BTree::<i32, i32, _>::load_with_storage(
+            root_id,
+            FlashStorage::mount(storage.into_inner())?,
+        )?;
+        assert_eq!(tree.len(), 100);
+        for i in 0..100 {
+            assert_eq!(tree.get(&i)?, Some(&49));
+        }
+
+        Ok(())
+    }
+
+    /// NOR flash with word-aligned reads and writes, like a typical driver.
+    struct Nor(Vec<u8>);
+
+    impl ErrorType for Nor {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for Nor {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            check_read(self, offset, bytes.len())?;
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl NorFlash for Nor {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 256;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            check_erase(self, from, to)?;
+            self.0[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            check_write(self, offset, bytes.len())?;
+            for (old, new) in self.0[offset as usize..].iter_mut().zip(bytes) {
+                *old &= new;
+            }
+            Ok(())
+        }
+    }
+
+    impl MultiwriteNorFlash for Nor {}
+
+    struct Eeprom(Vec<u8>);
+
+    impl ReadStorage for Eeprom {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl embedded_storage::Storage for Eeprom {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            self.0
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(())?
+                .copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn embedded_storage() -> Result<()> {
+        let nor = NorFlashAdapter::new(Nor(vec![0; 256 * 128]));
+        let mut tree = BTree::with_storage(FlashStorage::format(nor)?)?;
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+        let root_id = tree.persist()?;
+
+        let nor = tree.into_storage()?.into_inner();
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, FlashStorage::mount(nor)?)?;
+        let entries = tree.iter()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(entries
+            .into_iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..100).map(|i| (i, i))));
+
+        let eeprom = EepromStorage::format(Eeprom(vec![0; 128 * 128]), 128)?;
+        let mut tree = BTree::with_storage(eeprom)?;
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+        for i in (0..100).step_by(2) {
+            tree.remove(&i)?;
+        }
+        let root_id = tree.persist()?;
+
+        let eeprom = tree.into_storage()?.into_inner();
+        let tree =
+            BTree::<i32, i32, _>::load_with_storage(root_id, EepromStorage::mount(eeprom, 128)?)?;
+        let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq((1..100).step_by(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn direct_io() -> Result<()> {
+        let path = "/tmp/btreedir-direct-io";
+        let mut tree = BTree::with_storage(DirectoryStorage::with_direct_io(path)?)?;
+        for i in 0..1000 {
+            tree.insert(i, i.to_string())?;
+        }
+        let root_id = tree.persist()?;
+
+        // Objects are ordinary files, so they read back the same way with or without it.
+        let tree = BTree::<i32, String, _>::load_with_storage(
+            root_id,
+            DirectoryStorage::with_direct_io(path)?,
+        )?;
+        assert_eq!(tree.get(&999)?, Some(&"999".to_string()));
+
+        let tree = BTree::<i32, String>::load(root_id, path)?;
+        assert_eq!(tree.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(tree.get(&i)?, Some(&i.to_string()));
+        }
+
+        let _ = fs::remove_dir_all(path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dir_locking() -> Result<()> {
+        let path = "/tmp/btreedir-locking";
+        let _ = fs::remove_dir_all(path);
+
+        let mut tree = BTree::with_storage(DirectoryStorage::open_exclusive(path)?)?;
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+        let root_id = tree.persist()?;
+
+        // A second writer or any reader fails fast while the writer has the tree open.
+        assert!(matches!(
+            DirectoryStorage::open_exclusive(path),
+            Err(dir::Error::Locked)
+        ));
+        assert!(matches!(
+            DirectoryStorage::open_shared(path),
+            Err(dir::Error::Locked)
+        ));
+        drop(tree);
+
+        // Readers can share the tree, but can't modify it or let a writer in.
+        let mut reader =
+            BTree::<i32, i32, _>::load_with_storage(root_id, DirectoryStorage::open_shared(path)?)?;
+        let other =
+            BTree::<i32, i32, _>::load_with_storage(root_id, DirectoryStorage::open_shared(path)?)?;
+        assert_eq!(reader.get(&42)?, Some(&42));
+        assert_eq!(other.len(), 100);
+        assert!(matches!(
+            DirectoryStorage::open_exclusive(path),
+            Err(dir::Error::Locked)
+        ));
+        *reader.get_mut(&42)?.unwrap() = 0;
+        assert!(matches!(
+            reader.persist(),
+            Err(Error::Storage(dir::Error::ReadOnly))
+        ));
+
+        drop((reader, other));
+        assert!(DirectoryStorage::open_exclusive(path).is_ok());
+
+        let _ = fs::remove_dir_all(path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn kv_storage() -> Result<()> {
+        let path = "/tmp/btree-kv.log";
+        let _ = fs::remove_file(path);
+
+        let mut tree = BTree::with_storage(KvStorage::new(FileKv::open(path)?)?)?;
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+        for i in (0..100).step_by(2) {
+            tree.remove(&i)?;
+        }
+        let root_id = tree.persist()?;
+        drop(tree);
+
+        // Reopening replays the log, and new IDs don't collide with the ones in use.
+        let mut tree =
+            BTree::<i32, i32, _>::load_with_storage(root_id, KvStorage::new(FileKv::open(path)?)?)?;
+        for i in 100..200 {
+            tree.insert(i, i)?;
+        }
+        let root_id = tree.persist()?;
+
+        let mut kv = tree.into_storage()?.into_inner();
+        kv.compact(path)?;
+        drop(kv);
+
+        let tree =
+            BTree::<i32, i32, _>::load_with_storage(root_id, KvStorage::new(FileKv::open(path)?)?)?;
+        let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(keys
+            .into_iter()
+            .copied()
+            .eq((1..100).step_by(2).chain(100..200)));
+
+        let _ = fs::remove_file(path);
+
+        Ok(())
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("timed out")]
+    struct TimedOut;
+
+    impl Transient for TimedOut {
+        fn is_transient(&self) -> bool {
+            true
+        }
+    }
+
+    /// A backend whose every third read times out.
+    #[derive(Default)]
+    struct FlakyKv {
+        map: std::collections::HashMap<u64, Vec<u8>>,
+        gets: u32,
+    }
+
+    impl KvBackend for FlakyKv {
+        type Error = TimedOut;
+
+        fn get(&mut self, id: u64) -> Result<Option<Vec<u8>>, TimedOut> {
+            self.gets += 1;
+            if self.gets.is_multiple_of(3) {
+                return Err(TimedOut);
+            }
+            Ok(self.map.get(&id).cloned())
+        }
+
+        fn put(&mut self, id: u64, value: &[u8]) -> Result<(), TimedOut> {
+            self.map.insert(id, value.to_vec());
+            Ok(())
+        }
+
+        fn delete(&mut self, id: u64) -> Result<(), TimedOut> {
+            self.map.remove(&id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn retry_storage() -> Result<()> {
+        let backoff = Backoff {
+            initial: Duration::ZERO,
+            ..Default::default()
+        };
+        let storage = RetryStorage::new(KvStorage::new(FlakyKv::default())?, backoff);
+        let mut tree = BTree::with_storage(storage)?;
+
+        for i in 0..200 {
+            tree.insert(i, i)?;
+        }
+        let root_id = tree.persist()?;
+        let storage = tree.into_storage()?;
+
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, storage)?;
+        for i in 0..200 {
+            assert_eq!(tree.get(&i)?, Some(&i));
+        }
+
+        // Without retries, the first timeout fails the operation.
+        let mut storage = tree.into_storage()?.into_inner();
+        let err = (0..3)
+            .try_for_each(|_| storage.read_handle(&root_id).map(drop))
+            .unwrap_err();
+        assert!(err.is_transient());
+        #[cfg(feature = "retry")]
+        {
+            assert!(Error::Storage(err).is_transient());
+            assert!(!Error::<TimedOut>::Poisoned.is_transient());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_insert() -> Result<()> {
+        for degree in 2..5 {
+            let mut tree = BTree::with_storage_and_degree(MemStorage::new(), degree)?;
+
+            // Interleave the keys so that both halves of split nodes receive inserts.
+            for i in (0..200).step_by(2).chain((1..200).step_by(2)) {
+                tree.try_insert(i, i)?;
+            }
+            assert_eq!(tree.len(), 200);
+            assert!(tree.verify()?.is_ok());
+
+            let events = Arc::new(Mutex::new(vec![]));
+            tree.on_event({
+                let events = Arc::clone(&events);
+                move |event| events.lock().unwrap().push(*event)
+            });
+
+            for i in 0..200 {
+                assert!(matches!(
+                    tree.try_insert(i, i + 1),
+                    Err(Error::AlreadyExists)
+                ));
+                assert_eq!(tree.get(&i)?, Some(&i));
+            }
+            assert_eq!(tree.len(), 200);
+            assert!(events.lock().unwrap().is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_or_compute() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        let mut computed = 0;
+
+        for i in (0..100).chain(0..100) {
+            let v = tree.get_or_compute(i, || {
+                computed += 1;
+                i * i
+            })?;
+            assert_eq!(v, i * i);
+        }
+        assert_eq!(computed, 100);
+        assert_eq!(tree.len(), 100);
+        assert!(tree.verify()?.is_ok());
+
+        // Every computed value was persisted without an explicit `persist`.
+        let root_id = tree.root_id();
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, tree.into_storage()?)?;
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree.get(&99)?, Some(&9801));
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_max() -> Result<()> {
+        for degree in 2..5 {
+            let mut tree = BTree::with_storage_and_degree(MemStorage::new(), degree)?;
+            for i in 0..500 {
+                tree.append_max(i, i + 1)?;
+            }
+            assert_eq!(tree.len(), 500);
+            assert!(tree.verify()?.is_ok());
+
+            assert!(matches!(tree.append_max(499, 0), Err(Error::Unsorted)));
+            assert!(matches!(tree.append_max(0, 0), Err(Error::Unsorted)));
+            assert_eq!(tree.len(), 500);
+
+            // Appends mix with ordinary inserts.
+            tree.remove(&499)?;
+            tree.insert(-1, 0)?;
+            tree.append_max(499, 0)?;
+            assert!(tree.verify()?.is_ok());
+
+            let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+            assert!(keys.into_iter().copied().eq(-1..500));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for i in 0..100u64 {
+            tree.insert(i, i as u32)?;
+        }
+        let root_id = tree.persist()?;
+
+        // Values written before the schema existed are at version 0.
+        let schema = Schema::new(1).upgrade(0, |old: u32| format!("#{old}"));
+        let mut tree =
+            BTree::<u64, String, _>::load_with_schema(root_id, tree.into_storage()?, schema)?;
+        for i in 0..100 {
+            assert_eq!(tree.get(&i)?, Some(&format!("#{i}")));
+        }
+
+        // Every node was read, so every node is written back under the new version.
+        tree.insert(100, "new".to_string())?;
+        let root_id = tree.persist()?;
+        let storage = tree.into_storage()?;
+
+        let tree = BTree::<u64, String, _>::load_with_schema(root_id, storage, Schema::new(1))?;
+        assert_eq!(tree.len(), 101);
+        assert_eq!(
+            tree.values()?.collect::<Result<Vec<_>, _>>()?[..2],
+            ["#0", "#1"]
+        );
+
+        let storage = tree.into_storage()?;
+        assert!(matches!(
+            BTree::<u64, String, _>::load_with_storage(root_id, storage),
+            Err(Error::UnknownVersion(1))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn collation() -> Result<()> {
+        let mut tree: BTree<String, usize, _, CaseInsensitive> =
+            BTree::with_comparator(MemStorage::new(), 2)?;
+        for (i, k) in ["Banana", "apple", "cherry", "APPLE"]
+            .into_iter()
+            .enumerate()
+        {
+            tree.insert(k.to_string(), i)?;
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(&"BANANA".to_string())?, Some(&0));
+
+        // The collation is persisted, so the tree can't be opened under byte order.
+        let root_id = tree.persist()?;
+        let storage = tree.into_storage()?;
+        assert!(matches!(
+            BTree::<String, usize, _>::load_with_storage(root_id, storage),
+            Err(Error::Comparator {
+                expected: 0,
+                found: 2
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn comparator() -> Result<()> {
+        let mut tree: BTree<i32, i32, _, Descending> =
+            BTree::with_comparator(MemStorage::new(), 2)?;
+        for i in 0..200 {
+            tree.insert(i, i)?;
+        }
+        for i in (0..200).step_by(3) {
+            tree.remove(&i)?;
+        }
+        assert!(tree.verify()?.is_ok());
+
+        let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+        assert!(keys
+            .into_iter()
+            .copied()
+            .eq((0..200).rev().filter(|i| i % 3 != 0)));
+
+        // The order is persisted with the tree, and checked when it's loaded.
+        let root_id = tree.persist()?;
+        let tree =
+            BTree::<i32, i32, _, Descending>::load_with_comparator(root_id, tree.into_storage()?)?;
+        assert_eq!(tree.get(&100)?, Some(&100));
+
+        assert!(matches!(
+            BTree::<i32, i32, _>::load_with_storage(root_id, tree.into_storage()?),
+            Err(Error::Comparator {
+                expected: 0,
+                found: 1
+            })
+        ));
+
+        struct CaseInsensitive;
+
+        impl Comparator<String> for CaseInsensitive {
+            const ID: u32 = 100;
+
+            fn cmp(a: &String, b: &String) -> std::cmp::Ordering {
+                a.to_lowercase().cmp(&b.to_lowercase())
+            }
+        }
+
+        let mut tree: BTree<String, i32, _, CaseInsensitive> =
+            BTree::with_comparator(MemStorage::new(), 2)?;
+        for (i, k) in ["b", "A", "c", "D"].into_iter().enumerate() {
+            tree.insert(k.to_string(), i as i32)?;
+        }
+        assert_eq!(tree.insert("B".to_string(), 4)?, Some(0));
+
+        let keys = tree.keys()?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(keys, ["A", "b", "c", "D"]);
+
+        Ok(())
+    }
 }
@@ -1,71 +1,135 @@
-use super::error::Error;
+use super::{
+    codec::{self, KeyCodec, ValueCodec},
+    error::Error,
+    hooks::{Event, Hooks},
+    schema::SharedSchema,
+};
+use crate::comparator::{Comparator, Natural};
 use embedded_io::blocking::{Read, Write};
-use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, mem};
+use std::{
+    cmp::Ordering,
+    marker::PhantomData,
+    mem,
+    ops::{Bound, RangeBounds},
+    sync::{Mutex, OnceLock},
+};
 use storage::Storage;
 
-fn read_length_prefixed_bytes<S>(reader: &mut S::ReadHandle<'_>) -> Result<Vec<u8>, Error<S::Error>>
+/// The top byte of a length prefix is a tag, which holds the version of a node's values.
+const TAG_SHIFT: u32 = 56;
+
+/// The most bytes of a length-prefixed field to allocate for ahead of reading them.
+const READ_CHUNK: u64 = 1 << 20;
+
+/// Extends the CRC-32 (IEEE) `crc` of some bytes with `bytes`.
+///
+/// A node's encoding ends with the checksum of everything before it, so that a corrupt node
+/// is caught when it's loaded rather than decoded into the wrong keys or children.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn read_length_prefixed_bytes<S>(
+    reader: &mut S::ReadHandle<'_>,
+    crc: &mut u32,
+) -> Result<(u8, Vec<u8>), Error<S::Error>>
 where
     S: Storage,
 {
     let mut len_raw = [0; mem::size_of::<u64>()];
     reader.read_exact(&mut len_raw).map_err(|_| Error::Read)?;
+    *crc = crc32(*crc, &len_raw);
+
+    let prefix = u64::from_le_bytes(len_raw);
+    let len = prefix & ((1 << TAG_SHIFT) - 1);
+
+    // The length may come from a corrupt page, so the buffer only grows as bytes actually
+    // arrive, and a bogus length fails at the end of the object rather than in the allocator.
+    let mut bytes = Vec::new();
+    while (bytes.len() as u64) < len {
+        let start = bytes.len();
+        let chunk = (len - start as u64).min(READ_CHUNK);
+        bytes.resize(start + chunk as usize, 0);
+        reader
+            .read_exact(&mut bytes[start..])
+            .map_err(|_| Error::Read)?;
+    }
 
-    let len = u64::from_le_bytes(len_raw);
-    let mut bytes = vec![0; len as usize];
-    reader.read_exact(&mut bytes).map_err(|_| Error::Read)?;
-
-    Ok(bytes)
+    *crc = crc32(*crc, &bytes);
+    Ok(((prefix >> TAG_SHIFT) as u8, bytes))
 }
 
-fn write_length_prefixed_bytes<S>(
-    writer: &mut S::WriteHandle<'_>,
-    bytes: &[u8],
-) -> Result<(), Error<S::Error>>
-where
-    S: Storage,
-{
-    writer
-        .write_all(&(bytes.len() as u64).to_le_bytes())
-        .map_err(|_| Error::Write)?;
-    Ok(writer.write_all(bytes).map_err(|_| Error::Write)?)
+#[cfg(feature = "metrics")]
+fn record_cache_access(hit: bool) {
+    if hit {
+        metrics::counter!("btree_node_cache_hits_total").increment(1);
+    } else {
+        metrics::counter!("btree_node_cache_misses_total").increment(1);
+    }
 }
 
-pub enum Child<K, V> {
-    Unloaded(u64),
-    Loaded(Node<K, V>),
+pub struct Child<K, V, C = Natural> {
+    id: u64,
+    node: OnceLock<Node<K, V, C>>,
 }
 
-impl<K, V> Child<K, V> {
-    pub fn as_option_owned(self) -> Option<Node<K, V>> {
-        match self {
-            Child::Unloaded(_) => None,
-            Child::Loaded(node) => Some(node),
+impl<K, V, C> Child<K, V, C> {
+    pub fn unloaded(id: u64) -> Self {
+        Self {
+            id,
+            node: OnceLock::new(),
         }
     }
 
-    pub fn as_option_mut(&mut self) -> Option<&mut Node<K, V>> {
-        match *self {
-            Child::Unloaded(_) => None,
-            Child::Loaded(ref mut node) => Some(node),
+    pub fn loaded(node: Node<K, V, C>) -> Self {
+        Self {
+            id: node.id,
+            node: OnceLock::from(node),
         }
     }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn as_option(&self) -> Option<&Node<K, V, C>> {
+        self.node.get()
+    }
+
+    pub fn as_option_owned(self) -> Option<Node<K, V, C>> {
+        self.node.into_inner()
+    }
+
+    pub fn as_option_mut(&mut self) -> Option<&mut Node<K, V, C>> {
+        self.node.get_mut()
+    }
 }
 
-pub(crate) struct Node<K, V> {
+pub(crate) struct Node<K, V, C = Natural> {
     pub(crate) id: u64,
     pub(crate) keys: Vec<K>,
     pub(crate) vals: Vec<V>,
-    pub(crate) children: Vec<Child<K, V>>,
+    pub(crate) children: Vec<Child<K, V, C>>,
+    pub(crate) schema: SharedSchema<V>,
+    pub(crate) order: PhantomData<fn() -> C>,
 }
 
-impl<K, V> Node<K, V> {
-    pub fn new(id: u64) -> Self {
+impl<K, V, C> Node<K, V, C> {
+    pub fn new(id: u64, schema: SharedSchema<V>) -> Self {
         Self {
             id,
             keys: Vec::new(),
             vals: Vec::new(),
             children: Vec::new(),
+            schema,
+            order: PhantomData,
         }
     }
 
@@ -85,80 +149,152 @@ impl<K, V> Node<K, V> {
         self.children.is_empty()
     }
 
-    pub fn load<S>(id: u64, storage: &mut S) -> Result<Self, Error<S::Error>>
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", name = "node.read", skip_all, fields(id = id)))]
+    pub fn load<S>(
+        id: u64,
+        storage: &mut S,
+        schema: &SharedSchema<V>,
+    ) -> Result<Self, Error<S::Error>>
     where
-        for<'de> K: Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         // Acquire a read handle.
         let mut reader = storage.read_handle(&id)?;
 
         // Read the fields, each of which is serialized as a length-prefixed array of bytes.
-        let keys_raw = read_length_prefixed_bytes::<S>(&mut reader)?;
-        let vals_raw = read_length_prefixed_bytes::<S>(&mut reader)?;
-        let children_raw = read_length_prefixed_bytes::<S>(&mut reader)?;
+        let mut crc = 0;
+        let (_, keys_raw) = read_length_prefixed_bytes::<S>(&mut reader, &mut crc)?;
+        let (version, vals_raw) = read_length_prefixed_bytes::<S>(&mut reader, &mut crc)?;
+        let (_, children_raw) = read_length_prefixed_bytes::<S>(&mut reader, &mut crc)?;
+
+        let mut crc_raw = [0; mem::size_of::<u32>()];
+        reader.read_exact(&mut crc_raw).map_err(|_| Error::Read)?;
+        if u32::from_le_bytes(crc_raw) != crc {
+            return Err(Error::Checksum(id));
+        }
+
+        trace!(
+            bytes = 3 * mem::size_of::<u64>()
+                + keys_raw.len()
+                + vals_raw.len()
+                + children_raw.len()
+                + mem::size_of::<u32>(),
+            "read node"
+        );
+
+        #[cfg(feature = "metrics")]
+        {
+            let bytes = 3 * mem::size_of::<u64>()
+                + keys_raw.len()
+                + vals_raw.len()
+                + children_raw.len()
+                + mem::size_of::<u32>();
+            metrics::counter!("btree_node_reads_total").increment(1);
+            metrics::counter!("btree_node_read_bytes_total").increment(bytes as u64);
+        }
 
         // The array of children will be serialized as a vector of IDs.
         let children: Vec<u64> =
-            bincode::deserialize(&children_raw).map_err(|_| Error::Deserialization)?;
+            codec::deserialize(&children_raw).map_err(|_| Error::Deserialization)?;
 
         Ok(Self {
             id,
-            keys: bincode::deserialize(&keys_raw).map_err(|_| Error::Deserialization)?,
-            vals: bincode::deserialize(&vals_raw).map_err(|_| Error::Deserialization)?,
-            children: children.iter().map(|id| Child::Unloaded(*id)).collect(),
+            keys: codec::decode_seq(&keys_raw, K::decode_key)
+                .map_err(|_| Error::Deserialization)?,
+            vals: schema.decode(version, &vals_raw)?,
+            children: children.iter().map(|id| Child::unloaded(*id)).collect(),
+            schema: SharedSchema::clone(schema),
+            order: PhantomData,
         })
     }
 
     pub fn persist<S>(&self, storage: &mut S) -> Result<u64, Error<S::Error>>
     where
-        K: Serialize,
-        V: Serialize,
+        K: KeyCodec,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         // Recursively persist children.
         for child in &self.children {
-            match child {
-                Child::Loaded(node) => {
-                    node.persist(storage)?;
-                }
-                _ => {}
+            if let Some(node) = child.as_option() {
+                node.persist(storage)?;
             }
         }
 
+        Self::write(self.id, &self.encode()?, storage)?;
+
+        Ok(self.id)
+    }
+
+    pub fn encode<E>(&self) -> Result<Vec<u8>, Error<E>>
+    where
+        K: KeyCodec,
+        V: ValueCodec,
+    {
         // Serialize the keys and values.
-        let keys_raw = bincode::serialize(&self.keys).map_err(|_| Error::Serialization)?;
-        let vals_raw = bincode::serialize(&self.vals).map_err(|_| Error::Serialization)?;
+        let keys_raw =
+            codec::encode_seq(&self.keys, K::encode_key).map_err(|_| Error::Serialization)?;
+        let vals_raw =
+            codec::encode_seq(&self.vals, V::encode_value).map_err(|_| Error::Serialization)?;
 
         // Serialize the children IDs.
-        let children_raw = bincode::serialize(
+        let children_raw = codec::serialize(
             &self
                 .children
                 .iter()
-                .map(|child| match child {
-                    Child::Loaded(node) => node.id,
-                    Child::Unloaded(id) => *id,
-                })
+                .map(|child| child.id())
                 .collect::<Vec<_>>(),
         )
         .map_err(|_| Error::Serialization)?;
 
-        // Acquire a write handle.
-        storage.truncate_id(&self.id, 0)?;
-        let mut writer = storage.write_handle(&self.id)?;
+        // Each of the fields is encoded as a length-prefixed array of bytes, with the values
+        // tagged with their version, and followed by the checksum of them all.
+        let mut bytes = Vec::with_capacity(
+            3 * mem::size_of::<u64>()
+                + keys_raw.len()
+                + vals_raw.len()
+                + children_raw.len()
+                + mem::size_of::<u32>(),
+        );
+        for (tag, raw) in [
+            (0, keys_raw),
+            (self.schema.version(), vals_raw),
+            (0, children_raw),
+        ] {
+            let prefix = raw.len() as u64 | (tag as u64) << TAG_SHIFT;
+            bytes.extend_from_slice(&prefix.to_le_bytes());
+            bytes.extend_from_slice(&raw);
+        }
+        bytes.extend_from_slice(&crc32(0, &bytes).to_le_bytes());
 
-        // Write each of the fields as a length-prefixed array of bytes.
-        write_length_prefixed_bytes::<S>(&mut writer, &keys_raw)?;
-        write_length_prefixed_bytes::<S>(&mut writer, &vals_raw)?;
-        write_length_prefixed_bytes::<S>(&mut writer, &children_raw)?;
+        Ok(bytes)
+    }
 
-        Ok(self.id)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", name = "node.write", skip_all, fields(id = id)))]
+    pub fn write<S>(id: u64, bytes: &[u8], storage: &mut S) -> Result<(), Error<S::Error>>
+    where
+        S: Storage<Id = u64>,
+    {
+        trace!(bytes = bytes.len(), "write node");
+
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!("btree_node_writes_total").increment(1);
+            metrics::counter!("btree_node_write_bytes_total").increment(bytes.len() as u64);
+        }
+
+        // Acquire a write handle.
+        storage.truncate_id(&id, 0)?;
+        let mut writer = storage.write_handle(&id)?;
+
+        writer.write_all(bytes).map_err(|_| Error::Write)
     }
 
     fn find_index(&self, k: &K) -> usize
     where
-        K: Ord,
+        C: Comparator<K>,
     {
         let mut size = self.len();
         let mut left = 0;
@@ -167,7 +303,7 @@ impl<K, V> Node<K, V> {
         while left < right {
             let mid = left + size / 2;
 
-            match self.keys[mid].cmp(k) {
+            match C::cmp(&self.keys[mid], k) {
                 Ordering::Equal => return mid,
                 Ordering::Less => left = mid + 1,
                 Ordering::Greater => right = mid,
@@ -183,58 +319,150 @@ impl<K, V> Node<K, V> {
         &mut self,
         idx: usize,
         storage: &mut S,
-    ) -> Result<&mut Node<K, V>, Error<S::Error>>
+    ) -> Result<&mut Node<K, V, C>, Error<S::Error>>
     where
-        for<'de> K: Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
-        match self.children[idx] {
-            Child::Unloaded(id) => {
-                self.children[idx] = Child::Loaded(Node::load(id, storage)?);
-            }
-            _ => {}
+        let child = &mut self.children[idx];
+
+        #[cfg(feature = "metrics")]
+        record_cache_access(child.as_option().is_some());
+
+        if child.as_option().is_none() {
+            *child = Child::loaded(Node::load(child.id(), storage, &self.schema)?);
+        }
+        Ok(child.as_option_mut().unwrap())
+    }
+
+    /// Like `access_child`, but loads the child through a shared reference.
+    ///
+    /// Concurrent loads of the same child may race, in which case one of the loaded copies is
+    /// kept and the others are dropped.
+    pub(crate) fn load_child<S>(
+        &self,
+        idx: usize,
+        storage: &Mutex<S>,
+    ) -> Result<&Node<K, V, C>, Error<S::Error>>
+    where
+        K: KeyCodec,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        let child = &self.children[idx];
+
+        #[cfg(feature = "metrics")]
+        record_cache_access(child.as_option().is_some());
+
+        if let Some(node) = child.as_option() {
+            return Ok(node);
         }
-        Ok(self.children[idx].as_option_mut().unwrap())
+
+        let node = {
+            let mut storage = storage.lock().map_err(|_| Error::Poisoned)?;
+            Node::load(child.id(), &mut *storage, &self.schema)?
+        };
+
+        Ok(child.node.get_or_init(|| node))
     }
 
     pub fn get<S>(
-        &mut self,
+        &self,
         k: &K,
-        storage: &mut S,
-    ) -> Result<Option<(usize, &Node<K, V>)>, Error<S::Error>>
+        storage: &Mutex<S>,
+    ) -> Result<Option<(usize, &Node<K, V, C>)>, Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         let mut node = self;
         loop {
             let idx = node.find_index(k);
-            if idx < node.len() && node.keys[idx] == *k {
+            if idx < node.len() && C::cmp(&node.keys[idx], k).is_eq() {
                 return Ok(Some((idx, node)));
             } else if node.is_leaf() {
                 return Ok(None);
             } else {
-                node = node.access_child(idx, storage)?;
+                node = node.load_child(idx, storage)?;
+            }
+        }
+    }
+
+    /// Loads the nodes on the path to `k`, returning how many weren't already loaded.
+    pub fn prefetch<S>(&self, k: &K, storage: &Mutex<S>) -> Result<usize, Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        let mut node = self;
+        let mut loaded = 0;
+        loop {
+            let idx = node.find_index(k);
+            if (idx < node.len() && C::cmp(&node.keys[idx], k).is_eq()) || node.is_leaf() {
+                return Ok(loaded);
+            }
+
+            loaded += usize::from(node.children[idx].as_option().is_none());
+            node = node.load_child(idx, storage)?;
+        }
+    }
+
+    /// Loads every node that may hold keys in `range`, returning how many weren't already
+    /// loaded.
+    pub fn warm<S, R>(&self, range: &R, storage: &Mutex<S>) -> Result<usize, Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+        R: RangeBounds<K>,
+    {
+        let mut loaded = 0;
+
+        for idx in 0..self.children.len() {
+            // The child only holds keys between the separators on either side of it.
+            let after_start = match range.start_bound() {
+                Bound::Included(start) | Bound::Excluded(start) => {
+                    idx == self.len() || C::cmp(&self.keys[idx], start).is_gt()
+                }
+                Bound::Unbounded => true,
+            };
+            let before_end = match range.end_bound() {
+                Bound::Included(end) | Bound::Excluded(end) => {
+                    idx == 0 || C::cmp(&self.keys[idx - 1], end).is_lt()
+                }
+                Bound::Unbounded => true,
+            };
+
+            if after_start && before_end {
+                loaded += usize::from(self.children[idx].as_option().is_none());
+                loaded += self.load_child(idx, storage)?.warm(range, storage)?;
             }
         }
+
+        Ok(loaded)
     }
 
     pub fn get_mut<S>(
         &mut self,
         k: &K,
         storage: &mut S,
-    ) -> Result<Option<(usize, &mut Node<K, V>)>, Error<S::Error>>
+    ) -> Result<Option<(usize, &mut Node<K, V, C>)>, Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         let mut node = self;
         loop {
             let idx = node.find_index(k);
-            if idx < node.len() && node.keys[idx] == *k {
+            if idx < node.len() && C::cmp(&node.keys[idx], k).is_eq() {
                 return Ok(Some((idx, node)));
             } else if node.is_leaf() {
                 return Ok(None);
@@ -248,16 +476,18 @@ impl<K, V> Node<K, V> {
         &mut self,
         idx: usize,
         degree: usize,
+        level: usize,
         storage: &mut S,
+        hooks: &Hooks,
     ) -> Result<(), Error<S::Error>>
     where
         S: Storage<Id = u64>,
     {
-        assert!(!self.is_full(degree));
-        // assert!(self.children[idx].is_full(degree));
+        // This node may fill up past 2t - 1 keys, as `insert_new` splits nodes that have.
+        assert!(self.len() < 2 * degree);
 
         let left = self.children[idx].as_option_mut().unwrap();
-        let mut right = Self::new(storage.alloc_id()?);
+        let mut right = Self::new(storage.alloc_id()?, SharedSchema::clone(&self.schema));
 
         // Move the largest keys and values from the left to the right.
         right.vals.extend(left.vals.drain(degree..));
@@ -272,10 +502,23 @@ impl<K, V> Node<K, V> {
             right.children.extend(left.children.drain(degree..));
         }
 
+        trace!(
+            parent = self.id,
+            left = left.id,
+            right = right.id,
+            "split node"
+        );
+        hooks.emit(Event::Split {
+            parent: self.id,
+            left: left.id,
+            right: right.id,
+            level,
+        });
+
         // Insert new key, value, and right child into the root.
         self.keys.insert(idx, key);
         self.vals.insert(idx, val);
-        self.children.insert(idx + 1, Child::Loaded(right));
+        self.children.insert(idx + 1, Child::loaded(right));
 
         Ok(())
     }
@@ -286,110 +529,225 @@ impl<K, V> Node<K, V> {
         mut v: V,
         degree: usize,
         storage: &mut S,
+        hooks: &Hooks,
     ) -> Result<Option<V>, Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         assert!(!self.is_full(degree));
 
         let mut node = self;
+        let mut depth = 0;
         loop {
             // Find index to insert key into or of the child to recurse down.
             let mut idx = node.find_index(&k);
 
+            if idx < node.len() && C::cmp(&k, &node.keys[idx]).is_eq() {
+                // The key already exists, so swap in the value.
+                mem::swap(&mut node.vals[idx], &mut v);
+                return Ok(Some(v));
+            }
+
             if node.is_leaf() {
                 // Insert key and value into non-full node.
-                if idx < node.len() && k == node.keys[idx] {
-                    // The key already exists, so swap in the value.
-                    mem::swap(&mut node.vals[idx], &mut v);
-                    return Ok(Some(v));
-                } else {
-                    // The key doesn't exist yet.
-                    node.keys.insert(idx, k);
-                    node.vals.insert(idx, v);
-                    return Ok(None);
-                }
-            } else {
-                if node.access_child(idx, storage)?.is_full(degree) {
-                    // Split the child and determine which child to recurse down.
-                    node.split_child(idx, degree, storage)?;
-                    if node.keys[idx] < k {
-                        idx += 1;
+                node.keys.insert(idx, k);
+                node.vals.insert(idx, v);
+                return Ok(None);
+            }
+
+            if node.access_child(idx, storage)?.is_full(degree) {
+                // Split the child and determine which child to recurse down. The split may have
+                // moved the key up into this node.
+                node.split_child(idx, degree, depth + 1, storage, hooks)?;
+                match C::cmp(&node.keys[idx], &k) {
+                    Ordering::Less => idx += 1,
+                    Ordering::Equal => {
+                        mem::swap(&mut node.vals[idx], &mut v);
+                        return Ok(Some(v));
                     }
+                    Ordering::Greater => {}
                 }
-                node = node.access_child(idx, storage)?;
             }
+            node = node.access_child(idx, storage)?;
+            depth += 1;
         }
     }
 
-    fn min_key<S>(&mut self, storage: &mut S) -> Result<&K, Error<S::Error>>
+    /// Returns the value of `k`, inserting the result of `f` if the key doesn't exist, along
+    /// with whether it was inserted.
+    ///
+    /// Full nodes are split on the way down like in `insert_nonfull`, so the value stays put
+    /// once it's found or inserted.
+    pub fn get_or_insert_with<S>(
+        &mut self,
+        k: K,
+        f: impl FnOnce() -> V,
+        degree: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<(&mut V, bool), Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
+        assert!(!self.is_full(degree));
+
         let mut node = self;
+        let mut depth = 0;
+        loop {
+            let mut idx = node.find_index(&k);
+
+            if idx < node.len() && C::cmp(&k, &node.keys[idx]).is_eq() {
+                return Ok((&mut node.vals[idx], false));
+            }
+
+            if node.is_leaf() {
+                node.keys.insert(idx, k);
+                node.vals.insert(idx, f());
+                return Ok((&mut node.vals[idx], true));
+            }
 
-        while !node.is_leaf() && !node.access_child(0, storage)?.is_empty() {
-            node = node.children.first_mut().unwrap().as_option_mut().unwrap();
+            if node.access_child(idx, storage)?.is_full(degree) {
+                node.split_child(idx, degree, depth + 1, storage, hooks)?;
+                match C::cmp(&node.keys[idx], &k) {
+                    Ordering::Less => idx += 1,
+                    Ordering::Equal => return Ok((&mut node.vals[idx], false)),
+                    Ordering::Greater => {}
+                }
+            }
+            node = node.access_child(idx, storage)?;
+            depth += 1;
+        }
+    }
+
+    /// Inserts an entry whose key mustn't exist yet, returning whether it was inserted.
+    ///
+    /// Unlike `insert_nonfull`, full nodes are split on the way back up rather than on the way
+    /// down, so nothing changes if the key exists. This node is left overfull, with 2t keys,
+    /// if it needs splitting by its parent.
+    pub fn insert_new<S>(
+        &mut self,
+        k: K,
+        v: V,
+        degree: usize,
+        depth: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<bool, Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        let idx = self.find_index(&k);
+
+        if idx < self.len() && C::cmp(&k, &self.keys[idx]).is_eq() {
+            return Ok(false);
+        }
+
+        if self.is_leaf() {
+            self.keys.insert(idx, k);
+            self.vals.insert(idx, v);
+            return Ok(true);
         }
 
-        Ok(node.keys.first().unwrap())
+        let child = self.access_child(idx, storage)?;
+        if !child.insert_new(k, v, degree, depth + 1, storage, hooks)? {
+            return Ok(false);
+        }
+
+        if child.len() == 2 * degree {
+            self.split_child(idx, degree, depth + 1, storage, hooks)?;
+        }
+
+        Ok(true)
     }
 
-    fn max_key<S>(&mut self, storage: &mut S) -> Result<&K, Error<S::Error>>
+    /// Appends an entry whose key is larger than every key in this subtree, returning whether
+    /// it was.
+    ///
+    /// The entry goes straight down the rightmost path, and full nodes are split on the way
+    /// back up like in `insert_new`.
+    pub fn append_max<S>(
+        &mut self,
+        k: K,
+        v: V,
+        degree: usize,
+        depth: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<bool, Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
-        let mut node = self;
+        if self.is_leaf() {
+            // The largest key of the subtree is the last key of its rightmost leaf.
+            if self
+                .keys
+                .last()
+                .is_some_and(|last| C::cmp(last, &k).is_ge())
+            {
+                return Ok(false);
+            }
+            self.keys.push(k);
+            self.vals.push(v);
+            return Ok(true);
+        }
 
-        while !node.is_leaf()
-            && !node
-                .access_child(node.children.len() - 1, storage)?
-                .is_empty()
-        {
-            node = node.children.last_mut().unwrap().as_option_mut().unwrap();
+        let idx = self.children.len() - 1;
+        let child = self.access_child(idx, storage)?;
+        if !child.append_max(k, v, degree, depth + 1, storage, hooks)? {
+            return Ok(false);
         }
 
-        Ok(node.keys.last().unwrap())
+        if child.len() == 2 * degree {
+            self.split_child(idx, degree, depth + 1, storage, hooks)?;
+        }
+
+        Ok(true)
     }
 
     pub fn remove<S>(
         &mut self,
         k: &K,
         degree: usize,
+        depth: usize,
         storage: &mut S,
+        hooks: &Hooks,
     ) -> Result<Option<(K, V)>, Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
-        let mut idx = self.find_index(k);
+        let idx = self.find_index(k);
 
         // Case 1: Key found in node and node is a leaf.
-        if idx < self.len() && self.keys[idx] == *k && self.is_leaf() {
+        if idx < self.len() && C::cmp(&self.keys[idx], k).is_eq() && self.is_leaf() {
             let key = self.keys.remove(idx);
             let val = self.vals.remove(idx);
             return Ok(Some((key, val)));
         }
 
         // Case 2: Key found in node and node is an internal node.
-        if idx < self.len() && self.keys[idx] == *k && !self.is_leaf() {
+        if idx < self.len() && C::cmp(&self.keys[idx], k).is_eq() && !self.is_leaf() {
             if self.access_child(idx, storage)?.len() >= degree {
                 // Case 2a: Child node that precedes k has at least t keys.
-                let pred = &mut self.children[idx].as_option_mut().unwrap();
-
-                // Replace key with the predecessor key and recursively delete it.
-                // Safety: we won't ever use the reference past this point.
-                let pred_key = pred.max_key(storage)? as *const _;
-                let (mut pred_key, mut pred_val) = pred
-                    .remove(unsafe { &*pred_key }, degree, storage)?
-                    .unwrap();
+                // Replace key with the predecessor key, deleting it from the child.
+                let (mut pred_key, mut pred_val) = self.children[idx]
+                    .as_option_mut()
+                    .unwrap()
+                    .remove_max(degree, depth + 1, storage, hooks)?;
 
                 // The actual replacement.
                 mem::swap(&mut self.keys[idx], &mut pred_key);
@@ -398,14 +756,10 @@ impl<K, V> Node<K, V> {
                 return Ok(Some((pred_key, pred_val)));
             } else if self.access_child(idx + 1, storage)?.len() >= degree {
                 // Case 2b: Child node that succeeds k has at least t keys.
-                let succ = &mut self.children[idx + 1].as_option_mut().unwrap();
-
-                // Replace key with the successor key and recursively delete it.
-                // Safety: we don't ever use the reference past this point.
-                let succ_key = succ.min_key(storage)? as *const _;
-                let (mut succ_key, mut succ_val) = succ
-                    .remove(unsafe { &*succ_key }, degree, storage)?
-                    .unwrap();
+                // Replace key with the successor key, deleting it from the child.
+                let (mut succ_key, mut succ_val) = self
+                    .access_child(idx + 1, storage)?
+                    .remove_min(degree, depth + 1, storage, hooks)?;
 
                 // The actual replacement.
                 mem::swap(&mut self.keys[idx], &mut succ_key);
@@ -428,11 +782,24 @@ impl<K, V> Node<K, V> {
                 pred.children.append(&mut succ.children);
                 assert!(pred.is_full(degree));
 
+                trace!(
+                    parent = self.id,
+                    into = pred.id,
+                    from = succ.id,
+                    "merge nodes"
+                );
+                hooks.emit(Event::Merge {
+                    parent: self.id,
+                    into: pred.id,
+                    from: succ.id,
+                    level: depth + 1,
+                });
+
                 // Deallocate the successor.
                 // This is the only case in which a node completely disappears.
                 storage.dealloc_id(succ.id)?;
 
-                return pred.remove(k, degree, storage);
+                return pred.remove(k, degree, depth + 1, storage, hooks);
             }
         }
 
@@ -442,9 +809,95 @@ impl<K, V> Node<K, V> {
         }
 
         // Case 3: Key not found in internal node.
+        let idx = self.fill_child(idx, degree, depth, storage, hooks)?;
+        self.access_child(idx, storage)?
+            .remove(k, degree, depth + 1, storage, hooks)
+    }
+
+    /// Removes the largest entry in the subtree rooted at this node.
+    ///
+    /// The node must have at least t keys unless it's the root, and mustn't be empty.
+    fn remove_max<S>(
+        &mut self,
+        degree: usize,
+        depth: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<(K, V), Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        if self.is_leaf() {
+            let key = self.keys.pop().expect("couldn't pop largest key");
+            let val = self.vals.pop().expect("couldn't pop largest value");
+            return Ok((key, val));
+        }
+
+        let idx = self.fill_child(self.children.len() - 1, degree, depth, storage, hooks)?;
+        self.access_child(idx, storage)?
+            .remove_max(degree, depth + 1, storage, hooks)
+    }
+
+    /// Removes the smallest entry in the subtree rooted at this node.
+    ///
+    /// The node must have at least t keys unless it's the root, and mustn't be empty.
+    fn remove_min<S>(
+        &mut self,
+        degree: usize,
+        depth: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<(K, V), Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
+        if self.is_leaf() {
+            return Ok((self.keys.remove(0), self.vals.remove(0)));
+        }
+
+        let idx = self.fill_child(0, degree, depth, storage, hooks)?;
+        self.access_child(idx, storage)?
+            .remove_min(degree, depth + 1, storage, hooks)
+    }
+
+    /// Ensures the child at `idx` has at least t keys before descending into it, by borrowing
+    /// from or merging with a sibling. Returns the index of the child to descend into, which
+    /// changes if the child was merged into its left sibling.
+    fn fill_child<S>(
+        &mut self,
+        mut idx: usize,
+        degree: usize,
+        depth: usize,
+        storage: &mut S,
+        hooks: &Hooks,
+    ) -> Result<usize, Error<S::Error>>
+    where
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
+        S: Storage<Id = u64>,
+    {
         if self.access_child(idx, storage)?.len() + 1 == degree {
             if idx > 0 && self.access_child(idx - 1, storage)?.len() >= degree {
                 // Case 3a: Immediate left sibling has at least t keys.
+                trace!(
+                    parent = self.id,
+                    into = self.children[idx].id(),
+                    from = self.children[idx - 1].id(),
+                    "borrow from left sibling"
+                );
+                hooks.emit(Event::Borrow {
+                    parent: self.id,
+                    into: self.children[idx].id(),
+                    from: self.children[idx - 1].id(),
+                    level: depth + 1,
+                });
 
                 // Move key and value from parent down to child.
                 {
@@ -476,6 +929,18 @@ impl<K, V> Node<K, V> {
                 && self.access_child(idx + 1, storage)?.len() >= degree
             {
                 // Case 3a: Immediate right sibling has at least t keys.
+                trace!(
+                    parent = self.id,
+                    into = self.children[idx].id(),
+                    from = self.children[idx + 1].id(),
+                    "borrow from right sibling"
+                );
+                hooks.emit(Event::Borrow {
+                    parent: self.id,
+                    into: self.children[idx].id(),
+                    from: self.children[idx + 1].id(),
+                    level: depth + 1,
+                });
 
                 // Move key and value from parent down to child.
                 {
@@ -505,6 +970,18 @@ impl<K, V> Node<K, V> {
                 }
             } else if idx > 0 {
                 // Case 3b: Merge into left sibling.
+                trace!(
+                    parent = self.id,
+                    into = self.children[idx - 1].id(),
+                    from = self.children[idx].id(),
+                    "merge nodes"
+                );
+                hooks.emit(Event::Merge {
+                    parent: self.id,
+                    into: self.children[idx - 1].id(),
+                    from: self.children[idx].id(),
+                    level: depth + 1,
+                });
 
                 // Move key and value from parent down to left sibling (merged node).
                 {
@@ -533,6 +1010,18 @@ impl<K, V> Node<K, V> {
                 idx -= 1;
             } else if idx + 1 < self.children.len() {
                 // Case 3b: Merge into right sibling.
+                trace!(
+                    parent = self.id,
+                    into = self.children[idx].id(),
+                    from = self.children[idx + 1].id(),
+                    "merge nodes"
+                );
+                hooks.emit(Event::Merge {
+                    parent: self.id,
+                    into: self.children[idx].id(),
+                    from: self.children[idx + 1].id(),
+                    level: depth + 1,
+                });
 
                 // Move key and value from parent down to right sibling (merged node).
                 {
@@ -557,13 +1046,14 @@ impl<K, V> Node<K, V> {
             }
         }
 
-        self.access_child(idx, storage)?.remove(k, degree, storage)
+        Ok(idx)
     }
 
     pub fn clear<S>(&mut self, storage: &mut S) -> Result<(), Error<S::Error>>
     where
-        for<'de> K: Ord + Deserialize<'de>,
-        for<'de> V: Deserialize<'de>,
+        K: KeyCodec,
+        C: Comparator<K>,
+        V: ValueCodec,
         S: Storage<Id = u64>,
     {
         for idx in 0..self.children.len() {
@@ -577,7 +1067,7 @@ impl<K, V> Node<K, V> {
         Ok(())
     }
 
-    // impl<K, V> Debug for Node<K, V>
+    // impl<K, V> Debug for Node<K, V, C>
     // where
     // K: Debug,
     // V: Debug,
@@ -585,7 +1075,7 @@ impl<K, V> Node<K, V> {
     // fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
     //     fn fmt_tree<K, V>(
     //         f: &mut Formatter,
-    //         node: &Node<K, V>,
+    //         node: &Node<K, V, C>,
     //         prefix: String,
     //         last: bool,
     //         root: bool,
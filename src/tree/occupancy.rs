@@ -0,0 +1,83 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    BTree,
+};
+use crate::comparator::Comparator;
+use crate::occupancy::Occupancy;
+use storage::Storage;
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Reports how full the nodes at each level are, and how many children they have.
+    ///
+    /// Every node is loaded to do so, so consider calling `trim_cache` afterwards.
+    pub fn occupancy(&self) -> Result<Occupancy, Error<S::Error>> {
+        let mut occupancy = Occupancy::new(self.degree);
+        let mut stack = vec![(&self.root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            occupancy.record(depth, node.len(), node.children.len());
+
+            for idx in 0..node.children.len() {
+                stack.push((node.load_child(idx, &self.storage)?, depth + 1));
+            }
+        }
+
+        Ok(occupancy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::BTreeMap;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn occupancy() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-occupancy")?;
+        let mut map = BTreeMap::new();
+
+        for i in 0..1000 {
+            tree.insert(i, i)?;
+            map.insert(i, i);
+        }
+
+        let tree = BTree::<i32, i32>::load(tree.persist()?, "/tmp/btreedir-occupancy")?;
+        let occupancy = tree.occupancy()?;
+
+        // The map and the tree split nodes identically.
+        assert_eq!(occupancy, map.occupancy());
+
+        assert_eq!(occupancy.levels[0].nodes, 1);
+        assert_eq!(
+            occupancy
+                .levels
+                .iter()
+                .map(|level| level.keys)
+                .sum::<usize>(),
+            1000
+        );
+
+        // Sequential inserts leave the leaves at minimum occupancy, except the rightmost.
+        let leaves = occupancy.leaves().unwrap();
+        assert_eq!(leaves.fanout[0], leaves.nodes);
+        assert_eq!(leaves.fill[1], leaves.nodes - 1);
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-occupancy");
+
+        let tree = BTree::bulk_load("/tmp/btreedir-occupancy", (0..1000).map(|i| (i, i)))?;
+        assert!(tree.occupancy()?.fill_factor() > occupancy.fill_factor());
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-occupancy");
+
+        Ok(())
+    }
+}
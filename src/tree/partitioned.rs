@@ -0,0 +1,250 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    iter::Iter,
+    BTree,
+};
+use std::slice;
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A map whose key range is sharded across several `BTree`s, each with its own storage.
+///
+/// Partition `i` holds the keys in `bounds[i - 1]..bounds[i]`, with the first and last
+/// partitions being unbounded below and above respectively.
+pub struct PartitionedBTree<K, V, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    bounds: Vec<K>,
+    partitions: Vec<BTree<K, V, S>>,
+}
+
+impl<K, V, S> PartitionedBTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Creates a partitioned tree from `bounds` and one more partition than there are bounds.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the smallest or largest key of a partition can't be read from its storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bounds aren't strictly increasing, the number of partitions is wrong, or
+    /// a partition holds keys outside its bounds.
+    pub fn new(bounds: Vec<K>, partitions: Vec<BTree<K, V, S>>) -> Result<Self, Error<S::Error>> {
+        assert!(
+            bounds.windows(2).all(|w| w[0] < w[1]),
+            "partition bounds must be strictly increasing"
+        );
+        assert_eq!(
+            partitions.len(),
+            bounds.len() + 1,
+            "expected one more partition than bounds"
+        );
+
+        for (idx, partition) in partitions.iter().enumerate() {
+            let Some((first, last)) = key_range(partition)? else {
+                continue;
+            };
+            let lower = idx.checked_sub(1).map(|idx| &bounds[idx]);
+            let upper = bounds.get(idx);
+            assert!(
+                lower.is_none_or(|lower| first >= lower) && upper.is_none_or(|upper| last < upper),
+                "partition {idx} holds keys outside its bounds"
+            );
+        }
+
+        Ok(Self { bounds, partitions })
+    }
+
+    pub fn bounds(&self) -> &[K] {
+        &self.bounds
+    }
+
+    pub fn partitions(&self) -> &[BTree<K, V, S>] {
+        &self.partitions
+    }
+
+    pub fn into_partitions(self) -> (Vec<K>, Vec<BTree<K, V, S>>) {
+        (self.bounds, self.partitions)
+    }
+
+    fn route(&self, k: &K) -> usize {
+        self.bounds.partition_point(|bound| bound <= k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.partitions.iter().map(BTree::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.partitions[self.route(k)].contains(k)
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        self.partitions[self.route(k)].get(k)
+    }
+
+    pub fn get_mut(&mut self, k: &K) -> Result<Option<&mut V>, Error<S::Error>> {
+        let idx = self.route(k);
+        self.partitions[idx].get_mut(k)
+    }
+
+    pub fn get_key_value(&self, k: &K) -> Result<Option<(&K, &V)>, Error<S::Error>> {
+        self.partitions[self.route(k)].get_key_value(k)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        let idx = self.route(&k);
+        self.partitions[idx].insert(k, v)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>> {
+        let idx = self.route(k);
+        self.partitions[idx].remove(k)
+    }
+
+    pub fn remove_entry(&mut self, k: &K) -> Result<Option<(K, V)>, Error<S::Error>> {
+        let idx = self.route(k);
+        self.partitions[idx].remove_entry(k)
+    }
+
+    pub fn clear(&mut self) -> Result<Vec<u64>, Error<S::Error>> {
+        self.partitions.iter_mut().map(BTree::clear).collect()
+    }
+
+    /// Persists every partition, returning their root IDs in partition order.
+    pub fn persist(&mut self) -> Result<Vec<u64>, Error<S::Error>> {
+        self.partitions.iter_mut().map(BTree::persist).collect()
+    }
+
+    pub fn iter(&self) -> PartitionedIter<'_, K, V, S> {
+        PartitionedIter {
+            partitions: self.partitions.iter(),
+            current: None,
+        }
+    }
+}
+
+/// Returns the smallest and largest keys of `tree`, if it has any.
+fn key_range<K, V, S>(tree: &BTree<K, V, S>) -> Result<Option<(&K, &K)>, Error<S::Error>>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    let mut first = &tree.root;
+    while !first.is_leaf() {
+        first = first.load_child(0, &tree.storage)?;
+    }
+
+    let mut last = &tree.root;
+    while !last.is_leaf() {
+        last = last.load_child(last.children.len() - 1, &tree.storage)?;
+    }
+
+    Ok(first.keys.first().zip(last.keys.last()))
+}
+
+/// Iterates over the entries of every partition in key order.
+pub struct PartitionedIter<'a, K, V, S>
+where
+    S: Storage,
+{
+    partitions: slice::Iter<'a, BTree<K, V, S>>,
+    current: Option<Iter<'a, K, V, S>>,
+}
+
+impl<'a, K, V, S> Iterator for PartitionedIter<'a, K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(entry);
+            }
+
+            // Partitions cover disjoint, increasing key ranges, so chaining them keeps order.
+            match self.partitions.next()?.iter() {
+                Ok(iter) => self.current = Some(iter),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    fn partitions(n: usize) -> Result<Vec<BTree<i32, i32, MemStorage>>> {
+        (0..n)
+            .map(|_| Ok(BTree::with_storage(MemStorage::new())?))
+            .collect()
+    }
+
+    #[test]
+    fn routing() -> Result<()> {
+        let mut tree = PartitionedBTree::new(vec![100, 200], partitions(3)?)?;
+
+        for i in 0..300 {
+            assert_eq!(tree.insert(i, i + 1)?, None);
+        }
+
+        assert_eq!(tree.len(), 300);
+        assert_eq!(tree.partitions()[0].len(), 100);
+        assert_eq!(tree.partitions()[1].len(), 100);
+        assert_eq!(tree.partitions()[2].len(), 100);
+
+        for i in 0..300 {
+            assert_eq!(tree.get(&i)?, Some(&(i + 1)));
+        }
+
+        for (i, kv) in tree.iter().enumerate() {
+            assert_eq!((&(i as i32), &(i as i32 + 1)), kv?);
+        }
+
+        for i in 0..150 {
+            assert_eq!(tree.remove(&i)?, Some(i + 1));
+        }
+
+        assert_eq!(tree.len(), 150);
+        assert_eq!(tree.partitions()[0].len(), 0);
+        assert_eq!(tree.iter().count(), 150);
+
+        // Partitions can be split off and put back together, as long as their keys stay put.
+        let (bounds, mut partitions) = tree.into_partitions();
+        for partition in &mut partitions {
+            partition.trim_cache()?;
+        }
+        let tree = PartitionedBTree::new(bounds, partitions)?;
+        assert_eq!(tree.iter().count(), 150);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "partition 1 holds keys outside its bounds")]
+    fn misplaced_keys() {
+        let mut partitions = partitions(3).unwrap();
+        for i in 0..200 {
+            partitions[1].insert(i, i).unwrap();
+        }
+        let _ = PartitionedBTree::new(vec![100, 200], partitions);
+    }
+}
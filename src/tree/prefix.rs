@@ -0,0 +1,138 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    iter::Iter,
+    BTree,
+};
+use std::cmp::Ordering;
+use storage::Storage;
+
+/// Keys made up of components that can be compared against their leading components alone.
+///
+/// This is implemented for tuples of up to four components, with every shorter tuple of
+/// leading components as a prefix, and the first component on its own.
+pub trait Prefix<P: ?Sized> {
+    /// Compares the key's leading components with `prefix`.
+    fn cmp_prefix(&self, prefix: &P) -> Ordering;
+}
+
+impl<A: Ord, B> Prefix<A> for (A, B) {
+    fn cmp_prefix(&self, prefix: &A) -> Ordering {
+        self.0.cmp(prefix)
+    }
+}
+
+impl<A: Ord, B, C> Prefix<A> for (A, B, C) {
+    fn cmp_prefix(&self, prefix: &A) -> Ordering {
+        self.0.cmp(prefix)
+    }
+}
+
+impl<A: Ord, B: Ord, C> Prefix<(A, B)> for (A, B, C) {
+    fn cmp_prefix(&self, prefix: &(A, B)) -> Ordering {
+        (&self.0, &self.1).cmp(&(&prefix.0, &prefix.1))
+    }
+}
+
+impl<A: Ord, B, C, D> Prefix<A> for (A, B, C, D) {
+    fn cmp_prefix(&self, prefix: &A) -> Ordering {
+        self.0.cmp(prefix)
+    }
+}
+
+impl<A: Ord, B: Ord, C, D> Prefix<(A, B)> for (A, B, C, D) {
+    fn cmp_prefix(&self, prefix: &(A, B)) -> Ordering {
+        (&self.0, &self.1).cmp(&(&prefix.0, &prefix.1))
+    }
+}
+
+impl<A: Ord, B: Ord, C: Ord, D> Prefix<(A, B, C)> for (A, B, C, D) {
+    fn cmp_prefix(&self, prefix: &(A, B, C)) -> Ordering {
+        (&self.0, &self.1, &self.2).cmp(&(&prefix.0, &prefix.1, &prefix.2))
+    }
+}
+
+impl<K, V, S> BTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Iterates over the entries whose keys start with `prefix`, e.g. every `(user, time)`
+    /// entry of one user, without needing the smallest and largest possible `time`.
+    ///
+    /// Only the nodes on the way to the first such entry are searched, as with `get`.
+    pub fn prefix<'a, P>(
+        &'a self,
+        prefix: &'a P,
+    ) -> Result<PrefixIter<'a, K, V, S, P>, Error<S::Error>>
+    where
+        K: Prefix<P>,
+        P: ?Sized,
+    {
+        let inner = Iter::seek(&self.root, &self.storage, |k| k.cmp_prefix(prefix).is_lt())?;
+        Ok(PrefixIter { inner, prefix })
+    }
+}
+
+/// Iterates over the entries of a `BTree` whose keys start with a prefix, in key order.
+pub struct PrefixIter<'a, K, V, S, P: ?Sized> {
+    inner: Iter<'a, K, V, S>,
+    prefix: &'a P,
+}
+
+impl<'a, K, V, S, P> Iterator for PrefixIter<'a, K, V, S, P>
+where
+    K: KeyCodec + Prefix<P>,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    P: ?Sized,
+{
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok((k, _)) if k.cmp_prefix(self.prefix).is_gt() => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn prefix() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for user in 0..20u32 {
+            for time in 0..30u64 {
+                tree.insert((user, time), user as u64 * time)?;
+            }
+        }
+
+        for user in 0..20 {
+            let entries = tree.prefix(&user)?.collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(entries.len(), 30);
+            assert!(entries
+                .iter()
+                .enumerate()
+                .all(|(i, (k, v))| **k == (user, i as u64) && **v == user as u64 * i as u64));
+        }
+        assert_eq!(tree.prefix(&20)?.count(), 0);
+
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for k in [(1, 'a', 0), (1, 'b', 0), (1, 'b', 1), (2, 'b', 0)] {
+            tree.insert(k, ())?;
+        }
+        let keys = tree
+            .prefix(&(1, 'b'))?
+            .map(|entry| entry.map(|(k, _)| *k))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(keys, [(1, 'b', 0), (1, 'b', 1)]);
+
+        Ok(())
+    }
+}
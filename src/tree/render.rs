@@ -0,0 +1,113 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use crate::comparator::Comparator;
+use std::{
+    fmt::{Debug, Write},
+    sync::Mutex,
+};
+use storage::Storage;
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Renders the keys of every node as an ASCII tree, loading nodes as needed.
+    pub fn render(&self) -> Result<String, Error<S::Error>>
+    where
+        K: Debug,
+    {
+        let mut out = String::new();
+        render_node(&self.root, &self.storage, "", true, true, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn render_node<K, V, S, C>(
+    node: &Node<K, V, C>,
+    storage: &Mutex<S>,
+    prefix: &str,
+    last: bool,
+    root: bool,
+    out: &mut String,
+) -> Result<(), Error<S::Error>>
+where
+    K: Debug + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    if !root {
+        out.push_str(prefix);
+        out.push_str(if last {
+            "└─── "
+        } else {
+            "├─── "
+        });
+    }
+
+    // Writing to a `String` can't fail.
+    let _ = writeln!(out, "{:?}", node.keys);
+
+    let next_prefix = if root {
+        prefix.to_string()
+    } else if last {
+        format!("{prefix}     ")
+    } else {
+        format!("{prefix}│    ")
+    };
+
+    for idx in 0..node.children.len() {
+        render_node(
+            node.load_child(idx, storage)?,
+            storage,
+            &next_prefix,
+            idx + 1 == node.children.len(),
+            false,
+            out,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn render() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-render")?;
+
+        for i in 0..10 {
+            tree.insert(i, i)?;
+        }
+
+        let tree = BTree::<i32, i32>::load(tree.persist()?, "/tmp/btreedir-render")?;
+
+        assert_eq!(
+            format!("\n{}", tree.render()?),
+            r#"
+[3]
+├─── [1]
+│    ├─── [0]
+│    └─── [2]
+└─── [5, 7]
+     ├─── [4]
+     ├─── [6]
+     └─── [8, 9]
+"#
+        );
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-render");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use std::collections::HashSet;
+use storage::Storage;
+
+/// A key range whose entries couldn't be recovered because a node was unreadable.
+///
+/// Both bounds are exclusive, and a missing bound means the range is unbounded on that side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LostRange<K> {
+    pub node: u64,
+    pub lower: Option<K>,
+    pub upper: Option<K>,
+}
+
+impl<K> LostRange<K>
+where
+    K: Ord,
+{
+    pub fn contains(&self, k: &K) -> bool {
+        self.lower.as_ref().is_none_or(|lower| lower < k)
+            && self.upper.as_ref().is_none_or(|upper| k < upper)
+    }
+}
+
+/// The outcome of `BTree::salvage_from`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SalvageReport<K> {
+    pub recovered: usize,
+    pub lost: Vec<LostRange<K>>,
+}
+
+impl<K, V, S> BTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Copies every readable entry of the tree rooted at `root_id` in `source` into this tree.
+    ///
+    /// Nodes that can't be read are skipped along with their subtrees, and the key ranges they
+    /// covered are reported instead. Only errors writing to this tree are returned as errors.
+    pub fn salvage_from<R>(
+        &mut self,
+        root_id: u64,
+        source: &mut R,
+    ) -> Result<SalvageReport<K>, Error<S::Error>>
+    where
+        K: Clone,
+        R: Storage<Id = u64>,
+    {
+        let mut report = SalvageReport {
+            recovered: 0,
+            lost: vec![],
+        };
+
+        self.salvage_node(
+            root_id,
+            None,
+            None,
+            source,
+            &mut HashSet::new(),
+            &mut report,
+        )?;
+
+        Ok(report)
+    }
+
+    fn salvage_node<R>(
+        &mut self,
+        id: u64,
+        lower: Option<&K>,
+        upper: Option<&K>,
+        source: &mut R,
+        seen: &mut HashSet<u64>,
+        report: &mut SalvageReport<K>,
+    ) -> Result<(), Error<S::Error>>
+    where
+        K: Clone,
+        R: Storage<Id = u64>,
+    {
+        // Guard against cycles in a damaged tree.
+        if !seen.insert(id) {
+            return Ok(());
+        }
+
+        let node = match Node::<K, V>::load(id, source, &self.root.schema) {
+            Ok(node) => node,
+            Err(_) => {
+                report.lost.push(LostRange {
+                    node: id,
+                    lower: lower.cloned(),
+                    upper: upper.cloned(),
+                });
+                return Ok(());
+            }
+        };
+
+        for (idx, child) in node.children.iter().enumerate() {
+            let lower = if idx == 0 {
+                lower
+            } else {
+                node.keys.get(idx - 1)
+            };
+            let upper = if idx < node.len() {
+                node.keys.get(idx)
+            } else {
+                upper
+            };
+
+            self.salvage_node(child.id(), lower, upper, source, seen, report)?;
+        }
+
+        for (k, v) in node.keys.into_iter().zip(node.vals) {
+            if self.insert(k, v)?.is_none() {
+                report.recovered += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+    use storage::dir::DirectoryStorage;
+
+    #[test]
+    fn lost_leaf() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-salvage-src")?;
+
+        for i in 0..1000 {
+            tree.insert(i, i + 1)?;
+        }
+
+        let root_id = tree.persist()?;
+
+        // Corrupt the leftmost leaf.
+        let mut node = &tree.root;
+        while !node.is_leaf() {
+            node = node.children[0].as_option().unwrap();
+        }
+        fs::write(format!("/tmp/btreedir-salvage-src/{}", node.id), [0xff; 3])?;
+
+        let mut salvaged = BTree::<i32, i32>::new("/tmp/btreedir-salvage-dst")?;
+        let report = salvaged.salvage_from(
+            root_id,
+            &mut DirectoryStorage::new("/tmp/btreedir-salvage-src")?,
+        )?;
+
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(report.lost[0].lower, None);
+        assert!(report.recovered < 1000);
+        assert_eq!(salvaged.len(), report.recovered);
+
+        for i in 0..1000 {
+            match salvaged.get(&i)? {
+                Some(v) => assert_eq!(*v, i + 1),
+                None => assert!(report.lost[0].contains(&i)),
+            }
+        }
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-salvage-src");
+        let _ = fs::remove_dir_all("/tmp/btreedir-salvage-dst");
+
+        Ok(())
+    }
+
+    #[test]
+    fn garbage_length_prefix() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-salvage-prefix-src")?;
+
+        for i in 0..1000 {
+            tree.insert(i, i + 1)?;
+        }
+
+        let root_id = tree.persist()?;
+
+        let mut node = &tree.root;
+        while !node.is_leaf() {
+            node = node.children[0].as_option().unwrap();
+        }
+        let path = format!("/tmp/btreedir-salvage-prefix-src/{}", node.id);
+        let original = fs::read(&path)?;
+
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        for round in 0..8 {
+            // Overwrite the first length prefix with random bytes, claiming petabytes of keys.
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let mut prefix = seed.to_le_bytes();
+            prefix[6] |= 0x80;
+
+            let mut bytes = original.clone();
+            bytes[..prefix.len()].copy_from_slice(&prefix);
+            fs::write(&path, bytes)?;
+
+            let dst = format!("/tmp/btreedir-salvage-prefix-dst-{round}");
+            let mut salvaged = BTree::<i32, i32>::new(&dst)?;
+            let report = salvaged.salvage_from(
+                root_id,
+                &mut DirectoryStorage::new("/tmp/btreedir-salvage-prefix-src")?,
+            )?;
+
+            assert_eq!(report.lost.len(), 1);
+            assert_eq!(report.lost[0].node, node.id);
+            assert_eq!(salvaged.len(), report.recovered);
+            assert!(salvaged.get(&999)?.is_some());
+
+            let _ = fs::remove_dir_all(dst);
+        }
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-salvage-prefix-src");
+
+        Ok(())
+    }
+}
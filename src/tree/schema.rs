@@ -0,0 +1,84 @@
+use super::{
+    codec::{self, ValueCodec},
+    error::Error,
+};
+use std::{collections::HashMap, sync::Arc};
+
+type Upgrade<V> = Box<dyn Fn(&[u8]) -> Option<Vec<V>> + Send + Sync>;
+
+/// The version of a tree's values, and how to read values written under older versions.
+///
+/// Every node records the version its values were written under. Nodes from an older version
+/// are upgraded as they're read, and written back under the current version the next time the
+/// tree is persisted, so the value type can change without rewriting the tree up front.
+///
+/// Trees are at version 0 by default, which is also what nodes written without a schema are.
+pub struct Schema<V> {
+    version: u8,
+    upgrades: HashMap<u8, Upgrade<V>>,
+}
+
+impl<V> Schema<V> {
+    pub fn new(version: u8) -> Self {
+        Self {
+            version,
+            upgrades: HashMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Reads values written under `version` as `Old`, converting each with `upgrade`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `version` isn't older than the current version.
+    pub fn upgrade<Old>(
+        mut self,
+        version: u8,
+        upgrade: impl Fn(Old) -> V + Send + Sync + 'static,
+    ) -> Self
+    where
+        Old: ValueCodec,
+    {
+        assert!(
+            version < self.version,
+            "can only upgrade from older versions"
+        );
+
+        self.upgrades.insert(
+            version,
+            Box::new(move |raw| {
+                let old = codec::decode_seq(raw, Old::decode_value).ok()?;
+                Some(old.into_iter().map(&upgrade).collect())
+            }),
+        );
+        self
+    }
+
+    pub(crate) fn decode<E>(&self, version: u8, raw: &[u8]) -> Result<Vec<V>, Error<E>>
+    where
+        V: ValueCodec,
+    {
+        if version == self.version {
+            return codec::decode_seq(raw, V::decode_value).map_err(|_| Error::Deserialization);
+        }
+
+        let upgrade = self
+            .upgrades
+            .get(&version)
+            .ok_or(Error::UnknownVersion(version))?;
+        upgrade(raw).ok_or(Error::Deserialization)
+    }
+}
+
+impl<V> Default for Schema<V> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// A schema shared by every node of a tree.
+pub(crate) type SharedSchema<V> = Arc<Schema<V>>;
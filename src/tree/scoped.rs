@@ -0,0 +1,190 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    iter::Iter,
+    BTree,
+};
+use std::ops::{Bound, RangeBounds, RangeFull};
+use storage::Storage;
+
+impl<P, K, V, S> BTree<(P, K), V, S>
+where
+    (P, K): Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Returns a view of the entries whose keys start with `prefix`, keyed by the rest of their
+    /// keys, so that separate namespaces can share a tree without seeing each other's entries.
+    pub fn scoped(&mut self, prefix: P) -> Scoped<'_, P, K, V, S> {
+        Scoped { tree: self, prefix }
+    }
+}
+
+/// The entries of a `BTree` under one key prefix, from `BTree::scoped`.
+///
+/// Keys are given and returned without the prefix, which is added and stripped by the view.
+pub struct Scoped<'a, P, K, V, S>
+where
+    S: Storage,
+{
+    tree: &'a mut BTree<(P, K), V, S>,
+    prefix: P,
+}
+
+impl<P, K, V, S> Scoped<'_, P, K, V, S>
+where
+    P: Ord,
+    K: Ord,
+    (P, K): KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    pub fn prefix(&self) -> &P {
+        &self.prefix
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        Ok(self.get(k)?.is_some())
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        match self.range((Bound::Included(k), Bound::Included(k)))?.next() {
+            Some(entry) => entry.map(|(_, v)| Some(v)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>>
+    where
+        P: Clone,
+    {
+        self.tree.insert((self.prefix.clone(), k), v)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>>
+    where
+        P: Clone,
+        K: Clone,
+    {
+        self.tree.remove(&(self.prefix.clone(), k.clone()))
+    }
+
+    /// Iterates over the entries under the prefix whose keys are in `range`, in key order.
+    pub fn range<R>(&self, range: R) -> ScopedIterResult<'_, P, K, V, S, R>
+    where
+        R: RangeBounds<K>,
+    {
+        let inner = Iter::seek(&self.tree.root, &self.tree.storage, |(p, k)| {
+            p < &self.prefix
+                || (p == &self.prefix
+                    && match range.start_bound() {
+                        Bound::Included(start) => k < start,
+                        Bound::Excluded(start) => k <= start,
+                        Bound::Unbounded => false,
+                    })
+        })?;
+
+        Ok(ScopedIter {
+            inner,
+            prefix: &self.prefix,
+            range,
+        })
+    }
+
+    /// Iterates over every entry under the prefix, in key order.
+    pub fn iter(&self) -> ScopedIterResult<'_, P, K, V, S, RangeFull> {
+        self.range(..)
+    }
+
+    /// Counts the entries under the prefix, which means visiting each of them.
+    pub fn len(&self) -> Result<usize, Error<S::Error>> {
+        let mut len = 0;
+        for entry in self.iter()? {
+            entry?;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error<S::Error>> {
+        Ok(self.iter()?.next().transpose()?.is_none())
+    }
+}
+
+/// The iterator from `Scoped::range` and `Scoped::iter`, or the error reading the first node.
+type ScopedIterResult<'a, P, K, V, S, R> =
+    Result<ScopedIter<'a, P, K, V, S, R>, Error<<S as Storage>::Error>>;
+
+/// Iterates over the entries of a `Scoped` view in a range, in key order.
+pub struct ScopedIter<'a, P, K, V, S, R> {
+    inner: Iter<'a, (P, K), V, S>,
+    prefix: &'a P,
+    range: R,
+}
+
+impl<'a, P, K, V, S, R> Iterator for ScopedIter<'a, P, K, V, S, R>
+where
+    P: Ord,
+    K: Ord,
+    (P, K): KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    R: RangeBounds<K>,
+{
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(((p, k), v)) => {
+                let past_end = match self.range.end_bound() {
+                    Bound::Included(end) => k > end,
+                    Bound::Excluded(end) => k >= end,
+                    Bound::Unbounded => false,
+                };
+                (p == self.prefix && !past_end).then_some(Ok((k, v)))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn scoped() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+
+        for (ns, n) in [("a", 50), ("b", 100), ("c", 10)] {
+            let mut scope = tree.scoped(ns.to_string());
+            for i in 0..n {
+                assert_eq!(scope.insert(i, format!("{ns}{i}"))?, None);
+            }
+        }
+        assert_eq!(tree.len(), 160);
+
+        let mut b = tree.scoped("b".to_string());
+        assert_eq!(b.len()?, 100);
+        assert_eq!(b.get(&42)?.map(String::as_str), Some("b42"));
+        assert!(!b.contains(&100)?);
+        assert!(b.iter()?.map(|entry| *entry.unwrap().0).eq(0..100));
+        assert!(b.range(95..)?.map(|entry| *entry.unwrap().0).eq(95..100));
+        assert!(b.range(..=3)?.map(|entry| *entry.unwrap().0).eq(0..=3));
+        assert_eq!(b.remove(&42)?.as_deref(), Some("b42"));
+        assert_eq!(b.remove(&42)?, None);
+        assert_eq!(b.len()?, 99);
+
+        // The other namespaces are untouched.
+        let a = tree.scoped("a".to_string());
+        assert_eq!(a.len()?, 50);
+        assert_eq!(a.get(&42)?.map(String::as_str), Some("a42"));
+        assert!(a.range(45..60)?.map(|entry| *entry.unwrap().0).eq(45..50));
+        assert!(tree.scoped("d".to_string()).is_empty()?);
+        assert!(tree.scoped(String::new()).is_empty()?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    BTree, GcReport,
+};
+use crate::comparator::{Comparator, Natural};
+use std::{
+    ops::{Deref, RangeBounds},
+    sync::{RwLock, RwLockReadGuard},
+};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A value borrowed from a `SharedBTree` by `get_ref`, if the key was there.
+type ValueRefResult<'a, K, V, S, C> =
+    Result<Option<ValueRef<'a, K, V, S, C>>, Error<<S as Storage>::Error>>;
+
+/// A `BTree` that can be shared between threads.
+///
+/// Lookups run under a shared lock, so many readers can proceed at once, while anything that
+/// modifies the tree takes the lock exclusively.
+pub struct SharedBTree<K, V, S = DirectoryStorage, C = Natural>
+where
+    S: Storage,
+{
+    inner: RwLock<BTree<K, V, S, C>>,
+}
+
+impl<K, V, S, C> SharedBTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    pub fn new(tree: BTree<K, V, S, C>) -> Self {
+        Self {
+            inner: RwLock::new(tree),
+        }
+    }
+
+    pub fn into_inner(self) -> Result<BTree<K, V, S, C>, Error<S::Error>> {
+        self.inner.into_inner().map_err(|_| Error::Poisoned)
+    }
+
+    pub fn len(&self) -> Result<usize, Error<S::Error>> {
+        Ok(self.inner.read().map_err(|_| Error::Poisoned)?.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error<S::Error>> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn root_id(&self) -> Result<u64, Error<S::Error>> {
+        Ok(self.inner.read().map_err(|_| Error::Poisoned)?.root_id())
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.inner.read().map_err(|_| Error::Poisoned)?.contains(k)
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<V>, Error<S::Error>>
+    where
+        V: Clone,
+    {
+        Ok(self
+            .inner
+            .read()
+            .map_err(|_| Error::Poisoned)?
+            .get(k)?
+            .cloned())
+    }
+
+    /// Like `get`, but borrows the value from the tree instead of cloning it.
+    ///
+    /// The tree stays locked for reading until the guard is dropped, which keeps the node that
+    /// holds the value from being modified or trimmed from the cache in the meantime.
+    pub fn get_ref(&self, k: &K) -> ValueRefResult<'_, K, V, S, C>
+    where
+        K: Clone,
+    {
+        let guard = self.inner.read().map_err(|_| Error::Poisoned)?;
+        if guard.get(k)?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(ValueRef {
+            guard,
+            key: k.clone(),
+        }))
+    }
+
+    pub fn insert(&self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        self.inner
+            .write()
+            .map_err(|_| Error::Poisoned)?
+            .insert(k, v)
+    }
+
+    /// Like `BTree::get_or_compute`, with `f` run under the exclusive lock so that it's run at
+    /// most once per key.
+    pub fn get_or_compute(&self, k: K, f: impl FnOnce() -> V) -> Result<V, Error<S::Error>>
+    where
+        V: Clone,
+    {
+        self.inner
+            .write()
+            .map_err(|_| Error::Poisoned)?
+            .get_or_compute(k, f)
+    }
+
+    pub fn remove(&self, k: &K) -> Result<Option<V>, Error<S::Error>> {
+        self.inner.write().map_err(|_| Error::Poisoned)?.remove(k)
+    }
+
+    pub fn remove_entry(&self, k: &K) -> Result<Option<(K, V)>, Error<S::Error>> {
+        self.inner
+            .write()
+            .map_err(|_| Error::Poisoned)?
+            .remove_entry(k)
+    }
+
+    pub fn persist(&self) -> Result<u64, Error<S::Error>> {
+        self.inner.write().map_err(|_| Error::Poisoned)?.persist()
+    }
+
+    pub fn warm_cache(&self, range: impl RangeBounds<K>) -> Result<usize, Error<S::Error>> {
+        self.inner
+            .read()
+            .map_err(|_| Error::Poisoned)?
+            .warm_cache(range)
+    }
+
+    pub fn prefetch<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> Result<usize, Error<S::Error>>
+    where
+        K: 'a,
+    {
+        self.inner
+            .read()
+            .map_err(|_| Error::Poisoned)?
+            .prefetch(keys)
+    }
+
+    pub fn trim_cache(&self) -> Result<u64, Error<S::Error>> {
+        self.inner
+            .write()
+            .map_err(|_| Error::Poisoned)?
+            .trim_cache()
+    }
+
+    pub fn gc(&self, objects: impl IntoIterator<Item = u64>) -> Result<GcReport, Error<S::Error>> {
+        self.inner.write().map_err(|_| Error::Poisoned)?.gc(objects)
+    }
+}
+
+/// A value borrowed from a `SharedBTree` by `get_ref`, which holds the tree's read lock.
+///
+/// The guard keeps the key rather than a reference into the tree, and looks the value up again
+/// on each dereference. The nodes on the way to it were loaded by `get_ref` and can't be
+/// trimmed while the lock is held, so this takes no reads from storage.
+pub struct ValueRef<'a, K, V, S, C>
+where
+    S: Storage,
+{
+    guard: RwLockReadGuard<'a, BTree<K, V, S, C>>,
+    key: K,
+}
+
+impl<K, V, S, C> Deref for ValueRef<'_, K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        let (idx, node) = self
+            .guard
+            .root
+            .get(&self.key, &self.guard.storage)
+            .ok()
+            .flatten()
+            .expect("the entry was found under the same read lock");
+        &node.vals[idx]
+    }
+}
+
+impl<K, V, S, C> From<BTree<K, V, S, C>> for SharedBTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    fn from(tree: BTree<K, V, S, C>) -> Self {
+        Self::new(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::{fs, sync::Arc, thread};
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn concurrent_readers() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-shared")?;
+
+        for i in 0..1000 {
+            tree.insert(i, i + 1)?;
+        }
+
+        let tree = Arc::new(SharedBTree::new(tree));
+
+        let readers = (0..4)
+            .map(|_| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        assert_eq!(tree.get(&i).unwrap(), Some(i + 1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1000..1100 {
+            tree.insert(i, i + 1)?;
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(tree.len()?, 1100);
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-shared");
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_ref() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for i in 0..100u32 {
+            tree.insert(i, vec![i; 1000])?;
+        }
+        tree.persist()?;
+        let tree = SharedBTree::new(tree);
+        tree.trim_cache()?;
+
+        {
+            let v = tree.get_ref(&42)?.unwrap();
+            assert_eq!(v.len(), 1000);
+            assert!(v.iter().all(|&x| x == 42));
+
+            // Other readers aren't held up by the guard. They have to be on another thread,
+            // since a thread taking a read lock it already holds may deadlock.
+            thread::scope(|scope| {
+                let reader = scope.spawn(|| tree.get_ref(&7).unwrap().unwrap()[0]);
+                assert_eq!(reader.join().unwrap(), 7);
+            });
+        }
+        assert!(tree.get_ref(&100)?.is_none());
+
+        // Writers can go ahead once the guards are dropped.
+        tree.insert(42, vec![])?;
+        assert!(tree.get_ref(&42)?.unwrap().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use super::{codec::KeyCodec, error::Error, iter::Iter, BTree};
+use serde::{Deserialize, Serialize};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A value along with whether it's been soft-deleted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record<V> {
+    pub value: V,
+    pub deleted: bool,
+}
+
+/// A map whose entries can be hidden with `soft_remove` and later recovered with `undelete`.
+///
+/// Soft-deleted entries stay in the underlying tree, flagged as deleted, until they're removed
+/// for good by `purge`. Reads other than `iter_with_deleted` skip over them.
+pub struct SoftBTree<K, V, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, Record<V>, S>,
+    deleted: usize,
+}
+
+impl<K, V, S> SoftBTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    for<'de> V: Serialize + Deserialize<'de>,
+    S: Storage<Id = u64>,
+{
+    /// Wraps `tree`, reading all of it to count the entries that are soft-deleted.
+    pub fn new(tree: BTree<K, Record<V>, S>) -> Result<Self, Error<S::Error>> {
+        let mut deleted = 0;
+        for entry in tree.iter()? {
+            deleted += entry?.1.deleted as usize;
+        }
+
+        Ok(Self { tree, deleted })
+    }
+
+    pub fn into_inner(self) -> BTree<K, Record<V>, S> {
+        self.tree
+    }
+
+    /// Returns the number of entries that aren't soft-deleted.
+    pub fn len(&self) -> usize {
+        self.tree.len() - self.deleted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of soft-deleted entries waiting to be purged.
+    pub fn deleted(&self) -> usize {
+        self.deleted
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        Ok(self.get(k)?.is_some())
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        Ok(self
+            .tree
+            .get(k)?
+            .filter(|record| !record.deleted)
+            .map(|record| &record.value))
+    }
+
+    /// Inserts an entry, replacing a soft-deleted one for the same key if there is one.
+    ///
+    /// Returns the previous value if it wasn't soft-deleted.
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        let record = Record {
+            value: v,
+            deleted: false,
+        };
+
+        match self.tree.insert(k, record)? {
+            Some(old) if old.deleted => {
+                self.deleted -= 1;
+                Ok(None)
+            }
+            old => Ok(old.map(|record| record.value)),
+        }
+    }
+
+    /// Hides the entry for `k`, returning whether there was a visible one to hide.
+    pub fn soft_remove(&mut self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.set_deleted(k, true)
+    }
+
+    /// Recovers the soft-deleted entry for `k`, returning whether there was one.
+    pub fn undelete(&mut self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.set_deleted(k, false)
+    }
+
+    fn set_deleted(&mut self, k: &K, deleted: bool) -> Result<bool, Error<S::Error>> {
+        match self.tree.get_mut(k)? {
+            Some(record) if record.deleted != deleted => {
+                record.deleted = deleted;
+            }
+            _ => return Ok(false),
+        }
+
+        if deleted {
+            self.deleted += 1;
+        } else {
+            self.deleted -= 1;
+        }
+
+        Ok(true)
+    }
+
+    /// Removes the entry for `k` for good, whether or not it's soft-deleted.
+    ///
+    /// Returns the value if it wasn't soft-deleted.
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>> {
+        match self.tree.remove(k)? {
+            Some(record) if record.deleted => {
+                self.deleted -= 1;
+                Ok(None)
+            }
+            record => Ok(record.map(|record| record.value)),
+        }
+    }
+
+    /// Removes every soft-deleted entry for good, returning how many there were.
+    pub fn purge(&mut self) -> Result<usize, Error<S::Error>>
+    where
+        K: Clone,
+    {
+        let mut keys = Vec::with_capacity(self.deleted);
+        for entry in self.tree.iter()? {
+            let (k, record) = entry?;
+            if record.deleted {
+                keys.push(k.clone());
+            }
+        }
+
+        for k in &keys {
+            self.tree.remove(k)?;
+        }
+        self.deleted = 0;
+
+        trace!(purged = keys.len(), "purge soft-deleted entries");
+
+        Ok(keys.len())
+    }
+
+    pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        self.deleted = 0;
+        self.tree.clear()
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    /// Iterates over the entries that aren't soft-deleted.
+    pub fn iter(&self) -> Result<SoftIter<'_, K, V, S>, Error<S::Error>> {
+        Ok(SoftIter {
+            inner: self.tree.iter()?,
+        })
+    }
+
+    /// Iterates over every entry, along with whether it's soft-deleted.
+    pub fn iter_with_deleted(&self) -> Result<Iter<'_, K, Record<V>, S>, Error<S::Error>> {
+        self.tree.iter()
+    }
+}
+
+/// Iterates over the entries of a `SoftBTree` that aren't soft-deleted, in key order.
+pub struct SoftIter<'a, K, V, S> {
+    inner: Iter<'a, K, Record<V>, S>,
+}
+
+impl<'a, K, V, S> Iterator for SoftIter<'a, K, V, S>
+where
+    K: KeyCodec,
+    for<'de> V: Serialize + Deserialize<'de>,
+    S: Storage<Id = u64>,
+{
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.inner.next()? {
+                Ok((_, record)) if record.deleted => continue,
+                entry => return Some(entry.map(|(k, record)| (k, &record.value))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn soft_delete() -> Result<()> {
+        let mut tree = SoftBTree::new(BTree::with_storage(MemStorage::new())?)?;
+
+        for i in 0..100 {
+            tree.insert(i, i + 1)?;
+        }
+        for i in (0..100).step_by(2) {
+            assert!(tree.soft_remove(&i)?);
+        }
+        assert!(!tree.soft_remove(&0)?);
+        assert!(!tree.soft_remove(&100)?);
+
+        assert_eq!(tree.len(), 50);
+        assert_eq!(tree.deleted(), 50);
+        assert_eq!(tree.get(&0)?, None);
+        assert_eq!(tree.get(&1)?, Some(&2));
+        assert!(tree
+            .iter()?
+            .map(|entry| entry.unwrap().0 % 2)
+            .all(|r| r == 1));
+        assert_eq!(tree.iter_with_deleted()?.count(), 100);
+
+        assert!(tree.undelete(&0)?);
+        assert!(!tree.undelete(&1)?);
+        assert_eq!(tree.get(&0)?, Some(&1));
+
+        // Inserting over a soft-deleted entry replaces it.
+        assert_eq!(tree.insert(2, 0)?, None);
+        assert_eq!(tree.get(&2)?, Some(&0));
+        assert_eq!(tree.len(), 52);
+
+        // The flags survive a reload.
+        let root_id = tree.persist()?;
+        let storage = tree.into_inner().into_storage()?;
+        let mut tree = SoftBTree::new(BTree::<i32, Record<i32>, _>::load_with_storage(
+            root_id, storage,
+        )?)?;
+        assert_eq!(tree.deleted(), 48);
+
+        assert_eq!(tree.purge()?, 48);
+        assert_eq!(tree.deleted(), 0);
+        assert_eq!(tree.into_inner().len(), 52);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+//! Export to, and import from, immutable sorted runs in the style of an SSTable.
+//!
+//! A run is a sequence of Snappy-compressed data blocks, followed by an index of the blocks and
+//! a fixed-size footer:
+//!
+//! ```text
+//! block 0 | block 1 | ... | index | index offset | index length | entry count | magic
+//! ```
+//!
+//! Each block holds consecutive entries, encoded as a `u64` count followed by each key and its
+//! value in turn, with the same codecs as nodes. The index holds the last key, offset, and
+//! compressed length of each block, so that a reader can find the block that may hold a key
+//! without reading the others. The footer's fields are `u64`s in little-endian.
+
+use super::{
+    codec::{self, KeyCodec, ValueCodec},
+    error::Error,
+    BTree,
+};
+use crate::comparator::Comparator;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use storage::Storage;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"btsstab1");
+const FOOTER: usize = 4 * std::mem::size_of::<u64>();
+
+/// The default uncompressed size that blocks are filled up to.
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+#[derive(Serialize, Deserialize)]
+struct BlockHandle<K> {
+    last_key: K,
+    offset: u64,
+    len: u64,
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Writes every entry to `writer` as a sorted run, filling blocks up to `block_size`
+    /// uncompressed bytes. Returns the number of bytes written.
+    pub fn export_sstable(
+        &self,
+        mut writer: impl Write,
+        block_size: usize,
+    ) -> Result<u64, Error<S::Error>> {
+        let mut index = Vec::new();
+        let mut offset = 0;
+        let mut block = Vec::new();
+        let mut count = 0u64;
+        let mut last = None;
+
+        let mut flush = |block: &mut Vec<u8>, count: u64, last_key: &K| {
+            // A block is encoded like a `Vec`, i.e. a length followed by the elements.
+            let mut raw = count.to_le_bytes().to_vec();
+            raw.append(block);
+
+            let compressed = snap::raw::Encoder::new()
+                .compress_vec(&raw)
+                .map_err(|_| Error::Serialization)?;
+            writer.write_all(&compressed).map_err(|_| Error::Write)?;
+
+            index.push(BlockHandle {
+                last_key: {
+                    let mut buf = Vec::new();
+                    last_key
+                        .encode_key(&mut buf)
+                        .map_err(|_| Error::Serialization)?;
+                    buf
+                },
+                offset,
+                len: compressed.len() as u64,
+            });
+            offset += compressed.len() as u64;
+
+            Ok::<_, Error<S::Error>>(())
+        };
+
+        let mut in_block = 0;
+        for entry in self.iter()? {
+            let (k, v) = entry?;
+            k.encode_key(&mut block)
+                .and_then(|_| v.encode_value(&mut block))
+                .map_err(|_| Error::Serialization)?;
+            in_block += 1;
+            count += 1;
+            last = Some(k);
+
+            if block.len() >= block_size {
+                flush(&mut block, in_block, k)?;
+                in_block = 0;
+            }
+        }
+        if in_block > 0 {
+            flush(&mut block, in_block, last.unwrap())?;
+        }
+
+        // Keys in the index are kept encoded, since they're borrowed from the tree.
+        let index = codec::serialize(&index).map_err(|_| Error::Serialization)?;
+        let mut footer = Vec::with_capacity(FOOTER);
+        footer.extend(offset.to_le_bytes());
+        footer.extend((index.len() as u64).to_le_bytes());
+        footer.extend(count.to_le_bytes());
+        footer.extend(MAGIC.to_le_bytes());
+
+        writer.write_all(&index).map_err(|_| Error::Write)?;
+        writer.write_all(&footer).map_err(|_| Error::Write)?;
+        writer.flush().map_err(|_| Error::Write)?;
+
+        Ok(offset + (index.len() + footer.len()) as u64)
+    }
+}
+
+impl<K, V, S> BTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    /// Builds a tree from a sorted run written by `export_sstable`.
+    pub fn import_sstable(
+        storage: S,
+        degree: usize,
+        mut reader: impl Read + Seek,
+    ) -> Result<Self, Error<S::Error>> {
+        let mut footer = [0; FOOTER];
+        reader
+            .seek(SeekFrom::End(-(FOOTER as i64)))
+            .map_err(|_| Error::Seek)?;
+        reader.read_exact(&mut footer).map_err(|_| Error::Read)?;
+
+        let field = |i: usize| u64::from_le_bytes(footer[8 * i..8 * (i + 1)].try_into().unwrap());
+        let (index_offset, index_len, count) = (field(0), field(1), field(2));
+        if field(3) != MAGIC {
+            return Err(Error::Deserialization);
+        }
+
+        let index: Vec<BlockHandle<Vec<u8>>> =
+            codec::deserialize(&read_at(&mut reader, index_offset, index_len)?)
+                .map_err(|_| Error::Deserialization)?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for handle in index {
+            let raw = snap::raw::Decoder::new()
+                .decompress_vec(&read_at(&mut reader, handle.offset, handle.len)?)
+                .map_err(|_| Error::Deserialization)?;
+            let block = codec::decode_seq(&raw, |bytes| {
+                Ok((K::decode_key(bytes)?, V::decode_value(bytes)?))
+            })
+            .map_err(|_| Error::Deserialization)?;
+            entries.extend(block);
+        }
+
+        if entries.len() as u64 != count {
+            return Err(Error::Deserialization);
+        }
+
+        Self::bulk_load_with_storage_and_degree(storage, degree, entries)
+    }
+}
+
+fn read_at<E>(reader: &mut (impl Read + Seek), offset: u64, len: u64) -> Result<Vec<u8>, Error<E>> {
+    let mut buf = vec![0; len as usize];
+    reader
+        .seek(SeekFrom::Start(offset))
+        .map_err(|_| Error::Seek)?;
+    reader
+        .read_exact(&mut buf)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Deserialization,
+            _ => Error::Read,
+        })?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::io::Cursor;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn sstable() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for i in 0..2000u32 {
+            tree.insert(i, format!("value {i}"))?;
+        }
+
+        let mut run = vec![];
+        let written = tree.export_sstable(&mut run, 256)?;
+        assert_eq!(written, run.len() as u64);
+
+        let mut imported =
+            BTree::<u32, String, _>::import_sstable(MemStorage::new(), 3, Cursor::new(&run))?;
+        assert_eq!(imported.len(), 2000);
+        assert!(imported.verify()?.is_ok());
+        assert!(imported
+            .iter()?
+            .zip(tree.iter()?)
+            .all(|(a, b)| a.unwrap() == b.unwrap()));
+
+        // An empty tree exports to just an index and footer.
+        let empty = BTree::<u32, String, _>::with_storage(MemStorage::new())?;
+        let mut run = vec![];
+        empty.export_sstable(&mut run, DEFAULT_BLOCK_SIZE)?;
+        let imported =
+            BTree::<u32, String, _>::import_sstable(MemStorage::new(), 2, Cursor::new(&run))?;
+        assert!(imported.is_empty());
+
+        // Runs that are cut short are rejected.
+        run.truncate(run.len() - 1);
+        assert!(
+            BTree::<u32, String, _>::import_sstable(MemStorage::new(), 2, Cursor::new(&run))
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
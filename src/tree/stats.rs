@@ -0,0 +1,264 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use crate::comparator::Comparator;
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Mutex,
+};
+use storage::Storage;
+
+/// The distribution of a tree's keys, from `BTree::key_stats`, for estimating quantiles and the
+/// number of entries in ranges without visiting every entry.
+///
+/// It holds the keys of the nodes down to some level, which split the rest of the entries into
+/// gaps. Trees don't record how many entries are under each node, so the entries that aren't
+/// held are assumed to be spread evenly over the gaps. Once the walk reaches the leaves there
+/// are no such entries, and the estimates are exact.
+pub struct KeyStats<'a, K, C> {
+    len: usize,
+    keys: Vec<&'a K>,
+    gaps: Vec<f64>,
+    order: PhantomData<fn() -> C>,
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Samples the distribution of keys, descending until a level has at least `resolution`
+    /// nodes or is made up of leaves.
+    ///
+    /// Only the nodes above and on that level are loaded, about `resolution` times the degree
+    /// of them, and the estimates get finer the higher `resolution` is.
+    pub fn key_stats(&self, resolution: usize) -> Result<KeyStats<'_, K, C>, Error<S::Error>> {
+        let mut depth = 0;
+        let mut level = vec![&self.root];
+        while level.len() < resolution && !level[0].is_leaf() {
+            let mut next = Vec::new();
+            for node in level {
+                for idx in 0..node.children.len() {
+                    next.push(node.load_child(idx, &self.storage)?);
+                }
+            }
+            level = next;
+            depth += 1;
+        }
+
+        let mut stats = KeyStats {
+            len: self.len,
+            keys: Vec::new(),
+            gaps: Vec::new(),
+            order: PhantomData,
+        };
+        sample(&self.root, &self.storage, depth, &mut stats)?;
+
+        // Spread the entries that weren't sampled evenly over the gaps under the deepest level.
+        let rest = (self.len - stats.keys.len()) as f64;
+        let under = stats.gaps.iter().sum::<f64>();
+        if under > 0.0 {
+            for gap in &mut stats.gaps {
+                *gap *= rest / under;
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Records the keys of `node`'s subtree down to `depth` levels below it, and marks the gaps
+/// between them that have unsampled subtrees under them.
+fn sample<'a, K, V, S, C>(
+    node: &'a Node<K, V, C>,
+    storage: &Mutex<S>,
+    depth: usize,
+    stats: &mut KeyStats<'a, K, C>,
+) -> Result<(), Error<S::Error>>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+{
+    for (idx, k) in node.keys.iter().enumerate() {
+        if depth == 0 {
+            stats.gaps.push(if node.is_leaf() { 0.0 } else { 1.0 });
+        } else {
+            sample(node.load_child(idx, storage)?, storage, depth - 1, stats)?;
+        }
+        stats.keys.push(k);
+    }
+
+    if depth == 0 {
+        stats.gaps.push(if node.is_leaf() { 0.0 } else { 1.0 });
+    } else {
+        sample(
+            node.load_child(node.keys.len(), storage)?,
+            storage,
+            depth - 1,
+            stats,
+        )?;
+    }
+
+    Ok(())
+}
+
+impl<'a, K, C> KeyStats<'a, K, C>
+where
+    C: Comparator<K>,
+{
+    /// Returns the number of entries in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether every key was sampled, in which case the estimates are exact.
+    pub fn is_exact(&self) -> bool {
+        self.keys.len() == self.len
+    }
+
+    /// Returns the sampled keys, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &'a K> + '_ {
+        self.keys.iter().copied()
+    }
+
+    /// Estimates the number of entries with keys in `range`.
+    ///
+    /// Gaps that are only partly in the range are assumed to be half in it.
+    pub fn count<R>(&self, range: R) -> usize
+    where
+        R: RangeBounds<K>,
+    {
+        let after_start = |k: &K| match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => C::cmp(k, start).is_ge(),
+            Bound::Unbounded => true,
+        };
+        let before_end = |k: &K| match range.end_bound() {
+            Bound::Included(end) | Bound::Excluded(end) => C::cmp(k, end).is_le(),
+            Bound::Unbounded => true,
+        };
+        let contains = |k: &K| {
+            after_start(k)
+                && match range.end_bound() {
+                    Bound::Included(end) => C::cmp(k, end).is_le(),
+                    Bound::Excluded(end) => C::cmp(k, end).is_lt(),
+                    Bound::Unbounded => true,
+                }
+        };
+
+        let mut count = self.keys.iter().filter(|k| contains(k)).count() as f64;
+        for (idx, gap) in self.gaps.iter().enumerate() {
+            let left = idx.checked_sub(1).map(|idx| self.keys[idx]);
+            let right = self.keys.get(idx).copied();
+
+            let starts_in =
+                left.map_or(matches!(range.start_bound(), Bound::Unbounded), after_start);
+            let ends_in = right.map_or(matches!(range.end_bound(), Bound::Unbounded), before_end);
+            let outside =
+                right.is_some_and(|k| !after_start(k)) || left.is_some_and(|k| !before_end(k));
+
+            count += match (starts_in, ends_in) {
+                (true, true) => *gap,
+                _ if outside => 0.0,
+                _ => gap / 2.0,
+            };
+        }
+
+        count.round() as usize
+    }
+
+    /// Estimates the key that `q` of the entries come before, where `q` is between 0 and 1,
+    /// returning the nearest sampled key.
+    ///
+    /// Returns `None` if the tree is empty.
+    pub fn quantile(&self, q: f64) -> Option<&'a K> {
+        let target = q.clamp(0.0, 1.0) * self.len as f64;
+
+        let mut rank = 0.0;
+        for (k, gap) in self.keys.iter().zip(&self.gaps) {
+            rank += gap;
+            if rank >= target {
+                return Some(k);
+            }
+            rank += 1.0;
+        }
+
+        self.keys.last().copied()
+    }
+
+    /// Picks keys that split the tree into `shards` ranges of about the same number of entries,
+    /// with each key starting a range after the first.
+    ///
+    /// Fewer keys are returned if there aren't enough sampled keys to tell shards apart.
+    pub fn boundaries(&self, shards: usize) -> Vec<&'a K> {
+        let mut boundaries: Vec<&K> = Vec::with_capacity(shards.saturating_sub(1));
+        for idx in 1..shards {
+            let Some(k) = self.quantile(idx as f64 / shards as f64) else {
+                break;
+            };
+            if boundaries.last().is_none_or(|last| C::cmp(last, k).is_lt()) {
+                boundaries.push(k);
+            }
+        }
+        boundaries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn key_stats() -> Result<()> {
+        let mut tree = BTree::with_storage_and_degree(MemStorage::new(), 4)?;
+        for i in 0..10_000u32 {
+            tree.insert(i, ())?;
+        }
+        let root_id = tree.persist()?;
+        let tree = BTree::<u32, (), _>::load_with_storage(root_id, tree.into_storage()?)?;
+
+        let stats = tree.key_stats(16)?;
+        assert!(!stats.is_exact());
+        assert!(stats.keys().count() < 1000);
+
+        let close = |estimate: usize, actual: usize| estimate.abs_diff(actual) <= 1000;
+        assert!(close(*stats.quantile(0.5).unwrap() as usize, 5000));
+        assert!(close(stats.count(2000..6000), 4000));
+        assert_eq!(stats.count(..), 10_000);
+        assert!(close(stats.count(20_000..), 0));
+        let boundaries = stats.boundaries(4);
+        assert_eq!(boundaries.len(), 3);
+        for (boundary, actual) in boundaries.into_iter().zip([2500, 5000, 7500]) {
+            assert!(close(*boundary as usize, actual));
+        }
+
+        // Sampling down to the leaves is exact.
+        let stats = tree.key_stats(usize::MAX)?;
+        assert!(stats.is_exact());
+        assert_eq!(stats.count(2000..6000), 4000);
+        assert_eq!(stats.count(2000..=6000), 4001);
+        assert_eq!(stats.quantile(0.25), Some(&2500));
+        assert_eq!(stats.boundaries(2), [&5000]);
+
+        let empty = BTree::<u32, (), _>::with_storage(MemStorage::new())?;
+        let stats = empty.key_stats(16)?;
+        assert!(stats.is_empty());
+        assert_eq!(stats.quantile(0.5), None);
+        assert_eq!(stats.count(..), 0);
+        assert!(stats.boundaries(4).is_empty());
+
+        Ok(())
+    }
+}
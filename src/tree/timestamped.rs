@@ -0,0 +1,162 @@
+use super::{codec::KeyCodec, error::Error, iter::Iter, BTree};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A value along with when its entry was created and last modified, in microseconds since the
+/// Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamped<V> {
+    pub value: V,
+    pub created: u64,
+    pub modified: u64,
+}
+
+/// When an entry of a `TimestampedBTree` was created and last modified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Meta {
+    pub created: SystemTime,
+    pub modified: SystemTime,
+}
+
+impl<V> Stamped<V> {
+    pub fn meta(&self) -> Meta {
+        Meta {
+            created: UNIX_EPOCH + Duration::from_micros(self.created),
+            modified: UNIX_EPOCH + Duration::from_micros(self.modified),
+        }
+    }
+}
+
+/// A map that records when each entry was created and last modified, alongside its value.
+///
+/// The timestamps take 16 bytes per entry, and are read with `get_with_meta`.
+pub struct TimestampedBTree<K, V, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, Stamped<V>, S>,
+}
+
+impl<K, V, S> TimestampedBTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    for<'de> V: Serialize + Deserialize<'de>,
+    S: Storage<Id = u64>,
+{
+    pub fn new(tree: BTree<K, Stamped<V>, S>) -> Self {
+        Self { tree }
+    }
+
+    pub fn into_inner(self) -> BTree<K, Stamped<V>, S> {
+        self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.tree.contains(k)
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        Ok(self.tree.get(k)?.map(|stamped| &stamped.value))
+    }
+
+    pub fn get_with_meta(&self, k: &K) -> Result<Option<(&V, Meta)>, Error<S::Error>> {
+        Ok(self
+            .tree
+            .get(k)?
+            .map(|stamped| (&stamped.value, stamped.meta())))
+    }
+
+    /// Inserts an entry, stamping it as modified now, and also as created now if it's new.
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error<S::Error>> {
+        let now = now();
+
+        if let Some(stamped) = self.tree.get_mut(&k)? {
+            stamped.modified = now;
+            return Ok(Some(std::mem::replace(&mut stamped.value, v)));
+        }
+
+        self.tree.insert(
+            k,
+            Stamped {
+                value: v,
+                created: now,
+                modified: now,
+            },
+        )?;
+        Ok(None)
+    }
+
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, Error<S::Error>> {
+        Ok(self.tree.remove(k)?.map(|stamped| stamped.value))
+    }
+
+    pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.clear()
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    /// Iterates over the entries along with their timestamps.
+    pub fn iter(&self) -> Result<Iter<'_, K, Stamped<V>, S>, Error<S::Error>> {
+        self.tree.iter()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::thread;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn timestamps() -> Result<()> {
+        let before = SystemTime::now();
+        let mut tree = TimestampedBTree::new(BTree::with_storage(MemStorage::new())?);
+        for i in 0..100u32 {
+            tree.insert(i, i)?;
+        }
+
+        let (_, meta) = tree.get_with_meta(&7)?.unwrap();
+        assert!(meta.created >= before - Duration::from_micros(1));
+        assert_eq!(meta.created, meta.modified);
+
+        thread::sleep(Duration::from_millis(2));
+        assert_eq!(tree.insert(7, 70)?, Some(7));
+        let (v, modified) = tree.get_with_meta(&7)?.unwrap();
+        assert_eq!(*v, 70);
+        assert_eq!(modified.created, meta.created);
+        assert!(modified.modified > meta.modified);
+
+        // Timestamps survive a reload.
+        let root_id = tree.persist()?;
+        let storage = tree.into_inner().into_storage()?;
+        let tree =
+            TimestampedBTree::<u32, u32, _>::new(BTree::load_with_storage(root_id, storage)?);
+        assert_eq!(tree.get_with_meta(&7)?.unwrap().1, modified);
+        assert!(tree.iter()?.all(|entry| {
+            let (_, stamped) = entry.unwrap();
+            stamped.created <= stamped.modified
+        }));
+
+        Ok(())
+    }
+}
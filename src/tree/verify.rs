@@ -0,0 +1,573 @@
+use super::{
+    codec::{KeyCodec, ValueCodec},
+    error::Error,
+    node::Node,
+    BTree,
+};
+use crate::comparator::Comparator;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashSet};
+#[cfg(feature = "rayon")]
+use std::sync::Mutex;
+use storage::Storage;
+
+/// A single inconsistency found by `BTree::verify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The node couldn't be read or decoded from storage.
+    Unreadable { node: u64 },
+
+    /// The node's bytes don't match the checksum stored with them.
+    Checksum { node: u64 },
+
+    /// The node is reachable from more than one parent.
+    Duplicate { node: u64 },
+
+    /// The node's keys aren't strictly increasing.
+    Unordered { node: u64 },
+
+    /// The node has a key outside the range allowed by its parent's separators.
+    OutOfRange { node: u64 },
+
+    /// The node's key and value counts differ.
+    Mismatched { node: u64, keys: usize, vals: usize },
+
+    /// An internal node doesn't have exactly one more child than it has keys.
+    ChildCount {
+        node: u64,
+        keys: usize,
+        children: usize,
+    },
+
+    /// The node holds fewer than `degree - 1` keys and isn't the root, or is an empty internal
+    /// node.
+    Underfull { node: u64, keys: usize },
+
+    /// The node holds more than `2 * degree - 1` keys.
+    Overfull { node: u64, keys: usize },
+
+    /// The leaf isn't at the same depth as the first leaf found.
+    LeafDepth {
+        node: u64,
+        depth: usize,
+        expected: usize,
+    },
+
+    /// The number of entries found doesn't match the tree's length.
+    Len { expected: usize, found: usize },
+
+    /// The object isn't reachable from the root, so nothing will ever free it but `gc`.
+    Leaked { node: u64 },
+}
+
+/// The outcome of `BTree::verify`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub nodes: usize,
+    pub entries: usize,
+    pub height: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Returns the problem with a child that couldn't be loaded.
+fn unloadable<E>(node: u64, err: Error<E>) -> Problem {
+    match err {
+        Error::Checksum(_) => Problem::Checksum { node },
+        _ => Problem::Unreadable { node },
+    }
+}
+
+/// Checks the invariants of a single node, given the separators that bound its keys.
+fn check<K, V, C>(
+    node: &Node<K, V, C>,
+    depth: usize,
+    lower: Option<&K>,
+    upper: Option<&K>,
+    degree: usize,
+    problems: &mut Vec<Problem>,
+) where
+    C: Comparator<K>,
+{
+    if node.keys.len() != node.vals.len() {
+        problems.push(Problem::Mismatched {
+            node: node.id,
+            keys: node.keys.len(),
+            vals: node.vals.len(),
+        });
+    }
+
+    if node.keys.windows(2).any(|w| C::cmp(&w[0], &w[1]).is_ge()) {
+        problems.push(Problem::Unordered { node: node.id });
+    }
+
+    if lower.is_some_and(|lower| node.keys.first().is_some_and(|k| C::cmp(k, lower).is_le()))
+        || upper.is_some_and(|upper| node.keys.last().is_some_and(|k| C::cmp(k, upper).is_ge()))
+    {
+        problems.push(Problem::OutOfRange { node: node.id });
+    }
+
+    if (depth > 0 && node.len() + 1 < degree) || (!node.is_leaf() && node.is_empty()) {
+        problems.push(Problem::Underfull {
+            node: node.id,
+            keys: node.len(),
+        });
+    }
+
+    if node.len() > 2 * degree - 1 {
+        problems.push(Problem::Overfull {
+            node: node.id,
+            keys: node.len(),
+        });
+    }
+
+    if !node.is_leaf() && node.children.len() != node.len() + 1 {
+        problems.push(Problem::ChildCount {
+            node: node.id,
+            keys: node.len(),
+            children: node.children.len(),
+        });
+    }
+}
+
+struct Walk<'a, S> {
+    storage: &'a mut S,
+    degree: usize,
+    leaf_depth: Option<usize>,
+    seen: HashSet<u64>,
+    report: VerifyReport,
+}
+
+impl<'a, S> Walk<'a, S>
+where
+    S: Storage<Id = u64>,
+{
+    fn node<K, V, C>(
+        &mut self,
+        node: &Node<K, V, C>,
+        depth: usize,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) where
+        K: KeyCodec,
+        V: ValueCodec,
+        C: Comparator<K>,
+    {
+        if !self.seen.insert(node.id) {
+            self.report
+                .problems
+                .push(Problem::Duplicate { node: node.id });
+            return;
+        }
+
+        self.report.nodes += 1;
+        self.report.entries += node.len();
+        check(
+            node,
+            depth,
+            lower,
+            upper,
+            self.degree,
+            &mut self.report.problems,
+        );
+
+        if node.is_leaf() {
+            match self.leaf_depth {
+                None => self.leaf_depth = Some(depth),
+                Some(expected) if expected != depth => {
+                    self.report.problems.push(Problem::LeafDepth {
+                        node: node.id,
+                        depth,
+                        expected,
+                    })
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        for (idx, child) in node.children.iter().enumerate() {
+            // The separators on either side of a child bound its keys.
+            let lower = if idx == 0 {
+                lower
+            } else {
+                node.keys.get(idx - 1)
+            };
+            let upper = if idx < node.len() {
+                node.keys.get(idx)
+            } else {
+                upper
+            };
+
+            // Prefer the loaded copy since it may have changes that haven't been persisted.
+            match child.as_option() {
+                Some(child) => self.node(child, depth + 1, lower, upper),
+                None => match Node::<K, V, C>::load(child.id(), self.storage, &node.schema) {
+                    Ok(child) => self.node(&child, depth + 1, lower, upper),
+                    Err(err) => self.report.problems.push(unloadable(child.id(), err)),
+                },
+            }
+        }
+    }
+}
+
+/// Like `Walk`, but checks the children of each node in parallel.
+///
+/// Reads still take turns on the storage lock, but decoding and checking nodes doesn't. The
+/// expected leaf depth is found up front, since there's no first leaf to compare against.
+#[cfg(feature = "rayon")]
+struct ParWalk<'a, S> {
+    storage: &'a Mutex<S>,
+    degree: usize,
+    leaf_depth: usize,
+    seen: Mutex<HashSet<u64>>,
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, S> ParWalk<'a, S>
+where
+    S: Storage<Id = u64> + Send,
+{
+    fn node<K, V, C>(
+        &self,
+        node: &Node<K, V, C>,
+        depth: usize,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> VerifyReport
+    where
+        K: KeyCodec + Send + Sync,
+        V: ValueCodec + Send + Sync,
+        C: Comparator<K>,
+    {
+        let mut report = VerifyReport::default();
+
+        if !self.seen.lock().unwrap().insert(node.id) {
+            report.problems.push(Problem::Duplicate { node: node.id });
+            return report;
+        }
+
+        report.nodes = 1;
+        report.entries = node.len();
+        check(node, depth, lower, upper, self.degree, &mut report.problems);
+
+        if node.is_leaf() {
+            if depth != self.leaf_depth {
+                report.problems.push(Problem::LeafDepth {
+                    node: node.id,
+                    depth,
+                    expected: self.leaf_depth,
+                });
+            }
+            return report;
+        }
+
+        let children = (0..node.children.len())
+            .into_par_iter()
+            .map(|idx| {
+                let lower = if idx == 0 {
+                    lower
+                } else {
+                    node.keys.get(idx - 1)
+                };
+                let upper = if idx < node.len() {
+                    node.keys.get(idx)
+                } else {
+                    upper
+                };
+
+                let child = &node.children[idx];
+                match child.as_option() {
+                    Some(child) => self.node(child, depth + 1, lower, upper),
+                    None => match self.load(child.id(), node) {
+                        Ok(child) => self.node(&child, depth + 1, lower, upper),
+                        Err(problem) => VerifyReport {
+                            problems: vec![problem],
+                            ..Default::default()
+                        },
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Children are merged in order so that problems are reported in key order.
+        for child in children {
+            report.nodes += child.nodes;
+            report.entries += child.entries;
+            report.problems.extend(child.problems);
+        }
+
+        report
+    }
+
+    fn load<K, V, C>(&self, id: u64, parent: &Node<K, V, C>) -> Result<Node<K, V, C>, Problem>
+    where
+        K: KeyCodec,
+        V: ValueCodec,
+    {
+        let mut storage = self
+            .storage
+            .lock()
+            .map_err(|_| Problem::Unreadable { node: id })?;
+        Node::load(id, &mut *storage, &parent.schema).map_err(|err| unloadable(id, err))
+    }
+
+    /// Returns the depth of the leftmost leaf that can be read.
+    fn leaf_depth<K, V, C>(&self, node: &Node<K, V, C>, depth: usize) -> Option<usize>
+    where
+        K: KeyCodec,
+        V: ValueCodec,
+    {
+        if node.is_leaf() {
+            return Some(depth);
+        }
+
+        node.children
+            .iter()
+            .find_map(|child| match child.as_option() {
+                Some(child) => self.leaf_depth(child, depth + 1),
+                None => self
+                    .load(child.id(), node)
+                    .ok()
+                    .and_then(|child| self.leaf_depth(&child, depth + 1)),
+            })
+    }
+}
+
+impl<K, V, S, C> BTree<K, V, S, C>
+where
+    K: KeyCodec,
+    V: ValueCodec,
+    S: Storage<Id = u64>,
+    C: Comparator<K>,
+{
+    /// Walks every reachable node and checks the tree's structural invariants, and that each
+    /// node read from storage matches its checksum.
+    ///
+    /// Nodes that aren't already loaded are read from storage just for the check and aren't
+    /// kept in memory.
+    pub fn verify(&self) -> Result<VerifyReport, Error<S::Error>> {
+        self.verify_with_objects([])
+    }
+
+    /// Like `verify`, but also reports any of `objects` that aren't reachable from the root as
+    /// leaked.
+    ///
+    /// Storage can't list the objects it holds, so they have to be supplied, e.g. from the
+    /// file names of a `DirectoryStorage`.
+    pub fn verify_with_objects(
+        &self,
+        objects: impl IntoIterator<Item = u64>,
+    ) -> Result<VerifyReport, Error<S::Error>> {
+        let mut storage = self.storage.lock().map_err(|_| Error::Poisoned)?;
+        let mut walk = Walk {
+            storage: &mut *storage,
+            degree: self.degree,
+            leaf_depth: None,
+            seen: HashSet::new(),
+            report: VerifyReport::default(),
+        };
+
+        walk.node(&self.root, 0, None, None);
+
+        let mut report = walk.report;
+        report.height = walk.leaf_depth.map_or(0, |depth| depth + 1);
+
+        if report.entries != self.len {
+            report.problems.push(Problem::Len {
+                expected: self.len,
+                found: report.entries,
+            });
+        }
+
+        let leaked = objects
+            .into_iter()
+            .filter(|id| !walk.seen.contains(id))
+            .collect::<BTreeSet<_>>();
+        report
+            .problems
+            .extend(leaked.into_iter().map(|node| Problem::Leaked { node }));
+
+        Ok(report)
+    }
+
+    /// Like `verify`, but checks distinct subtrees on separate threads.
+    #[cfg(feature = "rayon")]
+    pub fn par_verify(&self) -> Result<VerifyReport, Error<S::Error>>
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Send,
+    {
+        let mut walk = ParWalk {
+            storage: &self.storage,
+            degree: self.degree,
+            leaf_depth: 0,
+            seen: Mutex::new(HashSet::new()),
+        };
+
+        let leaf_depth = walk.leaf_depth(&self.root, 0);
+        walk.leaf_depth = leaf_depth.unwrap_or(0);
+
+        let mut report = walk.node(&self.root, 0, None, None);
+        report.height = leaf_depth.map_or(0, |depth| depth + 1);
+
+        if report.entries != self.len {
+            report.problems.push(Problem::Len {
+                expected: self.len,
+                found: report.entries,
+            });
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn healthy() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-verify")?;
+        assert!(tree.verify()?.is_ok());
+
+        for i in 0..1000 {
+            tree.insert(i, i)?;
+        }
+
+        let report = tree.verify()?;
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.entries, 1000);
+
+        let tree = BTree::<i32, i32>::load(tree.persist()?, "/tmp/btreedir-verify")?;
+        assert_eq!(tree.verify()?, report);
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-verify");
+
+        let tree = BTree::bulk_load("/tmp/btreedir-verify", (0..1000).map(|i| (i, i)))?;
+        let report = tree.verify()?;
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.entries, 1000);
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-verify");
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted() -> Result<()> {
+        let mut tree = BTree::new("/tmp/btreedir-verify-corrupted")?;
+
+        for i in 0..100 {
+            tree.insert(i, i)?;
+        }
+
+        tree.root.keys.reverse();
+        tree.len += 1;
+
+        let report = tree.verify()?;
+        assert!(report
+            .problems
+            .contains(&Problem::Unordered { node: tree.root.id }));
+        assert!(report.problems.contains(&Problem::Len {
+            expected: 101,
+            found: 100
+        }));
+        assert!(report
+            .problems
+            .iter()
+            .any(|problem| matches!(problem, Problem::OutOfRange { .. })));
+
+        let _ = fs::remove_dir_all("/tmp/btreedir-verify-corrupted");
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksums_and_leaks() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+
+        // Nodes that were persisted and then merged away are left behind in storage.
+        for i in 0..500 {
+            tree.insert(i, i)?;
+        }
+        tree.persist()?;
+        for i in 0..450 {
+            tree.remove(&i)?;
+        }
+        let root_id = tree.persist()?;
+        let child = tree.root.children[0].id();
+
+        let storage = tree.into_storage()?;
+        let objects = (0..10_000)
+            .filter(|id| storage.get(*id).is_some())
+            .collect::<Vec<_>>();
+
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, storage)?;
+        let report = tree.verify_with_objects(objects.iter().copied())?;
+        assert!(report
+            .problems
+            .iter()
+            .all(|problem| matches!(problem, Problem::Leaked { .. })));
+        assert!(!report.problems.is_empty());
+        assert_eq!(report.nodes + report.problems.len(), objects.len());
+        assert!(tree.verify()?.is_ok());
+
+        // Flip a bit in one of the values of a node that isn't loaded.
+        let mut storage = tree.into_storage()?;
+        storage.get_mut(child).unwrap()[40] ^= 1;
+
+        let tree = BTree::<i32, i32, _>::load_with_storage(root_id, storage)?;
+        let report = tree.verify()?;
+        assert_eq!(report.problems[0], Problem::Checksum { node: child });
+
+        // The node's entries go uncounted too.
+        assert!(matches!(
+            report.problems[1..],
+            [Problem::Len { expected: 50, .. }]
+        ));
+        assert!(matches!(tree.get(&450), Err(Error::Checksum(id)) if id == child));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel() -> Result<()> {
+        let mut tree = BTree::with_storage(MemStorage::new())?;
+        for i in 0..2000 {
+            tree.insert(i, i)?;
+        }
+        let root_id = tree.persist()?;
+
+        let mut tree = BTree::<i32, i32, _>::load_with_storage(root_id, tree.into_storage()?)?;
+        let report = tree.par_verify()?;
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report, tree.verify()?);
+
+        tree.root.keys.reverse();
+        tree.len += 1;
+
+        let mut report = tree.par_verify()?;
+        let mut expected = tree.verify()?;
+        report
+            .problems
+            .sort_by_key(|problem| format!("{problem:?}"));
+        expected
+            .problems
+            .sort_by_key(|problem| format!("{problem:?}"));
+        assert_eq!(report, expected);
+
+        Ok(())
+    }
+}
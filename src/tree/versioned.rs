@@ -0,0 +1,163 @@
+use super::{codec::KeyCodec, error::Error, iter::Iter, BTree};
+use serde::{Deserialize, Serialize};
+use storage::{dir::DirectoryStorage, Storage};
+
+/// A map that keeps the last few values of each key, not just the current one.
+///
+/// Each key's values are stored together as one entry of the underlying tree, oldest first, so
+/// reading a key's history takes the same single descent as reading its current value. The
+/// number of versions kept isn't persisted; a tree reloaded with a smaller `retain` is only
+/// trimmed as its keys are next written.
+pub struct VersionedBTree<K, V, S = DirectoryStorage>
+where
+    S: Storage,
+{
+    tree: BTree<K, Vec<V>, S>,
+    retain: usize,
+}
+
+impl<K, V, S> VersionedBTree<K, V, S>
+where
+    K: Ord + KeyCodec,
+    for<'de> V: Serialize + Deserialize<'de>,
+    S: Storage<Id = u64>,
+{
+    /// Wraps `tree`, keeping up to `retain` versions of each key, including the current one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `retain` is 0.
+    pub fn new(tree: BTree<K, Vec<V>, S>, retain: usize) -> Self {
+        assert!(retain > 0, "must retain at least the current version");
+        Self { tree, retain }
+    }
+
+    pub fn retain(&self) -> usize {
+        self.retain
+    }
+
+    pub fn into_inner(self) -> BTree<K, Vec<V>, S> {
+        self.tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains(&self, k: &K) -> Result<bool, Error<S::Error>> {
+        self.tree.contains(k)
+    }
+
+    /// Returns the current value of `k`.
+    pub fn get(&self, k: &K) -> Result<Option<&V>, Error<S::Error>> {
+        Ok(self.tree.get(k)?.and_then(|versions| versions.last()))
+    }
+
+    /// Returns the retained values of `k`, oldest first, ending with the current one.
+    pub fn get_versions(&self, k: &K) -> Result<Option<&[V]>, Error<S::Error>> {
+        Ok(self.tree.get(k)?.map(Vec::as_slice))
+    }
+
+    /// Makes `v` the current value of `k`, dropping its oldest versions beyond `retain`.
+    ///
+    /// Returns whether `k` is new.
+    pub fn insert(&mut self, k: K, v: V) -> Result<bool, Error<S::Error>> {
+        if let Some(versions) = self.tree.get_mut(&k)? {
+            versions.push(v);
+            let excess = versions.len().saturating_sub(self.retain);
+            versions.drain(..excess);
+            return Ok(false);
+        }
+
+        self.tree.insert(k, vec![v])?;
+        Ok(true)
+    }
+
+    /// Removes `k` along with all of its versions.
+    pub fn remove(&mut self, k: &K) -> Result<Option<Vec<V>>, Error<S::Error>> {
+        self.tree.remove(k)
+    }
+
+    pub fn clear(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.clear()
+    }
+
+    pub fn persist(&mut self) -> Result<u64, Error<S::Error>> {
+        self.tree.persist()
+    }
+
+    /// Iterates over every key and its current value.
+    pub fn iter(&self) -> Result<VersionedIter<'_, K, V, S>, Error<S::Error>> {
+        Ok(VersionedIter {
+            inner: self.tree.iter()?,
+        })
+    }
+}
+
+/// Iterates over the current value of each key in key order.
+pub struct VersionedIter<'a, K, V, S> {
+    inner: Iter<'a, K, Vec<V>, S>,
+}
+
+impl<'a, K, V, S> Iterator for VersionedIter<'a, K, V, S>
+where
+    K: KeyCodec,
+    for<'de> V: Serialize + Deserialize<'de>,
+    S: Storage<Id = u64>,
+{
+    type Item = Result<(&'a K, &'a V), Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Every stored entry holds at least its current version.
+        self.inner
+            .next()
+            .map(|res| res.map(|(k, versions)| (k, versions.last().unwrap())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn versions() -> Result<()> {
+        let mut tree = VersionedBTree::new(BTree::with_storage(MemStorage::new())?, 3);
+
+        for i in 0..100 {
+            assert!(tree.insert(i, 0)?);
+        }
+        for v in 1..5 {
+            for i in (0..100).step_by(2) {
+                assert!(!tree.insert(i, v)?);
+            }
+        }
+
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree.get(&0)?, Some(&4));
+        assert_eq!(tree.get_versions(&0)?, Some(&[2, 3, 4][..]));
+        assert_eq!(tree.get_versions(&1)?, Some(&[0][..]));
+        assert_eq!(tree.get_versions(&100)?, None);
+
+        let current = tree.iter()?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(current[..2], [(&0, &4), (&1, &0)]);
+
+        // Reloading with fewer versions trims each key the next time it's written.
+        let root_id = tree.persist()?;
+        let storage = tree.into_inner().into_storage()?;
+        let mut tree = VersionedBTree::new(BTree::load_with_storage(root_id, storage)?, 2);
+        assert_eq!(tree.get_versions(&0)?, Some(&[2, 3, 4][..]));
+        tree.insert(0, 5)?;
+        assert_eq!(tree.get_versions(&0)?, Some(&[4, 5][..]));
+
+        assert_eq!(tree.remove(&0)?, Some(vec![4, 5]));
+        assert_eq!(tree.get(&0)?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! YCSB-style workload generation for benchmarking trees.
+//!
+//! A `Workload` describes a load phase, which inserts `records` sequential keys, and a run
+//! phase of `operations` operations drawn from a `Mix`, with keys picked according to a
+//! `Distribution`. Workloads are generated from a seed, so the same configuration always
+//! produces the same operations.
+
+use crate::tree::{error::Error, BTree};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, Instant};
+use storage::Storage;
+
+/// How keys are picked for reads, updates, and removes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Every existing key is equally likely.
+    Uniform,
+
+    /// Smaller keys are more popular, with skew `theta` in `(0, 1)`. YCSB uses 0.99.
+    Zipfian { theta: f64 },
+
+    /// Keys are visited in increasing order, wrapping around at the end.
+    Sequential,
+}
+
+/// Relative weights of each kind of operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mix {
+    pub read: u32,
+    pub update: u32,
+    pub insert: u32,
+    pub remove: u32,
+}
+
+impl Mix {
+    /// YCSB workload A: 50% reads and 50% updates.
+    pub const UPDATE_HEAVY: Self = Self {
+        read: 50,
+        update: 50,
+        insert: 0,
+        remove: 0,
+    };
+
+    /// YCSB workload B: 95% reads and 5% updates.
+    pub const READ_MOSTLY: Self = Self {
+        read: 95,
+        update: 5,
+        insert: 0,
+        remove: 0,
+    };
+
+    /// YCSB workload C: only reads.
+    pub const READ_ONLY: Self = Self {
+        read: 100,
+        update: 0,
+        insert: 0,
+        remove: 0,
+    };
+
+    /// YCSB workload D: 95% reads and 5% inserts of new keys.
+    pub const READ_LATEST: Self = Self {
+        read: 95,
+        update: 0,
+        insert: 5,
+        remove: 0,
+    };
+
+    fn total(&self) -> u32 {
+        self.read + self.update + self.insert + self.remove
+    }
+}
+
+/// A single operation of the run phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Read(u64),
+    Update(u64, u64),
+    Insert(u64, u64),
+    Remove(u64),
+}
+
+/// A reproducible benchmark configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Workload {
+    /// Number of keys inserted during the load phase.
+    pub records: u64,
+
+    /// Number of operations in the run phase.
+    pub operations: usize,
+
+    pub distribution: Distribution,
+    pub mix: Mix,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            records: 1000,
+            operations: 10000,
+            distribution: Distribution::Zipfian { theta: 0.99 },
+            mix: Mix::UPDATE_HEAVY,
+            seed: 0,
+        }
+    }
+}
+
+impl Workload {
+    /// Returns the entries inserted during the load phase, in key order.
+    pub fn load(&self) -> impl Iterator<Item = (u64, u64)> {
+        (0..self.records).map(|k| (k, k))
+    }
+
+    /// Generates the operations of the run phase.
+    ///
+    /// Inserts always use new keys, following the largest key so far, and later operations
+    /// may pick those keys as well.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mix has no operations, or if a Zipfian skew isn't in `(0, 1)`.
+    pub fn ops(&self) -> Vec<Op> {
+        assert!(self.mix.total() > 0, "mix has no operations");
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut chooser = KeyChooser::new(self.distribution);
+        let mut count = self.records;
+
+        (0..self.operations)
+            .map(|_| {
+                let mut pick = rng.gen_range(0..self.mix.total());
+
+                if pick < self.mix.insert || count == 0 {
+                    count += 1;
+                    return Op::Insert(count - 1, rng.gen());
+                }
+                pick -= self.mix.insert;
+
+                let k = chooser.next(&mut rng, count);
+                if pick < self.mix.read {
+                    Op::Read(k)
+                } else if pick < self.mix.read + self.mix.update {
+                    Op::Update(k, rng.gen())
+                } else {
+                    Op::Remove(k)
+                }
+            })
+            .collect()
+    }
+}
+
+/// The outcome of `run`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub ops: usize,
+
+    /// Reads, updates, and removes that found their key.
+    pub hits: usize,
+
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Applies `ops` to `tree`, timing the whole run.
+pub fn run<S>(tree: &mut BTree<u64, u64, S>, ops: &[Op]) -> Result<Summary, Error<S::Error>>
+where
+    S: Storage<Id = u64>,
+{
+    let start = Instant::now();
+    let mut hits = 0;
+
+    for op in ops {
+        let hit = match *op {
+            Op::Read(k) => tree.get(&k)?.is_some(),
+            Op::Update(k, v) => tree.insert(k, v)?.is_some(),
+            Op::Insert(k, v) => tree.insert(k, v)?.is_some(),
+            Op::Remove(k) => tree.remove(&k)?.is_some(),
+        };
+
+        if hit && !matches!(op, Op::Insert(..)) {
+            hits += 1;
+        }
+    }
+
+    Ok(Summary {
+        ops: ops.len(),
+        hits,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Picks keys in `0..count` according to a distribution.
+struct KeyChooser {
+    distribution: Distribution,
+    cursor: u64,
+
+    // Zipfian state, where `zetan` is kept up to date as the key count grows.
+    n: u64,
+    zetan: f64,
+}
+
+impl KeyChooser {
+    fn new(distribution: Distribution) -> Self {
+        if let Distribution::Zipfian { theta } = distribution {
+            assert!(theta > 0.0 && theta < 1.0, "Zipfian skew must be in (0, 1)");
+        }
+
+        Self {
+            distribution,
+            cursor: 0,
+            n: 0,
+            zetan: 0.0,
+        }
+    }
+
+    fn next(&mut self, rng: &mut impl Rng, count: u64) -> u64 {
+        match self.distribution {
+            Distribution::Uniform => rng.gen_range(0..count),
+            Distribution::Sequential => {
+                let k = self.cursor % count;
+                self.cursor = k + 1;
+                k
+            }
+            Distribution::Zipfian { theta } => {
+                // Gray et al., "Quickly Generating Billion-Record Synthetic Databases".
+                while self.n < count {
+                    self.n += 1;
+                    self.zetan += 1.0 / (self.n as f64).powf(theta);
+                }
+
+                let n = count as f64;
+                let zeta2 = 1.0 + 0.5f64.powf(theta);
+                let alpha = 1.0 / (1.0 - theta);
+                let eta = (1.0 - (2.0 / n).powf(1.0 - theta)) / (1.0 - zeta2 / self.zetan);
+
+                let u: f64 = rng.gen();
+                let uz = u * self.zetan;
+
+                if uz < 1.0 {
+                    0
+                } else if uz < zeta2 {
+                    1.min(count - 1)
+                } else {
+                    ((n * (eta * u - eta + 1.0).powf(alpha)) as u64).min(count - 1)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use storage::mem::MemStorage;
+
+    #[test]
+    fn distributions() {
+        let ops = |distribution| {
+            Workload {
+                records: 100,
+                operations: 10000,
+                distribution,
+                mix: Mix::READ_ONLY,
+                seed: 7,
+            }
+            .ops()
+        };
+
+        let mut hist = [0; 100];
+        for op in ops(Distribution::Zipfian { theta: 0.99 }) {
+            let Op::Read(k) = op else { unreachable!() };
+            hist[k as usize] += 1;
+        }
+        assert!(hist[0] > hist[1] && hist[1] > hist[50]);
+
+        let keys = ops(Distribution::Sequential)
+            .into_iter()
+            .map(|op| match op {
+                Op::Read(k) => k,
+                _ => unreachable!(),
+            })
+            .take(150)
+            .collect::<Vec<_>>();
+        assert!(keys.iter().copied().eq((0..100).chain(0..50)));
+
+        let zipfian = Distribution::Zipfian { theta: 0.99 };
+        assert_eq!(ops(zipfian), ops(zipfian));
+        assert_ne!(ops(Distribution::Uniform), ops(zipfian));
+    }
+
+    #[test]
+    fn run_workload() -> Result<()> {
+        let workload = Workload {
+            mix: Mix::READ_LATEST,
+            ..Default::default()
+        };
+
+        let mut tree =
+            BTree::bulk_load_with_storage_and_degree(MemStorage::new(), 4, workload.load())?;
+
+        let ops = workload.ops();
+        let inserts = ops.iter().filter(|op| matches!(op, Op::Insert(..))).count();
+
+        let summary = run(&mut tree, &ops)?;
+        assert_eq!(summary.ops, workload.operations);
+        assert_eq!(summary.hits, workload.operations - inserts);
+        assert_eq!(tree.len(), workload.records as usize + inserts);
+
+        Ok(())
+    }
+}
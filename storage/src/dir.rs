@@ -1,15 +1,25 @@
 use crate::Storage;
 use allocator::{seq::SequentialAllocator, Allocator};
 use embedded_io::adapters::FromStd;
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
 use std::{
-    fs::{self, File},
-    io,
+    fs::{self, File, TryLockError},
+    io::{self, Read, Seek, SeekFrom, Write},
 };
 use thiserror::Error;
 
 pub struct DirectoryStorage {
     root: String,
     allocator: SequentialAllocator<u64>,
+    direct: bool,
+    lock: Option<Lock>,
+}
+
+/// An advisory lock on a directory, held on its `LOCK` file until the storage is dropped.
+struct Lock {
+    _file: File,
+    shared: bool,
 }
 
 #[derive(Debug, Error)]
@@ -22,6 +32,12 @@ pub enum Error {
 
     #[error("couldn't deallocate ID: {0}")]
     Dealloc(u64),
+
+    #[error("the directory is locked by another storage")]
+    Locked,
+
+    #[error("the directory is opened shared, which is read-only")]
+    ReadOnly,
 }
 
 impl DirectoryStorage {
@@ -31,9 +47,87 @@ impl DirectoryStorage {
         Ok(Self {
             root: root.into(),
             allocator: SequentialAllocator::new(),
+            direct: false,
+            lock: None,
         })
     }
 
+    /// Creates storage that holds an exclusive lock on `root`, failing with `Error::Locked`
+    /// instead of waiting if any other storage has it open with a lock.
+    ///
+    /// The locks are advisory, so they only exclude storage opened with `open_exclusive` or
+    /// `open_shared`, including in other processes.
+    pub fn open_exclusive(root: &str) -> Result<Self, Error> {
+        Self::open_locked(root, false)
+    }
+
+    /// Creates read-only storage that holds a shared lock on `root`, which other readers can
+    /// share, failing with `Error::Locked` if a writer has it open with `open_exclusive`.
+    ///
+    /// Anything that would modify the directory fails with `Error::ReadOnly`.
+    pub fn open_shared(root: &str) -> Result<Self, Error> {
+        Self::open_locked(root, true)
+    }
+
+    fn open_locked(root: &str, shared: bool) -> Result<Self, Error> {
+        let mut storage = Self::new(root)?;
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(format!("{root}/LOCK"))?;
+        let locked = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(Error::Locked),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        storage.lock = Some(Lock {
+            _file: file,
+            shared,
+        });
+        Ok(storage)
+    }
+
+    fn check_writable(&self) -> Result<(), Error> {
+        match &self.lock {
+            Some(lock) if lock.shared => Err(Error::ReadOnly),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates storage whose objects are read and written with `O_DIRECT`, bypassing the page
+    /// cache.
+    ///
+    /// Each handle reads its whole object into a page-aligned buffer when it's opened, and
+    /// writes are passed straight through to the pages they touch. Flushing a write handle
+    /// syncs the object's data to disk.
+    #[cfg(all(feature = "direct-io", target_os = "linux"))]
+    pub fn with_direct_io(root: &str) -> Result<Self, Error> {
+        let mut storage = Self::new(root)?;
+        storage.direct = true;
+        Ok(storage)
+    }
+
+    fn open(&self, id: u64, options: &mut fs::OpenOptions) -> Result<DirFile, Error> {
+        let path = self.canonicalize(id);
+
+        #[cfg(all(feature = "direct-io", target_os = "linux"))]
+        if self.direct {
+            let file = options.custom_flags(libc::O_DIRECT).open(path)?;
+            return Ok(DirFile::Direct(DirectFile::new(file)?));
+        }
+
+        Ok(DirFile::Buffered(options.open(path)?))
+    }
+
     fn canonicalize(&self, id: u64) -> String {
         format!("{}/{}", self.root, id)
     }
@@ -42,19 +136,28 @@ impl DirectoryStorage {
 impl Storage for DirectoryStorage {
     type Id = u64;
     type Error = Error;
-    type ReadHandle<'a> = FromStd<File>;
-    type WriteHandle<'a> = FromStd<File>;
-    type RwHandle<'a> = FromStd<File>;
+    type ReadHandle<'a> = FromStd<DirFile>;
+    type WriteHandle<'a> = FromStd<DirFile>;
+    type RwHandle<'a> = FromStd<DirFile>;
 
     fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        self.check_writable()?;
         self.allocator.alloc().map_err(|_| Error::Alloc)
     }
 
     fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
-        self.allocator.dealloc(id).map_err(|_| Error::Dealloc(id))
+        self.check_writable()?;
+        self.allocator.dealloc(id).map_err(|_| Error::Dealloc(id))?;
+
+        // Remove the object too, so that the directory only lists the objects in use.
+        match fs::remove_file(self.canonicalize(id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
     }
 
     fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
         Ok(File::options()
             .write(true)
             .create(true)
@@ -63,27 +166,189 @@ impl Storage for DirectoryStorage {
     }
 
     fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
-        Ok(FromStd::new(
-            File::options().read(true).open(self.canonicalize(*id))?,
-        ))
+        Ok(FromStd::new(self.open(*id, File::options().read(true))?))
     }
 
     fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
-        Ok(FromStd::new(
-            File::options()
-                .write(true)
-                .create(true)
-                .open(self.canonicalize(*id))?,
-        ))
+        self.check_writable()?;
+
+        // Direct writes need to read back the rest of any page they partly overwrite.
+        Ok(FromStd::new(self.open(
+            *id,
+            File::options().read(self.direct).write(true).create(true),
+        )?))
     }
 
     fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
-        Ok(FromStd::new(
-            File::options()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(self.canonicalize(*id))?,
-        ))
+        self.check_writable()?;
+        Ok(FromStd::new(self.open(
+            *id,
+            File::options().read(true).write(true).create(true),
+        )?))
+    }
+}
+
+/// An object opened by `DirectoryStorage`.
+pub enum DirFile {
+    Buffered(File),
+    #[cfg(all(feature = "direct-io", target_os = "linux"))]
+    Direct(DirectFile),
+}
+
+impl Read for DirFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Buffered(file) => file.read(buf),
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            Self::Direct(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for DirFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Buffered(file) => file.write(buf),
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            Self::Direct(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Buffered(file) => file.flush(),
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            Self::Direct(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for DirFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Buffered(file) => file.seek(pos),
+            #[cfg(all(feature = "direct-io", target_os = "linux"))]
+            Self::Direct(file) => file.seek(pos),
+        }
+    }
+}
+
+/// The alignment of `O_DIRECT` buffers, offsets, and lengths.
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+const PAGE: usize = 4096;
+
+/// An object opened with `O_DIRECT`, along with a page-aligned copy of its contents.
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+pub struct DirectFile {
+    file: File,
+    // Over-allocated so that a page-aligned run of it can be used, since `Vec`s can't be
+    // aligned directly.
+    raw: Vec<u8>,
+    start: usize,
+    len: usize,
+    pos: usize,
+}
+
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+impl DirectFile {
+    fn new(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        let mut direct = Self {
+            file,
+            raw: Vec::new(),
+            start: 0,
+            len: 0,
+            pos: 0,
+        };
+        direct.reserve(len);
+
+        // Reads at the end of the file come up short, but still have to ask for whole pages.
+        let mut read = 0;
+        while read < len {
+            let end = read + (len - read).next_multiple_of(PAGE);
+            let n = direct.file.read_at(
+                &mut direct.raw[direct.start + read..direct.start + end],
+                read as u64,
+            )?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        direct.len = len;
+
+        Ok(direct)
+    }
+
+    /// Makes room for `len` bytes, keeping the contents and zeroing the rest.
+    fn reserve(&mut self, len: usize) {
+        let pages = len.next_multiple_of(PAGE);
+        if self.start + pages <= self.raw.len() {
+            return;
+        }
+
+        let mut raw = vec![0; 2 * pages + PAGE];
+        let start = raw.as_ptr().align_offset(PAGE);
+        raw[start..start + self.len].copy_from_slice(&self.data()[..self.len]);
+
+        self.raw = raw;
+        self.start = start;
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.raw[self.start..]
+    }
+}
+
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+impl Read for DirectFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.pos.min(self.len);
+        let n = (self.len - start).min(buf.len());
+        buf[..n].copy_from_slice(&self.data()[start..start + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+impl Write for DirectFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.pos + buf.len();
+        self.reserve(end);
+        self.raw[self.start + self.pos..self.start + end].copy_from_slice(buf);
+
+        // Write back every page the write touched. The last one is padded, so the file is
+        // trimmed back to its length afterwards.
+        let first = self.pos / PAGE * PAGE;
+        let last = end.next_multiple_of(PAGE);
+        self.file.write_all_at(
+            &self.raw[self.start + first..self.start + last],
+            first as u64,
+        )?;
+
+        self.pos = end;
+        self.len = self.len.max(end);
+        self.file.set_len(self.len as u64)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+impl Seek for DirectFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => (self.len as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => (self.pos as u64).checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))? as usize;
+        Ok(self.pos as u64)
     }
 }
@@ -0,0 +1,381 @@
+//! Storage over drivers implementing the `embedded-storage` traits.
+
+use crate::{flash::Flash, Storage};
+use embedded_io::{
+    blocking::{Read, Seek, Write},
+    ErrorKind, Io, SeekFrom,
+};
+use embedded_storage::{nor_flash::MultiwriteNorFlash, Storage as EmbeddedStorage};
+use std::fmt::Debug;
+use thiserror::Error;
+
+/// An error from an `embedded-storage` driver.
+#[derive(Debug, Error)]
+#[error("device error: {0:?}")]
+pub struct DeviceError<E>(pub E);
+
+/// Makes a NOR flash driver usable with `FlashStorage`.
+///
+/// Reads and writes are widened to the driver's alignment, padding writes with erased bytes,
+/// which leave the flash as it is. This relies on words being programmable more than once,
+/// hence the `MultiwriteNorFlash` bound.
+pub struct NorFlashAdapter<T> {
+    inner: T,
+}
+
+impl<T> NorFlashAdapter<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Returns the `align`-aligned range covering `len` bytes at `offset`.
+fn widen(offset: u32, len: usize, align: usize) -> (u32, usize) {
+    let start = offset - offset % align as u32;
+    let end = (offset as usize + len).next_multiple_of(align);
+    (start, end - start as usize)
+}
+
+impl<T> Flash for NorFlashAdapter<T>
+where
+    T: MultiwriteNorFlash,
+    T::Error: 'static,
+{
+    type Error = DeviceError<T::Error>;
+
+    fn block_size(&self) -> u32 {
+        T::ERASE_SIZE as u32
+    }
+
+    fn blocks(&self) -> u32 {
+        (self.inner.capacity() / T::ERASE_SIZE) as u32
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let (start, len) = widen(offset, buf.len(), T::READ_SIZE);
+        let mut wide = vec![0; len];
+        self.inner.read(start, &mut wide).map_err(DeviceError)?;
+
+        let skip = (offset - start) as usize;
+        buf.copy_from_slice(&wide[skip..skip + buf.len()]);
+        Ok(())
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let (start, len) = widen(offset, data.len(), T::WRITE_SIZE);
+        let mut wide = vec![0xff; len];
+
+        let skip = (offset - start) as usize;
+        wide[skip..skip + data.len()].copy_from_slice(data);
+        self.inner.write(start, &wide).map_err(DeviceError)
+    }
+
+    fn erase(&mut self, block: u32) -> Result<(), Self::Error> {
+        let size = T::ERASE_SIZE as u32;
+        self.inner
+            .erase(block * size, (block + 1) * size)
+            .map_err(DeviceError)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("device error: {0:?}")]
+    Device(E),
+
+    #[error("no such object: {0}")]
+    NotFound(u64),
+
+    #[error("couldn't deallocate ID: {0}")]
+    Dealloc(u64),
+
+    #[error("object {0} doesn't fit in a slot")]
+    TooLarge(u64),
+
+    #[error("invalid seek")]
+    Seek,
+
+    #[error("no free slots")]
+    Full,
+
+    #[error("slots of {0} bytes can't hold an object header")]
+    SlotSize(u32),
+}
+
+impl<E> embedded_io::Error for Error<E>
+where
+    E: Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// Marks a free slot in place of a length.
+const FREE: u32 = u32::MAX;
+
+/// Size of the length at the start of each slot.
+const LEN: u32 = 4;
+
+/// Storage on byte-addressable, rewritable memory such as EEPROM.
+///
+/// The memory is divided into fixed-size slots, one per object, each starting with the
+/// object's length. Objects are updated in place, so writes are cheap but wear isn't spread.
+pub struct EepromStorage<T> {
+    device: T,
+    slot_size: u32,
+    lens: Vec<Option<u32>>,
+}
+
+impl<T> EepromStorage<T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    /// Divides `device` into slots of `slot_size` bytes and frees all of them.
+    pub fn format(mut device: T, slot_size: u32) -> Result<Self, Error<T::Error>> {
+        let slots = Self::slots(&device, slot_size)?;
+        for slot in 0..slots {
+            device
+                .write(slot * slot_size, &FREE.to_le_bytes())
+                .map_err(Error::Device)?;
+        }
+
+        Ok(Self {
+            device,
+            slot_size,
+            lens: vec![None; slots as usize],
+        })
+    }
+
+    /// Recovers the objects already stored on `device`, which was formatted with `slot_size`.
+    pub fn mount(mut device: T, slot_size: u32) -> Result<Self, Error<T::Error>> {
+        let slots = Self::slots(&device, slot_size)?;
+        let mut lens = Vec::with_capacity(slots as usize);
+
+        for slot in 0..slots {
+            let mut len = [0; LEN as usize];
+            device
+                .read(slot * slot_size, &mut len)
+                .map_err(Error::Device)?;
+            lens.push(Some(u32::from_le_bytes(len)).filter(|&len| len != FREE));
+        }
+
+        Ok(Self {
+            device,
+            slot_size,
+            lens,
+        })
+    }
+
+    fn slots(device: &T, slot_size: u32) -> Result<u32, Error<T::Error>> {
+        if slot_size <= LEN {
+            return Err(Error::SlotSize(slot_size));
+        }
+        Ok((device.capacity() / slot_size as usize) as u32)
+    }
+
+    /// Returns the largest object that fits in a slot.
+    pub fn capacity(&self) -> u32 {
+        self.slot_size - LEN
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    fn len(&self, id: u64) -> Result<u32, Error<T::Error>> {
+        self.lens
+            .get(id as usize)
+            .copied()
+            .flatten()
+            .ok_or(Error::NotFound(id))
+    }
+
+    fn set_len(&mut self, id: u64, len: Option<u32>) -> Result<(), Error<T::Error>> {
+        self.device
+            .write(
+                id as u32 * self.slot_size,
+                &len.unwrap_or(FREE).to_le_bytes(),
+            )
+            .map_err(Error::Device)?;
+        self.lens[id as usize] = len;
+        Ok(())
+    }
+
+    fn read_at(&mut self, id: u64, pos: u64, buf: &mut [u8]) -> Result<usize, Error<T::Error>> {
+        let len = self.len(id)?;
+        let n = (len as u64).saturating_sub(pos).min(buf.len() as u64) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+
+        let offset = id as u32 * self.slot_size + LEN + pos as u32;
+        self.device
+            .read(offset, &mut buf[..n])
+            .map_err(Error::Device)?;
+        Ok(n)
+    }
+
+    fn write_at(&mut self, id: u64, pos: u64, buf: &[u8]) -> Result<(), Error<T::Error>> {
+        let len = self.len(id)?;
+        let end = pos + buf.len() as u64;
+        if end > self.capacity() as u64 {
+            return Err(Error::TooLarge(id));
+        }
+
+        // Writing past the end fills the gap with zeros, like a file.
+        let start = pos.min(len as u64);
+        let mut data = vec![0; (pos - start) as usize];
+        data.extend_from_slice(buf);
+
+        let offset = id as u32 * self.slot_size + LEN + start as u32;
+        self.device.write(offset, &data).map_err(Error::Device)?;
+
+        if end > len as u64 {
+            self.set_len(id, Some(end as u32))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Storage for EepromStorage<T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    type Id = u64;
+    type Error = Error<T::Error>;
+    type ReadHandle<'a>
+        = EepromHandle<'a, T>
+    where
+        T: 'a;
+    type WriteHandle<'a>
+        = EepromHandle<'a, T>
+    where
+        T: 'a;
+    type RwHandle<'a>
+        = EepromHandle<'a, T>
+    where
+        T: 'a;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        let id = self
+            .lens
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::Full)? as u64;
+        self.set_len(id, Some(0))?;
+        Ok(id)
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        if self.len(id).is_err() {
+            return Err(Error::Dealloc(id));
+        }
+        self.set_len(id, None)
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        let len = self.len(*id)? as u64;
+        if size > len {
+            self.write_at(*id, len, &vec![0; (size - len) as usize])
+        } else {
+            self.set_len(*id, Some(size as u32))
+        }
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        EepromHandle::new(self, *id)
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        EepromHandle::new(self, *id)
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        EepromHandle::new(self, *id)
+    }
+}
+
+/// A handle to an object in `EepromStorage`.
+pub struct EepromHandle<'a, T> {
+    storage: &'a mut EepromStorage<T>,
+    id: u64,
+    pos: u64,
+}
+
+impl<'a, T> EepromHandle<'a, T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    fn new(storage: &'a mut EepromStorage<T>, id: u64) -> Result<Self, Error<T::Error>> {
+        storage.len(id)?;
+        Ok(Self {
+            storage,
+            id,
+            pos: 0,
+        })
+    }
+}
+
+impl<T> Io for EepromHandle<'_, T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    type Error = Error<T::Error>;
+}
+
+impl<T> Read for EepromHandle<'_, T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.storage.read_at(self.id, self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T> Write for EepromHandle<'_, T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.storage.write_at(self.id, self.pos, buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<T> Seek for EepromHandle<'_, T>
+where
+    T: EmbeddedStorage,
+    T::Error: Debug,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let len = self.storage.len(self.id)? as u64;
+
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or(Error::Seek)?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,896 @@
+use crate::Storage;
+use embedded_io::{
+    blocking::{Read, Seek, Write},
+    ErrorKind, Io, SeekFrom,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+};
+use thiserror::Error;
+
+/// Raw NOR flash, addressed in bytes and erased a block at a time.
+///
+/// Erasing sets every bit of a block, and programming can only clear bits, so a range can be
+/// programmed again only with values that don't set any bits that are already clear.
+pub trait Flash {
+    type Error: std::error::Error + 'static;
+
+    /// Returns the size of an erase block in bytes.
+    fn block_size(&self) -> u32;
+
+    /// Returns the number of erase blocks.
+    fn blocks(&self) -> u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    fn erase(&mut self, block: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Error)]
+pub enum RamFlashError {
+    #[error("access out of bounds at offset {0}")]
+    OutOfBounds(u32),
+
+    #[error("programming offset {0} would set cleared bits")]
+    Program(u32),
+}
+
+/// Flash simulated in memory, enforcing NOR programming rules and counting erases.
+#[derive(Debug)]
+pub struct RamFlash {
+    data: Vec<u8>,
+    block_size: u32,
+    erases: Vec<u64>,
+}
+
+impl RamFlash {
+    /// Creates fully erased flash with `blocks` blocks of `block_size` bytes.
+    pub fn new(block_size: u32, blocks: u32) -> Self {
+        Self {
+            data: vec![0xff; block_size as usize * blocks as usize],
+            block_size,
+            erases: vec![0; blocks as usize],
+        }
+    }
+
+    /// Returns how many times each block has been erased.
+    pub fn erase_counts(&self) -> &[u64] {
+        &self.erases
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<std::ops::Range<usize>, RamFlashError> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok(start..end),
+            _ => Err(RamFlashError::OutOfBounds(offset)),
+        }
+    }
+}
+
+impl Flash for RamFlash {
+    type Error = RamFlashError;
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn blocks(&self) -> u32 {
+        self.erases.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, data.len())?;
+        let old = &mut self.data[range];
+
+        if let Some(idx) = old
+            .iter()
+            .zip(data)
+            .position(|(old, new)| old & new != *new)
+        {
+            return Err(RamFlashError::Program(offset + idx as u32));
+        }
+
+        old.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn erase(&mut self, block: u32) -> Result<(), Self::Error> {
+        // Past the last block, the offset may not even fit in a `u32`.
+        let offset = block
+            .checked_mul(self.block_size)
+            .ok_or(RamFlashError::OutOfBounds(u32::MAX))?;
+        let range = self.range(offset, self.block_size as usize)?;
+        self.data[range].fill(0xff);
+        self.erases[block as usize] += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("flash error: {0}")]
+    Flash(#[from] E),
+
+    #[error("no such object: {0}")]
+    NotFound(u64),
+
+    #[error("couldn't deallocate ID: {0}")]
+    Dealloc(u64),
+
+    #[error("object {0} doesn't fit in an erase block")]
+    TooLarge(u64),
+
+    #[error("invalid seek")]
+    Seek,
+
+    #[error("no free erase blocks")]
+    Full,
+
+    #[error("erase blocks of {0} bytes can't hold an object header")]
+    BlockSize(u32),
+}
+
+impl<E> embedded_io::Error for Error<E>
+where
+    E: Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+// Every block holding an object starts with a header, which is programmed before the data and
+// committed by programming the magic number last:
+//
+// | magic: u32 | dead: u32 | erases: u32 | unused: u32 | id: u64 | seq: u64 | lens: [u32; 8] |
+//
+// `dead` is cleared once a newer version of the object exists or it has been deallocated, and
+// the object's length is the last programmed entry of `lens`.
+const MAGIC: u32 = 0x4254_5245;
+const ERASED: u32 = u32::MAX;
+const DEAD: u32 = 4;
+const ERASES: u32 = 8;
+const ID: u32 = 16;
+const SEQ: u32 = 24;
+const LENS: u32 = 32;
+const SLOTS: usize = 8;
+const HEADER: u32 = LENS + 4 * SLOTS as u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Free,
+    Live,
+    Dead,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    state: State,
+    erases: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Object {
+    block: u32,
+    len: u32,
+    slot: usize,
+}
+
+/// Wear and space usage of a `FlashStorage`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlashStats {
+    /// Blocks holding the current version of an object.
+    pub live: u32,
+
+    /// Blocks holding obsolete data that must be erased before reuse.
+    pub dead: u32,
+
+    /// Erased blocks.
+    pub free: u32,
+
+    pub min_erases: u32,
+    pub max_erases: u32,
+}
+
+/// Storage on raw NOR flash, keeping each object in its own erase block.
+///
+/// Objects are updated out of place: writes that only clear bits, such as appends, are
+/// programmed in place, and anything else copies the object into a fresh block and marks the
+/// old one dead. Dead blocks are erased when no free blocks are left, and new blocks are
+/// always the least worn free ones. With `leveling`, objects that sit in rarely erased blocks
+/// are also moved to worn ones, so that their blocks get reused.
+///
+/// Erase counts are stored in block headers, so those of blocks that were free at `mount` are
+/// lost and estimated from the rest.
+pub struct FlashStorage<F> {
+    flash: F,
+    blocks: Vec<Block>,
+    objects: HashMap<u64, Object>,
+    next: u64,
+    free: BTreeSet<u64>,
+    seq: u64,
+    threshold: Option<u32>,
+}
+
+impl<F> FlashStorage<F>
+where
+    F: Flash,
+{
+    /// Erases all of `flash` and starts with no objects.
+    pub fn format(mut flash: F) -> Result<Self, Error<F::Error>> {
+        for block in 0..flash.blocks() {
+            flash.erase(block)?;
+        }
+
+        let blocks = vec![
+            Block {
+                state: State::Free,
+                erases: 1,
+            };
+            flash.blocks() as usize
+        ];
+
+        Self::with_blocks(flash, blocks)
+    }
+
+    /// Recovers the objects already stored on `flash`.
+    pub fn mount(mut flash: F) -> Result<Self, Error<F::Error>> {
+        let mut blocks = Vec::with_capacity(flash.blocks() as usize);
+        let mut objects = HashMap::<u64, (Object, u64)>::new();
+        let mut shadowed = vec![];
+
+        for block in 0..flash.blocks() {
+            let mut header = [0; HEADER as usize];
+            flash.read(block * flash.block_size(), &mut header)?;
+
+            let u32_at = |offset: u32| {
+                let offset = offset as usize;
+                u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+            };
+            let u64_at = |offset: u32| {
+                let offset = offset as usize;
+                u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap())
+            };
+
+            if header.iter().all(|&b| b == 0xff) {
+                blocks.push(Block {
+                    state: State::Free,
+                    erases: ERASED,
+                });
+                continue;
+            }
+
+            let erases = u32_at(ERASES);
+            if u32_at(0) != MAGIC || u32_at(DEAD) != ERASED {
+                blocks.push(Block {
+                    state: State::Dead,
+                    erases,
+                });
+                continue;
+            }
+
+            blocks.push(Block {
+                state: State::Live,
+                erases,
+            });
+
+            let slot = (0..SLOTS)
+                .take_while(|&slot| u32_at(LENS + 4 * slot as u32) != ERASED)
+                .count();
+            let object = Object {
+                block,
+                len: u32_at(LENS + 4 * slot.saturating_sub(1) as u32),
+                slot,
+            };
+
+            let (id, seq) = (u64_at(ID), u64_at(SEQ));
+            match objects.get(&id) {
+                Some(&(_, newer)) if newer > seq => shadowed.push(object.block),
+                Some(&(older, _)) => {
+                    shadowed.push(older.block);
+                    objects.insert(id, (object, seq));
+                }
+                None => {
+                    objects.insert(id, (object, seq));
+                }
+            }
+        }
+
+        // Free blocks don't record how worn they are, so assume they're average.
+        let known: Vec<_> = blocks
+            .iter()
+            .filter(|block| block.erases != ERASED)
+            .map(|block| block.erases as u64)
+            .collect();
+        let average = known.iter().sum::<u64>() / known.len().max(1) as u64;
+        for block in &mut blocks {
+            if block.erases == ERASED {
+                block.erases = average as u32;
+            }
+        }
+
+        let seq = objects.values().map(|&(_, seq)| seq).max().unwrap_or(0);
+        let objects: HashMap<_, _> = objects
+            .into_iter()
+            .map(|(id, (object, _))| (id, object))
+            .collect();
+
+        let mut storage = Self::with_blocks(flash, blocks)?;
+        storage.seq = seq;
+        storage.next = objects.keys().max().map_or(0, |id| id + 1);
+        storage.free = (0..storage.next)
+            .filter(|id| !objects.contains_key(id))
+            .collect();
+        storage.objects = objects;
+
+        // Versions left behind by an interrupted update.
+        for block in shadowed {
+            storage.kill(block)?;
+        }
+
+        Ok(storage)
+    }
+
+    fn with_blocks(flash: F, blocks: Vec<Block>) -> Result<Self, Error<F::Error>> {
+        if flash.block_size() <= HEADER {
+            return Err(Error::BlockSize(flash.block_size()));
+        }
+
+        Ok(Self {
+            flash,
+            blocks,
+            objects: HashMap::new(),
+            next: 0,
+            free: BTreeSet::new(),
+            seq: 0,
+            threshold: None,
+        })
+    }
+
+    /// Moves objects out of blocks that have been erased `threshold` times fewer than the most
+    /// worn block that's available.
+    pub fn leveling(mut self, threshold: u32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Returns the largest object that fits in an erase block.
+    pub fn capacity(&self) -> u32 {
+        self.flash.block_size() - HEADER
+    }
+
+    pub fn stats(&self) -> FlashStats {
+        let count = |state| {
+            self.blocks
+                .iter()
+                .filter(|block| block.state == state)
+                .count() as u32
+        };
+
+        FlashStats {
+            live: count(State::Live),
+            dead: count(State::Dead),
+            free: count(State::Free),
+            min_erases: self
+                .blocks
+                .iter()
+                .map(|block| block.erases)
+                .min()
+                .unwrap_or(0),
+            max_erases: self
+                .blocks
+                .iter()
+                .map(|block| block.erases)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn addr(&self, block: u32, offset: u32) -> u32 {
+        block * self.flash.block_size() + offset
+    }
+
+    fn object(&self, id: u64) -> Result<Object, Error<F::Error>> {
+        self.objects.get(&id).copied().ok_or(Error::NotFound(id))
+    }
+
+    /// Returns the object, creating it empty if it doesn't exist yet.
+    fn object_or_create(&mut self, id: u64) -> Result<Object, Error<F::Error>> {
+        if !self.objects.contains_key(&id) {
+            self.rewrite(id, &[])?;
+        }
+        self.object(id)
+    }
+
+    fn read_object(&mut self, id: u64) -> Result<Vec<u8>, Error<F::Error>> {
+        let object = self.object(id)?;
+        let mut data = vec![0; object.len as usize];
+        self.flash
+            .read(self.addr(object.block, HEADER), &mut data)?;
+        Ok(data)
+    }
+
+    fn read_at(&mut self, id: u64, pos: u64, buf: &mut [u8]) -> Result<usize, Error<F::Error>> {
+        let object = self.object(id)?;
+        let n = (object.len as u64)
+            .saturating_sub(pos)
+            .min(buf.len() as u64) as usize;
+        if n == 0 {
+            return Ok(0);
+        }
+
+        self.flash
+            .read(self.addr(object.block, HEADER + pos as u32), &mut buf[..n])?;
+        Ok(n)
+    }
+
+    fn write_at(&mut self, id: u64, pos: u64, buf: &[u8]) -> Result<(), Error<F::Error>> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let object = self.object_or_create(id)?;
+        let end = pos + buf.len() as u64;
+        if end > self.capacity() as u64 {
+            return Err(Error::TooLarge(id));
+        }
+
+        // Writing past the end fills the gap with zeros, like a file.
+        let start = pos.min(object.len as u64) as u32;
+        let mut data = vec![0; (pos - start as u64) as usize];
+        data.extend_from_slice(buf);
+
+        let mut old = vec![0; data.len()];
+        self.flash
+            .read(self.addr(object.block, HEADER + start), &mut old)?;
+
+        if old.iter().zip(&data).all(|(old, new)| old & new == *new) {
+            self.flash
+                .program(self.addr(object.block, HEADER + start), &data)?;
+            if end > object.len as u64 {
+                self.set_len(id, end as u32)?;
+            }
+        } else {
+            let mut contents = self.read_object(id)?;
+            contents.resize(contents.len().max(end as usize), 0);
+            contents[start as usize..end as usize].copy_from_slice(&data);
+            self.rewrite(id, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_len(&mut self, id: u64, len: u32) -> Result<(), Error<F::Error>> {
+        let object = self.object_or_create(id)?;
+
+        if object.slot < SLOTS {
+            let offset = self.addr(object.block, LENS + 4 * object.slot as u32);
+            self.flash.program(offset, &len.to_le_bytes())?;
+            self.objects.insert(
+                id,
+                Object {
+                    len,
+                    slot: object.slot + 1,
+                    ..object
+                },
+            );
+        } else {
+            // Out of length slots, so start over in a new block.
+            let mut contents = self.read_object(id)?;
+            contents.resize(len as usize, 0);
+            self.rewrite(id, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a new version of an object to a fresh block.
+    fn rewrite(&mut self, id: u64, contents: &[u8]) -> Result<(), Error<F::Error>> {
+        let block = self.take_block(false)?;
+        self.place(id, contents, block)?;
+        self.level()
+    }
+
+    /// Writes an object to the erased `block` and retires its previous version.
+    fn place(&mut self, id: u64, contents: &[u8], block: u32) -> Result<(), Error<F::Error>> {
+        self.seq += 1;
+
+        let mut header = [0xff; HEADER as usize];
+        header[ERASES as usize..][..4]
+            .copy_from_slice(&self.blocks[block as usize].erases.to_le_bytes());
+        header[ID as usize..][..8].copy_from_slice(&id.to_le_bytes());
+        header[SEQ as usize..][..8].copy_from_slice(&self.seq.to_le_bytes());
+        header[LENS as usize..][..4].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+
+        self.flash.program(self.addr(block, 0), &header)?;
+        self.flash
+            .program(self.addr(block, 0), &MAGIC.to_le_bytes())?;
+        self.flash.program(self.addr(block, HEADER), contents)?;
+        self.blocks[block as usize].state = State::Live;
+
+        let old = self.objects.insert(
+            id,
+            Object {
+                block,
+                len: contents.len() as u32,
+                slot: 1,
+            },
+        );
+        if let Some(old) = old {
+            self.kill(old.block)?;
+        }
+
+        Ok(())
+    }
+
+    fn kill(&mut self, block: u32) -> Result<(), Error<F::Error>> {
+        self.flash
+            .program(self.addr(block, DEAD), &0u32.to_le_bytes())?;
+        self.blocks[block as usize].state = State::Dead;
+        Ok(())
+    }
+
+    /// Takes the least worn free block, or the most worn one if `worn` is set, erasing a dead
+    /// block if none are free.
+    fn take_block(&mut self, worn: bool) -> Result<u32, Error<F::Error>> {
+        let pick = |state| {
+            let candidates = (0..self.blocks.len() as u32)
+                .filter(|&block| self.blocks[block as usize].state == state);
+            if worn {
+                candidates.max_by_key(|&block| self.blocks[block as usize].erases)
+            } else {
+                candidates.min_by_key(|&block| self.blocks[block as usize].erases)
+            }
+        };
+
+        if let Some(block) = pick(State::Free) {
+            return Ok(block);
+        }
+
+        let block = pick(State::Dead).ok_or(Error::Full)?;
+        self.flash.erase(block)?;
+        let block_info = &mut self.blocks[block as usize];
+        block_info.state = State::Free;
+        block_info.erases += 1;
+
+        Ok(block)
+    }
+
+    /// Moves the object in the least worn live block if it's lagging behind the most worn
+    /// available block by more than the threshold.
+    fn level(&mut self) -> Result<(), Error<F::Error>> {
+        let Some(threshold) = self.threshold else {
+            return Ok(());
+        };
+
+        let erases = |state| {
+            self.blocks
+                .iter()
+                .filter(move |block| block.state == state)
+                .map(|block| block.erases)
+        };
+        let Some(cold) = erases(State::Live).min() else {
+            return Ok(());
+        };
+        let Some(hot) = erases(State::Free).chain(erases(State::Dead)).max() else {
+            return Ok(());
+        };
+        if hot.saturating_sub(cold) <= threshold {
+            return Ok(());
+        }
+
+        let (&id, _) = self
+            .objects
+            .iter()
+            .min_by_key(|(_, object)| self.blocks[object.block as usize].erases)
+            .unwrap();
+        let contents = self.read_object(id)?;
+        let block = self.take_block(true)?;
+        self.place(id, &contents, block)
+    }
+}
+
+impl<F> Storage for FlashStorage<F>
+where
+    F: Flash,
+{
+    type Id = u64;
+    type Error = Error<F::Error>;
+    type ReadHandle<'a>
+        = FlashHandle<'a, F>
+    where
+        F: 'a;
+    type WriteHandle<'a>
+        = FlashHandle<'a, F>
+    where
+        F: 'a;
+    type RwHandle<'a>
+        = FlashHandle<'a, F>
+    where
+        F: 'a;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        Ok(self.free.pop_first().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        }))
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        if id >= self.next || !self.free.insert(id) {
+            return Err(Error::Dealloc(id));
+        }
+        if let Some(object) = self.objects.remove(&id) {
+            self.kill(object.block)?;
+        }
+        Ok(())
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        let object = self.object_or_create(*id)?;
+        if size > self.capacity() as u64 {
+            return Err(Error::TooLarge(*id));
+        }
+
+        if size <= object.len as u64 {
+            self.set_len(*id, size as u32)
+        } else {
+            self.write_at(
+                *id,
+                object.len as u64,
+                &vec![0; (size - object.len as u64) as usize],
+            )
+        }
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        self.object(*id)?;
+        Ok(FlashHandle {
+            storage: self,
+            id: *id,
+            pos: 0,
+        })
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        Ok(FlashHandle {
+            storage: self,
+            id: *id,
+            pos: 0,
+        })
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        Ok(FlashHandle {
+            storage: self,
+            id: *id,
+            pos: 0,
+        })
+    }
+}
+
+/// A handle to an object in `FlashStorage`.
+pub struct FlashHandle<'a, F> {
+    storage: &'a mut FlashStorage<F>,
+    id: u64,
+    pos: u64,
+}
+
+impl<F> Io for FlashHandle<'_, F>
+where
+    F: Flash,
+{
+    type Error = Error<F::Error>;
+}
+
+impl<F> Read for FlashHandle<'_, F>
+where
+    F: Flash,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.storage.read_at(self.id, self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F> Write for FlashHandle<'_, F>
+where
+    F: Flash,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.storage.write_at(self.id, self.pos, buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<F> Seek for FlashHandle<'_, F>
+where
+    F: Flash,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let len = self
+            .storage
+            .objects
+            .get(&self.id)
+            .map_or(0, |object| object.len as u64);
+
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or(Error::Seek)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flash whose programming fails once a budget runs out, as if power were cut.
+    struct TornFlash {
+        inner: RamFlash,
+        programs: usize,
+    }
+
+    impl Flash for TornFlash {
+        type Error = RamFlashError;
+
+        fn block_size(&self) -> u32 {
+            self.inner.block_size()
+        }
+
+        fn blocks(&self) -> u32 {
+            self.inner.blocks()
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+            self.inner.read(offset, buf)
+        }
+
+        fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+            if self.programs == 0 {
+                return Err(RamFlashError::Program(offset));
+            }
+            self.programs -= 1;
+            self.inner.program(offset, data)
+        }
+
+        fn erase(&mut self, block: u32) -> Result<(), Self::Error> {
+            self.inner.erase(block)
+        }
+    }
+
+    fn write<F: Flash>(storage: &mut FlashStorage<F>, id: u64, data: &[u8]) {
+        storage.truncate_id(&id, 0).unwrap();
+        storage.write_handle(&id).unwrap().write_all(data).unwrap();
+    }
+
+    fn read<F: Flash>(storage: &mut FlashStorage<F>, id: u64) -> Vec<u8> {
+        let mut handle = storage.read_handle(&id).unwrap();
+        let len = handle.seek(SeekFrom::End(0)).unwrap();
+        handle.seek(SeekFrom::Start(0)).unwrap();
+        let mut data = vec![0; len as usize];
+        handle.read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut storage = FlashStorage::format(RamFlash::new(256, 8)).unwrap();
+        let ids: Vec<_> = (0..3).map(|_| storage.alloc_id().unwrap()).collect();
+        write(&mut storage, ids[0], b"first");
+        write(&mut storage, ids[2], &[0x5a; 100]);
+        storage.dealloc_id(ids[1]).unwrap();
+
+        // Appends only program further length slots in the same block.
+        let mut handle = storage.write_handle(&ids[0]).unwrap();
+        handle.seek(SeekFrom::End(0)).unwrap();
+        handle.write_all(b", then more").unwrap();
+
+        let mut storage = FlashStorage::mount(storage.into_inner()).unwrap();
+        assert_eq!(read(&mut storage, ids[0]), b"first, then more");
+        assert_eq!(read(&mut storage, ids[2]), [0x5a; 100]);
+        assert!(matches!(
+            storage.read_handle(&ids[1]),
+            Err(Error::NotFound(_))
+        ));
+
+        // The deallocated ID is free again, and new IDs carry on after the old ones.
+        assert_eq!(storage.alloc_id().unwrap(), ids[1]);
+        assert_eq!(storage.alloc_id().unwrap(), 3);
+    }
+
+    #[test]
+    fn reclamation() {
+        let mut storage = FlashStorage::format(RamFlash::new(128, 4)).unwrap();
+        assert_eq!(storage.flash().erase_counts(), [1; 4]);
+
+        // Each write sets cleared bits, so it moves the object to a fresh block.
+        let id = storage.alloc_id().unwrap();
+        for round in 0..20 {
+            let byte = if round % 2 == 0 { 0x0f } else { 0xf0 };
+            write(&mut storage, id, &[byte; 16]);
+            let stats = storage.stats();
+            assert_eq!(stats.live, 1);
+            assert_eq!(stats.live + stats.dead + stats.free, 4);
+        }
+        assert_eq!(read(&mut storage, id), [0xf0; 16]);
+
+        // Dead blocks were erased for reuse, spread evenly over the flash.
+        let counts = storage.flash().erase_counts();
+        assert!(counts.iter().sum::<u64>() > 4 + 10, "{counts:?}");
+        let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+        assert!(max - min <= 1, "{counts:?}");
+
+        // With every block live, there's nowhere to put a new version.
+        for _ in 0..3 {
+            let id = storage.alloc_id().unwrap();
+            write(&mut storage, id, &[0; 8]);
+        }
+        assert_eq!(storage.stats().free, 0);
+        assert!(matches!(
+            storage.write_handle(&id).unwrap().write_all(&[0xff; 16]),
+            Err(Error::Full)
+        ));
+    }
+
+    #[test]
+    fn torn_write() {
+        for budget in 0..8 {
+            let mut storage = FlashStorage::format(TornFlash {
+                inner: RamFlash::new(128, 4),
+                programs: usize::MAX,
+            })
+            .unwrap();
+            let id = storage.alloc_id().unwrap();
+            write(&mut storage, id, &[0; 32]);
+
+            // Cut the power partway through moving the object to a new block.
+            storage.flash.programs = budget;
+            let _ = storage.write_handle(&id).unwrap().write_all(&[0xff; 32]);
+
+            let mut storage = FlashStorage::mount(storage.into_inner().inner).unwrap();
+            let data = read(&mut storage, id);
+            assert!(data == [0; 32] || data == [0xff; 32], "{budget}: {data:?}");
+            assert_eq!(storage.stats().live, 1, "{budget}");
+        }
+    }
+
+    #[test]
+    fn erase_out_of_bounds() {
+        let mut flash = RamFlash::new(1 << 16, 4);
+        assert!(matches!(flash.erase(4), Err(RamFlashError::OutOfBounds(_))));
+        assert!(matches!(
+            flash.erase(1 << 16),
+            Err(RamFlashError::OutOfBounds(_))
+        ));
+        assert!(flash.erase(3).is_ok());
+        assert_eq!(flash.erase_counts(), [0, 0, 0, 1]);
+    }
+}
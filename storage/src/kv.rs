@@ -0,0 +1,464 @@
+//! Storage on top of an existing key-value store.
+
+use crate::Storage;
+use embedded_io::{
+    blocking::{Read, Seek, Write},
+    ErrorKind, Io, SeekFrom,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read as _, Write as _},
+    path::Path,
+};
+use thiserror::Error;
+
+/// A key-value store that maps object IDs to their bytes.
+pub trait KvBackend {
+    type Error: std::error::Error;
+
+    /// Returns the value of `id`, if there is one.
+    fn get(&mut self, id: u64) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Sets the value of `id`, replacing any previous one.
+    fn put(&mut self, id: u64, value: &[u8]) -> Result<(), Self::Error>;
+
+    /// Removes the value of `id`, if there is one.
+    fn delete(&mut self, id: u64) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("backend error: {0}")]
+    Backend(E),
+
+    #[error("no such object: {0}")]
+    NotFound(u64),
+
+    #[error("couldn't deallocate ID: {0}")]
+    Dealloc(u64),
+
+    #[error("object {0} is corrupt")]
+    Corrupt(u64),
+
+    #[error("invalid seek")]
+    Seek,
+}
+
+impl<E> embedded_io::Error for Error<E>
+where
+    E: Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// The key under which the next unused ID is kept.
+const NEXT: u64 = u64::MAX;
+
+/// Storage that keeps each object as a value in a `KvBackend`.
+///
+/// Objects are read into memory whole and written back whole on every write. IDs are allocated
+/// in increasing order, with the next one kept in the backend itself so that reopening the
+/// store doesn't hand out IDs that are in use. Freed IDs are only reused until it's reopened.
+pub struct KvStorage<B> {
+    backend: B,
+    next: u64,
+    free: BTreeSet<u64>,
+}
+
+impl<B> KvStorage<B>
+where
+    B: KvBackend,
+{
+    pub fn new(mut backend: B) -> Result<Self, Error<B::Error>> {
+        let next = match backend.get(NEXT).map_err(Error::Backend)? {
+            Some(raw) => u64::from_le_bytes(raw.try_into().map_err(|_| Error::Corrupt(NEXT))?),
+            None => 0,
+        };
+
+        Ok(Self {
+            backend,
+            next,
+            free: BTreeSet::new(),
+        })
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+
+    fn get(&mut self, id: u64) -> Result<Vec<u8>, Error<B::Error>> {
+        self.backend
+            .get(id)
+            .map_err(Error::Backend)?
+            .ok_or(Error::NotFound(id))
+    }
+
+    fn put(&mut self, id: u64, value: &[u8]) -> Result<(), Error<B::Error>> {
+        self.backend.put(id, value).map_err(Error::Backend)
+    }
+}
+
+impl<B> Storage for KvStorage<B>
+where
+    B: KvBackend,
+{
+    type Id = u64;
+    type Error = Error<B::Error>;
+    type ReadHandle<'a>
+        = KvHandle<'a, B>
+    where
+        B: 'a;
+    type WriteHandle<'a>
+        = KvHandle<'a, B>
+    where
+        B: 'a;
+    type RwHandle<'a>
+        = KvHandle<'a, B>
+    where
+        B: 'a;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        let id = match self.free.pop_first() {
+            Some(id) => id,
+            None => {
+                self.put(NEXT, &(self.next + 1).to_le_bytes())?;
+                self.next += 1;
+                self.next - 1
+            }
+        };
+
+        self.put(id, &[])?;
+        Ok(id)
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        if id >= self.next || self.free.contains(&id) {
+            return Err(Error::Dealloc(id));
+        }
+
+        self.backend.delete(id).map_err(Error::Backend)?;
+        self.free.insert(id);
+        Ok(())
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        let mut data = self.get(*id)?;
+        data.resize(size as usize, 0);
+        self.put(*id, &data)
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        KvHandle::new(self, *id)
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        KvHandle::new(self, *id)
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        KvHandle::new(self, *id)
+    }
+}
+
+/// A handle to an object in `KvStorage`, holding a copy of its bytes.
+pub struct KvHandle<'a, B> {
+    storage: &'a mut KvStorage<B>,
+    id: u64,
+    data: Vec<u8>,
+    pos: u64,
+}
+
+impl<'a, B> KvHandle<'a, B>
+where
+    B: KvBackend,
+{
+    fn new(storage: &'a mut KvStorage<B>, id: u64) -> Result<Self, Error<B::Error>> {
+        let data = storage.get(id)?;
+        Ok(Self {
+            storage,
+            id,
+            data,
+            pos: 0,
+        })
+    }
+}
+
+impl<B> Io for KvHandle<'_, B>
+where
+    B: KvBackend,
+{
+    type Error = Error<B::Error>;
+}
+
+impl<B> Read for KvHandle<'_, B>
+where
+    B: KvBackend,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let start = (self.pos as usize).min(self.data.len());
+        let n = (self.data.len() - start).min(buf.len());
+        buf[..n].copy_from_slice(&self.data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<B> Write for KvHandle<'_, B>
+where
+    B: KvBackend,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // Writing past the end fills the gap with zeros, like a file.
+        let end = self.pos as usize + buf.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[self.pos as usize..end].copy_from_slice(buf);
+        self.pos = end as u64;
+
+        self.storage.put(self.id, &self.data)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<B> Seek for KvHandle<'_, B>
+where
+    B: KvBackend,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => (self.data.len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or(Error::Seek)?;
+        Ok(self.pos)
+    }
+}
+
+/// Marks a deletion in place of a length in `FileKv`'s log.
+const DELETED: u64 = u64::MAX;
+
+/// A `KvBackend` that keeps a `HashMap` in memory and a log of its changes in a file.
+///
+/// Every put and delete is appended to the log, which is replayed when the file is reopened.
+/// The log only grows; `compact` rewrites it with just the current values.
+pub struct FileKv {
+    map: HashMap<u64, Vec<u8>>,
+    log: BufWriter<File>,
+}
+
+impl FileKv {
+    /// Opens the log at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut map = HashMap::new();
+        let mut reader = BufReader::new(&file);
+        let mut header = [0; 16];
+        let mut valid = 0;
+
+        while reader.read_exact(&mut header).is_ok() {
+            let id = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(header[8..].try_into().unwrap());
+
+            if len == DELETED {
+                map.remove(&id);
+            } else {
+                // Read through `take` rather than allocating `len` bytes up front, since a torn
+                // header can claim any length.
+                let mut value = Vec::new();
+                (&mut reader).take(len).read_to_end(&mut value)?;
+                if value.len() as u64 != len {
+                    break;
+                }
+                map.insert(id, value);
+            }
+
+            valid += header.len() as u64 + if len == DELETED { 0 } else { len };
+        }
+
+        // Drop any record that was cut short by a crash, so that new ones follow on from the
+        // last complete one.
+        file.set_len(valid)?;
+
+        Ok(Self {
+            map,
+            log: BufWriter::new(file),
+        })
+    }
+
+    /// Rewrites the log at `path` to hold only the current values.
+    pub fn compact(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("compact");
+
+        {
+            let mut log = BufWriter::new(File::create(&tmp)?);
+            for (id, value) in &self.map {
+                Self::append(&mut log, *id, Some(value))?;
+            }
+            log.flush()?;
+        }
+
+        std::fs::rename(&tmp, path)?;
+        self.log = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+
+    fn append(log: &mut impl io::Write, id: u64, value: Option<&[u8]>) -> io::Result<()> {
+        let len = value.map_or(DELETED, |value| value.len() as u64);
+        log.write_all(&id.to_le_bytes())?;
+        log.write_all(&len.to_le_bytes())?;
+        log.write_all(value.unwrap_or_default())
+    }
+}
+
+impl KvBackend for FileKv {
+    type Error = io::Error;
+
+    fn get(&mut self, id: u64) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.map.get(&id).cloned())
+    }
+
+    fn put(&mut self, id: u64, value: &[u8]) -> Result<(), Self::Error> {
+        Self::append(&mut self.log, id, Some(value))?;
+        self.log.flush()?;
+        self.map.insert(id, value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, id: u64) -> Result<(), Self::Error> {
+        Self::append(&mut self.log, id, None)?;
+        self.log.flush()?;
+        self.map.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf, process};
+
+    /// A log path for `name` that's unique to this test run, removed when dropped.
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("kv-{}-{name}.log", process::id()));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn record(id: u64, len: u64) -> Vec<u8> {
+        [id.to_le_bytes(), len.to_le_bytes()].concat()
+    }
+
+    #[test]
+    fn replay() {
+        let log = TempLog::new("replay");
+
+        let mut kv = FileKv::open(&log.0).unwrap();
+        kv.put(1, b"one").unwrap();
+        kv.put(2, b"two").unwrap();
+        kv.delete(1).unwrap();
+        kv.put(2, b"").unwrap();
+        kv.put(3, b"three").unwrap();
+        drop(kv);
+
+        // Each record is a little-endian ID and length, then the value unless it's a deletion.
+        let raw = fs::read(&log.0).unwrap();
+        assert_eq!(raw[..16], record(1, 3));
+        assert_eq!(raw[16..19], *b"one");
+        assert_eq!(raw[38..54], record(1, DELETED));
+
+        let mut kv = FileKv::open(&log.0).unwrap();
+        assert_eq!(kv.get(1).unwrap(), None);
+        assert_eq!(kv.get(2).unwrap(), Some(vec![]));
+        assert_eq!(kv.get(3).unwrap(), Some(b"three".to_vec()));
+    }
+
+    #[test]
+    fn torn_records() {
+        let log = TempLog::new("torn");
+
+        let mut kv = FileKv::open(&log.0).unwrap();
+        kv.put(1, b"one").unwrap();
+        drop(kv);
+        let valid = fs::metadata(&log.0).unwrap().len();
+
+        // A header cut short, and a whole header whose value was cut short, claiming far more
+        // than could ever be allocated.
+        for tail in [record(2, 3)[..5].to_vec(), [record(2, 1 << 60), b"tw".to_vec()].concat()] {
+            let mut file = OpenOptions::new().append(true).open(&log.0).unwrap();
+            file.write_all(&tail).unwrap();
+            drop(file);
+
+            let mut kv = FileKv::open(&log.0).unwrap();
+            assert_eq!(fs::metadata(&log.0).unwrap().len(), valid);
+            assert_eq!(kv.get(1).unwrap(), Some(b"one".to_vec()));
+            assert_eq!(kv.get(2).unwrap(), None);
+        }
+
+        // New records follow on from the last complete one.
+        let mut kv = FileKv::open(&log.0).unwrap();
+        kv.put(2, b"two").unwrap();
+        drop(kv);
+        let mut kv = FileKv::open(&log.0).unwrap();
+        assert_eq!(kv.get(1).unwrap(), Some(b"one".to_vec()));
+        assert_eq!(kv.get(2).unwrap(), Some(b"two".to_vec()));
+    }
+
+    #[test]
+    fn id_reuse() {
+        let log = TempLog::new("reuse");
+
+        let mut storage = KvStorage::new(FileKv::open(&log.0).unwrap()).unwrap();
+        let ids: Vec<_> = (0..3).map(|_| storage.alloc_id().unwrap()).collect();
+        assert_eq!(ids, [0, 1, 2]);
+
+        storage.dealloc_id(1).unwrap();
+        assert!(matches!(storage.dealloc_id(1), Err(Error::Dealloc(1))));
+        assert!(matches!(storage.dealloc_id(3), Err(Error::Dealloc(3))));
+        assert!(matches!(storage.read_handle(&1), Err(Error::NotFound(1))));
+
+        // A freed ID is handed out again, and comes back empty.
+        storage.write_handle(&0).unwrap().write_all(b"zero").unwrap();
+        storage.dealloc_id(0).unwrap();
+        assert_eq!(storage.alloc_id().unwrap(), 0);
+        assert_eq!(storage.alloc_id().unwrap(), 1);
+        let mut handle = storage.read_handle(&0).unwrap();
+        assert_eq!(handle.seek(SeekFrom::End(0)).unwrap(), 0);
+
+        // Reopening picks up after the highest ID handed out, forgetting any freed ones.
+        storage.dealloc_id(2).unwrap();
+        drop(storage);
+        let mut storage = KvStorage::new(FileKv::open(&log.0).unwrap()).unwrap();
+        assert_eq!(storage.alloc_id().unwrap(), 3);
+    }
+}
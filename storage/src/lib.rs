@@ -1,5 +1,17 @@
 #[cfg(feature = "dir")]
 pub mod dir;
+#[cfg(feature = "embedded-storage")]
+pub mod embedded;
+#[cfg(feature = "flash")]
+pub mod flash;
+#[cfg(feature = "kv")]
+pub mod kv;
+#[cfg(feature = "mem")]
+pub mod mem;
+#[cfg(feature = "retry")]
+pub mod retry;
+#[cfg(feature = "sim")]
+pub mod sim;
 
 use embedded_io::blocking::{Read, Seek, Write};
 use std::error::Error;
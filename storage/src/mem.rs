@@ -0,0 +1,98 @@
+use crate::Storage;
+use embedded_io::adapters::FromStd;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Cursor,
+};
+use thiserror::Error;
+
+/// Storage that keeps every object in memory.
+///
+/// IDs are allocated deterministically: the smallest freed ID is reused first, and otherwise
+/// IDs are handed out in increasing order starting from 0. Deallocating an ID discards its
+/// object, so reading it afterwards fails.
+#[derive(Debug, Default)]
+pub struct MemStorage {
+    objects: HashMap<u64, Vec<u8>>,
+    next: u64,
+    free: BTreeSet<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no such object: {0}")]
+    NotFound(u64),
+
+    #[error("couldn't deallocate ID: {0}")]
+    Dealloc(u64),
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of objects currently stored.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Returns the raw bytes of object `id`.
+    pub fn get(&self, id: u64) -> Option<&[u8]> {
+        self.objects.get(&id).map(Vec::as_slice)
+    }
+
+    /// Returns the raw bytes of object `id` for modification.
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Vec<u8>> {
+        self.objects.get_mut(&id)
+    }
+
+    fn object(&mut self, id: u64) -> &mut Vec<u8> {
+        self.objects.entry(id).or_default()
+    }
+}
+
+impl Storage for MemStorage {
+    type Id = u64;
+    type Error = Error;
+    type ReadHandle<'a> = FromStd<Cursor<&'a mut Vec<u8>>>;
+    type WriteHandle<'a> = FromStd<Cursor<&'a mut Vec<u8>>>;
+    type RwHandle<'a> = FromStd<Cursor<&'a mut Vec<u8>>>;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        Ok(self.free.pop_first().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        }))
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        if id >= self.next || !self.free.insert(id) {
+            return Err(Error::Dealloc(id));
+        }
+        self.objects.remove(&id);
+        Ok(())
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        self.object(*id).resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        let object = self.objects.get_mut(id).ok_or(Error::NotFound(*id))?;
+        Ok(FromStd::new(Cursor::new(object)))
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        Ok(FromStd::new(Cursor::new(self.object(*id))))
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        Ok(FromStd::new(Cursor::new(self.object(*id))))
+    }
+}
@@ -0,0 +1,373 @@
+use crate::Storage;
+use std::{thread, time::Duration};
+
+/// Errors that may go away if the failed operation is tried again, e.g. timeouts of a
+/// network-backed store.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+#[cfg(feature = "dir")]
+impl Transient for crate::dir::Error {
+    fn is_transient(&self) -> bool {
+        use std::io::ErrorKind;
+
+        match self {
+            Self::Io(err) => matches!(
+                err.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::TimedOut
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::ResourceBusy
+            ),
+            Self::Locked => true,
+            Self::Alloc | Self::Dealloc(_) | Self::ReadOnly => false,
+        }
+    }
+}
+
+#[cfg(feature = "mem")]
+impl Transient for crate::mem::Error {
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "kv")]
+impl<E> Transient for crate::kv::Error<E>
+where
+    E: Transient,
+{
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Backend(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+/// Decides whether to retry a failed storage operation, and how long to wait before doing so.
+///
+/// Closures taking the error and the number of attempts made so far are policies.
+pub trait RetryPolicy<E> {
+    /// Returns how long to wait before the next attempt, or `None` to give up and return `err`.
+    fn retry(&mut self, err: &E, attempts: u32) -> Option<Duration>;
+}
+
+impl<E, F> RetryPolicy<E> for F
+where
+    F: FnMut(&E, u32) -> Option<Duration>,
+{
+    fn retry(&mut self, err: &E, attempts: u32) -> Option<Duration> {
+        self(err, attempts)
+    }
+}
+
+/// Retries transient errors with exponential backoff, giving up on other errors right away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// The most attempts to make, including the first.
+    pub attempts: u32,
+
+    /// How long to wait before the first retry, which doubles with each retry after that.
+    pub initial: Duration,
+
+    /// The longest to wait before any retry.
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+impl<E> RetryPolicy<E> for Backoff
+where
+    E: Transient,
+{
+    fn retry(&mut self, err: &E, attempts: u32) -> Option<Duration> {
+        if !err.is_transient() || attempts >= self.attempts {
+            return None;
+        }
+
+        let factor = 1u32.checked_shl(attempts - 1).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// Storage that retries the operations of another storage when they fail, as its policy says.
+///
+/// Reads and writes through a handle aren't retried, since they could have partly happened.
+/// Only the operations of the `Storage` trait itself are, including getting handles.
+pub struct RetryStorage<S, P> {
+    inner: S,
+    policy: P,
+}
+
+impl<S, P> RetryStorage<S, P> {
+    pub fn new(inner: S, policy: P) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+}
+
+impl<S, P> RetryStorage<S, P>
+where
+    S: Storage,
+    P: RetryPolicy<S::Error>,
+{
+    /// Runs `op` on the inner storage until it succeeds or the policy gives up.
+    ///
+    /// What `op` returns may borrow the storage for `'a`, as handles do, so every attempt is
+    /// handed a borrow for all of `'a` and the borrow checker can't see that a failed attempt's
+    /// borrow is over by the next one. `op` must only hand the storage on through what it
+    /// returns, and never keep it anywhere else, such as in a cell it captured, or a later
+    /// attempt would alias it.
+    fn attempt<'a, T>(
+        &'a mut self,
+        op: impl Fn(&'a mut S) -> Result<T, S::Error>,
+    ) -> Result<T, S::Error> {
+        let inner: *mut S = &mut self.inner;
+        let mut attempts = 1;
+        loop {
+            // SAFETY: `inner` points to `self.inner`, which is borrowed mutably for `'a`. `op`
+            // doesn't keep the storage other than in its result, and a failed attempt's error
+            // doesn't borrow it, so it's only ever borrowed by the one attempt that succeeds,
+            // whose result ends the loop.
+            match op(unsafe { &mut *inner }) {
+                Ok(value) => return Ok(value),
+                Err(err) => match self.policy.retry(&err, attempts) {
+                    Some(delay) => thread::sleep(delay),
+                    None => return Err(err),
+                },
+            }
+            attempts += 1;
+        }
+    }
+}
+
+impl<S, P> Storage for RetryStorage<S, P>
+where
+    S: Storage,
+    S::Id: Clone,
+    P: RetryPolicy<S::Error>,
+{
+    type Id = S::Id;
+    type Error = S::Error;
+    type ReadHandle<'a>
+        = S::ReadHandle<'a>
+    where
+        Self: 'a;
+    type WriteHandle<'a>
+        = S::WriteHandle<'a>
+    where
+        Self: 'a;
+    type RwHandle<'a>
+        = S::RwHandle<'a>
+    where
+        Self: 'a;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        self.attempt(|inner| inner.alloc_id())
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        self.attempt(|inner| inner.dealloc_id(id.clone()))
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        self.attempt(|inner| inner.truncate_id(id, size))
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        self.attempt(|inner| inner.read_handle(id))
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        self.attempt(|inner| inner.write_handle(id))
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        self.attempt(|inner| inner.rw_handle(id))
+    }
+}
+
+#[cfg(all(test, feature = "mem"))]
+mod tests {
+    use super::*;
+    use crate::mem::{self, MemStorage};
+    use embedded_io::blocking::{Read, Write};
+    use thiserror::Error;
+
+    #[derive(Debug, Error)]
+    enum FlakyError {
+        #[error("transient failure")]
+        Transient,
+
+        #[error("fatal failure")]
+        Fatal,
+
+        #[error(transparent)]
+        Mem(#[from] mem::Error),
+    }
+
+    impl Transient for FlakyError {
+        fn is_transient(&self) -> bool {
+            matches!(self, Self::Transient)
+        }
+    }
+
+    /// Storage that fails with each of `failures` in turn before passing calls on to `inner`.
+    #[derive(Default)]
+    struct Flaky {
+        inner: MemStorage,
+        failures: Vec<FlakyError>,
+        calls: u32,
+    }
+
+    impl Flaky {
+        fn call(&mut self) -> Result<&mut MemStorage, FlakyError> {
+            self.calls += 1;
+            if self.failures.is_empty() {
+                Ok(&mut self.inner)
+            } else {
+                Err(self.failures.remove(0))
+            }
+        }
+    }
+
+    impl Storage for Flaky {
+        type Id = u64;
+        type Error = FlakyError;
+        type ReadHandle<'a> = <MemStorage as Storage>::ReadHandle<'a>;
+        type WriteHandle<'a> = <MemStorage as Storage>::WriteHandle<'a>;
+        type RwHandle<'a> = <MemStorage as Storage>::RwHandle<'a>;
+
+        fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+            Ok(self.call()?.alloc_id()?)
+        }
+
+        fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+            Ok(self.call()?.dealloc_id(id)?)
+        }
+
+        fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+            Ok(self.call()?.truncate_id(id, size)?)
+        }
+
+        fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+            Ok(self.call()?.read_handle(id)?)
+        }
+
+        fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+            Ok(self.call()?.write_handle(id)?)
+        }
+
+        fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+            Ok(self.call()?.rw_handle(id)?)
+        }
+    }
+
+    const QUICK: Backoff = Backoff {
+        attempts: 3,
+        initial: Duration::ZERO,
+        max: Duration::ZERO,
+    };
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff {
+            attempts: 5,
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        let delays: Vec<_> = (1..=5)
+            .map(|attempts| backoff.retry(&FlakyError::Transient, attempts))
+            .collect();
+        let ms = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(delays, [ms(10), ms(20), ms(40), ms(50), None]);
+        assert_eq!(backoff.retry(&FlakyError::Fatal, 1), None);
+
+        // The doubling saturates rather than overflowing.
+        let mut backoff = Backoff {
+            attempts: u32::MAX,
+            initial: Duration::from_secs(1),
+            max: Duration::MAX,
+        };
+        assert_eq!(
+            backoff.retry(&FlakyError::Transient, 40),
+            Some(Duration::from_secs(u32::MAX.into()))
+        );
+    }
+
+    #[test]
+    fn retries() {
+        let mut storage = RetryStorage::new(Flaky::default(), QUICK);
+
+        // Transient errors are retried until an attempt succeeds...
+        storage.inner.failures = vec![FlakyError::Transient, FlakyError::Transient];
+        let id = storage.alloc_id().unwrap();
+        assert_eq!(storage.inner.calls, 3);
+
+        // ...or the policy runs out of attempts.
+        storage.inner.calls = 0;
+        storage.inner.failures = (0..4).map(|_| FlakyError::Transient).collect();
+        assert!(matches!(
+            storage.truncate_id(&id, 0),
+            Err(FlakyError::Transient)
+        ));
+        assert_eq!(storage.inner.calls, 3);
+
+        // Other errors are given up on right away.
+        storage.inner.calls = 0;
+        storage.inner.failures = vec![FlakyError::Fatal];
+        assert!(matches!(storage.dealloc_id(id), Err(FlakyError::Fatal)));
+        assert_eq!(storage.inner.calls, 1);
+    }
+
+    #[test]
+    fn handles() {
+        let mut storage = RetryStorage::new(Flaky::default(), QUICK);
+        let id = storage.alloc_id().unwrap();
+
+        storage.inner.failures = vec![FlakyError::Transient];
+        storage.write_handle(&id).unwrap().write_all(b"retried").unwrap();
+
+        storage.inner.failures = vec![FlakyError::Transient, FlakyError::Transient];
+        let mut data = [0; 7];
+        storage.read_handle(&id).unwrap().read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"retried");
+
+        storage.inner.failures = vec![FlakyError::Fatal];
+        assert!(matches!(storage.rw_handle(&id), Err(FlakyError::Fatal)));
+    }
+
+    #[test]
+    fn closure_policy() {
+        let mut seen = Vec::new();
+        let policy = |err: &FlakyError, attempts| {
+            seen.push(attempts);
+            err.is_transient().then_some(Duration::ZERO)
+        };
+        let mut storage = RetryStorage::new(Flaky::default(), policy);
+
+        storage.inner.failures = vec![FlakyError::Transient, FlakyError::Fatal];
+        assert!(matches!(storage.alloc_id(), Err(FlakyError::Fatal)));
+        drop(storage);
+        assert_eq!(seen, [1, 2]);
+    }
+}
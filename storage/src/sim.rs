@@ -0,0 +1,366 @@
+use crate::Storage;
+use embedded_io::{
+    blocking::{Read, Seek, Write},
+    Io, SeekFrom,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Simulated device characteristics.
+///
+/// Latencies are charged once per handle or truncation, i.e. once per request, and
+/// bandwidths are in bytes per second, where `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub read_latency: Duration,
+    pub write_latency: Duration,
+    pub read_bandwidth: Option<u64>,
+    pub write_bandwidth: Option<u64>,
+}
+
+impl Profile {
+    fn requests(count: u64, latency: Duration) -> Duration {
+        Duration::from_nanos((count as u128 * latency.as_nanos()) as u64)
+    }
+
+    fn transfer(bytes: u64, bandwidth: Option<u64>) -> Duration {
+        match bandwidth {
+            Some(bandwidth) => {
+                Duration::from_nanos((bytes as u128 * 1_000_000_000 / bandwidth as u128) as u64)
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Counters describing the simulated I/O performed so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+
+    /// Simulated time spent on I/O.
+    pub elapsed: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// A cloneable view of a `SimStorage`'s counters, usable after the storage has been moved
+/// into a tree.
+#[derive(Clone)]
+pub struct SimMonitor {
+    profile: Profile,
+    counters: Arc<Counters>,
+}
+
+impl SimMonitor {
+    pub fn stats(&self) -> SimStats {
+        let reads = self.counters.reads.load(Ordering::Relaxed);
+        let writes = self.counters.writes.load(Ordering::Relaxed);
+        let bytes_read = self.counters.bytes_read.load(Ordering::Relaxed);
+        let bytes_written = self.counters.bytes_written.load(Ordering::Relaxed);
+
+        // Simulated time is derived from the counters, so it doesn't depend on real timing.
+        let elapsed = Profile::requests(reads, self.profile.read_latency)
+            + Profile::requests(writes, self.profile.write_latency)
+            + Profile::transfer(bytes_read, self.profile.read_bandwidth)
+            + Profile::transfer(bytes_written, self.profile.write_bandwidth);
+
+        SimStats {
+            reads,
+            writes,
+            bytes_read,
+            bytes_written,
+            elapsed,
+        }
+    }
+
+    /// Resets every counter to zero.
+    pub fn reset(&self) {
+        self.counters.reads.store(0, Ordering::Relaxed);
+        self.counters.writes.store(0, Ordering::Relaxed);
+        self.counters.bytes_read.store(0, Ordering::Relaxed);
+        self.counters.bytes_written.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Storage that simulates a slower device in front of another storage.
+///
+/// By default, the simulated time is only accounted for in `SimStats::elapsed`, so
+/// experiments run at full speed and are deterministic. Use `sleeping` to also delay every
+/// request in real time.
+pub struct SimStorage<S> {
+    inner: S,
+    monitor: SimMonitor,
+    sleep: bool,
+}
+
+impl<S> SimStorage<S> {
+    pub fn new(inner: S, profile: Profile) -> Self {
+        Self {
+            inner,
+            monitor: SimMonitor {
+                profile,
+                counters: Arc::default(),
+            },
+            sleep: false,
+        }
+    }
+
+    /// Makes every request actually take its simulated time.
+    pub fn sleeping(mut self) -> Self {
+        self.sleep = true;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.monitor.profile
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.monitor.stats()
+    }
+
+    pub fn monitor(&self) -> SimMonitor {
+        self.monitor.clone()
+    }
+
+    fn request(&self, counter: &AtomicU64, latency: Duration) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if self.sleep {
+            thread::sleep(latency);
+        }
+    }
+}
+
+impl<S> Storage for SimStorage<S>
+where
+    S: Storage,
+{
+    type Id = S::Id;
+    type Error = S::Error;
+    type ReadHandle<'a>
+        = SimHandle<'a, S::ReadHandle<'a>>
+    where
+        S: 'a;
+    type WriteHandle<'a>
+        = SimHandle<'a, S::WriteHandle<'a>>
+    where
+        S: 'a;
+    type RwHandle<'a>
+        = SimHandle<'a, S::RwHandle<'a>>
+    where
+        S: 'a;
+
+    fn alloc_id(&mut self) -> Result<Self::Id, Self::Error> {
+        self.inner.alloc_id()
+    }
+
+    fn dealloc_id(&mut self, id: Self::Id) -> Result<(), Self::Error> {
+        self.inner.dealloc_id(id)
+    }
+
+    fn truncate_id(&mut self, id: &Self::Id, size: u64) -> Result<(), Self::Error> {
+        let profile = self.monitor.profile;
+        self.request(&self.monitor.counters.writes, profile.write_latency);
+        self.inner.truncate_id(id, size)
+    }
+
+    fn read_handle(&mut self, id: &Self::Id) -> Result<Self::ReadHandle<'_>, Self::Error> {
+        let profile = self.monitor.profile;
+        self.request(&self.monitor.counters.reads, profile.read_latency);
+        let inner = self.inner.read_handle(id)?;
+        Ok(SimHandle {
+            inner,
+            monitor: &self.monitor,
+            sleep: self.sleep,
+        })
+    }
+
+    fn write_handle(&mut self, id: &Self::Id) -> Result<Self::WriteHandle<'_>, Self::Error> {
+        let profile = self.monitor.profile;
+        self.request(&self.monitor.counters.writes, profile.write_latency);
+        let inner = self.inner.write_handle(id)?;
+        Ok(SimHandle {
+            inner,
+            monitor: &self.monitor,
+            sleep: self.sleep,
+        })
+    }
+
+    fn rw_handle(&mut self, id: &Self::Id) -> Result<Self::RwHandle<'_>, Self::Error> {
+        let profile = self.monitor.profile;
+        self.request(&self.monitor.counters.reads, profile.read_latency);
+        let inner = self.inner.rw_handle(id)?;
+        Ok(SimHandle {
+            inner,
+            monitor: &self.monitor,
+            sleep: self.sleep,
+        })
+    }
+}
+
+/// A handle that charges transferred bytes against the simulated bandwidth.
+pub struct SimHandle<'a, H> {
+    inner: H,
+    monitor: &'a SimMonitor,
+    sleep: bool,
+}
+
+impl<H> Io for SimHandle<'_, H>
+where
+    H: Io,
+{
+    type Error = H::Error;
+}
+
+impl<H> Read for SimHandle<'_, H>
+where
+    H: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.read(buf)?;
+        let counters = &self.monitor.counters;
+        counters.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        if self.sleep {
+            thread::sleep(Profile::transfer(
+                n as u64,
+                self.monitor.profile.read_bandwidth,
+            ));
+        }
+        Ok(n)
+    }
+}
+
+impl<H> Write for SimHandle<'_, H>
+where
+    H: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.write(buf)?;
+        let counters = &self.monitor.counters;
+        counters
+            .bytes_written
+            .fetch_add(n as u64, Ordering::Relaxed);
+        if self.sleep {
+            thread::sleep(Profile::transfer(
+                n as u64,
+                self.monitor.profile.write_bandwidth,
+            ));
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<H> Seek for SimHandle<'_, H>
+where
+    H: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(all(test, feature = "mem"))]
+mod tests {
+    use super::*;
+    use crate::mem::MemStorage;
+    use std::time::Instant;
+
+    const PROFILE: Profile = Profile {
+        read_latency: Duration::from_millis(2),
+        write_latency: Duration::from_millis(5),
+        read_bandwidth: Some(1000),
+        write_bandwidth: Some(500),
+    };
+
+    #[test]
+    fn accounting() {
+        let mut storage = SimStorage::new(MemStorage::new(), PROFILE);
+        let monitor = storage.monitor();
+
+        let id = storage.alloc_id().unwrap();
+        storage.write_handle(&id).unwrap().write_all(&[7; 100]).unwrap();
+        let mut buf = [0; 40];
+        storage.read_handle(&id).unwrap().read_exact(&mut buf).unwrap();
+        storage.truncate_id(&id, 10).unwrap();
+        assert_eq!(buf, [7; 40]);
+
+        let stats = monitor.stats();
+        assert_eq!(stats.reads, 1);
+        assert_eq!(stats.writes, 2);
+        assert_eq!(stats.bytes_read, 40);
+        assert_eq!(stats.bytes_written, 100);
+
+        // 2ms + 2 * 5ms of latency, 40 bytes at 1000B/s and 100 bytes at 500B/s.
+        assert_eq!(stats.elapsed, Duration::from_millis(2 + 10 + 40 + 200));
+        assert_eq!(storage.stats(), stats);
+
+        // The monitor outlives the storage.
+        let inner = storage.into_inner();
+        assert_eq!(inner.get(id), Some(&[7; 10][..]));
+        monitor.reset();
+        assert_eq!(monitor.stats(), SimStats::default());
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut storage = SimStorage::new(MemStorage::new(), Profile::default());
+        let id = storage.alloc_id().unwrap();
+        storage.write_handle(&id).unwrap().write_all(&[1; 1000]).unwrap();
+
+        let stats = storage.stats();
+        assert_eq!(stats.bytes_written, 1000);
+        assert_eq!(stats.elapsed, Duration::ZERO);
+    }
+
+    #[test]
+    fn failed_requests_are_charged() {
+        let mut storage = SimStorage::new(MemStorage::new(), PROFILE);
+        assert!(storage.read_handle(&3).is_err());
+
+        let stats = storage.stats();
+        assert_eq!(stats.reads, 1);
+        assert_eq!(stats.bytes_read, 0);
+        assert_eq!(stats.elapsed, PROFILE.read_latency);
+    }
+
+    #[test]
+    fn sleeping() {
+        let mut storage = SimStorage::new(MemStorage::new(), PROFILE).sleeping();
+        let id = storage.alloc_id().unwrap();
+
+        // Only a lower bound on real time can be relied on.
+        let start = Instant::now();
+        storage.write_handle(&id).unwrap().write_all(&[0; 5]).unwrap();
+        assert!(start.elapsed() >= PROFILE.write_latency + Duration::from_millis(10));
+
+        // Without sleeping, the same request only shows up in the stats.
+        let mut storage = SimStorage::new(MemStorage::new(), PROFILE);
+        let id = storage.alloc_id().unwrap();
+        storage.write_handle(&id).unwrap().write_all(&[0; 5]).unwrap();
+        assert_eq!(storage.stats().elapsed, Duration::from_millis(15));
+    }
+}